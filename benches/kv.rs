@@ -0,0 +1,192 @@
+use std::{
+    net::SocketAddr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use criterion::{BenchmarkId, Criterion, Throughput, black_box, criterion_group, criterion_main};
+use prime_time::config::{DEFAULT_SHARDS, default_workers};
+use prime_time::database_server::{
+    ServerOptions, ShardedStorage, Storage, run_udp_server_with_socket,
+};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use tokio::{net::UdpSocket, runtime::Runtime, sync::Mutex, time::timeout};
+use tokio_util::sync::CancellationToken;
+
+/// Tasks hitting the store at once.
+const TASKS: [usize; 3] = [1, 4, 16];
+const KEYS: usize = 10_000;
+/// One operation in this many is an insert, the rest reads.
+const WRITE_EVERY: u32 = 10;
+const VALUE: &[u8] = b"some value of a typical length";
+/// Clients each keeping one request in flight against the server.
+const CLIENTS: usize = 16;
+
+/// The store as the server held it before sharding, one async lock over everything, and as it
+/// holds it now, a blocking mutex per shard that reads take as well as inserts.
+enum Store {
+    Global(Mutex<Storage>),
+    Sharded(ShardedStorage),
+}
+
+impl Store {
+    /// A read answered the way the server answers it, or an insert.
+    async fn op(&self, key: &[u8], write: bool) {
+        let now = tokio::time::Instant::now();
+        match self {
+            Store::Global(storage) => {
+                let mut storage = storage.lock().await;
+                if write {
+                    storage.insert(key.to_vec(), VALUE.to_vec(), None);
+                } else if let Some(value) = storage.get(key, now) {
+                    black_box([key, b"=", value].concat());
+                }
+            }
+            Store::Sharded(storage) => {
+                if write {
+                    storage.insert(key.to_vec(), VALUE.to_vec(), None);
+                } else if let Some(value) = storage.get(key, now) {
+                    black_box([key, b"=", &value].concat());
+                }
+            }
+        }
+    }
+}
+
+fn runtime() -> Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn keys() -> Arc<Vec<Vec<u8>>> {
+    Arc::new((0..KEYS).map(|n| format!("key{}", n).into()).collect())
+}
+
+/// Read-heavy traffic from `tasks` tasks at once; each iteration is one operation per task.
+fn storage_ops(c: &mut Criterion) {
+    let runtime = runtime();
+    let keys = keys();
+    let mut group = c.benchmark_group("kv_storage");
+    for tasks in TASKS {
+        group.throughput(Throughput::Elements(tasks as u64));
+        let stores = [
+            (
+                "global_mutex",
+                Store::Global(Mutex::new(Storage::default())),
+            ),
+            (
+                "sharded",
+                Store::Sharded(ShardedStorage::new(DEFAULT_SHARDS, None)),
+            ),
+        ];
+        for (name, store) in stores {
+            let store = Arc::new(store);
+            runtime.block_on(async {
+                for key in keys.iter() {
+                    store.op(key, true).await;
+                }
+            });
+            group.bench_function(BenchmarkId::new(name, tasks), |b| {
+                b.iter_custom(|iters| {
+                    runtime.block_on(async {
+                        let start = Instant::now();
+                        let handles: Vec<_> = (0..tasks)
+                            .map(|task| {
+                                let (store, keys) = (Arc::clone(&store), Arc::clone(&keys));
+                                tokio::spawn(async move {
+                                    let mut rng = StdRng::seed_from_u64(task as u64);
+                                    for _ in 0..iters {
+                                        let key = &keys[rng.random_range(0..KEYS)];
+                                        store.op(key, rng.random_ratio(1, WRITE_EVERY)).await;
+                                    }
+                                })
+                            })
+                            .collect();
+                        for handle in handles {
+                            handle.await.unwrap();
+                        }
+                        start.elapsed()
+                    })
+                })
+            });
+        }
+    }
+    group.finish();
+}
+
+/// Reads over loopback from `CLIENTS` clients against one worker, the old receive loop's
+/// concurrency, and against one worker per core, the default.
+fn udp_round_trips(c: &mut Criterion) {
+    let runtime = runtime();
+    let keys = keys();
+    let mut group = c.benchmark_group("kv_udp_round_trips");
+    group.throughput(Throughput::Elements(CLIENTS as u64));
+    for workers in [1, default_workers().max(2)] {
+        let shutdown = CancellationToken::new();
+        let clients = runtime.block_on(async {
+            let storage = Arc::new(ShardedStorage::new(DEFAULT_SHARDS, None));
+            for key in keys.iter() {
+                storage.insert(key.clone(), VALUE.to_vec(), None);
+            }
+            let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let addr = socket.local_addr().unwrap();
+            let options = ServerOptions {
+                workers,
+                ..ServerOptions::default()
+            };
+            tokio::spawn(run_udp_server_with_socket(
+                socket,
+                storage,
+                options,
+                shutdown.clone(),
+            ));
+            let mut clients = vec![];
+            for _ in 0..CLIENTS {
+                clients.push(Arc::new(client(addr).await));
+            }
+            clients
+        });
+        group.bench_function(BenchmarkId::new("workers", workers), |b| {
+            b.iter_custom(|iters| {
+                runtime.block_on(async {
+                    let start = Instant::now();
+                    let handles: Vec<_> = clients
+                        .iter()
+                        .enumerate()
+                        .map(|(i, client)| {
+                            let (client, keys) = (Arc::clone(client), Arc::clone(&keys));
+                            tokio::spawn(async move {
+                                let mut rng = StdRng::seed_from_u64(i as u64);
+                                let mut buf = [0u8; 1000];
+                                for _ in 0..iters {
+                                    let key = &keys[rng.random_range(0..KEYS)];
+                                    client.send(key).await.unwrap();
+                                    // A lost datagram costs the timeout rather than the run
+                                    let _ = timeout(Duration::from_secs(1), client.recv(&mut buf))
+                                        .await;
+                                }
+                            })
+                        })
+                        .collect();
+                    for handle in handles {
+                        handle.await.unwrap();
+                    }
+                    start.elapsed()
+                })
+            })
+        });
+        shutdown.cancel();
+    }
+    group.finish();
+}
+
+async fn client(server: SocketAddr) -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(server).await.unwrap();
+    socket
+}
+
+criterion_group!(benches, storage_ops, udp_round_trips);
+criterion_main!(benches);
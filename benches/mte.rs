@@ -0,0 +1,46 @@
+use std::{collections::BTreeMap, ops::Bound::Included};
+
+use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
+use prime_time::handle_mte::RangeIndex;
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+/// Store sizes to query; each query spans the middle half of the timestamps, so the fold
+/// walks half the store while the index takes the same few dozen steps.
+const SIZES: [usize; 3] = [1_000, 100_000, 1_000_000];
+
+fn range_sum(c: &mut Criterion) {
+    let mut group = c.benchmark_group("mte_range_sum");
+    let mut rng = StdRng::seed_from_u64(7);
+    for size in SIZES {
+        let mut prices = BTreeMap::new();
+        let mut index = RangeIndex::default();
+        // Shuffled timestamps, the order clients are allowed to insert in
+        for _ in 0..size {
+            let (timestamp, price) = (rng.random_range(0..size as i32 * 4), rng.random());
+            prices.insert(timestamp, price);
+            index.insert(timestamp, price);
+        }
+        let (mintime, maxtime) = (size as i32, size as i32 * 3);
+
+        group.bench_with_input(
+            BenchmarkId::new("btree_fold", size),
+            &prices,
+            |b, prices| {
+                b.iter(|| {
+                    prices
+                        .range((Included(black_box(mintime)), Included(black_box(maxtime))))
+                        .fold((0u32, 0i64), |(count, sum), (_timestamp, price)| {
+                            (count + 1, sum + *price as i64)
+                        })
+                })
+            },
+        );
+        group.bench_with_input(BenchmarkId::new("index", size), &index, |b, index| {
+            b.iter(|| index.count_and_sum(black_box(mintime), black_box(maxtime)))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, range_sum);
+criterion_main!(benches);
@@ -0,0 +1,29 @@
+use criterion::{BenchmarkId, Criterion, black_box, criterion_group, criterion_main};
+use prime_time::primes::{is_prime, is_prime_trial_division};
+
+/// Primes of increasing size. Trial division on the 64-bit one would take seconds per
+/// iteration, so it only runs on the smaller three.
+const INPUTS: [(&str, u64); 4] = [
+    ("sieve", 65_521),
+    ("u32", 4_294_967_291),
+    ("2^40", 1_099_511_627_689),
+    ("u64", 18_446_744_073_709_551_557),
+];
+
+fn primality(c: &mut Criterion) {
+    let mut group = c.benchmark_group("is_prime");
+    for (name, n) in INPUTS {
+        group.bench_with_input(BenchmarkId::new("miller_rabin", name), &n, |b, &n| {
+            b.iter(|| is_prime(black_box(n)))
+        });
+        if n < 1 << 40 {
+            group.bench_with_input(BenchmarkId::new("trial_division", name), &n, |b, &n| {
+                b.iter(|| is_prime_trial_division(black_box(n)))
+            });
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, primality);
+criterion_main!(benches);
@@ -0,0 +1,79 @@
+use std::time::Duration;
+
+use futures::{SinkExt, StreamExt};
+use tokio::{
+    net::TcpStream,
+    time::{Instant, MissedTickBehavior, interval, sleep_until},
+};
+use tokio_util::codec::{Framed, LinesCodec};
+
+use crate::bench::{Run, config::ChatWorkload, stats::Stats};
+
+pub async fn client(
+    target: &str,
+    id: usize,
+    workload: &ChatWorkload,
+    run: &Run,
+    stats: &mut Stats,
+) -> anyhow::Result<()> {
+    let mut framed = Framed::new(TcpStream::connect(target).await?, LinesCodec::new());
+    next_line(&mut framed).await?;
+    framed.send(format!("bench{}", id)).await?;
+    let present = next_line(&mut framed).await?;
+    if !present.starts_with("* ") {
+        anyhow::bail!("not admitted to the room: {}", present);
+    }
+
+    let filler = "x".repeat(workload.message_bytes);
+    // An interval rather than a sleep per loop, so a busy room does not keep postponing sends
+    let mut ticks = interval(run.think.max(Duration::from_millis(1)));
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let deadline = sleep_until(run.deadline);
+    tokio::pin!(deadline);
+    loop {
+        tokio::select! {
+            biased;
+            _ = &mut deadline => return Ok(()),
+            line = framed.next() => {
+                let line = line.ok_or_else(|| anyhow::anyhow!("chat closed the connection"))??;
+                if let Some(sent_us) = sent_at(&line) {
+                    let now_us = run.started.elapsed().as_micros() as u64;
+                    stats.record("deliver", Duration::from_micros(now_us.saturating_sub(sent_us)));
+                }
+            }
+            _ = ticks.tick() => {
+                let started = Instant::now();
+                // Stamped with the send time so every receiver can tell how long delivery took
+                let sent_us = run.started.elapsed().as_micros();
+                framed.send(format!("t{} {}", sent_us, filler)).await?;
+                stats.record("send", started.elapsed());
+            }
+        }
+    }
+}
+
+async fn next_line(framed: &mut Framed<TcpStream, LinesCodec>) -> anyhow::Result<String> {
+    framed
+        .next()
+        .await
+        .ok_or_else(|| anyhow::anyhow!("chat closed the connection"))?
+        .map_err(Into::into)
+}
+
+/// Send time of a chat line from another bench client, `[name] t<micros> ...`.
+fn sent_at(line: &str) -> Option<u64> {
+    let (_, message) = line.strip_prefix('[')?.split_once("] t")?;
+    message.split(' ').next()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sent_at() {
+        assert_eq!(sent_at("[bench3] t1500 xxxx"), Some(1500));
+        assert_eq!(sent_at("* bench3 has entered the room"), None);
+        assert_eq!(sent_at("[alice] hello"), None);
+    }
+}
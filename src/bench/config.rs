@@ -0,0 +1,300 @@
+use std::{path::Path, time::Duration};
+
+use anyhow::{Context, bail};
+use serde::Deserialize;
+
+use crate::services::ServiceKind;
+
+/// Top level of a `protohack-bench` scenario file. Like the service config, every table rejects
+/// unknown keys.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BenchConfig {
+    #[serde(rename = "scenario")]
+    pub scenarios: Vec<Scenario>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Scenario {
+    pub name: String,
+    /// Address of a running service. When absent the service is started in-process on an
+    /// ephemeral port, so a scenario file runs with nothing else set up.
+    pub target: Option<String>,
+    /// Concurrent synthetic clients. For the road workload each one is a pair of cameras.
+    pub clients: usize,
+    #[serde(default = "default_duration_secs")]
+    pub duration_secs: u64,
+    /// Client connections are spread evenly over this window instead of opened all at once.
+    #[serde(default)]
+    pub ramp_ms: u64,
+    /// Pause between two operations of one client.
+    #[serde(default)]
+    pub think_ms: u64,
+    pub workload: Workload,
+}
+
+fn default_duration_secs() -> u64 {
+    10
+}
+
+impl Scenario {
+    pub fn duration(&self) -> Duration {
+        Duration::from_secs(self.duration_secs)
+    }
+
+    pub fn ramp(&self) -> Duration {
+        Duration::from_millis(self.ramp_ms)
+    }
+
+    pub fn think(&self) -> Duration {
+        Duration::from_millis(self.think_ms)
+    }
+
+    fn validate(&self, path: &str) -> anyhow::Result<()> {
+        if self.clients == 0 {
+            bail!("{}.clients: must be greater than 0", path);
+        }
+        if self.ramp_ms >= self.duration_secs * 1_000 {
+            bail!("{}.ramp_ms: must be shorter than the duration", path);
+        }
+        self.workload.validate(&format!("{}.workload", path))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "protocol", rename_all = "snake_case")]
+pub enum Workload {
+    JobCenter(JobCenterWorkload),
+    Road(RoadWorkload),
+    Chat(ChatWorkload),
+    Mte(MteWorkload),
+}
+
+impl Workload {
+    pub fn kind(&self) -> ServiceKind {
+        match self {
+            Workload::JobCenter(_) => ServiceKind::JobCenter,
+            Workload::Road(_) => ServiceKind::Road,
+            Workload::Chat(_) => ServiceKind::Chat,
+            Workload::Mte(_) => ServiceKind::MeansToEnd,
+        }
+    }
+
+    fn validate(&self, path: &str) -> anyhow::Result<()> {
+        let ratios = match self {
+            Workload::JobCenter(jobs) => {
+                if jobs.queues == 0 {
+                    bail!("{}.queues: must be greater than 0", path);
+                }
+                vec![("abort_ratio", jobs.abort_ratio)]
+            }
+            Workload::Road(road) => {
+                // IAmDispatcher lists its roads behind a one byte count
+                if road.roads == 0 || road.roads > 255 {
+                    bail!("{}.roads: must be between 1 and 255", path);
+                }
+                vec![("speeding_ratio", road.speeding_ratio)]
+            }
+            Workload::Chat(_) => vec![],
+            Workload::Mte(mte) => vec![("insert_ratio", mte.insert_ratio)],
+        };
+        for (name, ratio) in ratios {
+            if !(0.0..=1.0).contains(&ratio) {
+                bail!("{}.{}: must be between 0 and 1", path, name);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Each client puts a job, gets one back and then aborts or deletes it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JobCenterWorkload {
+    #[serde(default = "default_queues")]
+    pub queues: usize,
+    /// Share of fetched jobs aborted back onto their queue rather than deleted.
+    #[serde(default = "default_abort_ratio")]
+    pub abort_ratio: f64,
+}
+
+fn default_queues() -> usize {
+    4
+}
+
+fn default_abort_ratio() -> f64 {
+    0.1
+}
+
+/// Each client is two cameras a few miles apart on one road reporting the same cars, and
+/// `dispatchers` extra clients collect the resulting tickets for every road.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RoadWorkload {
+    #[serde(default = "default_roads")]
+    pub roads: u16,
+    #[serde(default = "default_dispatchers")]
+    pub dispatchers: usize,
+    /// Share of cars driving fast enough between the two cameras to be ticketed.
+    #[serde(default = "default_speeding_ratio")]
+    pub speeding_ratio: f64,
+}
+
+fn default_roads() -> u16 {
+    16
+}
+
+fn default_dispatchers() -> usize {
+    4
+}
+
+fn default_speeding_ratio() -> f64 {
+    0.2
+}
+
+/// Every client joins the single room and chats; latency is measured from sending a message
+/// to each other member receiving it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChatWorkload {
+    #[serde(default = "default_message_bytes")]
+    pub message_bytes: usize,
+}
+
+fn default_message_bytes() -> usize {
+    64
+}
+
+/// Each client inserts prices at increasing timestamps and queries means over them.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MteWorkload {
+    #[serde(default = "default_insert_ratio")]
+    pub insert_ratio: f64,
+}
+
+fn default_insert_ratio() -> f64 {
+    0.8
+}
+
+impl BenchConfig {
+    /// Loads a TOML or JSON scenario file, picking the format from the file extension.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read scenario file {}", path.display()))?;
+        let config = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml(&text),
+            Some("json") => Self::from_json(&text),
+            _ => bail!(
+                "scenario file {} must end in .toml or .json",
+                path.display()
+            ),
+        };
+        config.with_context(|| format!("invalid scenario file {}", path.display()))
+    }
+
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        let deserializer = toml::Deserializer::parse(text)?;
+        let config: BenchConfig = serde_path_to_error::deserialize(deserializer)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_json(text: &str) -> anyhow::Result<Self> {
+        let mut deserializer = serde_json::Deserializer::from_str(text);
+        let config: BenchConfig = serde_path_to_error::deserialize(&mut deserializer)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.scenarios.is_empty() {
+            bail!("no scenarios defined");
+        }
+        for (i, scenario) in self.scenarios.iter().enumerate() {
+            scenario.validate(&format!("scenario[{}]", i))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_example() {
+        let config = BenchConfig::from_toml(include_str!("../../bench.example.toml")).unwrap();
+        let kinds: Vec<ServiceKind> = config
+            .scenarios
+            .iter()
+            .map(|scenario| scenario.workload.kind())
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![
+                ServiceKind::JobCenter,
+                ServiceKind::Road,
+                ServiceKind::Chat,
+                ServiceKind::MeansToEnd
+            ]
+        );
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = BenchConfig::from_json(
+            r#"{"scenario": [{"name": "jobs", "clients": 2, "workload": {"protocol": "job_center"}}]}"#,
+        )
+        .unwrap();
+        let scenario = &config.scenarios[0];
+        assert_eq!(scenario.duration(), Duration::from_secs(10));
+        assert!(scenario.target.is_none());
+        match &scenario.workload {
+            Workload::JobCenter(jobs) => {
+                assert_eq!(jobs.queues, 4);
+                assert_eq!(jobs.abort_ratio, 0.1);
+            }
+            other => panic!("unexpected workload {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_unknown_workload_key_rejected() {
+        let err = BenchConfig::from_toml(
+            r#"
+            [[scenario]]
+            name = "mte"
+            clients = 1
+
+            [scenario.workload]
+            protocol = "mte"
+            insert_ratoi = 0.5
+            "#,
+        )
+        .unwrap_err();
+        let msg = format!("{:#}", err);
+        assert!(msg.contains("insert_ratoi"), "{}", msg);
+    }
+
+    #[test]
+    fn test_ratio_out_of_range_rejected() {
+        let err = BenchConfig::from_toml(
+            r#"
+            [[scenario]]
+            name = "road"
+            clients = 1
+
+            [scenario.workload]
+            protocol = "road"
+            speeding_ratio = 2.0
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "scenario[0].workload.speeding_ratio: must be between 0 and 1"
+        );
+    }
+}
@@ -0,0 +1,68 @@
+use futures::{SinkExt, StreamExt};
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use serde_json::json;
+use tokio::{net::TcpStream, time::Instant};
+use tokio_util::codec::{Framed, LinesCodec};
+
+use crate::{
+    bench::{Run, config::JobCenterWorkload, stats::Stats},
+    job_center::{Request, Response},
+};
+
+pub async fn client(
+    target: &str,
+    id: usize,
+    workload: &JobCenterWorkload,
+    run: &Run,
+    stats: &mut Stats,
+) -> anyhow::Result<()> {
+    let mut framed = Framed::new(TcpStream::connect(target).await?, LinesCodec::new());
+    let mut rng = StdRng::seed_from_u64(id as u64);
+    let queues: Vec<String> = (0..workload.queues)
+        .map(|queue| format!("bench{}", queue))
+        .collect();
+
+    while run.active() {
+        let put = Request::Put {
+            queue: queues[rng.random_range(0..queues.len())].clone(),
+            job: json!({ "client": id }),
+            pri: rng.random_range(0..1_000),
+        };
+        call(&mut framed, &put, stats).await?;
+
+        let get = Request::Get {
+            queues: queues.clone(),
+            wait: Some(false),
+        };
+        // Another client may have taken every job in the meantime
+        if let Response::Ok { id: Some(job), .. } = call(&mut framed, &get, stats).await? {
+            let done = if rng.random_bool(workload.abort_ratio) {
+                Request::Abort { id: job }
+            } else {
+                Request::Delete { id: job }
+            };
+            call(&mut framed, &done, stats).await?;
+        }
+        run.think().await;
+    }
+    Ok(())
+}
+
+async fn call(
+    framed: &mut Framed<TcpStream, LinesCodec>,
+    request: &Request,
+    stats: &mut Stats,
+) -> anyhow::Result<Response> {
+    let started = Instant::now();
+    framed.send(serde_json::to_string(request)?).await?;
+    let line = framed
+        .next()
+        .await
+        .ok_or_else(|| anyhow::anyhow!("job center closed the connection"))??;
+    stats.record(request.command(), started.elapsed());
+    let response: Response = serde_json::from_str(&line)?;
+    if matches!(response, Response::Error { .. }) {
+        stats.error(request.command());
+    }
+    Ok(response)
+}
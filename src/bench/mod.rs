@@ -0,0 +1,146 @@
+pub mod config;
+pub mod stats;
+
+mod chat;
+mod job_center;
+mod mte;
+mod road;
+
+use std::time::Duration;
+
+use tokio::{
+    task::{JoinHandle, JoinSet},
+    time::{Instant, sleep},
+};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    config::ServiceConfig,
+    services::{DrainSummary, Registry, ServiceKind},
+};
+
+pub use config::{BenchConfig, Scenario, Workload};
+pub use stats::{OpSummary, Report, Stats};
+
+/// Timing shared by every client of one scenario.
+#[derive(Debug, Clone, Copy)]
+struct Run {
+    started: Instant,
+    deadline: Instant,
+    think: Duration,
+}
+
+impl Run {
+    fn active(&self) -> bool {
+        Instant::now() < self.deadline
+    }
+
+    async fn think(&self) {
+        if !self.think.is_zero() {
+            sleep(self.think).await;
+        }
+    }
+}
+
+/// Runs every client of `scenario` until its duration is up and reports what they measured.
+pub async fn run(scenario: &Scenario) -> anyhow::Result<Report> {
+    let (target, local) = match &scenario.target {
+        Some(target) => (target.clone(), None),
+        None => {
+            let local = LocalService::start(scenario.workload.kind()).await?;
+            (local.addr.clone(), Some(local))
+        }
+    };
+
+    let started = Instant::now();
+    let run = Run {
+        started,
+        deadline: started + scenario.duration(),
+        think: scenario.think(),
+    };
+    let mut clients = JoinSet::new();
+    let pending = road::PendingTickets::default();
+    if let Workload::Road(workload) = &scenario.workload {
+        for _ in 0..workload.dispatchers {
+            let (target, workload, pending) = (target.clone(), workload.clone(), pending.clone());
+            clients.spawn(async move {
+                let mut stats = Stats::default();
+                let result = road::dispatcher(&target, &workload, &run, &pending, &mut stats).await;
+                finish(stats, result)
+            });
+        }
+    }
+    for id in 0..scenario.clients {
+        let delay = scenario.ramp().mul_f64(id as f64 / scenario.clients as f64);
+        let target = target.clone();
+        let workload = scenario.workload.clone();
+        let pending = pending.clone();
+        clients.spawn(async move {
+            sleep(delay).await;
+            let mut stats = Stats::default();
+            let result = match &workload {
+                Workload::JobCenter(workload) => {
+                    job_center::client(&target, id, workload, &run, &mut stats).await
+                }
+                Workload::Road(workload) => {
+                    road::camera_pair(&target, id, workload, &run, &pending, &mut stats).await
+                }
+                Workload::Chat(workload) => {
+                    chat::client(&target, id, workload, &run, &mut stats).await
+                }
+                Workload::Mte(workload) => {
+                    mte::client(&target, id, workload, &run, &mut stats).await
+                }
+            };
+            finish(stats, result)
+        });
+    }
+
+    let mut total = Stats::default();
+    while let Some(stats) = clients.join_next().await {
+        total.merge(stats?);
+    }
+    let elapsed = started.elapsed();
+    if let Some(local) = local {
+        local.stop().await?;
+    }
+    Ok(total.report(&scenario.name, scenario.clients, elapsed))
+}
+
+/// A client that fails stops early and counts as failed, but keeps the samples it took.
+fn finish(mut stats: Stats, result: anyhow::Result<()>) -> Stats {
+    if let Err(e) = result {
+        stats.client_failed(&e);
+    }
+    stats
+}
+
+/// The scenario's service, run in-process on an ephemeral port when no target is given.
+struct LocalService {
+    addr: String,
+    shutdown: CancellationToken,
+    handle: JoinHandle<anyhow::Result<DrainSummary>>,
+}
+
+impl LocalService {
+    async fn start(kind: ServiceKind) -> anyhow::Result<Self> {
+        let config = ServiceConfig::new("127.0.0.1:0");
+        let service = Registry::default().build(kind, &config)?;
+        let listener = service.bind(&config.bind).await?;
+        let addr = listener.local_addr()?.to_string();
+        let shutdown = CancellationToken::new();
+        let handle =
+            tokio::spawn(service.serve(listener, shutdown.clone(), Duration::from_secs(1)));
+        Ok(Self {
+            addr,
+            shutdown,
+            handle,
+        })
+    }
+
+    async fn stop(self) -> anyhow::Result<()> {
+        self.shutdown.cancel();
+        self.handle.await??;
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+    time::Instant,
+};
+
+use crate::bench::{Run, config::MteWorkload, stats::Stats};
+
+pub async fn client(
+    target: &str,
+    id: usize,
+    workload: &MteWorkload,
+    run: &Run,
+    stats: &mut Stats,
+) -> anyhow::Result<()> {
+    let mut stream = TcpStream::connect(target).await?;
+    stream.set_nodelay(true)?;
+    let mut rng = StdRng::seed_from_u64(id as u64);
+    let mut latest = 0i32;
+
+    while run.active() {
+        let started = Instant::now();
+        if latest == 0 || rng.random_bool(workload.insert_ratio) {
+            latest += 1;
+            stream
+                .write_all(&message(b'I', latest, rng.random_range(1..10_000)))
+                .await?;
+            stats.record("insert", started.elapsed());
+        } else {
+            let from = rng.random_range(1..=latest);
+            stream.write_all(&message(b'Q', from, latest)).await?;
+            let mut mean = [0u8; 4];
+            stream.read_exact(&mut mean).await?;
+            stats.record("query", started.elapsed());
+        }
+        run.think().await;
+    }
+    Ok(())
+}
+
+fn message(kind: u8, first: i32, second: i32) -> [u8; 9] {
+    let mut buf = [0u8; 9];
+    buf[0] = kind;
+    buf[1..5].copy_from_slice(&first.to_be_bytes());
+    buf[5..].copy_from_slice(&second.to_be_bytes());
+    buf
+}
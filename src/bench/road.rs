@@ -0,0 +1,131 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    time::{Instant, timeout_at},
+};
+use tokio_util::bytes::{BufMut, BytesMut};
+
+use crate::bench::{Run, config::RoadWorkload, stats::Stats};
+
+const LIMIT_MPH: u16 = 60;
+const CAMERA_SPACING_MILES: u32 = 10;
+
+/// When the sighting that should produce a ticket was sent, keyed by plate, so dispatchers can
+/// time each ticket end to end.
+#[derive(Clone, Default)]
+pub struct PendingTickets(Arc<Mutex<HashMap<String, Instant>>>);
+
+pub async fn camera_pair(
+    target: &str,
+    id: usize,
+    workload: &RoadWorkload,
+    run: &Run,
+    pending: &PendingTickets,
+    stats: &mut Stats,
+) -> anyhow::Result<()> {
+    let road = (id % workload.roads as usize) as u16;
+    let mut first = camera(target, road, 0).await?;
+    let mut second = camera(target, road, CAMERA_SPACING_MILES as u16).await?;
+    let mut rng = StdRng::seed_from_u64(id as u64);
+
+    let mut car = 0u32;
+    while run.active() {
+        car += 1;
+        let plate = format!("B{}C{}", id, car);
+        let speeding = rng.random_bool(workload.speeding_ratio);
+        let mph = if speeding {
+            LIMIT_MPH + 20
+        } else {
+            LIMIT_MPH - 10
+        };
+        // Plates are unique and cars an hour apart, so each speeding car is exactly one ticket
+        let entered = car * 3_600;
+        let left = entered + CAMERA_SPACING_MILES * 3_600 / mph as u32;
+
+        let started = Instant::now();
+        first.write_all(&plate_message(&plate, entered)).await?;
+        stats.record("plate", started.elapsed());
+
+        let started = Instant::now();
+        if speeding {
+            pending.0.lock().unwrap().insert(plate.clone(), started);
+        }
+        second.write_all(&plate_message(&plate, left)).await?;
+        stats.record("plate", started.elapsed());
+        run.think().await;
+    }
+    Ok(())
+}
+
+async fn camera(target: &str, road: u16, mile: u16) -> anyhow::Result<TcpStream> {
+    let mut stream = TcpStream::connect(target).await?;
+    stream.set_nodelay(true)?;
+    let mut buf = BytesMut::with_capacity(7);
+    buf.put_u8(0x80);
+    buf.put_u16(road);
+    buf.put_u16(mile);
+    buf.put_u16(LIMIT_MPH);
+    stream.write_all(&buf).await?;
+    Ok(stream)
+}
+
+fn plate_message(plate: &str, timestamp: u32) -> BytesMut {
+    let mut buf = BytesMut::with_capacity(6 + plate.len());
+    buf.put_u8(0x20);
+    buf.put_u8(plate.len() as u8);
+    buf.put_slice(plate.as_bytes());
+    buf.put_u32(timestamp);
+    buf
+}
+
+/// Takes tickets for every road until the run ends.
+pub async fn dispatcher(
+    target: &str,
+    workload: &RoadWorkload,
+    run: &Run,
+    pending: &PendingTickets,
+    stats: &mut Stats,
+) -> anyhow::Result<()> {
+    let stream = TcpStream::connect(target).await?;
+    let mut stream = BufReader::new(stream);
+    let mut buf = BytesMut::new();
+    buf.put_u8(0x81);
+    buf.put_u8(workload.roads as u8);
+    for road in 0..workload.roads {
+        buf.put_u16(road);
+    }
+    stream.get_mut().write_all(&buf).await?;
+
+    while let Ok(ticket) = timeout_at(run.deadline, read_ticket(&mut stream)).await {
+        let plate = ticket?;
+        let sent = pending.0.lock().unwrap().remove(&plate);
+        match sent {
+            Some(sent) => stats.record("ticket", sent.elapsed()),
+            // Left over from an earlier run against the same service
+            None => stats.error("ticket"),
+        }
+    }
+    Ok(())
+}
+
+/// Reads one Ticket message and returns its plate.
+async fn read_ticket(stream: &mut BufReader<TcpStream>) -> anyhow::Result<String> {
+    let opcode = stream.read_u8().await?;
+    let len = stream.read_u8().await? as usize;
+    let mut text = vec![0u8; len];
+    stream.read_exact(&mut text).await?;
+    let text = String::from_utf8(text)?;
+    if opcode != 0x21 {
+        anyhow::bail!("expected a ticket, got 0x{:02x}: {}", opcode, text);
+    }
+    // road, mile1, timestamp1, mile2, timestamp2, speed
+    let mut rest = [0u8; 16];
+    stream.read_exact(&mut rest).await?;
+    Ok(text)
+}
@@ -0,0 +1,201 @@
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use serde::Serialize;
+
+/// Latencies and failures collected by one client, merged into a report once it is done.
+#[derive(Debug, Default)]
+pub struct Stats {
+    ops: BTreeMap<&'static str, OpStats>,
+    failed_clients: usize,
+    last_failure: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct OpStats {
+    latencies_us: Vec<u64>,
+    errors: u64,
+}
+
+impl Stats {
+    pub fn record(&mut self, op: &'static str, latency: Duration) {
+        self.ops
+            .entry(op)
+            .or_default()
+            .latencies_us
+            .push(latency.as_micros() as u64);
+    }
+
+    /// The service answered, but with an error.
+    pub fn error(&mut self, op: &'static str) {
+        self.ops.entry(op).or_default().errors += 1;
+    }
+
+    /// The client gave up, e.g. because the connection broke.
+    pub fn client_failed(&mut self, error: &anyhow::Error) {
+        self.failed_clients += 1;
+        self.last_failure = Some(format!("{:#}", error));
+    }
+
+    pub fn merge(&mut self, other: Stats) {
+        for (op, stats) in other.ops {
+            let merged = self.ops.entry(op).or_default();
+            merged.latencies_us.extend(stats.latencies_us);
+            merged.errors += stats.errors;
+        }
+        self.failed_clients += other.failed_clients;
+        if other.last_failure.is_some() {
+            self.last_failure = other.last_failure;
+        }
+    }
+
+    pub fn report(mut self, scenario: &str, clients: usize, elapsed: Duration) -> Report {
+        let ops = self
+            .ops
+            .iter_mut()
+            .map(|(op, stats)| {
+                stats.latencies_us.sort_unstable();
+                let latencies = &stats.latencies_us;
+                OpSummary {
+                    op: op.to_string(),
+                    count: latencies.len() as u64,
+                    errors: stats.errors,
+                    per_sec: latencies.len() as f64 / elapsed.as_secs_f64(),
+                    p50_us: percentile(latencies, 50.0),
+                    p90_us: percentile(latencies, 90.0),
+                    p99_us: percentile(latencies, 99.0),
+                    max_us: latencies.last().copied().unwrap_or(0),
+                }
+            })
+            .collect();
+        Report {
+            scenario: scenario.to_string(),
+            clients,
+            elapsed_secs: elapsed.as_secs_f64(),
+            ops,
+            failed_clients: self.failed_clients,
+            last_failure: self.last_failure,
+        }
+    }
+}
+
+/// Nearest-rank percentile of sorted samples.
+fn percentile(sorted: &[u64], p: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Report {
+    pub scenario: String,
+    pub clients: usize,
+    pub elapsed_secs: f64,
+    pub ops: Vec<OpSummary>,
+    pub failed_clients: usize,
+    pub last_failure: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OpSummary {
+    pub op: String,
+    pub count: u64,
+    pub errors: u64,
+    pub per_sec: f64,
+    pub p50_us: u64,
+    pub p90_us: u64,
+    pub p99_us: u64,
+    pub max_us: u64,
+}
+
+impl Report {
+    pub fn op(&self, op: &str) -> Option<&OpSummary> {
+        self.ops.iter().find(|summary| summary.op == op)
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{}: {} clients for {:.1}s",
+            self.scenario, self.clients, self.elapsed_secs
+        )?;
+        writeln!(
+            f,
+            "  {:<10} {:>10} {:>10} {:>9} {:>9} {:>9} {:>9} {:>8}",
+            "op", "count", "ops/s", "p50", "p90", "p99", "max", "errors"
+        )?;
+        for op in &self.ops {
+            writeln!(
+                f,
+                "  {:<10} {:>10} {:>10.0} {:>9} {:>9} {:>9} {:>9} {:>8}",
+                op.op,
+                op.count,
+                op.per_sec,
+                format_us(op.p50_us),
+                format_us(op.p90_us),
+                format_us(op.p99_us),
+                format_us(op.max_us),
+                op.errors
+            )?;
+        }
+        if self.failed_clients > 0 {
+            writeln!(f, "  {} clients failed", self.failed_clients)?;
+            if let Some(failure) = &self.last_failure {
+                writeln!(f, "  last failure: {}", failure)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn format_us(us: u64) -> String {
+    if us >= 1_000_000 {
+        format!("{:.2}s", us as f64 / 1_000_000.0)
+    } else if us >= 1_000 {
+        format!("{:.2}ms", us as f64 / 1_000.0)
+    } else {
+        format!("{}us", us)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let samples: Vec<u64> = (1..=100).collect();
+        assert_eq!(percentile(&samples, 50.0), 50);
+        assert_eq!(percentile(&samples, 99.0), 99);
+        assert_eq!(percentile(&samples, 100.0), 100);
+        assert_eq!(percentile(&[7], 1.0), 7);
+        assert_eq!(percentile(&[], 50.0), 0);
+    }
+
+    #[test]
+    fn test_merge_and_report() {
+        let mut total = Stats::default();
+        for client in 0..2u64 {
+            let mut stats = Stats::default();
+            for i in 1..=5 {
+                stats.record("put", Duration::from_micros(client * 5 + i));
+            }
+            stats.error("get");
+            total.merge(stats);
+        }
+        total.client_failed(&anyhow::anyhow!("connection reset"));
+
+        let report = total.report("jobs", 2, Duration::from_secs(2));
+        let put = report.op("put").unwrap();
+        assert_eq!(put.count, 10);
+        assert_eq!(put.per_sec, 5.0);
+        assert_eq!(put.p50_us, 5);
+        assert_eq!(put.max_us, 10);
+        assert_eq!(report.op("get").unwrap().errors, 2);
+        assert_eq!(report.failed_clients, 1);
+        assert_eq!(report.last_failure.as_deref(), Some("connection reset"));
+    }
+}
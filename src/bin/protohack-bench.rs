@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+
+use clap::Parser;
+use prime_time::{
+    bench::{self, BenchConfig},
+    config::LogFormat,
+    logging,
+};
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "protohack-bench",
+    about = "Drives synthetic clients against the protohackers services and reports throughput and latency percentiles"
+)]
+struct Cli {
+    /// TOML or JSON file listing the scenarios to run.
+    scenarios: PathBuf,
+
+    /// Only run the scenario with this name. May be repeated.
+    #[arg(long = "only", value_name = "NAME")]
+    only: Vec<String>,
+
+    /// Print one JSON report per line instead of tables.
+    #[arg(long)]
+    json: bool,
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let cli = Cli::parse();
+    // In-process services would drown the report at any level; RUST_LOG turns them back on
+    logging::init("off", LogFormat::Pretty)?;
+    let config = BenchConfig::load(&cli.scenarios)?;
+
+    let selected: Vec<_> = config
+        .scenarios
+        .iter()
+        .filter(|scenario| cli.only.is_empty() || cli.only.contains(&scenario.name))
+        .collect();
+    if selected.is_empty() {
+        anyhow::bail!("no scenario named {}", cli.only.join(", "));
+    }
+
+    for scenario in selected {
+        let report = bench::run(scenario).await?;
+        if cli.json {
+            println!("{}", serde_json::to_string(&report)?);
+        } else {
+            print!("{}", report);
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,185 @@
+use std::{
+    fs::File,
+    io::{BufRead, BufReader},
+    net::SocketAddr,
+    path::Path,
+};
+
+use anyhow::Context;
+use serde::{Deserialize, Deserializer, Serialize, Serializer, de};
+
+pub const FORMAT_VERSION: u32 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Transport {
+    Tcp,
+    Udp,
+}
+
+/// First line of a capture file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Header {
+    pub version: u32,
+    pub transport: Transport,
+    pub upstream: String,
+    /// Wall clock at the start of the capture; event times are relative to it.
+    pub started_unix_ms: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    /// Client to service.
+    In,
+    /// Service to client.
+    Out,
+}
+
+/// One line after the header. A TCP connection or a UDP client address is one `conn`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    Open {
+        conn: u64,
+        at_us: u64,
+        peer: SocketAddr,
+    },
+    Data {
+        conn: u64,
+        at_us: u64,
+        dir: Direction,
+        #[serde(serialize_with = "to_hex", deserialize_with = "from_hex")]
+        bytes: Vec<u8>,
+    },
+    Close {
+        conn: u64,
+        at_us: u64,
+    },
+}
+
+impl Event {
+    pub fn conn(&self) -> u64 {
+        match self {
+            Event::Open { conn, .. } | Event::Data { conn, .. } | Event::Close { conn, .. } => {
+                *conn
+            }
+        }
+    }
+}
+
+/// A parsed capture; events keep their 1-based line numbers for error reports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Capture {
+    pub header: Header,
+    pub events: Vec<(usize, Event)>,
+}
+
+impl Capture {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let file = File::open(path)
+            .with_context(|| format!("failed to open capture {}", path.display()))?;
+        Self::parse(BufReader::new(file))
+            .with_context(|| format!("invalid capture {}", path.display()))
+    }
+
+    pub fn parse(reader: impl BufRead) -> anyhow::Result<Self> {
+        let mut lines = reader.lines().enumerate().map(|(i, line)| (i + 1, line));
+        let (_, first) = lines
+            .next()
+            .ok_or_else(|| anyhow::anyhow!("empty capture"))?;
+        let header: Header = serde_json::from_str(&first?).context("line 1: invalid header")?;
+        if header.version != FORMAT_VERSION {
+            anyhow::bail!(
+                "unsupported capture version {}, expected {}",
+                header.version,
+                FORMAT_VERSION
+            );
+        }
+
+        let mut events = vec![];
+        for (number, line) in lines {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let event = serde_json::from_str(&line).with_context(|| format!("line {}", number))?;
+            events.push((number, event));
+        }
+        Ok(Self { header, events })
+    }
+}
+
+fn to_hex<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    serializer.serialize_str(&hex)
+}
+
+fn from_hex<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    let hex = String::deserialize(deserializer)?;
+    if hex.len() % 2 != 0 {
+        return Err(de::Error::custom("odd number of hex digits"));
+    }
+    hex.as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).map_err(de::Error::custom)?;
+            u8::from_str_radix(pair, 16).map_err(de::Error::custom)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let header = Header {
+            version: FORMAT_VERSION,
+            transport: Transport::Tcp,
+            upstream: "127.0.0.1:4000".to_string(),
+            started_unix_ms: 1,
+        };
+        let events = [
+            Event::Open {
+                conn: 1,
+                at_us: 0,
+                peer: "127.0.0.1:5000".parse().unwrap(),
+            },
+            Event::Data {
+                conn: 1,
+                at_us: 10,
+                dir: Direction::In,
+                bytes: vec![0x00, 0x7f, 0xff],
+            },
+            Event::Close { conn: 1, at_us: 20 },
+        ];
+
+        let mut text = serde_json::to_string(&header).unwrap();
+        for event in &events {
+            text.push('\n');
+            text.push_str(&serde_json::to_string(event).unwrap());
+        }
+        assert!(text.contains(r#""bytes":"007fff""#), "{}", text);
+
+        let capture = Capture::parse(text.as_bytes()).unwrap();
+        assert_eq!(capture.header, header);
+        assert_eq!(
+            capture.events,
+            events
+                .into_iter()
+                .enumerate()
+                .map(|(i, e)| (i + 2, e))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_bad_line_is_named() {
+        let text = r#"{"version":1,"transport":"udp","upstream":"x","started_unix_ms":0}
+{"event":"data","conn":1,"at_us":0,"dir":"in","bytes":"0g"}"#;
+        let err = Capture::parse(text.as_bytes()).unwrap_err();
+        assert!(format!("{:#}", err).starts_with("line 2"), "{:#}", err);
+    }
+}
@@ -0,0 +1,9 @@
+pub mod format;
+pub mod proxy;
+pub mod recorder;
+pub mod replay;
+
+pub use format::{Capture, Direction, Event, Header, Transport};
+pub use proxy::{proxy_tcp, proxy_udp};
+pub use recorder::Recorder;
+pub use replay::{Mismatch, ReplayReport, replay};
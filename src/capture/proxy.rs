@@ -0,0 +1,76 @@
+use std::{net::SocketAddr, sync::Arc, time::Duration};
+
+use futures::future::BoxFuture;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::{
+    capture::{format::Direction, recorder::Recorder},
+    relay::{self, Hook, Step},
+};
+
+/// Records everything the relay forwards, unchanged.
+struct Recording(Recorder);
+
+impl Hook for Recording {
+    type Stream = (u64, Direction);
+
+    fn open(&self, peer: SocketAddr) -> BoxFuture<'_, u64> {
+        Box::pin(self.0.open(peer))
+    }
+
+    fn close(&self, conn: u64) -> BoxFuture<'_, ()> {
+        Box::pin(self.0.close(conn))
+    }
+
+    fn stream(&self, conn: u64, dir: Direction) -> Self::Stream {
+        (conn, dir)
+    }
+
+    fn chunk<'a>(
+        &'a self,
+        &mut (conn, dir): &'a mut Self::Stream,
+        data: &'a [u8],
+    ) -> BoxFuture<'a, Vec<Step>> {
+        Box::pin(async move {
+            self.0.data(conn, dir, data).await;
+            vec![Step::Write(data.len())]
+        })
+    }
+
+    fn datagram<'a>(
+        &'a self,
+        &mut (conn, dir): &'a mut Self::Stream,
+        datagram: &'a [u8],
+    ) -> BoxFuture<'a, Vec<Duration>> {
+        Box::pin(async move {
+            self.0.data(conn, dir, datagram).await;
+            vec![Duration::ZERO]
+        })
+    }
+}
+
+/// Forwards every connection on `listener` to `upstream`, recording both directions, until
+/// `shutdown` is cancelled. Open connections are cut at that point.
+pub async fn proxy_tcp(
+    listener: TcpListener,
+    upstream: String,
+    recorder: Recorder,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    info!(addr = %listener.local_addr()?, %upstream, "Capturing TCP");
+    relay::proxy_tcp(listener, upstream, Arc::new(Recording(recorder)), shutdown).await
+}
+
+/// Forwards datagrams on `socket` to `upstream`, recording both directions, until `shutdown`
+/// is cancelled. A client idle for [`relay::UDP_IDLE_TIMEOUT`] is recorded as closed.
+pub async fn proxy_udp(
+    socket: UdpSocket,
+    upstream: String,
+    recorder: Recorder,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    info!(addr = %socket.local_addr()?, %upstream, "Capturing UDP");
+    relay::proxy_udp(socket, upstream, Arc::new(Recording(recorder)), shutdown).await
+}
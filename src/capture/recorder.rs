@@ -0,0 +1,133 @@
+use std::{
+    net::SocketAddr,
+    path::Path,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use tokio::{
+    fs::File,
+    io::{AsyncWriteExt, BufWriter},
+    sync::mpsc,
+    task::JoinHandle,
+    time::Instant,
+};
+
+use crate::capture::format::{Direction, Event, FORMAT_VERSION, Header, Transport};
+
+const EVENT_CHANNEL_CAPACITY: usize = 1_024;
+
+/// Hands events to the task that owns the capture file. Every proxy task records through its
+/// own clone, and the file is complete once all clones are dropped and the writer is awaited.
+#[derive(Clone)]
+pub struct Recorder {
+    tx: mpsc::Sender<Event>,
+    started: Instant,
+    next_conn: Arc<AtomicU64>,
+}
+
+impl Recorder {
+    /// Creates `path` and writes the header. The handle resolves to the number of events written.
+    pub async fn create(
+        path: &Path,
+        transport: Transport,
+        upstream: &str,
+    ) -> anyhow::Result<(Self, JoinHandle<anyhow::Result<u64>>)> {
+        let file = File::create(path)
+            .await
+            .with_context(|| format!("failed to create capture {}", path.display()))?;
+        let header = Header {
+            version: FORMAT_VERSION,
+            transport,
+            upstream: upstream.to_string(),
+            started_unix_ms: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_millis() as u64,
+        };
+
+        let (tx, rx) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+        let writer = tokio::spawn(write_events(BufWriter::new(file), header, rx));
+        let recorder = Self {
+            tx,
+            started: Instant::now(),
+            next_conn: Arc::new(AtomicU64::new(1)),
+        };
+        Ok((recorder, writer))
+    }
+
+    pub async fn open(&self, peer: SocketAddr) -> u64 {
+        let conn = self.next_conn.fetch_add(1, Ordering::Relaxed);
+        self.send(Event::Open {
+            conn,
+            at_us: self.elapsed_us(),
+            peer,
+        })
+        .await;
+        conn
+    }
+
+    /// Must be called before the bytes are forwarded, so a response is never recorded ahead of
+    /// the request that caused it.
+    pub async fn data(&self, conn: u64, dir: Direction, bytes: &[u8]) {
+        self.send(Event::Data {
+            conn,
+            at_us: self.elapsed_us(),
+            dir,
+            bytes: bytes.to_vec(),
+        })
+        .await;
+    }
+
+    pub async fn close(&self, conn: u64) {
+        self.send(Event::Close {
+            conn,
+            at_us: self.elapsed_us(),
+        })
+        .await;
+    }
+
+    async fn send(&self, event: Event) {
+        // Only fails once the writer has died, which its handle reports
+        let _ = self.tx.send(event).await;
+    }
+
+    fn elapsed_us(&self) -> u64 {
+        self.started.elapsed().as_micros() as u64
+    }
+}
+
+async fn write_events(
+    mut file: BufWriter<File>,
+    header: Header,
+    mut rx: mpsc::Receiver<Event>,
+) -> anyhow::Result<u64> {
+    write_line(&mut file, &header).await?;
+    file.flush().await?;
+
+    let mut written = 0;
+    while let Some(event) = rx.recv().await {
+        write_line(&mut file, &event).await?;
+        written += 1;
+        // Flush whenever we catch up so a killed proxy still leaves a usable capture
+        if rx.is_empty() {
+            file.flush().await?;
+        }
+    }
+    file.flush().await?;
+    Ok(written)
+}
+
+async fn write_line<T: serde::Serialize>(
+    file: &mut BufWriter<File>,
+    value: &T,
+) -> anyhow::Result<()> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    file.write_all(&line).await?;
+    Ok(())
+}
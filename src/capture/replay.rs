@@ -0,0 +1,288 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Write as _,
+    time::Duration,
+};
+
+use similar::{ChangeTag, TextDiff};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt, ReadHalf, WriteHalf, split},
+    net::{TcpStream, UdpSocket},
+    time::{Instant, timeout_at},
+};
+use tracing::debug;
+
+use crate::capture::format::{Capture, Direction, Event, Transport};
+
+/// A response that did not match the recording.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub conn: u64,
+    /// Line of the recorded response in the capture file.
+    pub line: usize,
+    pub expected: Vec<u8>,
+    pub actual: Vec<u8>,
+}
+
+impl Mismatch {
+    /// Line diff of the two payloads, as text when both are printable and as a hex dump
+    /// otherwise.
+    pub fn diff(&self) -> String {
+        let printable = |bytes: &[u8]| {
+            std::str::from_utf8(bytes).is_ok_and(|text| {
+                text.chars()
+                    .all(|c| !c.is_control() || c == '\n' || c == '\t')
+            })
+        };
+        let (expected, actual) = if printable(&self.expected) && printable(&self.actual) {
+            (
+                String::from_utf8_lossy(&self.expected).into_owned(),
+                String::from_utf8_lossy(&self.actual).into_owned(),
+            )
+        } else {
+            (hex_dump(&self.expected), hex_dump(&self.actual))
+        };
+
+        let mut out = String::from("--- expected\n+++ actual\n");
+        for change in TextDiff::from_lines(&expected, &actual).iter_all_changes() {
+            let sign = match change.tag() {
+                ChangeTag::Delete => '-',
+                ChangeTag::Insert => '+',
+                ChangeTag::Equal => ' ',
+            };
+            let _ = write!(out, "{}{}", sign, change);
+            if change.missing_newline() {
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, row) in bytes.chunks(16).enumerate() {
+        let hex: Vec<String> = row.iter().map(|b| format!("{:02x}", b)).collect();
+        let ascii: String = row
+            .iter()
+            .map(|&b| {
+                if b.is_ascii_graphic() || b == b' ' {
+                    b as char
+                } else {
+                    '.'
+                }
+            })
+            .collect();
+        let _ = writeln!(out, "{:04x}: {:<47} |{}|", i * 16, hex.join(" "), ascii);
+    }
+    out
+}
+
+/// How long to keep listening after a recorded response for bytes the service sent beyond it.
+const EXTRA_GRACE: Duration = Duration::from_millis(20);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplayReport {
+    pub connections: usize,
+    pub responses: usize,
+    pub mismatches: Vec<Mismatch>,
+}
+
+/// Plays the client side of `capture` against `target` in recorded order, across all
+/// connections at once, so interleavings between clients (chat rooms, road dispatchers) are
+/// reproduced exactly. Each recorded response is read back byte for byte, waiting at most
+/// `read_timeout`, and compared. Anything else the service sends before the client's next
+/// request counts against that response, so an overlong answer doesn't shift later ones.
+pub async fn replay(
+    capture: &Capture,
+    target: &str,
+    read_timeout: Duration,
+) -> anyhow::Result<ReplayReport> {
+    let mut report = ReplayReport::default();
+    let mut clients: HashMap<u64, ReplayClient> = HashMap::new();
+    let last_responses = last_responses(capture);
+
+    for (index, (line, event)) in capture.events.iter().enumerate() {
+        match event {
+            Event::Open { conn, .. } => {
+                let client = ReplayClient::connect(capture.header.transport, target).await?;
+                clients.insert(*conn, client);
+                report.connections += 1;
+            }
+            Event::Data {
+                conn, dir, bytes, ..
+            } => {
+                let client = clients.get_mut(conn).ok_or_else(|| {
+                    anyhow::anyhow!("line {}: connection {} was never opened", line, conn)
+                })?;
+                match dir {
+                    Direction::In => {
+                        // The service may legitimately have hung up; the responses will show it
+                        if let Err(e) = client.send_bytes(bytes).await {
+                            debug!(conn, line, "Send failed: {}", e);
+                        }
+                    }
+                    Direction::Out => {
+                        report.responses += 1;
+                        let mut actual = client.read_exact(bytes.len(), read_timeout).await;
+                        if last_responses.contains(&index) {
+                            actual.extend(client.drain(EXTRA_GRACE.min(read_timeout)).await);
+                        }
+                        if actual != *bytes {
+                            report.mismatches.push(Mismatch {
+                                conn: *conn,
+                                line: *line,
+                                expected: bytes.clone(),
+                                actual,
+                            });
+                        }
+                    }
+                }
+            }
+            Event::Close { conn, .. } => {
+                clients.remove(conn);
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Indices of the response events not directly followed by another response on the same
+/// connection, i.e. where the recorded service had nothing more to say for now.
+fn last_responses(capture: &Capture) -> HashSet<usize> {
+    let mut next_is_response: HashMap<u64, bool> = HashMap::new();
+    let mut last = HashSet::new();
+    for (index, (_, event)) in capture.events.iter().enumerate().rev() {
+        match event {
+            Event::Open { conn, .. } | Event::Close { conn, .. } => {
+                next_is_response.insert(*conn, false);
+            }
+            Event::Data { conn, dir, .. } => {
+                let is_response = *dir == Direction::Out;
+                if is_response && !next_is_response.get(conn).copied().unwrap_or(false) {
+                    last.insert(index);
+                }
+                next_is_response.insert(*conn, is_response);
+            }
+        }
+    }
+    last
+}
+
+enum ReplayClient {
+    Tcp {
+        writer: WriteHalf<TcpStream>,
+        reader: ReadHalf<TcpStream>,
+    },
+    Udp(UdpSocket),
+}
+
+impl ReplayClient {
+    async fn connect(transport: Transport, addr: &str) -> anyhow::Result<Self> {
+        match transport {
+            Transport::Tcp => {
+                let stream = TcpStream::connect(addr).await?;
+                let (reader, writer) = split(stream);
+                Ok(Self::Tcp { writer, reader })
+            }
+            Transport::Udp => {
+                let socket = UdpSocket::bind("0.0.0.0:0").await?;
+                socket.connect(addr).await?;
+                Ok(Self::Udp(socket))
+            }
+        }
+    }
+
+    async fn send_bytes(&mut self, buf: &[u8]) -> anyhow::Result<()> {
+        match self {
+            Self::Tcp { writer, .. } => {
+                writer.write_all(buf).await?;
+                writer.flush().await?;
+            }
+            Self::Udp(socket) => {
+                socket.send(buf).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads `n` bytes (one datagram over UDP), returning whatever arrived before the timeout
+    /// or EOF if that is less.
+    async fn read_exact(&mut self, n: usize, read_timeout: Duration) -> Vec<u8> {
+        let deadline = Instant::now() + read_timeout;
+        match self {
+            Self::Tcp { reader, .. } => {
+                let mut buf = vec![0u8; n];
+                let mut filled = 0;
+                while filled < n {
+                    match timeout_at(deadline, reader.read(&mut buf[filled..])).await {
+                        Ok(Ok(read)) if read > 0 => filled += read,
+                        _ => break,
+                    }
+                }
+                buf.truncate(filled);
+                buf
+            }
+            Self::Udp(socket) => {
+                let mut buf = vec![0u8; 64 * 1024];
+                match timeout_at(deadline, socket.recv(&mut buf)).await {
+                    Ok(Ok(read)) => {
+                        buf.truncate(read);
+                        buf
+                    }
+                    _ => vec![],
+                }
+            }
+        }
+    }
+
+    /// Collects whatever arrives within `grace`, stopping early at EOF.
+    async fn drain(&mut self, grace: Duration) -> Vec<u8> {
+        let deadline = Instant::now() + grace;
+        let mut extra = Vec::new();
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let read = match self {
+                Self::Tcp { reader, .. } => timeout_at(deadline, reader.read(&mut buf)).await,
+                Self::Udp(socket) => timeout_at(deadline, socket.recv(&mut buf)).await,
+            };
+            match read {
+                Ok(Ok(read)) if read > 0 => extra.extend_from_slice(&buf[..read]),
+                _ => return extra,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_text_diff() {
+        let mismatch = Mismatch {
+            conn: 1,
+            line: 3,
+            expected: b"{\"prime\":true}\n".to_vec(),
+            actual: b"{\"prime\":false}\n".to_vec(),
+        };
+        assert_eq!(
+            mismatch.diff(),
+            "--- expected\n+++ actual\n-{\"prime\":true}\n+{\"prime\":false}\n"
+        );
+    }
+
+    #[test]
+    fn test_binary_diff() {
+        let mismatch = Mismatch {
+            conn: 1,
+            line: 3,
+            expected: vec![0x10, 0x03, b'b', b'a', b'd'],
+            actual: vec![],
+        };
+        assert_eq!(
+            mismatch.diff(),
+            "--- expected\n+++ actual\n-0000: 10 03 62 61 64                                  |..bad|\n"
+        );
+    }
+}
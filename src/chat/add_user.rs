@@ -2,6 +2,7 @@ use futures::{SinkExt, stream::SplitSink};
 use std::{net::SocketAddr, sync::Arc};
 use tokio::{net::TcpStream, sync::broadcast::Sender};
 use tokio_util::codec::{Framed, LinesCodec};
+use tracing::debug;
 
 use crate::chat::{handle_chat::UserStorage, user::User};
 
@@ -13,7 +14,7 @@ pub async fn add_user(
     tx: &Arc<Sender<(SocketAddr, String)>>,
 ) -> anyhow::Result<()> {
     let user = User::new(name)?;
-    let current_users = get_usernames(&users).await;
+    let current_users = get_usernames(users).await;
 
     if current_users.contains(&name.to_string()) {
         return Err(anyhow::anyhow!("User already exists"));
@@ -21,13 +22,8 @@ pub async fn add_user(
 
     let announcement = format!("* The room contains: {}", current_users.join(", "));
 
-    match writer.send(announcement).await {
-        Ok(()) => println!("Sent announcement to Alice successfully"),
-        Err(e) => {
-            println!("Failed to send announcement to Alice: {:?}", e);
-            return Err(e.into());
-        }
-    }
+    writer.send(announcement).await?;
+    debug!(user = %user.name, present = current_users.len(), "Joined room");
     let joined_message = format!("* {} has entered the room", &user.name);
     let mut guard = users.lock().await;
 
@@ -4,9 +4,15 @@ use tokio::{
     net::TcpStream,
     sync::{Mutex, broadcast::Sender},
 };
-use tokio_util::codec::{Framed, LinesCodec};
+use tokio_util::{
+    codec::{Framed, LinesCodec},
+    sync::CancellationToken,
+};
 
-use crate::chat::{ChatSession, user::User};
+use crate::{
+    chat::{ChatSession, user::User},
+    config::ConnectionSettings,
+};
 
 pub type Users = HashMap<SocketAddr, User>;
 pub type UserStorage = Arc<Mutex<Users>>;
@@ -15,13 +21,18 @@ pub async fn handle_chat(
     socket: TcpStream,
     tx: &Arc<Sender<(SocketAddr, String)>>,
     users: UserStorage,
+    settings: ConnectionSettings,
+    shutdown: CancellationToken,
 ) -> anyhow::Result<()> {
-    let framed: Framed<TcpStream, LinesCodec> = Framed::new(socket, LinesCodec::new());
+    let framed: Framed<TcpStream, LinesCodec> = Framed::new(
+        socket,
+        LinesCodec::new_with_max_length(settings.max_line_length),
+    );
 
     let session = ChatSession::handshake(framed, &users, tx).await?;
-    let result = session.run(users).await?;
+    session.run(users, settings.rate_limit, shutdown).await?;
 
-    Ok(result)
+    Ok(())
 }
 
 #[cfg(test)]
@@ -55,7 +66,14 @@ mod tests {
                 let users = Arc::clone(&users);
                 let tx = Arc::clone(&tx);
                 tokio::spawn(async move {
-                    if let Err(e) = handle_chat(stream, &tx, users).await {
+                    if let Err(e) = handle_chat(
+                        stream,
+                        &tx,
+                        users,
+                        ConnectionSettings::default(),
+                        CancellationToken::new(),
+                    )
+                    .await {
                         eprintln!("error in connection from {}: {:?}", peer_addr, e);
                     }
                 });
@@ -3,5 +3,6 @@ pub mod user;
 mod add_user;
 mod session;
 mod cleanup;
+pub mod service;
 
 pub use session::ChatSession;
\ No newline at end of file
@@ -0,0 +1,76 @@
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
+
+use futures::{SinkExt, future::BoxFuture};
+use tokio::{
+    net::TcpStream,
+    sync::{
+        Mutex,
+        broadcast::{self, Sender},
+    },
+};
+use tokio_util::{
+    codec::{Framed, LinesCodec},
+    sync::CancellationToken,
+};
+
+use crate::{
+    chat::handle_chat::{UserStorage, handle_chat},
+    config::{ConnectionSettings, ServiceConfig},
+    metrics::{self, CollectorGuard, Sample},
+    services::{Rejection, ServiceKind, TcpService},
+};
+
+pub struct ChatService {
+    tx: Arc<Sender<(SocketAddr, String)>>,
+    users: UserStorage,
+    settings: ConnectionSettings,
+    _collector: CollectorGuard,
+}
+
+impl ChatService {
+    pub fn new(config: &ServiceConfig) -> Self {
+        let (tx, _rx) = broadcast::channel(config.channel_capacity.unwrap_or(100));
+        let users: UserStorage = Arc::new(Mutex::new(HashMap::new()));
+        let collected = Arc::clone(&users);
+        let collector = metrics::add_collector(
+            ServiceKind::Chat,
+            Box::new(move || {
+                let users = Arc::clone(&collected);
+                Box::pin(async move {
+                    let count = users.lock().await.len();
+                    vec![Sample::gauge(
+                        "protohack_chat_users",
+                        "Users who have joined the chat room.",
+                        count as f64,
+                    )]
+                })
+            }),
+        );
+        Self {
+            tx: Arc::new(tx),
+            users,
+            settings: config.connection_settings(),
+            _collector: collector,
+        }
+    }
+}
+
+impl TcpService for ChatService {
+    fn handle(
+        &self,
+        socket: TcpStream,
+        shutdown: CancellationToken,
+    ) -> BoxFuture<'static, anyhow::Result<()>> {
+        let tx = Arc::clone(&self.tx);
+        let users = Arc::clone(&self.users);
+        let settings = self.settings;
+        Box::pin(async move { handle_chat(socket, &tx, users, settings, shutdown).await })
+    }
+
+    fn reject(&self, socket: TcpStream, rejection: Rejection) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            let mut framed = Framed::new(socket, LinesCodec::new());
+            let _ = framed.send(format!("* {}", rejection)).await;
+        })
+    }
+}
@@ -1,4 +1,8 @@
-use crate::chat::{add_user::add_user, cleanup::cleanup, handle_chat::UserStorage};
+use crate::{
+    chat::{add_user::add_user, cleanup::cleanup, handle_chat::UserStorage},
+    metrics,
+    services::{RateLimit, RateLimited, ServiceKind, Throttled},
+};
 use futures::{
     SinkExt, StreamExt,
     stream::{SplitSink, SplitStream},
@@ -6,12 +10,16 @@ use futures::{
 use std::{net::SocketAddr, sync::Arc};
 use tokio::{
     net::TcpStream,
-    sync::broadcast::{Receiver, Sender},
+    sync::{
+        broadcast::{Receiver, Sender},
+        mpsc,
+    },
 };
 use tokio_util::{
     codec::{Framed, LinesCodec},
     sync::CancellationToken,
 };
+use tracing::{Instrument, debug, error, info_span, warn};
 
 pub struct ChatSession {
     name: String,
@@ -52,7 +60,12 @@ impl ChatSession {
         })
     }
 
-    pub async fn run(self, users: UserStorage) -> anyhow::Result<()> {
+    pub async fn run(
+        self,
+        users: UserStorage,
+        rate_limit: Option<RateLimit>,
+        server_shutdown: CancellationToken,
+    ) -> anyhow::Result<()> {
         let ChatSession {
             name,
             peer,
@@ -63,28 +76,31 @@ impl ChatSession {
         } = self;
 
         let shutdown = CancellationToken::new();
+        // Nested under the conn span so every line from both tasks carries the user name
+        let span = info_span!("chat", user = %name);
+        let reader = RateLimited::new(reader, rate_limit, metrics::service(ServiceKind::Chat));
+        // System lines meant only for this user, e.g. rate limit warnings
+        let (notice_tx, notice_rx) = mpsc::channel(8);
 
         let reader_task = {
             let tx_clone = tx.clone();
             let shutdown_clone = shutdown.clone();
             let users_clone = users.clone();
             let name_clone = name.clone();
-            tokio::spawn(async move {
-                read_task(
-                    reader,
-                    tx_clone,
-                    peer,
-                    name_clone,
-                    users_clone,
-                    shutdown_clone,
-                )
-                .await
-            })
+            tokio::spawn(
+                async move {
+                    read_task(reader, notice_tx, &tx_clone, peer, name_clone, server_shutdown)
+                        .await?;
+                    cleanup(users_clone, peer, &tx_clone).await?;
+                    shutdown_clone.cancel();
+                    anyhow::Ok(())
+                }
+                .instrument(span.clone()),
+            )
         };
 
-        let writer_task = {
-            tokio::spawn(async move { write_task(writer, rx, peer).await })
-        };
+        let writer_task =
+            tokio::spawn(write_task(writer, rx, notice_rx, peer, shutdown).instrument(span));
 
         // FIXED: Wait for BOTH tasks to complete before exiting
         let (reader_result, writer_result) = tokio::join!(reader_task, writer_task);
@@ -92,14 +108,14 @@ impl ChatSession {
         // Handle results properly
         match reader_result {
             Ok(Ok(())) => {}
-            Ok(Err(e)) => eprintln!("Reader error: {:?}", e),
-            Err(e) => eprintln!("Reader task panicked: {:?}", e),
+            Ok(Err(e)) => warn!(error = format!("{:#}", e), "Reader failed"),
+            Err(e) => error!(error = %e, "Reader task panicked"),
         }
 
         match writer_result {
             Ok(Ok(())) => {}
-            Ok(Err(e)) => eprintln!("Writer error: {:?}", e),
-            Err(e) => eprintln!("Writer task panicked: {:?}", e),
+            Ok(Err(e)) => warn!(error = format!("{:#}", e), "Writer failed"),
+            Err(e) => error!(error = %e, "Writer task panicked"),
         }
 
         Ok(())
@@ -107,23 +123,31 @@ impl ChatSession {
 }
 
 async fn read_task(
-    mut reader: SplitStream<Framed<TcpStream, LinesCodec>>,
-    tx: Arc<Sender<(SocketAddr, String)>>,
+    mut reader: RateLimited<SplitStream<Framed<TcpStream, LinesCodec>>>,
+    notices: mpsc::Sender<String>,
+    tx: &Sender<(SocketAddr, String)>,
     peer: SocketAddr,
     name: String,
-    users: UserStorage,
-    shutdown: CancellationToken,
+    server_shutdown: CancellationToken,
 ) -> anyhow::Result<()> {
-    println!("Read task started for {} ({})", name, peer);
-
     loop {
-        match reader.next().await {
-            Some(Ok(line)) => {
+        let Some(next) = server_shutdown.run_until_cancelled(reader.next()).await else {
+            break; // Server shutting down
+        };
+        match next {
+            Some(Err(Throttled)) => {
+                warn!("Message throttled");
+                // Dropped rather than queued so a slow reader cannot stall the room
+                let _ = notices.try_send(format!("* {}", Throttled::MESSAGE));
+            }
+            Some(Ok(Ok(line))) => {
+                metrics::service(ServiceKind::Chat).messages_decoded.inc();
                 // Process message
                 let _ = tx.send((peer, format!("[{}] {}", name, line)));
             }
-            Some(Err(e)) => {
-                eprintln!("Read error: {:?}", e);
+            Some(Ok(Err(e))) => {
+                metrics::service(ServiceKind::Chat).decode_errors.inc();
+                warn!(error = %e, "Read failed");
                 break; // Connection error
             }
             None => {
@@ -131,26 +155,26 @@ async fn read_task(
             }
         }
     }
-
-    cleanup(users, peer, &tx).await?;
-    shutdown.cancel();
     Ok(())
 }
 
 async fn write_task(
     mut writer: SplitSink<Framed<TcpStream, LinesCodec>, String>,
     mut rx: Receiver<(SocketAddr, String)>,
+    mut notices: mpsc::Receiver<String>,
     peer: SocketAddr,
+    shutdown: CancellationToken,
 ) -> anyhow::Result<()> {
-    println!("Writer task started for {}", peer);
-
     loop {
         tokio::select! {
-            recv = rx.recv() => match recv {
-                Ok((src, msg)) if src != peer => {
+            recv = rx.recv() => {
+                if let Ok((src, msg)) = recv
+                    && src != peer
+                {
                     if writer.send(msg).await.is_err() {
                         break;
                     }
+                    metrics::service(ServiceKind::Chat).messages_encoded.inc();
                 }
                 // Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => {
                 //     continue;
@@ -158,12 +182,18 @@ async fn write_task(
                 // Err(tokio::sync::broadcast::error::RecvError::Closed) => {
                 //     break;
                 // }
-                _ => {}
             },
-            // _ = shutdown.cancelled() => {
-            //     break;
-            // }
+            Some(notice) = notices.recv() => {
+                if writer.send(notice).await.is_err() {
+                    break;
+                }
+            },
+            // Cancelled by the reader once the leave message is broadcast
+            _ = shutdown.cancelled() => {
+                break;
+            }
         }
     }
+    debug!("Writer exiting");
     Ok(())
 }
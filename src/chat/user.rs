@@ -10,7 +10,7 @@ impl User {
     }
 
     fn validate(name: &str) -> anyhow::Result<()> {
-        if name == "" {
+        if name.is_empty() {
             return Err(anyhow::anyhow!("Will not accept blank name"));
         }
         if !name.chars().all(|ch| ch.is_alphanumeric()) {
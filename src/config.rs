@@ -0,0 +1,829 @@
+use std::{
+    collections::BTreeMap,
+    net::ToSocketAddrs,
+    num::NonZeroUsize,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{Context, bail};
+use serde::Deserialize;
+use tracing_subscriber::EnvFilter;
+
+use crate::services::{RateLimit, ServiceKind};
+
+/// Top level of the `--config` file. Every table rejects unknown keys so a typo fails at startup
+/// instead of silently falling back to a default.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    #[serde(default)]
+    pub log: LogConfig,
+    #[serde(default)]
+    pub shutdown: ShutdownConfig,
+    /// Serves `GET /metrics` when present.
+    pub metrics: Option<MetricsConfig>,
+    #[serde(default)]
+    pub services: BTreeMap<ServiceKind, ServiceConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LogConfig {
+    /// `EnvFilter` directives, e.g. `info` or `info,prime_time::road=debug`.
+    #[serde(default = "default_log_level")]
+    pub level: String,
+    #[serde(default)]
+    pub format: LogFormat,
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        Self {
+            level: default_log_level(),
+            format: LogFormat::default(),
+        }
+    }
+}
+
+/// `pretty` for a terminal, `json` (one object per line, with span fields) for log shipping.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LogFormat {
+    #[default]
+    Pretty,
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pretty" => Ok(LogFormat::Pretty),
+            "json" => Ok(LogFormat::Json),
+            _ => bail!("unknown log format `{}`, expected pretty or json", s),
+        }
+    }
+}
+
+fn default_log_level() -> String {
+    "info".to_string()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShutdownConfig {
+    /// How long live connections get to finish after SIGINT/SIGTERM before they are aborted.
+    #[serde(default = "default_drain_timeout_ms")]
+    pub drain_timeout_ms: u64,
+}
+
+impl Default for ShutdownConfig {
+    fn default() -> Self {
+        Self {
+            drain_timeout_ms: default_drain_timeout_ms(),
+        }
+    }
+}
+
+impl ShutdownConfig {
+    pub fn drain_timeout(&self) -> Duration {
+        Duration::from_millis(self.drain_timeout_ms)
+    }
+}
+
+fn default_drain_timeout_ms() -> u64 {
+    5_000
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricsConfig {
+    pub bind: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ServiceConfig {
+    pub bind: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Per-connection response channel (road, job center, version control) or the chat
+    /// broadcast channel.
+    pub channel_capacity: Option<usize>,
+    /// Command channel of the `JobManager` / `FileManager` actors.
+    pub actor_channel_capacity: Option<usize>,
+    /// Longest line accepted by `LinesCodec` based services before the connection errors.
+    /// Unlimited by default, except for the prime service.
+    pub max_line_length: Option<usize>,
+    /// Prime requests read ahead of the oldest unanswered one on a connection. Once this many
+    /// are outstanding the connection is not read until the oldest response is written.
+    pub max_pipelined: Option<usize>,
+    /// Pipelined prime requests evaluated at the same time per connection.
+    pub max_concurrent: Option<usize>,
+    /// Primality results the prime service remembers across all its connections.
+    pub cache_capacity: Option<usize>,
+    /// Append-only file the means-to-end service keeps session prices in. Without it sessions
+    /// outlive their connections but not the process.
+    pub session_log: Option<PathBuf>,
+    /// What a means-to-end insert at a timestamp that already has a price does.
+    pub duplicate_timestamps: Option<DuplicatePolicy>,
+    /// Means-to-end connections that send nothing for this long are closed.
+    pub idle_timeout_ms: Option<u64>,
+    /// Most prices one means-to-end session, or connection without one, may hold. Inserts of
+    /// new timestamps past it close the connection.
+    pub max_prices: Option<usize>,
+    /// Keeps the UDP store on disk when present; without it every insert is lost on restart.
+    pub wal: Option<WalConfig>,
+    /// Total key and value bytes the UDP store holds before evicting the least recently used
+    /// keys. Unlimited when absent.
+    pub max_bytes: Option<usize>,
+    /// How often the UDP store drops keys whose TTL has passed.
+    pub reap_interval_ms: Option<u64>,
+    /// Makes the UDP store one node of a replicated group.
+    pub replication: Option<ReplicationConfig>,
+    /// Independently locked parts the UDP store is split into by key hash.
+    pub shards: Option<usize>,
+    /// Tasks answering UDP store datagrams; defaults to one per core. Each client's datagrams
+    /// are all answered by the same one, in order.
+    pub workers: Option<usize>,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+}
+
+/// Admission and rate limits, all off unless set.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LimitsConfig {
+    pub max_connections: Option<usize>,
+    pub max_connections_per_ip: Option<usize>,
+    /// Sustained messages per second on one connection (road, job center, chat).
+    pub messages_per_sec: Option<f64>,
+    /// Messages a connection may send back to back; defaults to one second's worth.
+    pub burst: Option<u32>,
+}
+
+impl LimitsConfig {
+    pub fn rate_limit(&self) -> Option<RateLimit> {
+        self.messages_per_sec.map(|per_sec| RateLimit {
+            per_sec,
+            burst: self.burst.unwrap_or(per_sec.ceil() as u32).max(1),
+        })
+    }
+
+    fn validate(&self, key: &str) -> anyhow::Result<()> {
+        for (field, value) in [
+            ("max_connections", self.max_connections),
+            ("max_connections_per_ip", self.max_connections_per_ip),
+            ("burst", self.burst.map(|burst| burst as usize)),
+        ] {
+            if value == Some(0) {
+                bail!("{}.limits.{}: must be greater than 0", key, field);
+            }
+        }
+        if let Some(per_sec) = self.messages_per_sec
+            && !(per_sec.is_finite() && per_sec > 0.0)
+        {
+            bail!("{}.limits.messages_per_sec: must be greater than 0", key);
+        }
+        Ok(())
+    }
+}
+
+/// Write-ahead log and snapshot of the UDP store.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WalConfig {
+    /// Directory holding `wal.log` and `snapshot`, created if missing.
+    pub dir: PathBuf,
+    #[serde(default)]
+    pub fsync: FsyncPolicy,
+    /// With `fsync = "interval"`, the longest an insert waits to be synced by a later one.
+    pub fsync_interval_ms: Option<u64>,
+    /// Inserts logged before the store is compacted into a new snapshot and the log emptied.
+    pub snapshot_every: Option<usize>,
+}
+
+pub const DEFAULT_FSYNC_INTERVAL: Duration = Duration::from_secs(1);
+pub const DEFAULT_SNAPSHOT_EVERY: usize = 10_000;
+
+impl WalConfig {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            fsync: FsyncPolicy::default(),
+            fsync_interval_ms: None,
+            snapshot_every: None,
+        }
+    }
+
+    pub fn fsync_interval(&self) -> Duration {
+        self.fsync_interval_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_FSYNC_INTERVAL)
+    }
+
+    pub fn snapshot_every(&self) -> usize {
+        self.snapshot_every.unwrap_or(DEFAULT_SNAPSHOT_EVERY)
+    }
+
+    fn validate(&self, key: &str) -> anyhow::Result<()> {
+        for (field, value) in [
+            (
+                "fsync_interval_ms",
+                self.fsync_interval_ms.map(|ms| ms as usize),
+            ),
+            ("snapshot_every", self.snapshot_every),
+        ] {
+            if value == Some(0) {
+                bail!("{}.wal.{}: must be greater than 0", key, field);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Links UDP store instances into a group that forwards every insert to the others.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReplicationConfig {
+    /// Address of this node's replication socket, separate from the one clients use.
+    pub bind: String,
+    /// Replication addresses of the other nodes. Traffic from any other address is dropped.
+    #[serde(default)]
+    pub peers: Vec<String>,
+    /// How often unacknowledged inserts and snapshot requests are sent again.
+    pub retransmit_ms: Option<u64>,
+    /// Own inserts kept for peers that fall behind. A peer missing older ones catches up from a
+    /// snapshot instead.
+    pub log_capacity: Option<usize>,
+}
+
+pub const DEFAULT_RETRANSMIT_INTERVAL: Duration = Duration::from_millis(100);
+pub const DEFAULT_LOG_CAPACITY: usize = 10_000;
+
+impl ReplicationConfig {
+    pub fn new(bind: impl Into<String>) -> Self {
+        Self {
+            bind: bind.into(),
+            peers: vec![],
+            retransmit_ms: None,
+            log_capacity: None,
+        }
+    }
+
+    pub fn retransmit_interval(&self) -> Duration {
+        self.retransmit_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_RETRANSMIT_INTERVAL)
+    }
+
+    pub fn log_capacity(&self) -> usize {
+        self.log_capacity.unwrap_or(DEFAULT_LOG_CAPACITY)
+    }
+
+    fn validate(&self, key: &str) -> anyhow::Result<()> {
+        for addr in std::iter::once(&self.bind).chain(&self.peers) {
+            addr.to_socket_addrs()
+                .with_context(|| format!("{}.replication: invalid address `{}`", key, addr))?;
+        }
+        for (field, value) in [
+            ("retransmit_ms", self.retransmit_ms.map(|ms| ms as usize)),
+            ("log_capacity", self.log_capacity),
+        ] {
+            if value == Some(0) {
+                bail!("{}.replication.{}: must be greater than 0", key, field);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// When logged inserts reach the disk: `always` before the worker that received the insert
+/// handles another datagram, `interval` at most `fsync_interval_ms` apart, `never` only at
+/// shutdown.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FsyncPolicy {
+    #[default]
+    Always,
+    Interval,
+    Never,
+}
+
+/// `overwrite` keeps the latest price, `keep_first` ignores the new one and `reject` closes
+/// the connection.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicatePolicy {
+    Reject,
+    #[default]
+    Overwrite,
+    KeepFirst,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+pub fn default_workers() -> usize {
+    std::thread::available_parallelism().map_or(1, NonZeroUsize::get)
+}
+
+pub const DEFAULT_CHANNEL_CAPACITY: usize = 1_000;
+pub const DEFAULT_ACTOR_CHANNEL_CAPACITY: usize = 32;
+pub const DEFAULT_MAX_PIPELINED: usize = 1_000;
+pub const DEFAULT_MAX_CONCURRENT: usize = 8;
+pub const DEFAULT_CACHE_CAPACITY: NonZeroUsize = NonZeroUsize::new(100_000).unwrap();
+pub const DEFAULT_REAP_INTERVAL: Duration = Duration::from_secs(1);
+pub const DEFAULT_SHARDS: usize = 16;
+/// Prime requests are parsed whole, so unlike the other line based services the prime
+/// service does not accept unbounded lines by default.
+pub const DEFAULT_PRIME_MAX_LINE_LENGTH: usize = 1 << 20;
+
+/// Tunables handed to each connection handler.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConnectionSettings {
+    pub channel_capacity: usize,
+    pub max_line_length: usize,
+    pub max_pipelined: usize,
+    pub max_concurrent: usize,
+    pub rate_limit: Option<RateLimit>,
+    pub duplicate_timestamps: DuplicatePolicy,
+    pub idle_timeout: Option<Duration>,
+    pub max_prices: Option<usize>,
+}
+
+impl Default for ConnectionSettings {
+    fn default() -> Self {
+        Self {
+            channel_capacity: DEFAULT_CHANNEL_CAPACITY,
+            max_line_length: usize::MAX,
+            max_pipelined: DEFAULT_MAX_PIPELINED,
+            max_concurrent: DEFAULT_MAX_CONCURRENT,
+            rate_limit: None,
+            duplicate_timestamps: DuplicatePolicy::default(),
+            idle_timeout: None,
+            max_prices: None,
+        }
+    }
+}
+
+impl ServiceConfig {
+    pub fn new(bind: impl Into<String>) -> Self {
+        Self {
+            bind: bind.into(),
+            enabled: true,
+            channel_capacity: None,
+            actor_channel_capacity: None,
+            max_line_length: None,
+            max_pipelined: None,
+            max_concurrent: None,
+            cache_capacity: None,
+            session_log: None,
+            duplicate_timestamps: None,
+            idle_timeout_ms: None,
+            max_prices: None,
+            wal: None,
+            max_bytes: None,
+            reap_interval_ms: None,
+            replication: None,
+            shards: None,
+            workers: None,
+            limits: LimitsConfig::default(),
+        }
+    }
+
+    pub fn connection_settings(&self) -> ConnectionSettings {
+        let defaults = ConnectionSettings::default();
+        ConnectionSettings {
+            channel_capacity: self.channel_capacity.unwrap_or(defaults.channel_capacity),
+            max_line_length: self.max_line_length.unwrap_or(defaults.max_line_length),
+            max_pipelined: self.max_pipelined.unwrap_or(defaults.max_pipelined),
+            max_concurrent: self.max_concurrent.unwrap_or(defaults.max_concurrent),
+            rate_limit: self.limits.rate_limit(),
+            duplicate_timestamps: self.duplicate_timestamps.unwrap_or_default(),
+            idle_timeout: self.idle_timeout_ms.map(Duration::from_millis),
+            max_prices: self.max_prices,
+        }
+    }
+
+    pub fn actor_channel_capacity(&self) -> usize {
+        self.actor_channel_capacity
+            .unwrap_or(DEFAULT_ACTOR_CHANNEL_CAPACITY)
+    }
+
+    pub fn cache_capacity(&self) -> NonZeroUsize {
+        self.cache_capacity
+            .and_then(NonZeroUsize::new)
+            .unwrap_or(DEFAULT_CACHE_CAPACITY)
+    }
+
+    pub fn reap_interval(&self) -> Duration {
+        self.reap_interval_ms
+            .map(Duration::from_millis)
+            .unwrap_or(DEFAULT_REAP_INTERVAL)
+    }
+
+    pub fn shards(&self) -> usize {
+        self.shards.unwrap_or(DEFAULT_SHARDS)
+    }
+
+    pub fn workers(&self) -> usize {
+        self.workers.unwrap_or_else(default_workers)
+    }
+
+    fn validate(&self, kind: ServiceKind, key: &str) -> anyhow::Result<()> {
+        self.bind
+            .to_socket_addrs()
+            .with_context(|| format!("{}.bind: invalid address `{}`", key, self.bind))?;
+        if let Some(field) = self.unused_field(kind) {
+            bail!("{}.{}: not used by the {} service", key, field, kind);
+        }
+        for (field, value) in [
+            ("channel_capacity", self.channel_capacity),
+            ("actor_channel_capacity", self.actor_channel_capacity),
+            ("max_line_length", self.max_line_length),
+            ("max_pipelined", self.max_pipelined),
+            ("max_concurrent", self.max_concurrent),
+            ("cache_capacity", self.cache_capacity),
+            (
+                "idle_timeout_ms",
+                self.idle_timeout_ms.map(|ms| ms as usize),
+            ),
+            ("max_prices", self.max_prices),
+            ("max_bytes", self.max_bytes),
+            (
+                "reap_interval_ms",
+                self.reap_interval_ms.map(|ms| ms as usize),
+            ),
+            ("shards", self.shards),
+            ("workers", self.workers),
+        ] {
+            if value == Some(0) {
+                bail!("{}.{}: must be greater than 0", key, field);
+            }
+        }
+        if let Some(wal) = &self.wal {
+            wal.validate(key)?;
+        }
+        if let Some(replication) = &self.replication {
+            replication.validate(key)?;
+        }
+        self.limits.validate(key)
+    }
+
+    /// The first field set in this table that `kind` never reads, so a setting copied into the
+    /// wrong service fails instead of silently doing nothing.
+    fn unused_field(&self, kind: ServiceKind) -> Option<&'static str> {
+        use ServiceKind::*;
+        const TCP: &[ServiceKind] = &[
+            Prime,
+            MeansToEnd,
+            Chat,
+            Road,
+            Crypto,
+            VersionControl,
+            JobCenter,
+        ];
+        const RATE_LIMITED: &[ServiceKind] = &[Chat, Road, JobCenter];
+        let limits = &self.limits;
+        let fields: [(&str, bool, &[ServiceKind]); 20] = [
+            (
+                "channel_capacity",
+                self.channel_capacity.is_some(),
+                &[Chat, Road, VersionControl, JobCenter],
+            ),
+            (
+                "actor_channel_capacity",
+                self.actor_channel_capacity.is_some(),
+                &[VersionControl, JobCenter],
+            ),
+            (
+                "max_line_length",
+                self.max_line_length.is_some(),
+                &[Prime, Chat, JobCenter],
+            ),
+            ("max_pipelined", self.max_pipelined.is_some(), &[Prime]),
+            ("max_concurrent", self.max_concurrent.is_some(), &[Prime]),
+            ("cache_capacity", self.cache_capacity.is_some(), &[Prime]),
+            ("session_log", self.session_log.is_some(), &[MeansToEnd]),
+            (
+                "duplicate_timestamps",
+                self.duplicate_timestamps.is_some(),
+                &[MeansToEnd],
+            ),
+            (
+                "idle_timeout_ms",
+                self.idle_timeout_ms.is_some(),
+                &[MeansToEnd],
+            ),
+            ("max_prices", self.max_prices.is_some(), &[MeansToEnd]),
+            ("wal", self.wal.is_some(), &[Database]),
+            ("max_bytes", self.max_bytes.is_some(), &[Database]),
+            (
+                "reap_interval_ms",
+                self.reap_interval_ms.is_some(),
+                &[Database],
+            ),
+            ("replication", self.replication.is_some(), &[Database]),
+            ("shards", self.shards.is_some(), &[Database]),
+            ("workers", self.workers.is_some(), &[Database]),
+            (
+                "limits.max_connections",
+                limits.max_connections.is_some(),
+                TCP,
+            ),
+            (
+                "limits.max_connections_per_ip",
+                limits.max_connections_per_ip.is_some(),
+                TCP,
+            ),
+            (
+                "limits.messages_per_sec",
+                limits.messages_per_sec.is_some(),
+                RATE_LIMITED,
+            ),
+            ("limits.burst", limits.burst.is_some(), RATE_LIMITED),
+        ];
+        fields
+            .into_iter()
+            .find(|(_, set, kinds)| *set && !kinds.contains(&kind))
+            .map(|(field, _, _)| field)
+    }
+}
+
+impl Config {
+    /// Loads a TOML or JSON config, picking the format from the file extension.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = std::fs::read_to_string(path)
+            .with_context(|| format!("failed to read config file {}", path.display()))?;
+        let config = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml(&text),
+            Some("json") => Self::from_json(&text),
+            _ => bail!("config file {} must end in .toml or .json", path.display()),
+        };
+        config.with_context(|| format!("invalid config file {}", path.display()))
+    }
+
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        let deserializer = toml::Deserializer::parse(text)?;
+        let config: Config = serde_path_to_error::deserialize(deserializer)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn from_json(text: &str) -> anyhow::Result<Self> {
+        let mut deserializer = serde_json::Deserializer::from_str(text);
+        let config: Config = serde_path_to_error::deserialize(&mut deserializer)?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        EnvFilter::try_new(&self.log.level).context("log.level: invalid filter")?;
+        if let Some(metrics) = &self.metrics {
+            metrics
+                .bind
+                .to_socket_addrs()
+                .with_context(|| format!("metrics.bind: invalid address `{}`", metrics.bind))?;
+        }
+        for (kind, service) in &self.services {
+            service.validate(*kind, &format!("services.{}", kind))?;
+        }
+        Ok(())
+    }
+
+    pub fn enabled_services(&self) -> impl Iterator<Item = (ServiceKind, &ServiceConfig)> {
+        self.services
+            .iter()
+            .filter(|(_, service)| service.enabled)
+            .map(|(kind, service)| (*kind, service))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_toml() {
+        let config = Config::from_toml(
+            r#"
+            [log]
+            level = "debug"
+            format = "json"
+
+            [shutdown]
+            drain_timeout_ms = 250
+
+            [metrics]
+            bind = "127.0.0.1:9100"
+
+            [services.road]
+            bind = "127.0.0.1:4000"
+            channel_capacity = 10
+
+            [services.job_center]
+            bind = "127.0.0.1:4001"
+            actor_channel_capacity = 8
+            max_line_length = 1024
+
+            [services.prime]
+            bind = "127.0.0.1:4002"
+            max_pipelined = 64
+            cache_capacity = 500
+
+            [services.mte]
+            bind = "127.0.0.1:4003"
+            duplicate_timestamps = "keep_first"
+            idle_timeout_ms = 30000
+            max_prices = 10000
+
+            [services.database]
+            bind = "127.0.0.1:4004"
+            wal = { dir = "data", fsync = "interval", fsync_interval_ms = 50 }
+            max_bytes = 1048576
+            workers = 4
+
+            [services.database.replication]
+            bind = "127.0.0.1:5004"
+            peers = ["127.0.0.1:5005", "127.0.0.1:5006"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(config.log.level, "debug");
+        assert_eq!(config.log.format, LogFormat::Json);
+        assert_eq!(config.shutdown.drain_timeout(), Duration::from_millis(250));
+        assert_eq!(config.metrics.unwrap().bind, "127.0.0.1:9100");
+        let road = &config.services[&ServiceKind::Road];
+        assert_eq!(road.connection_settings().channel_capacity, 10);
+        assert_eq!(
+            road.actor_channel_capacity(),
+            DEFAULT_ACTOR_CHANNEL_CAPACITY
+        );
+        let jobs = &config.services[&ServiceKind::JobCenter];
+        assert_eq!(jobs.actor_channel_capacity(), 8);
+        assert_eq!(jobs.connection_settings().max_line_length, 1024);
+        let prime = &config.services[&ServiceKind::Prime];
+        assert_eq!(prime.cache_capacity().get(), 500);
+        assert_eq!(prime.connection_settings().max_pipelined, 64);
+        assert_eq!(
+            prime.connection_settings().max_concurrent,
+            DEFAULT_MAX_CONCURRENT
+        );
+        let mte = config.services[&ServiceKind::MeansToEnd].connection_settings();
+        assert_eq!(mte.duplicate_timestamps, DuplicatePolicy::KeepFirst);
+        assert_eq!(mte.idle_timeout, Some(Duration::from_secs(30)));
+        assert_eq!(mte.max_prices, Some(10_000));
+        assert_eq!(
+            prime.connection_settings().duplicate_timestamps,
+            DuplicatePolicy::Overwrite
+        );
+        let wal = config.services[&ServiceKind::Database]
+            .wal
+            .as_ref()
+            .unwrap();
+        assert_eq!(wal.dir, Path::new("data"));
+        assert_eq!(wal.fsync, FsyncPolicy::Interval);
+        assert_eq!(wal.fsync_interval(), Duration::from_millis(50));
+        assert_eq!(wal.snapshot_every(), DEFAULT_SNAPSHOT_EVERY);
+        assert!(prime.wal.is_none());
+        let database = &config.services[&ServiceKind::Database];
+        assert_eq!(database.max_bytes, Some(1 << 20));
+        assert_eq!(database.reap_interval(), DEFAULT_REAP_INTERVAL);
+        assert_eq!(database.shards(), DEFAULT_SHARDS);
+        assert_eq!(database.workers(), 4);
+        let replication = database.replication.as_ref().unwrap();
+        assert_eq!(replication.peers, ["127.0.0.1:5005", "127.0.0.1:5006"]);
+        assert_eq!(
+            replication.retransmit_interval(),
+            DEFAULT_RETRANSMIT_INTERVAL
+        );
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let config = Config::from_json(
+            r#"{"services": {"mte": {"bind": "127.0.0.1:4000", "enabled": false}}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.log.level, "info");
+        assert_eq!(config.log.format, LogFormat::Pretty);
+        assert_eq!(config.shutdown.drain_timeout(), Duration::from_secs(5));
+        assert_eq!(config.enabled_services().count(), 0);
+    }
+
+    #[test]
+    fn test_unknown_key_is_named() {
+        let err = Config::from_toml(
+            r#"
+            [services.road]
+            bind = "127.0.0.1:4000"
+            chanel_capacity = 10
+            "#,
+        )
+        .unwrap_err();
+        let msg = format!("{:#}", err);
+        assert!(msg.contains("services.road"), "{}", msg);
+        assert!(msg.contains("chanel_capacity"), "{}", msg);
+    }
+
+    #[test]
+    fn test_unknown_service_is_named() {
+        let err =
+            Config::from_json(r#"{"services": {"rode": {"bind": "127.0.0.1:4000"}}}"#).unwrap_err();
+        assert!(format!("{:#}", err).contains("rode"));
+    }
+
+    #[test]
+    fn test_zero_capacity_rejected() {
+        let err = Config::from_toml(
+            r#"
+            [services.chat]
+            bind = "127.0.0.1:4000"
+            channel_capacity = 0
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "services.chat.channel_capacity: must be greater than 0"
+        );
+    }
+
+    #[test]
+    fn test_limits() {
+        let config = Config::from_toml(
+            r#"
+            [services.chat]
+            bind = "127.0.0.1:4000"
+
+            [services.chat.limits]
+            max_connections_per_ip = 4
+            messages_per_sec = 2.5
+            "#,
+        )
+        .unwrap();
+        let chat = &config.services[&ServiceKind::Chat];
+        assert_eq!(chat.limits.max_connections_per_ip, Some(4));
+        assert_eq!(
+            chat.connection_settings().rate_limit,
+            Some(RateLimit {
+                per_sec: 2.5,
+                burst: 3
+            })
+        );
+
+        let err = Config::from_toml(
+            r#"
+            [services.chat]
+            bind = "127.0.0.1:4000"
+            limits = { messages_per_sec = -1.0 }
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "services.chat.limits.messages_per_sec: must be greater than 0"
+        );
+    }
+
+    #[test]
+    fn test_field_of_another_service_rejected() {
+        let err = Config::from_toml(
+            r#"
+            [services.road]
+            bind = "127.0.0.1:4000"
+            cache_capacity = 10
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "services.road.cache_capacity: not used by the road service"
+        );
+
+        let err = Config::from_toml(
+            r#"
+            [services.database]
+            bind = "127.0.0.1:4000"
+            limits = { max_connections = 10 }
+            "#,
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "services.database.limits.max_connections: not used by the database service"
+        );
+    }
+
+    #[test]
+    fn test_bad_log_level_rejected() {
+        let err = Config::from_toml("[log]\nlevel = \"prime_time=loud\"").unwrap_err();
+        assert!(err.to_string().starts_with("log.level"));
+    }
+}
@@ -4,7 +4,7 @@ use tokio_util::{
     codec::{Decoder, Encoder},
 };
 
-use crate::crypto::ops::Op;
+use crate::{crypto::ops::Op, metrics, services::ServiceKind};
 
 #[derive(Debug, Clone)]
 pub enum DecodeState {
@@ -37,6 +37,14 @@ impl Decoder for CryptoCodec {
     type Error = anyhow::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>> {
+        let decoded = self.decode_frame(src);
+        metrics::service(ServiceKind::Crypto).record_decode(&decoded);
+        decoded
+    }
+}
+
+impl CryptoCodec {
+    fn decode_frame(&mut self, src: &mut BytesMut) -> Result<Option<Message>> {
         if src.is_empty() {
             return Ok(None);
         }
@@ -51,21 +59,20 @@ impl Decoder for CryptoCodec {
                     }
                     src.advance(consume);
                     self.state = DecodeState::Secure(cipher.clone());
-                    println!("{:?}", cipher);
-                    return Ok(Some(Message::Cipher(cipher)));
+                    Ok(Some(Message::Cipher(cipher)))
                 }
-                Err(_) => return Ok(None),
+                Err(_) => Ok(None),
             },
             DecodeState::Secure(cipher) => {
-                let result = decrypt(&cipher, &buf, self.client_pos);
+                let result = decrypt(cipher, &buf, self.client_pos);
                 if let Some(pos) = result.iter().position(|&b| b == b'\n') {
                     let to_decrypt = &result[..=pos]; // include newline
                     src.advance(to_decrypt.len());
                     self.client_pos += to_decrypt.len();
-                    return Ok(Some(Message::Text(String::from_utf8(to_decrypt.to_vec())?)));
+                    Ok(Some(Message::Text(String::from_utf8(to_decrypt.to_vec())?)))
                 } else {
                     // Wait for more data to arrive
-                    return Ok(None);
+                    Ok(None)
                 }
             }
         }
@@ -77,10 +84,7 @@ fn parse_buf(src: &mut BytesMut) -> anyhow::Result<(Vec<Op>, usize)> {
 
     while idx < src.len() {
         match src[idx] {
-            0x00 => {
-                idx += 1;
-                break;
-            }
+            0x00 => return Ok((cipher, idx + 1)),
             0x01 => {
                 cipher.push(Op::Reverse);
                 idx += 1;
@@ -112,7 +116,8 @@ fn parse_buf(src: &mut BytesMut) -> anyhow::Result<(Vec<Op>, usize)> {
             b => anyhow::bail!("Invalid opcode 0x{:02x} at idx {}", b, idx),
         }
     }
-    return Ok((cipher, idx));
+    // The spec only ends at its 0x00, so anything shorter is still arriving
+    anyhow::bail!("Incomplete cipher spec")
 }
 
 fn decrypt(cipher: &[Op], input: &[u8], start_pos: usize) -> Vec<u8> {
@@ -144,10 +149,18 @@ impl Encoder<Message> for CryptoCodec {
     type Error = anyhow::Error;
 
     fn encode(&mut self, item: Message, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode_frame(item, dst)?;
+        metrics::service(ServiceKind::Crypto).messages_encoded.inc();
+        Ok(())
+    }
+}
+
+impl CryptoCodec {
+    fn encode_frame(&mut self, item: Message, dst: &mut BytesMut) -> Result<()> {
         match &self.state {
             DecodeState::Secure(cipher) => match item {
                 Message::Text(raw_sting) => {
-                    let result = encrypt(&cipher, raw_sting.as_bytes(), self.server_pos);
+                    let result = encrypt(cipher, raw_sting.as_bytes(), self.server_pos);
                     self.server_pos += result.len();
                     dst.extend(result);
                     Ok(())
@@ -160,3 +173,33 @@ impl Encoder<Message> for CryptoCodec {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode_bytewise(codec: &mut CryptoCodec, bytes: &[u8]) -> Vec<Message> {
+        let mut src = BytesMut::new();
+        let mut decoded = vec![];
+        for &b in bytes {
+            src.extend_from_slice(&[b]);
+            while let Some(message) = codec.decode(&mut src).unwrap() {
+                decoded.push(message);
+            }
+        }
+        decoded
+    }
+
+    #[test]
+    fn test_cipher_spec_split_before_terminator() {
+        let mut codec = CryptoCodec::new();
+        let cipher = vec![Op::Xor(1), Op::Reverse];
+        let mut input = vec![0x02, 0x01, 0x01, 0x00];
+        input.extend(encrypt(&cipher, b"4x dog,5x car\n", 0));
+
+        let decoded = decode_bytewise(&mut codec, &input);
+        assert_eq!(decoded.len(), 2);
+        assert!(matches!(&decoded[0], Message::Cipher(ops) if *ops == cipher));
+        assert!(matches!(&decoded[1], Message::Text(text) if text == "4x dog,5x car\n"));
+    }
+}
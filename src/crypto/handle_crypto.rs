@@ -1,21 +1,26 @@
 use futures::{SinkExt, StreamExt};
 use tokio::net::TcpStream;
-use tokio_util::codec::Framed;
-use tracing::info;
+use tokio_util::{codec::Framed, sync::CancellationToken};
+use tracing::debug;
 
 use crate::crypto::{
     crypto_codec::{CryptoCodec, Message},
     toys::Toy,
 };
 
-pub async fn handle_cipher(socket: TcpStream) -> anyhow::Result<()> {
+pub async fn handle_cipher(socket: TcpStream, shutdown: CancellationToken) -> anyhow::Result<()> {
 
     let mut framed: Framed<TcpStream, CryptoCodec> = Framed::new(socket, CryptoCodec::new());
-    while let Some(message) = framed.next().await.transpose()? {
+    while let Some(message) = shutdown
+        .run_until_cancelled(framed.next())
+        .await
+        .flatten()
+        .transpose()?
+    {
         match message {
-            Message::Cipher(_) => {}
+            Message::Cipher(cipher) => debug!(?cipher, "Cipher negotiated"),
             Message::Text(text) => {    
-                info!("{text}");            
+                debug!(request = %text, "Received request");
                 let mut toys: Vec<Toy> = text
                     .split(',')
                     .filter_map(|entry| {
@@ -28,10 +33,10 @@ pub async fn handle_cipher(socket: TcpStream) -> anyhow::Result<()> {
                     })
                     .collect();
 
-                toys.sort_by(|a, b| b.amount.cmp(&a.amount));
+                toys.sort_by_key(|toy| std::cmp::Reverse(toy.amount));
                 
                 if let Some(largest) = toys.first() {
-                    info!("sending largest toy {}", largest);
+                    debug!(toy = %largest, "Sending largest toy");
                     let reply = Message::Text(largest.to_string());
                     framed.send(reply).await?;
                 }
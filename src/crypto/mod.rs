@@ -1,4 +1,5 @@
 mod ops;
 mod crypto_codec;
 mod toys;
-pub mod handle_crypto;
\ No newline at end of file
+pub mod handle_crypto;
+pub mod service;
\ No newline at end of file
@@ -1,5 +1,5 @@
 // 00: End of cipher spec.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Op {
     Reverse,
     Xor(u8),
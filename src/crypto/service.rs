@@ -0,0 +1,17 @@
+use futures::future::BoxFuture;
+use tokio::net::TcpStream;
+use tokio_util::sync::CancellationToken;
+
+use crate::{crypto::handle_crypto::handle_cipher, services::TcpService};
+
+pub struct CryptoService;
+
+impl TcpService for CryptoService {
+    fn handle(
+        &self,
+        socket: TcpStream,
+        shutdown: CancellationToken,
+    ) -> BoxFuture<'static, anyhow::Result<()>> {
+        Box::pin(handle_cipher(socket, shutdown))
+    }
+}
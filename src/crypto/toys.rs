@@ -10,7 +10,7 @@ pub struct Toy {
 impl fmt::Display for Toy {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         // Write whatever custom format you want
-        write!(f, "{}x {}\n", self.amount, self.name.trim())
+        writeln!(f, "{}x {}", self.amount, self.name.trim())
     }
 }
 
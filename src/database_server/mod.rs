@@ -0,0 +1,470 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::future::BoxFuture;
+use tokio::{
+    net::UdpSocket,
+    sync::{mpsc, oneshot},
+    task::{JoinError, JoinSet},
+    time::{Instant, Interval},
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, warn};
+
+use crate::{
+    config::{DEFAULT_REAP_INTERVAL, ReplicationConfig, ServiceConfig, WalConfig, default_workers},
+    metrics::{self, CollectorGuard, Sample},
+    services::{ServiceKind, UdpService},
+};
+
+mod replication;
+mod request;
+mod sharded;
+mod storage;
+mod wal;
+
+pub use replication::{MAX_PEER_DATAGRAM, Replica};
+use replication::{Version, Versions, Write};
+pub use request::{MAX_DATAGRAM, Oversize, Request, TTL_KEY, VERSION_KEY};
+pub use sharded::ShardedStorage;
+pub use storage::Storage;
+pub use wal::Wal;
+
+/// Inserts queued for the task applying them before workers wait to hand over more.
+const INSERT_QUEUE: usize = 1024;
+/// Datagrams queued for a worker before more from its clients are dropped.
+const WORKER_QUEUE: usize = 1024;
+
+pub struct DatabaseService {
+    storage: Arc<ShardedStorage>,
+    wal: Option<WalConfig>,
+    reap_interval: Duration,
+    replication: Option<ReplicationConfig>,
+    workers: usize,
+    _collector: CollectorGuard,
+}
+
+impl DatabaseService {
+    pub fn new(config: &ServiceConfig) -> Self {
+        let storage = Arc::new(ShardedStorage::new(config.shards(), config.max_bytes));
+        let collected = Arc::clone(&storage);
+        let collector = metrics::add_collector(
+            ServiceKind::Database,
+            Box::new(move || {
+                let storage = Arc::clone(&collected);
+                Box::pin(async move {
+                    vec![
+                        Sample::gauge(
+                            "protohack_kv_keys",
+                            "Keys held by the UDP store.",
+                            storage.len() as f64,
+                        ),
+                        Sample::gauge(
+                            "protohack_kv_bytes",
+                            "Total key and value bytes held by the UDP store.",
+                            storage.bytes() as f64,
+                        ),
+                        Sample::counter(
+                            "protohack_kv_expired_total",
+                            "Keys the UDP store dropped because their TTL passed.",
+                            storage.expired() as f64,
+                        ),
+                        Sample::counter(
+                            "protohack_kv_evicted_total",
+                            "Keys the UDP store evicted to stay under max_bytes.",
+                            storage.evicted() as f64,
+                        ),
+                    ]
+                })
+            }),
+        );
+        Self {
+            storage,
+            wal: config.wal.clone(),
+            reap_interval: config.reap_interval(),
+            replication: config.replication.clone(),
+            workers: config.workers(),
+            _collector: collector,
+        }
+    }
+}
+
+/// What a store does besides answering clients from memory.
+pub struct ServerOptions {
+    /// Rebuilds the store from disk at startup and logs every insert.
+    pub wal: Option<WalConfig>,
+    /// How often keys whose TTL has passed are dropped.
+    pub reap_interval: Duration,
+    /// Makes the store one node of a replicated group.
+    pub replica: Option<Replica>,
+    /// Tasks answering client datagrams. Each client's datagrams all go to the same one.
+    pub workers: usize,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            wal: None,
+            reap_interval: DEFAULT_REAP_INTERVAL,
+            replica: None,
+            workers: default_workers(),
+        }
+    }
+}
+
+/// An insert a worker received, waiting to be applied.
+struct Insert {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    expires_at: Option<Instant>,
+    /// Tells the worker it may read its next datagram.
+    applied: oneshot::Sender<()>,
+}
+
+enum Event {
+    Insert(Insert),
+    Worker(Option<Result<anyhow::Result<()>, JoinError>>),
+    Peer(io::Result<(usize, SocketAddr)>),
+    Retransmit,
+    Shutdown,
+}
+
+impl UdpService for DatabaseService {
+    fn serve(
+        self: Box<Self>,
+        socket: UdpSocket,
+        shutdown: CancellationToken,
+    ) -> BoxFuture<'static, anyhow::Result<()>> {
+        Box::pin(async move {
+            let replica = match &self.replication {
+                Some(config) => Some(Replica::bind(config).await?),
+                None => None,
+            };
+            let options = ServerOptions {
+                wal: self.wal,
+                reap_interval: self.reap_interval,
+                replica,
+                workers: self.workers,
+            };
+            run_udp_server_with_socket(socket, self.storage, options, shutdown).await
+        })
+    }
+}
+
+pub async fn run_udp_server(
+    addr: &str,
+    storage: Arc<ShardedStorage>,
+    options: ServerOptions,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let socket = UdpSocket::bind(addr).await?;
+    run_udp_server_with_socket(socket, storage, options, shutdown).await
+}
+
+/// Serves the store until shutdown, with `workers` tasks answering clients from the same socket.
+/// With a WAL config the store is first rebuilt from disk. Expired keys are reaped every
+/// `reap_interval` meanwhile, and with a replica inserts are exchanged with its peers.
+///
+/// With more than one worker, a receiver hands each client's datagrams to the same worker in
+/// the order they arrived, and a worker finishes an insert before it handles its next datagram,
+/// so every read sees the inserts its client sent before it. With a WAL or a replica, inserts
+/// are handed to this task, which applies, logs and forwards them one at a time. While the
+/// replica fetches a peer's snapshot, inserts are held until it has been applied, so they are
+/// versioned above everything in it.
+pub async fn run_udp_server_with_socket(
+    socket: UdpSocket,
+    storage: Arc<ShardedStorage>,
+    options: ServerOptions,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let ServerOptions {
+        wal,
+        reap_interval,
+        mut replica,
+        workers: worker_count,
+    } = options;
+    let mut wal = match wal {
+        Some(config) => {
+            let (wal, versions) = Wal::open(&config, &storage).await?;
+            if let Some(replica) = &mut replica {
+                replica.restore(versions);
+            }
+            Some(wal)
+        }
+        None => None,
+    };
+    // Stops the reaper however the server returns
+    let reaper = shutdown.child_token();
+    let _stop_reaper = reaper.clone().drop_guard();
+    tokio::spawn(reap(Arc::clone(&storage), reap_interval, reaper));
+
+    let (insert_tx, mut inserts) = mpsc::channel(INSERT_QUEUE);
+    // Without a log or peers to tell, workers store inserts themselves
+    let insert_tx = (wal.is_some() || replica.is_some()).then_some(insert_tx);
+    let socket = Arc::new(socket);
+    let mut workers = JoinSet::new();
+    let sources = match worker_count {
+        0 | 1 => vec![Datagrams::Socket(Arc::clone(&socket))],
+        count => {
+            let (queues, sources): (Vec<_>, Vec<_>) =
+                (0..count).map(|_| mpsc::channel(WORKER_QUEUE)).unzip();
+            workers.spawn(route(Arc::clone(&socket), queues, shutdown.clone()));
+            sources.into_iter().map(Datagrams::Queue).collect()
+        }
+    };
+    for datagrams in sources {
+        workers.spawn(serve_clients(
+            datagrams,
+            Arc::clone(&socket),
+            Arc::clone(&storage),
+            insert_tx.clone(),
+            shutdown.clone(),
+        ));
+    }
+    drop(insert_tx);
+
+    let mut retransmit = replica
+        .as_ref()
+        .map(|replica| tokio::time::interval(replica.retransmit_interval()));
+    let mut peer_buf = vec![0u8; MAX_PEER_DATAGRAM];
+    loop {
+        // Inserts wait in the queue, and their workers with them, until they can be versioned
+        let accepting = replica.as_ref().is_none_or(Replica::accepts_writes);
+        let event = tokio::select! {
+            _ = shutdown.cancelled() => Event::Shutdown,
+            Some(insert) = inserts.recv(), if accepting => Event::Insert(insert),
+            joined = workers.join_next() => Event::Worker(joined),
+            received = recv_peer(replica.as_ref(), &mut peer_buf) => Event::Peer(received),
+            _ = next_tick(retransmit.as_mut()) => Event::Retransmit,
+        };
+        match event {
+            Event::Insert(insert) => write(insert, &storage, &mut wal, replica.as_mut()).await?,
+            Event::Worker(Some(joined)) => joined??,
+            Event::Worker(None) | Event::Shutdown => break,
+            Event::Peer(Ok((len, peer))) => {
+                if let Some(replica) = &mut replica {
+                    replica
+                        .handle(&peer_buf[..len], peer, &storage, &mut wal)
+                        .await?;
+                }
+            }
+            Event::Peer(Err(e)) => warn!("Receiving from peers failed: {}", e),
+            Event::Retransmit => {
+                if let Some(replica) = &mut replica {
+                    replica.tick(&storage).await;
+                }
+            }
+        }
+    }
+
+    // Workers stop on the same shutdown; inserts they already handed over are still applied
+    inserts.close();
+    while let Some(insert) = inserts.recv().await {
+        write(insert, &storage, &mut wal, replica.as_mut()).await?;
+    }
+    while let Some(joined) = workers.join_next().await {
+        joined??;
+    }
+    if let Some(wal) = &mut wal {
+        wal.sync().await?;
+    }
+    Ok(())
+}
+
+/// Where a worker gets its datagrams: the socket itself when it is the only one, or the queue
+/// [`route`] fills with its clients' datagrams.
+enum Datagrams {
+    Socket(Arc<UdpSocket>),
+    Queue(mpsc::Receiver<(Vec<u8>, SocketAddr)>),
+}
+
+impl Datagrams {
+    /// Copies the next datagram into `buf`, or returns `None` once the router has stopped.
+    async fn recv(&mut self, buf: &mut [u8]) -> Option<io::Result<(usize, SocketAddr)>> {
+        match self {
+            Self::Socket(socket) => Some(socket.recv_from(buf).await),
+            Self::Queue(queue) => {
+                let (datagram, addr) = queue.recv().await?;
+                buf[..datagram.len()].copy_from_slice(&datagram);
+                Some(Ok((datagram.len(), addr)))
+            }
+        }
+    }
+}
+
+/// Receives client datagrams on `socket` until shutdown and queues each for the worker its
+/// sender's address hashes to. One arriving while that worker's queue is full is dropped, as
+/// the network might have.
+async fn route(
+    socket: Arc<UdpSocket>,
+    queues: Vec<mpsc::Sender<(Vec<u8>, SocketAddr)>>,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let hasher = RandomState::new();
+    let mut buf = [0u8; MAX_DATAGRAM + 1];
+    while let Some(received) = shutdown
+        .run_until_cancelled(socket.recv_from(&mut buf))
+        .await
+    {
+        let (len, addr) = received?;
+        let queue = &queues[hasher.hash_one(addr) as usize % queues.len()];
+        if queue.try_send((buf[..len].to_vec(), addr)).is_err() {
+            debug!(peer = %addr, "Worker queue full, dropping datagram");
+        }
+    }
+    Ok(())
+}
+
+/// Answers client datagrams from `datagrams` on `socket` until shutdown. Inserts go to
+/// `inserts` when given, each applied before the next datagram is handled, and straight into
+/// `storage` otherwise.
+async fn serve_clients(
+    mut datagrams: Datagrams,
+    socket: Arc<UdpSocket>,
+    storage: Arc<ShardedStorage>,
+    inserts: Option<mpsc::Sender<Insert>>,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let metrics = metrics::service(ServiceKind::Database);
+    // One byte more than the protocol allows, so an oversize datagram shows up as one
+    let mut buf = [0u8; MAX_DATAGRAM + 1];
+    while let Some(received) = shutdown
+        .run_until_cancelled(datagrams.recv(&mut buf))
+        .await
+        .flatten()
+    {
+        let (len, addr) = received?;
+        let request = match Request::parse(&buf[..len]) {
+            Ok(request) => request,
+            Err(e) => {
+                metrics.decode_errors.inc();
+                warn!(peer = %addr, error = %e, "Dropping datagram");
+                continue;
+            }
+        };
+        metrics.messages_decoded.inc();
+        debug!(
+            peer = %addr,
+            request = %String::from_utf8_lossy(&buf[..len]),
+            "Received datagram"
+        );
+        match request {
+            Request::Insert { key, value, ttl } => {
+                debug!(
+                    peer = %addr,
+                    key = %String::from_utf8_lossy(key),
+                    value_len = value.len(),
+                    ?ttl,
+                    "Storing key"
+                );
+                let expires_at = ttl.map(|ttl| Instant::now() + ttl);
+                match &inserts {
+                    Some(inserts) => {
+                        let (applied, applying) = oneshot::channel();
+                        let insert = Insert {
+                            key: key.to_vec(),
+                            value: value.to_vec(),
+                            expires_at,
+                            applied,
+                        };
+                        // Closed once the server is shutting down
+                        if inserts.send(insert).await.is_err() || applying.await.is_err() {
+                            break;
+                        }
+                    }
+                    None => storage.insert(key.to_vec(), value.to_vec(), expires_at),
+                }
+            }
+            Request::Retrieve { key } => {
+                // An unknown or expired key is answered with the key alone
+                let response = match storage.get(key, Instant::now()) {
+                    Some(value) => [key, b"=", &value].concat(),
+                    None => key.to_vec(),
+                };
+                socket.send_to(&response, &addr).await?;
+                metrics.messages_encoded.inc();
+            }
+            Request::Version => {
+                socket
+                    .send_to("version=Ken's Key-Value Store 1.0".as_bytes(), &addr)
+                    .await?;
+                metrics.messages_encoded.inc();
+            }
+            Request::VersionWrite => {
+                debug!(peer = %addr, "Ignoring write to the version key");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies an insert a worker received, logs it and hands it to the replica.
+async fn write(
+    insert: Insert,
+    storage: &ShardedStorage,
+    wal: &mut Option<Wal>,
+    mut replica: Option<&mut Replica>,
+) -> anyhow::Result<()> {
+    let Insert {
+        key,
+        value,
+        expires_at,
+        applied,
+    } = insert;
+    let version = match &mut replica {
+        Some(replica) => replica.next_version(&key),
+        None => Version::default(),
+    };
+    let write = Write {
+        version,
+        expires_at,
+        key,
+        value,
+    };
+    storage.insert(write.key.clone(), write.value.clone(), write.expires_at);
+    if let Some(wal) = wal {
+        let unreplicated = Versions::new();
+        let versions = replica.as_deref().map_or(&unreplicated, Replica::versions);
+        wal.append(storage, versions, &write).await?;
+    }
+    if let Some(replica) = replica {
+        replica.record(write).await;
+    }
+    // The worker may have stopped waiting at shutdown
+    let _ = applied.send(());
+    Ok(())
+}
+
+async fn recv_peer(replica: Option<&Replica>, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+    match replica {
+        Some(replica) => replica.recv_from(buf).await,
+        None => std::future::pending().await,
+    }
+}
+
+async fn next_tick(ticks: Option<&mut Interval>) {
+    match ticks {
+        Some(ticks) => {
+            ticks.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Drops expired keys every `interval` until `stop`, so ones nobody reads again still free
+/// their memory.
+async fn reap(storage: Arc<ShardedStorage>, interval: Duration, stop: CancellationToken) {
+    let mut ticks = tokio::time::interval(interval);
+    ticks.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+    while stop.run_until_cancelled(ticks.tick()).await.is_some() {
+        let reaped = storage.reap(Instant::now());
+        if reaped > 0 {
+            debug!(reaped, "Reaped expired keys");
+        }
+    }
+}
@@ -0,0 +1,907 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    io,
+    net::SocketAddr,
+    time::Duration,
+};
+
+use anyhow::Context;
+use tokio::{
+    net::{UdpSocket, lookup_host},
+    time::Instant,
+};
+use tracing::{debug, info, warn};
+
+use super::{ShardedStorage, Wal};
+use crate::config::ReplicationConfig;
+
+/// Largest datagram nodes send each other, a snapshot chunk with its header included.
+pub const MAX_PEER_DATAGRAM: usize = 16 * 1024;
+const CHUNK_LEN: usize = 8 * 1024;
+/// Own inserts sent again to one peer per retransmit tick.
+const RETRANSMIT_BATCH: usize = 64;
+/// Missing chunks named in one snapshot request.
+const MAX_MISSING: usize = 256;
+/// Snapshots kept while peers may still be fetching their chunks.
+const SERVED_SNAPSHOTS: usize = 4;
+/// Retransmit ticks without a chunk before a catch-up asks every peer again.
+const MAX_STALLED_TICKS: u32 = 10;
+const NO_TTL: u64 = u64::MAX;
+
+const INSERT: u8 = 1;
+const APPLIED: u8 = 2;
+const RESYNC: u8 = 3;
+const SNAPSHOT_REQUEST: u8 = 4;
+const SNAPSHOT_CHUNK: u8 = 5;
+
+/// Orders writes to one key the same way on every node: by Lamport clock, then by the id of the
+/// node that made them. Keys never written through the group sort first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub(super) struct Version {
+    pub(super) clock: u64,
+    pub(super) node: u64,
+}
+
+/// The version of every key written through the group, including ones evicted or expired
+/// recently enough that older writes to them may still be on the way.
+pub(super) type Versions = HashMap<Vec<u8>, Version>;
+
+/// An insert with its version. One whose expiry has passed is a tombstone: it deletes the key
+/// but still outranks older writes to it.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Write {
+    pub(super) version: Version,
+    pub(super) expires_at: Option<Instant>,
+    pub(super) key: Vec<u8>,
+    pub(super) value: Vec<u8>,
+}
+
+impl Write {
+    fn is_tombstone(&self, now: Instant) -> bool {
+        self.expires_at.is_some_and(|expires_at| expires_at <= now)
+    }
+}
+
+/// What nodes send each other. TTLs travel as the time left, so clocks need not agree.
+#[derive(Debug, Clone, PartialEq)]
+enum Message {
+    /// The `seq`th insert made on `origin`.
+    Insert {
+        origin: u64,
+        seq: u64,
+        write: Write,
+    },
+    /// Every origin's inserts the sender has applied, up to the paired sequence number.
+    Applied(Vec<(u64, u64)>),
+    /// `origin` no longer keeps the inserts before `first` that the receiver still needs.
+    Resync {
+        origin: u64,
+        first: u64,
+    },
+    /// The chunks at `missing` of snapshot `id`, or all of a new snapshot when `id` is unknown.
+    SnapshotRequest {
+        id: u64,
+        missing: Vec<u32>,
+    },
+    SnapshotChunk(Chunk),
+}
+
+/// Piece `index` of the `count` a snapshot is sent in.
+#[derive(Debug, Clone, PartialEq)]
+struct Chunk {
+    id: u64,
+    index: u32,
+    count: u32,
+    data: Vec<u8>,
+}
+
+/// Every live key of a node with its version, tombstones for the versioned keys it no longer
+/// holds, and how far it had applied each origin's inserts.
+#[derive(Debug, PartialEq)]
+struct Snapshot {
+    applied: Vec<(u64, u64)>,
+    writes: Vec<Write>,
+}
+
+/// A snapshot being fetched, from whichever peer answered first.
+#[derive(Default)]
+struct CatchUp {
+    source: Option<(SocketAddr, u64)>,
+    chunks: Vec<Option<Vec<u8>>>,
+    stalled: u32,
+    /// No peer answered before the last attempt stalled, as when every other node is down.
+    unanswered: bool,
+}
+
+/// One node of a replicated group of UDP stores.
+///
+/// Each node numbers its own inserts and sends them to every peer, again on each retransmit
+/// tick until the peer reports having applied them; peers apply each origin's inserts in
+/// order. Concurrent writes to a key settle on the one with the highest [`Version`] everywhere.
+/// A node starts by fetching a peer's snapshot, and does so again whenever it has fallen
+/// further behind than an origin's log reaches.
+pub struct Replica {
+    socket: UdpSocket,
+    peers: Vec<SocketAddr>,
+    /// Random per start, so a restarted node's inserts never reuse its last run's numbers.
+    id: u64,
+    clock: u64,
+    /// Number of the last own insert.
+    seq: u64,
+    /// The newest `log_capacity` own inserts, oldest first.
+    log: VecDeque<Write>,
+    log_capacity: usize,
+    retransmit_interval: Duration,
+    /// How far each peer has applied this node's inserts.
+    acked: HashMap<SocketAddr, u64>,
+    /// How far this node has applied each origin's inserts.
+    applied: HashMap<u64, u64>,
+    /// Kept for keys after they are evicted or expire, so a late retransmit of an older write
+    /// cannot bring one back on this node alone, until [`Replica::sweep`] forgets them.
+    versions: Versions,
+    /// Versioned keys storage did not hold at the last sweep, with their version then.
+    absent: Versions,
+    last_sweep: Instant,
+    catch_up: Option<CatchUp>,
+    served: VecDeque<Served>,
+}
+
+/// The chunks of a snapshot built for a peer to fetch.
+struct Served {
+    id: u64,
+    built: Instant,
+    chunks: Vec<Vec<u8>>,
+}
+
+impl Replica {
+    pub async fn bind(config: &ReplicationConfig) -> anyhow::Result<Self> {
+        let socket = UdpSocket::bind(&config.bind)
+            .await
+            .with_context(|| format!("binding replication socket {}", config.bind))?;
+        Self::with_socket(socket, config).await
+    }
+
+    /// Replicates over `socket` instead of binding `config.bind`.
+    pub async fn with_socket(
+        socket: UdpSocket,
+        config: &ReplicationConfig,
+    ) -> anyhow::Result<Self> {
+        let mut peers = vec![];
+        for peer in &config.peers {
+            let addr = lookup_host(peer)
+                .await?
+                .next()
+                .with_context(|| format!("peer {} has no address", peer))?;
+            peers.push(addr);
+        }
+        Ok(Self {
+            socket,
+            catch_up: (!peers.is_empty()).then(CatchUp::default),
+            peers,
+            id: rand::random(),
+            clock: 0,
+            seq: 0,
+            log: VecDeque::new(),
+            log_capacity: config.log_capacity(),
+            retransmit_interval: config.retransmit_interval(),
+            acked: HashMap::new(),
+            applied: HashMap::new(),
+            versions: HashMap::new(),
+            absent: HashMap::new(),
+            last_sweep: Instant::now(),
+            served: VecDeque::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn retransmit_interval(&self) -> Duration {
+        self.retransmit_interval
+    }
+
+    pub async fn recv_from(&self, buf: &mut [u8]) -> io::Result<(usize, SocketAddr)> {
+        self.socket.recv_from(buf).await
+    }
+
+    /// Takes over the versions a WAL replayed, so inserts made from here on outrank all of them.
+    pub(super) fn restore(&mut self, versions: Versions) {
+        let clock = versions.values().map(|version| version.clock).max();
+        self.clock = self.clock.max(clock.unwrap_or(0));
+        self.versions = versions;
+    }
+
+    pub(super) fn versions(&self) -> &Versions {
+        &self.versions
+    }
+
+    /// Whether client inserts can be versioned yet. Not while a snapshot is being fetched, as
+    /// they could not outrank writes in it that this node has not seen, unless no peer answered
+    /// the last attempt at all.
+    pub(super) fn accepts_writes(&self) -> bool {
+        self.catch_up
+            .as_ref()
+            .is_none_or(|catch_up| catch_up.unanswered && catch_up.source.is_none())
+    }
+
+    /// Versions an insert a client made on this node, above every write seen so far.
+    pub(super) fn next_version(&mut self, key: &[u8]) -> Version {
+        self.clock += 1;
+        let version = Version {
+            clock: self.clock,
+            node: self.id,
+        };
+        self.versions.insert(key.to_vec(), version);
+        version
+    }
+
+    /// Sends an insert a client made on this node, already applied and logged locally with a
+    /// version from [`Replica::next_version`], to every peer.
+    pub(super) async fn record(&mut self, write: Write) {
+        self.seq += 1;
+        if self.log.len() == self.log_capacity {
+            self.log.pop_front();
+        }
+        self.log.push_back(write.clone());
+        let insert = Message::Insert {
+            origin: self.id,
+            seq: self.seq,
+            write,
+        };
+        for &peer in &self.peers {
+            self.send(&insert, peer).await;
+        }
+    }
+
+    /// Handles one datagram from a peer. Inserts it brings are applied to `storage` and, with
+    /// one, logged to `wal`; only a failure to log is an error. Datagrams from any address not
+    /// configured as a peer are dropped.
+    pub async fn handle(
+        &mut self,
+        datagram: &[u8],
+        from: SocketAddr,
+        storage: &ShardedStorage,
+        wal: &mut Option<Wal>,
+    ) -> anyhow::Result<()> {
+        if !self.peers.contains(&from) {
+            debug!(%from, len = datagram.len(), "Dropping datagram from a non-peer");
+            return Ok(());
+        }
+        let Some(message) = Message::decode(datagram, Instant::now()) else {
+            warn!(peer = %from, len = datagram.len(), "Dropping malformed replication datagram");
+            return Ok(());
+        };
+        match message {
+            Message::Insert { origin, seq, write } => {
+                let applied = self.applied.entry(origin).or_default();
+                // Anything out of order is dropped; the reply tells the origin where to resume
+                if seq == *applied + 1 {
+                    *applied = seq;
+                    self.apply(write, storage, wal).await?;
+                }
+                self.send(&Message::Applied(self.applied_list()), from)
+                    .await;
+            }
+            Message::Applied(applied) => {
+                let acked = applied
+                    .iter()
+                    .find(|(origin, _)| *origin == self.id)
+                    .map_or(0, |(_, seq)| *seq);
+                self.acked.insert(from, acked);
+            }
+            Message::Resync { origin, first } => {
+                let applied = self.applied.get(&origin).copied().unwrap_or(0);
+                if applied + 1 < first && self.catch_up.is_none() {
+                    info!(peer = %from, applied, first, "Fell behind a peer's log, catching up");
+                    self.catch_up = Some(CatchUp::default());
+                    self.request_snapshot().await;
+                }
+            }
+            Message::SnapshotRequest { id, missing } => {
+                self.serve_snapshot(id, missing, from, storage).await;
+            }
+            Message::SnapshotChunk(chunk) => {
+                self.receive_chunk(chunk, from, storage, wal).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reports progress to every peer, sends each the inserts it has not applied yet, chases a
+    /// snapshot that is still incomplete, and now and then forgets old tombstones.
+    pub async fn tick(&mut self, storage: &ShardedStorage) {
+        let now = Instant::now();
+        if now - self.last_sweep >= self.tombstone_horizon() {
+            self.sweep(storage);
+            self.last_sweep = now;
+        }
+
+        let applied = Message::Applied(self.applied_list());
+        let first = self.seq + 1 - self.log.len() as u64;
+        for &peer in &self.peers {
+            self.send(&applied, peer).await;
+            let acked = self.acked.get(&peer).copied().unwrap_or(0);
+            if acked + 1 < first {
+                let resync = Message::Resync {
+                    origin: self.id,
+                    first,
+                };
+                self.send(&resync, peer).await;
+                continue;
+            }
+            let unacked = self.log.iter().skip((acked + 1 - first) as usize);
+            for (seq, write) in (acked + 1..).zip(unacked).take(RETRANSMIT_BATCH) {
+                let insert = Message::Insert {
+                    origin: self.id,
+                    seq,
+                    write: write.clone(),
+                };
+                self.send(&insert, peer).await;
+            }
+        }
+
+        if let Some(catch_up) = &mut self.catch_up {
+            catch_up.stalled += 1;
+            if catch_up.stalled > MAX_STALLED_TICKS {
+                *catch_up = CatchUp {
+                    unanswered: catch_up.source.is_none(),
+                    ..CatchUp::default()
+                };
+            }
+        }
+        self.request_snapshot().await;
+    }
+
+    /// How long a key must have been missing from storage before its version is forgotten. An
+    /// insert is retransmitted every tick until its peer applies it, and after `log_capacity`
+    /// newer ones a lagging peer fetches a snapshot instead, so long before this any older write
+    /// to the key has reached every node that was up. One that shows up later anyway, say after
+    /// a long partition, is applied as new.
+    fn tombstone_horizon(&self) -> Duration {
+        let ticks = u32::try_from(self.log_capacity).unwrap_or(u32::MAX);
+        self.retransmit_interval.saturating_mul(ticks)
+    }
+
+    /// Forgets the versions of keys missing from storage at this sweep and the last without
+    /// having been written in between, so `versions` stays in proportion to what storage holds.
+    fn sweep(&mut self, storage: &ShardedStorage) {
+        let previous = std::mem::take(&mut self.absent);
+        let mut absent = Versions::new();
+        let before = self.versions.len();
+        self.versions.retain(|key, version| {
+            if storage.contains(key) {
+                return true;
+            }
+            if previous.get(key) == Some(version) {
+                return false;
+            }
+            absent.insert(key.clone(), *version);
+            true
+        });
+        self.absent = absent;
+        let forgotten = before - self.versions.len();
+        if forgotten > 0 {
+            debug!(
+                forgotten,
+                tombstones = self.absent.len(),
+                "Forgot old tombstones"
+            );
+        }
+    }
+
+    async fn apply(
+        &mut self,
+        write: Write,
+        storage: &ShardedStorage,
+        wal: &mut Option<Wal>,
+    ) -> anyhow::Result<()> {
+        self.clock = self.clock.max(write.version.clock);
+        let current = self.versions.get(&write.key).copied().unwrap_or_default();
+        if write.version <= current {
+            return Ok(());
+        }
+        self.versions.insert(write.key.clone(), write.version);
+        if write.is_tombstone(Instant::now()) {
+            storage.remove(&write.key);
+        } else {
+            storage.insert(write.key.clone(), write.value.clone(), write.expires_at);
+        }
+        if let Some(wal) = wal {
+            wal.append(storage, &self.versions, &write).await?;
+        }
+        Ok(())
+    }
+
+    async fn request_snapshot(&self) {
+        let Some(catch_up) = &self.catch_up else {
+            return;
+        };
+        match catch_up.source {
+            Some((peer, id)) => {
+                let missing = (0..)
+                    .zip(&catch_up.chunks)
+                    .filter(|(_, chunk)| chunk.is_none())
+                    .map(|(index, _)| index)
+                    .take(MAX_MISSING)
+                    .collect();
+                self.send(&Message::SnapshotRequest { id, missing }, peer)
+                    .await;
+            }
+            None => {
+                let request = Message::SnapshotRequest {
+                    id: 0,
+                    missing: vec![],
+                };
+                for &peer in &self.peers {
+                    self.send(&request, peer).await;
+                }
+            }
+        }
+    }
+
+    /// Sends the chunks of snapshot `id` at `missing`, or all of one for an unknown `id`. That
+    /// is the newest snapshot when built within the last retransmit interval, so requests
+    /// cannot make a node build one per datagram.
+    async fn serve_snapshot(
+        &mut self,
+        id: u64,
+        missing: Vec<u32>,
+        to: SocketAddr,
+        storage: &ShardedStorage,
+    ) {
+        let now = Instant::now();
+        let (served, wanted) = match self.served.iter().position(|served| served.id == id) {
+            Some(known) => (&self.served[known], missing),
+            None => {
+                let recent = self
+                    .served
+                    .back()
+                    .is_some_and(|served| now - served.built < self.retransmit_interval);
+                if !recent {
+                    let snapshot = self.snapshot(storage, now);
+                    let chunks = snapshot.chunks(CHUNK_LEN).map(<[u8]>::to_vec).collect();
+                    if self.served.len() == SERVED_SNAPSHOTS {
+                        self.served.pop_front();
+                    }
+                    self.served.push_back(Served {
+                        id: rand::random::<u64>().max(1),
+                        built: now,
+                        chunks,
+                    });
+                }
+                let served = self.served.back().unwrap();
+                debug!(peer = %to, id = served.id, chunks = served.chunks.len(), recent, "Serving a snapshot");
+                (served, (0..served.chunks.len() as u32).collect())
+            }
+        };
+        let (id, count) = (served.id, served.chunks.len() as u32);
+        for index in wanted {
+            if let Some(data) = served.chunks.get(index as usize) {
+                let chunk = Message::SnapshotChunk(Chunk {
+                    id,
+                    index,
+                    count,
+                    data: data.clone(),
+                });
+                self.send(&chunk, to).await;
+            }
+        }
+    }
+
+    async fn receive_chunk(
+        &mut self,
+        chunk: Chunk,
+        from: SocketAddr,
+        storage: &ShardedStorage,
+        wal: &mut Option<Wal>,
+    ) -> anyhow::Result<()> {
+        let Some(catch_up) = &mut self.catch_up else {
+            return Ok(());
+        };
+        let Chunk {
+            id,
+            index,
+            count,
+            data,
+        } = chunk;
+        match catch_up.source {
+            Some((peer, current)) if peer == from && current == id => {}
+            // A later answer to the first request
+            Some((peer, _)) if peer != from => return Ok(()),
+            // The first answer, or a new snapshot from a peer that forgot the one being fetched
+            _ => {
+                catch_up.source = Some((from, id));
+                catch_up.chunks = vec![None; count as usize];
+            }
+        }
+        if catch_up.chunks.len() != count as usize || index >= count {
+            return Ok(());
+        }
+        catch_up.chunks[index as usize] = Some(data);
+        catch_up.stalled = 0;
+        if catch_up.chunks.iter().any(Option::is_none) {
+            return Ok(());
+        }
+
+        let chunks = self.catch_up.take().unwrap().chunks;
+        let snapshot: Vec<u8> = chunks.into_iter().flatten().flatten().collect();
+        let Some(Snapshot { applied, writes }) = Snapshot::decode(&snapshot, Instant::now()) else {
+            warn!(peer = %from, "Malformed snapshot, asking again");
+            self.catch_up = Some(CatchUp::default());
+            return Ok(());
+        };
+        for (origin, seq) in applied {
+            if origin != self.id {
+                let applied = self.applied.entry(origin).or_default();
+                *applied = (*applied).max(seq);
+            }
+        }
+        let keys = writes.len();
+        for write in writes {
+            self.apply(write, storage, wal).await?;
+        }
+        info!(peer = %from, keys, "Caught up from a peer's snapshot");
+        // Lets origins resume from here without waiting for the next tick
+        let applied = Message::Applied(self.applied_list());
+        for &peer in &self.peers {
+            self.send(&applied, peer).await;
+        }
+        Ok(())
+    }
+
+    fn applied_list(&self) -> Vec<(u64, u64)> {
+        self.applied
+            .iter()
+            .map(|(&origin, &seq)| (origin, seq))
+            .chain([(self.id, self.seq)])
+            .collect()
+    }
+
+    /// Every live key with its version and a tombstone for every other versioned key, and how
+    /// far each origin's inserts are applied.
+    fn snapshot(&self, storage: &ShardedStorage, now: Instant) -> Vec<u8> {
+        let mut writes = vec![];
+        let mut count = 0u32;
+        let mut live = HashSet::new();
+        storage.for_each(|key, value, expires_at| {
+            if expires_at.is_none_or(|expires_at| expires_at > now) {
+                let version = self.versions.get(key).copied().unwrap_or_default();
+                put_write(&mut writes, version, expires_at, key, value, now);
+                live.insert(key.to_vec());
+                count += 1;
+            }
+        });
+        for (key, &version) in &self.versions {
+            if !live.contains(key) {
+                put_write(&mut writes, version, Some(now), key, b"", now);
+                count += 1;
+            }
+        }
+        let mut bytes = vec![];
+        put_applied(&mut bytes, &self.applied_list());
+        bytes.extend(count.to_be_bytes());
+        bytes.extend(writes);
+        bytes
+    }
+
+    async fn send(&self, message: &Message, to: SocketAddr) {
+        if let Err(e) = self
+            .socket
+            .send_to(&message.encode(Instant::now()), to)
+            .await
+        {
+            warn!(peer = %to, "Sending to peer failed: {}", e);
+        }
+    }
+}
+
+impl Message {
+    fn encode(&self, now: Instant) -> Vec<u8> {
+        let mut bytes = vec![];
+        match self {
+            Message::Insert { origin, seq, write } => {
+                bytes.push(INSERT);
+                bytes.extend(origin.to_be_bytes());
+                bytes.extend(seq.to_be_bytes());
+                put_write(
+                    &mut bytes,
+                    write.version,
+                    write.expires_at,
+                    &write.key,
+                    &write.value,
+                    now,
+                );
+            }
+            Message::Applied(applied) => {
+                bytes.push(APPLIED);
+                put_applied(&mut bytes, applied);
+            }
+            Message::Resync { origin, first } => {
+                bytes.push(RESYNC);
+                bytes.extend(origin.to_be_bytes());
+                bytes.extend(first.to_be_bytes());
+            }
+            Message::SnapshotRequest { id, missing } => {
+                bytes.push(SNAPSHOT_REQUEST);
+                bytes.extend(id.to_be_bytes());
+                bytes.extend((missing.len() as u32).to_be_bytes());
+                for index in missing {
+                    bytes.extend(index.to_be_bytes());
+                }
+            }
+            Message::SnapshotChunk(Chunk {
+                id,
+                index,
+                count,
+                data,
+            }) => {
+                bytes.push(SNAPSHOT_CHUNK);
+                bytes.extend(id.to_be_bytes());
+                bytes.extend(index.to_be_bytes());
+                bytes.extend(count.to_be_bytes());
+                bytes.extend(data);
+            }
+        }
+        bytes
+    }
+
+    fn decode(bytes: &[u8], now: Instant) -> Option<Self> {
+        let mut reader = Reader(bytes);
+        let message = match reader.u8()? {
+            INSERT => Message::Insert {
+                origin: reader.u64()?,
+                seq: reader.u64()?,
+                write: reader.write(now)?,
+            },
+            APPLIED => Message::Applied(reader.applied()?),
+            RESYNC => Message::Resync {
+                origin: reader.u64()?,
+                first: reader.u64()?,
+            },
+            SNAPSHOT_REQUEST => {
+                let id = reader.u64()?;
+                let count = reader.u32()?;
+                let missing = (0..count).map(|_| reader.u32()).collect::<Option<_>>()?;
+                Message::SnapshotRequest { id, missing }
+            }
+            SNAPSHOT_CHUNK => Message::SnapshotChunk(Chunk {
+                id: reader.u64()?,
+                index: reader.u32()?,
+                count: reader.u32()?,
+                data: reader.take(reader.0.len())?.to_vec(),
+            }),
+            _ => return None,
+        };
+        reader.0.is_empty().then_some(message)
+    }
+}
+
+fn put_applied(bytes: &mut Vec<u8>, applied: &[(u64, u64)]) {
+    bytes.extend((applied.len() as u32).to_be_bytes());
+    for (origin, seq) in applied {
+        bytes.extend(origin.to_be_bytes());
+        bytes.extend(seq.to_be_bytes());
+    }
+}
+
+fn put_write(
+    bytes: &mut Vec<u8>,
+    version: Version,
+    expires_at: Option<Instant>,
+    key: &[u8],
+    value: &[u8],
+    now: Instant,
+) {
+    let ttl_ms = expires_at.map_or(NO_TTL, |expires_at| {
+        (expires_at.saturating_duration_since(now).as_millis() as u64).min(NO_TTL - 1)
+    });
+    bytes.extend(version.clock.to_be_bytes());
+    bytes.extend(version.node.to_be_bytes());
+    bytes.extend(ttl_ms.to_be_bytes());
+    for field in [key, value] {
+        bytes.extend((field.len() as u32).to_be_bytes());
+        bytes.extend(field);
+    }
+}
+
+impl Snapshot {
+    fn decode(bytes: &[u8], now: Instant) -> Option<Self> {
+        let mut reader = Reader(bytes);
+        let applied = reader.applied()?;
+        let count = reader.u32()?;
+        let writes = (0..count)
+            .map(|_| reader.write(now))
+            .collect::<Option<_>>()?;
+        reader.0.is_empty().then_some(Self { applied, writes })
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        let (taken, rest) = self.0.split_at_checked(len)?;
+        self.0 = rest;
+        Some(taken)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn field(&mut self) -> Option<Vec<u8>> {
+        let len = self.u32()? as usize;
+        Some(self.take(len)?.to_vec())
+    }
+
+    fn applied(&mut self) -> Option<Vec<(u64, u64)>> {
+        let count = self.u32()?;
+        (0..count)
+            .map(|_| Some((self.u64()?, self.u64()?)))
+            .collect()
+    }
+
+    fn write(&mut self, now: Instant) -> Option<Write> {
+        let version = Version {
+            clock: self.u64()?,
+            node: self.u64()?,
+        };
+        let ttl_ms = self.u64()?;
+        Some(Write {
+            version,
+            expires_at: (ttl_ms != NO_TTL).then(|| now + Duration::from_millis(ttl_ms)),
+            key: self.field()?,
+            value: self.field()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(clock: u64, key: &str, ttl_ms: Option<u64>, now: Instant) -> Write {
+        Write {
+            version: Version { clock, node: 7 },
+            expires_at: ttl_ms.map(|ms| now + Duration::from_millis(ms)),
+            key: key.into(),
+            value: format!("{}-value", key).into(),
+        }
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        let now = Instant::now();
+        let messages = [
+            Message::Insert {
+                origin: 1,
+                seq: 2,
+                write: write(3, "key=", Some(1500), now),
+            },
+            Message::Insert {
+                origin: u64::MAX,
+                seq: 1,
+                write: write(1, "", None, now),
+            },
+            Message::Applied(vec![(1, 10), (2, 0)]),
+            Message::Applied(vec![]),
+            Message::Resync {
+                origin: 4,
+                first: 100,
+            },
+            Message::SnapshotRequest {
+                id: 0,
+                missing: vec![],
+            },
+            Message::SnapshotRequest {
+                id: 9,
+                missing: vec![0, 3],
+            },
+            Message::SnapshotChunk(Chunk {
+                id: 9,
+                index: 3,
+                count: 4,
+                data: b"\x00\xff".to_vec(),
+            }),
+        ];
+        for message in messages {
+            let bytes = message.encode(now);
+            assert!(bytes.len() <= MAX_PEER_DATAGRAM);
+            assert_eq!(Message::decode(&bytes, now), Some(message.clone()));
+            // Every strict prefix, and anything trailing, is rejected
+            if !matches!(message, Message::SnapshotChunk(_)) {
+                for len in 0..bytes.len() {
+                    assert_eq!(Message::decode(&bytes[..len], now), None, "{:?}", message);
+                }
+                assert_eq!(Message::decode(&[bytes, vec![0]].concat(), now), None);
+            }
+        }
+        assert_eq!(Message::decode(&[0], now), None);
+    }
+
+    #[test]
+    fn test_snapshot_round_trip() {
+        let now = Instant::now();
+        let mut bytes = vec![];
+        put_applied(&mut bytes, &[(1, 5)]);
+        bytes.extend(2u32.to_be_bytes());
+        let writes = [write(4, "a", None, now), write(2, "b", Some(60_000), now)];
+        for write in &writes {
+            put_write(
+                &mut bytes,
+                write.version,
+                write.expires_at,
+                &write.key,
+                &write.value,
+                now,
+            );
+        }
+        assert_eq!(
+            Snapshot::decode(&bytes, now),
+            Some(Snapshot {
+                applied: vec![(1, 5)],
+                writes: writes.to_vec(),
+            })
+        );
+        assert_eq!(Snapshot::decode(&bytes[..bytes.len() - 1], now), None);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_ttl_churn_keeps_versions_bounded() {
+        let mut config = ReplicationConfig::new("127.0.0.1:0");
+        config.retransmit_ms = Some(1);
+        config.log_capacity = Some(4);
+        let mut replica = Replica::bind(&config).await.unwrap();
+        let storage = ShardedStorage::default();
+        for round in 0..100 {
+            for i in 0..10 {
+                let key = format!("key-{}-{}", round, i).into_bytes();
+                let write = Write {
+                    version: replica.next_version(&key),
+                    expires_at: Some(Instant::now() + Duration::from_millis(1)),
+                    key,
+                    value: b"value".to_vec(),
+                };
+                storage.insert(write.key.clone(), write.value.clone(), write.expires_at);
+                replica.record(write).await;
+            }
+            tokio::time::advance(Duration::from_millis(2)).await;
+            storage.reap(Instant::now());
+            replica.tick(&storage).await;
+        }
+        // Each round's keys are gone by its tick, and forgotten at the second sweep after that
+        assert!(
+            replica.versions().len() <= 80,
+            "{}",
+            replica.versions().len()
+        );
+
+        // A key that is still held keeps its version however long ago it was written
+        let key = b"kept".to_vec();
+        let version = replica.next_version(&key);
+        storage.insert(key.clone(), b"value".to_vec(), None);
+        for _ in 0..10 {
+            tokio::time::advance(Duration::from_millis(4)).await;
+            replica.tick(&storage).await;
+        }
+        assert_eq!(replica.versions().get(&key), Some(&version));
+        assert_eq!(replica.versions().len(), 1);
+    }
+
+    #[test]
+    fn test_versions_order_by_clock_then_node() {
+        let version = |clock, node| Version { clock, node };
+        assert!(version(2, 1) > version(1, 9));
+        assert!(version(2, 2) > version(2, 1));
+        assert!(version(1, 0) > Version::default());
+    }
+}
@@ -0,0 +1,213 @@
+use std::{fmt, time::Duration};
+
+/// Largest datagram the protocol allows, requests and responses alike.
+pub const MAX_DATAGRAM: usize = 1000;
+
+/// The key clients may read but never write.
+pub const VERSION_KEY: &[u8] = b"version";
+
+/// Key that marks an insert with a TTL: `@ttl=<ms> key=value`.
+pub const TTL_KEY: &[u8] = b"@ttl";
+
+/// One datagram's request. Keys and values are raw bytes: nothing requires them to be UTF-8.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Request<'a> {
+    /// Everything before the first `=` is the key and everything after it the value, so the
+    /// value may hold more `=`s. Either may be empty. `@ttl=<ms> key=value` inserts `key` with
+    /// a TTL; anything else starting `@ttl=` is a plain insert to [`TTL_KEY`].
+    Insert {
+        key: &'a [u8],
+        value: &'a [u8],
+        ttl: Option<Duration>,
+    },
+    /// A datagram without `=`, the whole of it being the key.
+    Retrieve { key: &'a [u8] },
+    /// Retrieve of [`VERSION_KEY`].
+    Version,
+    /// Insert to [`VERSION_KEY`], which is ignored.
+    VersionWrite,
+}
+
+/// A datagram longer than [`MAX_DATAGRAM`], dropped rather than read in part.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Oversize(pub usize);
+
+impl fmt::Display for Oversize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "datagram of {} bytes is over the {} byte limit",
+            self.0, MAX_DATAGRAM
+        )
+    }
+}
+
+impl std::error::Error for Oversize {}
+
+impl<'a> Request<'a> {
+    pub fn parse(datagram: &'a [u8]) -> Result<Self, Oversize> {
+        if datagram.len() > MAX_DATAGRAM {
+            return Err(Oversize(datagram.len()));
+        }
+        let request = match split_insert(datagram) {
+            Some((key, value)) if key == TTL_KEY => match parse_ttl(value) {
+                Some((_, VERSION_KEY, _)) => Request::VersionWrite,
+                Some((ttl, key, value)) => Request::Insert {
+                    key,
+                    value,
+                    ttl: Some(ttl),
+                },
+                None => Request::Insert {
+                    key,
+                    value,
+                    ttl: None,
+                },
+            },
+            Some((VERSION_KEY, _)) => Request::VersionWrite,
+            Some((key, value)) => Request::Insert {
+                key,
+                value,
+                ttl: None,
+            },
+            None if datagram == VERSION_KEY => Request::Version,
+            None => Request::Retrieve { key: datagram },
+        };
+        Ok(request)
+    }
+}
+
+fn split_insert(bytes: &[u8]) -> Option<(&[u8], &[u8])> {
+    let i = bytes.iter().position(|&byte| byte == b'=')?;
+    Some((&bytes[..i], &bytes[i + 1..]))
+}
+
+/// Splits `<ms> key=value`. Only ASCII digits that fit a `u64` count as a TTL, and the rest
+/// must be an insert.
+fn parse_ttl(bytes: &[u8]) -> Option<(Duration, &[u8], &[u8])> {
+    let space = bytes.iter().position(|&byte| byte == b' ')?;
+    let digits = &bytes[..space];
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    let ms = std::str::from_utf8(digits).ok()?.parse().ok()?;
+    let (key, value) = split_insert(&bytes[space + 1..])?;
+    Some((Duration::from_millis(ms), key, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn insert<'a>(key: &'a [u8], value: &'a [u8]) -> Request<'a> {
+        Request::Insert {
+            key,
+            value,
+            ttl: None,
+        }
+    }
+
+    fn insert_with_ttl<'a>(key: &'a [u8], value: &'a [u8], ms: u64) -> Request<'a> {
+        Request::Insert {
+            key,
+            value,
+            ttl: Some(Duration::from_millis(ms)),
+        }
+    }
+
+    #[test]
+    fn test_first_equals_splits() {
+        assert_eq!(Request::parse(b"foo=bar"), Ok(insert(b"foo", b"bar")));
+        assert_eq!(
+            Request::parse(b"foo=bar=baz"),
+            Ok(insert(b"foo", b"bar=baz"))
+        );
+        assert_eq!(Request::parse(b"foo==="), Ok(insert(b"foo", b"==")));
+    }
+
+    #[test]
+    fn test_empty_keys_and_values() {
+        assert_eq!(Request::parse(b"foo="), Ok(insert(b"foo", b"")));
+        assert_eq!(Request::parse(b"=foo"), Ok(insert(b"", b"foo")));
+        assert_eq!(Request::parse(b"="), Ok(insert(b"", b"")));
+        assert_eq!(Request::parse(b""), Ok(Request::Retrieve { key: b"" }));
+    }
+
+    #[test]
+    fn test_version_is_read_only() {
+        assert_eq!(Request::parse(b"version"), Ok(Request::Version));
+        assert_eq!(Request::parse(b"version=hacked"), Ok(Request::VersionWrite));
+        assert_eq!(Request::parse(b"version="), Ok(Request::VersionWrite));
+        // Only the exact key is reserved
+        assert_eq!(
+            Request::parse(b"Version"),
+            Ok(Request::Retrieve { key: b"Version" })
+        );
+        assert_eq!(Request::parse(b"versions=1"), Ok(insert(b"versions", b"1")));
+    }
+
+    #[test]
+    fn test_binary_keys() {
+        assert_eq!(
+            Request::parse(b"\xff\x00key=\xfe\n"),
+            Ok(insert(b"\xff\x00key", b"\xfe\n"))
+        );
+        assert_eq!(
+            Request::parse(b"\xc3\x28"),
+            Ok(Request::Retrieve { key: b"\xc3\x28" })
+        );
+    }
+
+    #[test]
+    fn test_ttl() {
+        assert_eq!(
+            Request::parse(b"@ttl=1500 foo=bar"),
+            Ok(insert_with_ttl(b"foo", b"bar", 1500))
+        );
+        assert_eq!(
+            Request::parse(b"@ttl=0 foo=a b=c"),
+            Ok(insert_with_ttl(b"foo", b"a b=c", 0))
+        );
+        assert_eq!(
+            Request::parse(b"@ttl=10 =x"),
+            Ok(insert_with_ttl(b"", b"x", 10))
+        );
+        assert_eq!(
+            Request::parse(b"@ttl=10 version=x"),
+            Ok(Request::VersionWrite)
+        );
+        // Reading the key back needs no special syntax
+        assert_eq!(
+            Request::parse(b"@ttl"),
+            Ok(Request::Retrieve { key: b"@ttl" })
+        );
+    }
+
+    #[test]
+    fn test_malformed_ttl_is_plain_insert() {
+        for datagram in [
+            &b"@ttl=5"[..],
+            b"@ttl=5 foo",
+            b"@ttl= foo=bar",
+            b"@ttl=-5 foo=bar",
+            b"@ttl=5s foo=bar",
+            b"@ttl=99999999999999999999 foo=bar",
+        ] {
+            assert_eq!(
+                Request::parse(datagram),
+                Ok(insert(b"@ttl", &datagram[5..])),
+                "{}",
+                String::from_utf8_lossy(datagram)
+            );
+        }
+    }
+
+    #[test]
+    fn test_oversize_dropped() {
+        let limit = [b'a'; MAX_DATAGRAM];
+        assert!(Request::parse(&limit).is_ok());
+        assert_eq!(
+            Request::parse(&[b'a'; MAX_DATAGRAM + 1]),
+            Err(Oversize(MAX_DATAGRAM + 1))
+        );
+    }
+}
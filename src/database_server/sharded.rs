@@ -0,0 +1,191 @@
+use std::{
+    hash::{BuildHasher, RandomState},
+    iter::Sum,
+    sync::{Mutex, MutexGuard},
+};
+
+use tokio::time::Instant;
+
+use super::Storage;
+
+/// Shards a byte limit is not split below; smaller limits get fewer shards, down to one.
+const MIN_SHARD_BYTES: usize = 64 * 1024;
+
+/// [`Storage`] split by key hash into shards, each behind its own lock, so tasks touching
+/// different keys rarely wait on each other. Locks are only ever held for one map operation
+/// and never across an await. Reads lock their shard too, since they move the key to the
+/// front of the shard's LRU order; nothing here is lock-free.
+///
+/// A byte limit is divided evenly between the shards and least recently used keys are evicted
+/// per shard, which approximates LRU across the whole store once there are enough keys.
+pub struct ShardedStorage {
+    shards: Box<[Mutex<Storage>]>,
+    hasher: RandomState,
+}
+
+impl Default for ShardedStorage {
+    fn default() -> Self {
+        Self::new(1, None)
+    }
+}
+
+impl ShardedStorage {
+    pub fn new(shards: usize, max_bytes: Option<usize>) -> Self {
+        let shards = match max_bytes {
+            Some(max_bytes) => shards.min(max_bytes / MIN_SHARD_BYTES),
+            None => shards,
+        }
+        .max(1);
+        let shard_bytes = max_bytes.map(|max_bytes| max_bytes.div_ceil(shards));
+        Self {
+            shards: (0..shards)
+                .map(|_| Mutex::new(Storage::new(shard_bytes)))
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn insert(&self, key: Vec<u8>, value: Vec<u8>, expires_at: Option<Instant>) {
+        self.shard(&key).insert(key, value, expires_at);
+    }
+
+    pub fn remove(&self, key: &[u8]) {
+        self.shard(key).remove(key);
+    }
+
+    /// A copy of the live value of `key`, see [`Storage::get`].
+    pub fn get(&self, key: &[u8], now: Instant) -> Option<Vec<u8>> {
+        self.shard(key).get(key, now).map(<[u8]>::to_vec)
+    }
+
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.shard(key).contains(key)
+    }
+
+    /// Reaps every shard in turn and returns how many keys were dropped.
+    pub fn reap(&self, now: Instant) -> usize {
+        self.each_shard(|shard| shard.reap(now))
+    }
+
+    /// Calls `f` with every key, value and expiry, one shard at a time and least recently used
+    /// first within each. Inserts into shards already visited are not seen.
+    pub fn for_each(&self, mut f: impl FnMut(&[u8], &[u8], Option<Instant>)) {
+        for shard in &self.shards {
+            for (key, value, expires_at) in lock(shard).iter() {
+                f(key, value, expires_at);
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.each_shard(|shard| shard.len())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn bytes(&self) -> usize {
+        self.each_shard(|shard| shard.bytes())
+    }
+
+    pub fn expired(&self) -> u64 {
+        self.each_shard(|shard| shard.expired())
+    }
+
+    pub fn evicted(&self) -> u64 {
+        self.each_shard(|shard| shard.evicted())
+    }
+
+    fn shard(&self, key: &[u8]) -> MutexGuard<'_, Storage> {
+        let index = self.hasher.hash_one(key) as usize % self.shards.len();
+        lock(&self.shards[index])
+    }
+
+    /// Sums `f` over the shards, locking one at a time.
+    fn each_shard<T: Sum>(&self, mut f: impl FnMut(&mut Storage) -> T) -> T {
+        self.shards.iter().map(|shard| f(&mut lock(shard))).sum()
+    }
+}
+
+/// Nothing panics with a shard locked, so poisoning cannot leave one half updated.
+fn lock(shard: &Mutex<Storage>) -> MutexGuard<'_, Storage> {
+    shard
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, sync::Arc};
+
+    use super::*;
+
+    #[test]
+    fn test_small_limits_get_fewer_shards() {
+        assert_eq!(ShardedStorage::new(16, None).shard_count(), 16);
+        assert_eq!(ShardedStorage::new(16, Some(6)).shard_count(), 1);
+        assert_eq!(
+            ShardedStorage::new(16, Some(4 * MIN_SHARD_BYTES)).shard_count(),
+            4
+        );
+        assert_eq!(ShardedStorage::new(0, None).shard_count(), 1);
+    }
+
+    #[test]
+    fn test_keys_spread_over_shards() {
+        let storage = ShardedStorage::new(8, None);
+        let now = Instant::now();
+        for n in 0..1_000 {
+            storage.insert(format!("key{}", n).into(), n.to_string().into(), None);
+        }
+        assert_eq!(storage.len(), 1_000);
+        assert_eq!(storage.get(b"key500", now), Some(b"500".to_vec()));
+        let used: HashSet<_> = (0..1_000)
+            .map(|n| storage.hasher.hash_one(format!("key{}", n).as_bytes()) as usize % 8)
+            .collect();
+        assert_eq!(used.len(), 8);
+
+        let mut seen = 0;
+        storage.for_each(|_, _, _| seen += 1);
+        assert_eq!(seen, 1_000);
+    }
+
+    #[test]
+    fn test_limit_split_between_shards() {
+        let storage = ShardedStorage::new(2, Some(2 * MIN_SHARD_BYTES));
+        let value = vec![0; 1024];
+        for n in 0..1_000 {
+            storage.insert(format!("{:04}", n).into(), value.clone(), None);
+        }
+        assert!(storage.bytes() <= 2 * MIN_SHARD_BYTES);
+        assert_eq!(storage.len() as u64 + storage.evicted(), 1_000);
+    }
+
+    #[test]
+    fn test_concurrent_writers() {
+        let storage = Arc::new(ShardedStorage::new(4, None));
+        let threads: Vec<_> = (0..4)
+            .map(|thread| {
+                let storage = Arc::clone(&storage);
+                std::thread::spawn(move || {
+                    for n in 0..250 {
+                        storage.insert(format!("{}-{}", thread, n).into(), vec![1], None);
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        assert_eq!(storage.len(), 1_000);
+        let expected: usize = (0..4)
+            .flat_map(|thread| (0..250).map(move |n| format!("{}-{}", thread, n).len() + 1))
+            .sum();
+        assert_eq!(storage.bytes(), expected);
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::BTreeSet;
+
+use lru::LruCache;
+use tokio::time::Instant;
+
+struct Entry {
+    value: Vec<u8>,
+    expires_at: Option<Instant>,
+}
+
+/// Values by key, both raw bytes as they arrived, in least recently used order.
+///
+/// With a byte limit, inserts that push the total of key and value lengths past it evict the
+/// least recently read or written keys until it fits again. Keys with a TTL disappear once it
+/// has passed: reads never see them, and [`Storage::reap`] frees them.
+pub struct Storage {
+    entries: LruCache<Vec<u8>, Entry>,
+    expiries: BTreeSet<(Instant, Vec<u8>)>,
+    bytes: usize,
+    max_bytes: Option<usize>,
+    expired: u64,
+    evicted: u64,
+}
+
+impl Default for Storage {
+    fn default() -> Self {
+        Self::new(None)
+    }
+}
+
+impl Storage {
+    pub fn new(max_bytes: Option<usize>) -> Self {
+        Self {
+            entries: LruCache::unbounded(),
+            expiries: BTreeSet::new(),
+            bytes: 0,
+            max_bytes,
+            expired: 0,
+            evicted: 0,
+        }
+    }
+
+    /// Sets `key`, replacing its value and TTL. `expires_at: None` keeps it until overwritten
+    /// or evicted.
+    pub fn insert(&mut self, key: Vec<u8>, value: Vec<u8>, expires_at: Option<Instant>) {
+        self.bytes += key.len() + value.len();
+        let expiry = expires_at.map(|expires_at| (expires_at, key.clone()));
+        if let Some((key, old)) = self.entries.push(key, Entry { value, expires_at }) {
+            self.forget(&key, &old);
+        }
+        if let Some(expiry) = expiry {
+            self.expiries.insert(expiry);
+        }
+        while let Some(max_bytes) = self.max_bytes
+            && self.bytes > max_bytes
+            && let Some((key, entry)) = self.entries.pop_lru()
+        {
+            self.forget(&key, &entry);
+            self.evicted += 1;
+        }
+    }
+
+    /// Drops `key` without counting it as expired or evicted.
+    pub fn remove(&mut self, key: &[u8]) {
+        if let Some((key, entry)) = self.entries.pop_entry(key) {
+            self.forget(&key, &entry);
+        }
+    }
+
+    /// The live value of `key`, which now counts as recently used.
+    pub fn get(&mut self, key: &[u8], now: Instant) -> Option<&[u8]> {
+        let expired = match self.entries.peek(key)?.expires_at {
+            Some(expires_at) => expires_at <= now,
+            None => false,
+        };
+        if expired {
+            let (key, entry) = self.entries.pop_entry(key)?;
+            self.forget(&key, &entry);
+            self.expired += 1;
+            return None;
+        }
+        self.entries.get(key).map(|entry| entry.value.as_slice())
+    }
+
+    /// Whether `key` is held, expired or not, without touching its place in the LRU order.
+    pub fn contains(&self, key: &[u8]) -> bool {
+        self.entries.contains(key)
+    }
+
+    /// Drops every key whose TTL has passed by `now` and returns how many there were.
+    pub fn reap(&mut self, now: Instant) -> usize {
+        let mut reaped = 0;
+        while let Some((expires_at, _)) = self.expiries.first()
+            && *expires_at <= now
+        {
+            let (_, key) = self.expiries.pop_first().unwrap();
+            if let Some(entry) = self.entries.pop(&key) {
+                self.bytes -= key.len() + entry.value.len();
+                reaped += 1;
+            }
+        }
+        self.expired += reaped as u64;
+        reaped
+    }
+
+    /// Every key with its value and expiry, expired ones included until reaped. Least recently
+    /// used come first, so inserting them in order rebuilds the same eviction order.
+    pub fn iter(&self) -> impl Iterator<Item = (&[u8], &[u8], Option<Instant>)> {
+        self.entries
+            .iter()
+            .rev()
+            .map(|(key, entry)| (key.as_slice(), entry.value.as_slice(), entry.expires_at))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Total length of all keys and values held.
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    /// Keys dropped because their TTL passed, since startup.
+    pub fn expired(&self) -> u64 {
+        self.expired
+    }
+
+    /// Keys dropped to stay under the byte limit, since startup.
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    /// Releases the accounting of an entry that has left `entries`.
+    fn forget(&mut self, key: &[u8], entry: &Entry) {
+        self.bytes -= key.len() + entry.value.len();
+        if let Some(expires_at) = entry.expires_at {
+            self.expiries.remove(&(expires_at, key.to_vec()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn insert(storage: &mut Storage, key: &str, value: &str, expires_at: Option<Instant>) {
+        storage.insert(key.into(), value.into(), expires_at);
+    }
+
+    #[test]
+    fn test_overwrite_keeps_byte_count() {
+        let mut storage = Storage::default();
+        insert(&mut storage, "foo", "bar", None);
+        insert(&mut storage, "foo", "longer", None);
+        assert_eq!(storage.len(), 1);
+        assert_eq!(storage.bytes(), 9);
+        assert_eq!(storage.get(b"foo", Instant::now()), Some(&b"longer"[..]));
+    }
+
+    #[test]
+    fn test_least_recently_used_evicted() {
+        // Room for three 2-byte entries
+        let mut storage = Storage::new(Some(6));
+        let now = Instant::now();
+        insert(&mut storage, "a", "1", None);
+        insert(&mut storage, "b", "2", None);
+        insert(&mut storage, "c", "3", None);
+        // Reading `a` leaves `b` as the one to go
+        storage.get(b"a", now);
+        insert(&mut storage, "d", "4", None);
+        assert_eq!(storage.get(b"b", now), None);
+        assert!(storage.get(b"a", now).is_some());
+        assert_eq!(
+            (storage.len(), storage.bytes(), storage.evicted()),
+            (3, 6, 1)
+        );
+
+        // A bigger value can push out more than one key
+        insert(&mut storage, "e", "5555", None);
+        assert_eq!(
+            (storage.len(), storage.bytes(), storage.evicted()),
+            (1, 5, 4)
+        );
+    }
+
+    #[test]
+    fn test_expired_keys_unreadable() {
+        let mut storage = Storage::default();
+        let now = Instant::now();
+        insert(
+            &mut storage,
+            "short",
+            "1",
+            Some(now + Duration::from_secs(1)),
+        );
+        insert(
+            &mut storage,
+            "long",
+            "2",
+            Some(now + Duration::from_secs(60)),
+        );
+        insert(&mut storage, "forever", "3", None);
+        assert!(storage.get(b"short", now).is_some());
+
+        let later = now + Duration::from_secs(1);
+        assert_eq!(storage.get(b"short", later), None);
+        assert!(storage.get(b"long", later).is_some());
+        assert_eq!((storage.len(), storage.expired()), (2, 1));
+    }
+
+    #[test]
+    fn test_reap() {
+        let mut storage = Storage::default();
+        let now = Instant::now();
+        for (key, secs) in [("a", 1), ("b", 2), ("c", 3)] {
+            insert(
+                &mut storage,
+                key,
+                "x",
+                Some(now + Duration::from_secs(secs)),
+            );
+        }
+        // Overwriting without a TTL takes `b` out of the reaper's hands
+        insert(&mut storage, "b", "y", None);
+
+        assert_eq!(storage.reap(now + Duration::from_secs(2)), 1);
+        assert_eq!(storage.reap(now + Duration::from_secs(10)), 1);
+        assert_eq!(storage.len(), 1);
+        assert_eq!(storage.bytes(), 2);
+        assert_eq!(storage.expired(), 2);
+        assert_eq!(
+            storage.get(b"b", now + Duration::from_secs(10)),
+            Some(&b"y"[..])
+        );
+    }
+}
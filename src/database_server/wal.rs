@@ -0,0 +1,569 @@
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use tokio::{
+    fs::{self, File, OpenOptions},
+    io::AsyncWriteExt,
+};
+use tracing::{info, warn};
+
+use super::{
+    ShardedStorage,
+    replication::{Version, Versions, Write},
+};
+use crate::config::{FsyncPolicy, WalConfig};
+
+const LOG_FILE: &str = "wal.log";
+const SNAPSHOT_FILE: &str = "snapshot";
+const SNAPSHOT_TMP_FILE: &str = "snapshot.tmp";
+
+/// CRC32 of the rest of the record as a big-endian `u32`, the expiry in milliseconds since the
+/// Unix epoch (0 for none) as a `u64`, the version's clock and node as `u64`s, then key and
+/// value lengths as `u32`s.
+const HEADER_LEN: usize = 36;
+
+/// One insert as kept on disk. Expiries are wall-clock time so they still mean the same moment
+/// after a restart. Stores outside a replicated group log the default version.
+struct Record {
+    key: Vec<u8>,
+    value: Vec<u8>,
+    expires_at: Option<SystemTime>,
+    version: Version,
+}
+
+/// Every insert into the store, appended as one checksummed record, plus a snapshot of the
+/// whole store that the log is folded into every `snapshot_every` inserts. Versioned keys no
+/// longer in the store are kept as already expired records, so their versions survive too.
+///
+/// The snapshot is written beside the old one and renamed over it before the log is emptied, so
+/// a crash at any point leaves a snapshot and log that replay to the last synced insert.
+pub struct Wal {
+    dir: PathBuf,
+    log: File,
+    fsync: FsyncPolicy,
+    fsync_interval: Duration,
+    snapshot_every: usize,
+    /// Inserts in the log since the last snapshot.
+    logged: usize,
+    /// When the log was last synced, if anything has been written since.
+    unsynced_since: Option<Instant>,
+}
+
+impl Wal {
+    /// Opens the log in `config.dir` and replays the snapshot and log found there into
+    /// `storage`. A torn or corrupt record ends the log: it and anything after it are cut off,
+    /// so new records land right after the last good one. Keys whose TTL ran out while the
+    /// store was down are left out. The snapshot holds keys least recently used first and the
+    /// log follows in insert order, so the byte limit evicts as it would have live, except
+    /// that reads since the snapshot no longer count. Returns the versions replayed with it.
+    pub(super) async fn open(
+        config: &WalConfig,
+        storage: &ShardedStorage,
+    ) -> anyhow::Result<(Self, Versions)> {
+        let dir = config.dir.clone();
+        fs::create_dir_all(&dir)
+            .await
+            .with_context(|| format!("failed to create WAL directory {}", dir.display()))?;
+
+        let snapshot_path = dir.join(SNAPSHOT_FILE);
+        let snapshot = read_if_exists(&snapshot_path).await?;
+        let mut versions = Versions::new();
+        let (snapshot_records, snapshot_len) =
+            decode(&snapshot, |record| replay(storage, &mut versions, record));
+        if snapshot_len < snapshot.len() {
+            // Only ever renamed into place once complete and synced
+            anyhow::bail!("snapshot {} is corrupt", snapshot_path.display());
+        }
+
+        let log_path = dir.join(LOG_FILE);
+        let log = read_if_exists(&log_path).await?;
+        let (logged, log_len) = decode(&log, |record| replay(storage, &mut versions, record));
+        let file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&log_path)
+            .await
+            .with_context(|| format!("failed to open WAL {}", log_path.display()))?;
+        if log_len < log.len() {
+            warn!(
+                path = %log_path.display(),
+                bytes = log.len() - log_len,
+                "Dropping torn record at end of WAL"
+            );
+            file.set_len(log_len as u64).await?;
+            file.sync_all().await?;
+        }
+        info!(
+            dir = %dir.display(),
+            snapshot_records,
+            logged,
+            keys = storage.len(),
+            "Replayed WAL"
+        );
+
+        let wal = Self {
+            dir,
+            log: file,
+            fsync: config.fsync,
+            fsync_interval: config.fsync_interval(),
+            snapshot_every: config.snapshot_every(),
+            logged,
+            unsynced_since: None,
+        };
+        Ok((wal, versions))
+    }
+
+    /// Logs an insert already applied to `storage`, compacting once enough have piled up.
+    /// `versions` are those of the keys `storage` no longer holds, and any it does.
+    pub(super) async fn append(
+        &mut self,
+        storage: &ShardedStorage,
+        versions: &Versions,
+        write: &Write,
+    ) -> anyhow::Result<()> {
+        let record = encode(
+            &write.key,
+            &write.value,
+            write.expires_at.map(wall_clock),
+            write.version,
+        );
+        self.log.write_all(&record).await?;
+        self.logged += 1;
+        if self.logged >= self.snapshot_every {
+            return self.snapshot(storage, versions).await;
+        }
+        self.log.flush().await?;
+        let unsynced_since = *self.unsynced_since.get_or_insert_with(Instant::now);
+        match self.fsync {
+            FsyncPolicy::Always => self.sync().await,
+            FsyncPolicy::Interval if unsynced_since.elapsed() >= self.fsync_interval => {
+                self.sync().await
+            }
+            FsyncPolicy::Interval | FsyncPolicy::Never => Ok(()),
+        }
+    }
+
+    /// Syncs anything logged but not yet on disk.
+    pub async fn sync(&mut self) -> anyhow::Result<()> {
+        if self.unsynced_since.take().is_some() {
+            self.log.sync_data().await?;
+        }
+        Ok(())
+    }
+
+    /// Writes all of `storage` as the new snapshot, least recently used keys first, and empties
+    /// the log. Keys in `versions` that `storage` no longer holds go in as tombstones.
+    pub(super) async fn snapshot(
+        &mut self,
+        storage: &ShardedStorage,
+        versions: &Versions,
+    ) -> anyhow::Result<()> {
+        let tmp_path = self.dir.join(SNAPSHOT_TMP_FILE);
+        let mut bytes = vec![];
+        for (key, &version) in versions {
+            if !storage.contains(key) {
+                bytes.extend(encode(key, b"", Some(UNIX_EPOCH), version));
+            }
+        }
+        storage.for_each(|key, value, expires_at| {
+            let version = versions.get(key).copied().unwrap_or_default();
+            bytes.extend(encode(key, value, expires_at.map(wall_clock), version));
+        });
+        let mut tmp = File::create(&tmp_path).await?;
+        tmp.write_all(&bytes).await?;
+        tmp.sync_all().await?;
+        fs::rename(&tmp_path, self.dir.join(SNAPSHOT_FILE)).await?;
+        File::open(&self.dir).await?.sync_all().await?;
+
+        // A crash before this replays the old log over the new snapshot; every key's last logged
+        // value is the one the snapshot holds, so that changes nothing.
+        self.log.set_len(0).await?;
+        self.log.sync_all().await?;
+        self.logged = 0;
+        self.unsynced_since = None;
+        Ok(())
+    }
+}
+
+async fn read_if_exists(path: &Path) -> anyhow::Result<Vec<u8>> {
+    match fs::read(path).await {
+        Ok(bytes) => Ok(bytes),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => Err(e).with_context(|| format!("failed to read {}", path.display())),
+    }
+}
+
+/// Where `expires_at` falls on the wall clock.
+fn wall_clock(expires_at: tokio::time::Instant) -> SystemTime {
+    SystemTime::now() + expires_at.saturating_duration_since(tokio::time::Instant::now())
+}
+
+/// Applies a replayed record. One that has already expired still deletes whatever it replaced,
+/// and its version is kept all the same.
+fn replay(storage: &ShardedStorage, versions: &mut Versions, record: Record) {
+    if record.version != Version::default() {
+        versions.insert(record.key.clone(), record.version);
+    }
+    let expires_at = match record.expires_at {
+        Some(expires_at) => match expires_at.duration_since(SystemTime::now()) {
+            Ok(left) => Some(tokio::time::Instant::now() + left),
+            Err(_) => {
+                storage.remove(&record.key);
+                return;
+            }
+        },
+        None => None,
+    };
+    storage.insert(record.key, record.value, expires_at);
+}
+
+fn encode(key: &[u8], value: &[u8], expires_at: Option<SystemTime>, version: Version) -> Vec<u8> {
+    // Anything expiring within the first millisecond of the epoch is long gone anyway
+    let expiry_ms = expires_at.map_or(0, |expires_at| {
+        expires_at
+            .duration_since(UNIX_EPOCH)
+            .map_or(1, |since| since.as_millis().max(1) as u64)
+    });
+    let mut record = vec![0; 4];
+    record.extend(expiry_ms.to_be_bytes());
+    record.extend(version.clock.to_be_bytes());
+    record.extend(version.node.to_be_bytes());
+    record.extend((key.len() as u32).to_be_bytes());
+    record.extend((value.len() as u32).to_be_bytes());
+    record.extend(key);
+    record.extend(value);
+    let crc = crc32fast::hash(&record[4..]);
+    record[..4].copy_from_slice(&crc.to_be_bytes());
+    record
+}
+
+/// Hands the records in `bytes` to `apply` in order. Returns how many there were and how many
+/// bytes they took, which falls short of the end at the first incomplete or bad record.
+fn decode(bytes: &[u8], mut apply: impl FnMut(Record)) -> (usize, usize) {
+    let mut records = 0;
+    let mut offset = 0;
+    while let Some((record, len)) = decode_record(&bytes[offset..]) {
+        apply(record);
+        records += 1;
+        offset += len;
+    }
+    (records, offset)
+}
+
+fn decode_record(bytes: &[u8]) -> Option<(Record, usize)> {
+    let u32_at = |at: usize| -> Option<usize> {
+        let field = bytes.get(at..at + 4)?;
+        Some(u32::from_be_bytes(field.try_into().unwrap()) as usize)
+    };
+    let (crc, key_len, value_len) = (u32_at(0)?, u32_at(28)?, u32_at(32)?);
+    let len = HEADER_LEN.checked_add(key_len)?.checked_add(value_len)?;
+    let record = bytes.get(..len)?;
+    if crc32fast::hash(&record[4..]) as usize != crc {
+        return None;
+    }
+    let u64_at = |at: usize| u64::from_be_bytes(record[at..at + 8].try_into().unwrap());
+    let expiry_ms = u64_at(4);
+    let record = Record {
+        key: record[HEADER_LEN..HEADER_LEN + key_len].to_vec(),
+        value: record[HEADER_LEN + key_len..].to_vec(),
+        expires_at: (expiry_ms > 0).then(|| UNIX_EPOCH + Duration::from_millis(expiry_ms)),
+        version: Version {
+            clock: u64_at(12),
+            node: u64_at(20),
+        },
+    };
+    Some((record, len))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    fn wal_config(name: &str) -> WalConfig {
+        let dir =
+            std::env::temp_dir().join(format!("prime_time-wal-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        WalConfig::new(dir)
+    }
+
+    fn write(key: &str, value: &str, expires_at: Option<tokio::time::Instant>) -> Write {
+        Write {
+            version: Version::default(),
+            expires_at,
+            key: key.into(),
+            value: value.into(),
+        }
+    }
+
+    async fn insert(wal: &mut Wal, storage: &ShardedStorage, key: &str, value: &str) {
+        storage.insert(key.into(), value.into(), None);
+        wal.append(storage, &Versions::new(), &write(key, value, None))
+            .await
+            .unwrap();
+    }
+
+    async fn replayed(config: &WalConfig) -> HashMap<Vec<u8>, Vec<u8>> {
+        let storage = sharded();
+        Wal::open(config, &storage).await.unwrap();
+        contents(&storage)
+    }
+
+    fn sharded() -> ShardedStorage {
+        ShardedStorage::new(4, None)
+    }
+
+    fn contents(storage: &ShardedStorage) -> HashMap<Vec<u8>, Vec<u8>> {
+        let mut contents = HashMap::new();
+        storage.for_each(|key, value, _| {
+            contents.insert(key.to_vec(), value.to_vec());
+        });
+        contents
+    }
+
+    fn expected(entries: &[(&str, &str)]) -> HashMap<Vec<u8>, Vec<u8>> {
+        entries
+            .iter()
+            .map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec()))
+            .collect()
+    }
+
+    fn plain(key: &[u8], value: &[u8]) -> Vec<u8> {
+        encode(key, value, None, Version::default())
+    }
+
+    #[test]
+    fn test_record_round_trip() {
+        let expiry = UNIX_EPOCH + Duration::from_millis(1_700_000_000_123);
+        let version = Version {
+            clock: 3,
+            node: u64::MAX,
+        };
+        let mut bytes = plain(b"k=", b"multi\nline=value");
+        bytes.extend(plain(b"", b""));
+        bytes.extend(encode(b"\xff\x00", b"\xc3\x28", Some(expiry), version));
+        let mut records = vec![];
+        assert_eq!(
+            decode(&bytes, |record| records.push(record)),
+            (3, bytes.len())
+        );
+        let decoded: Vec<_> = records
+            .iter()
+            .map(|record| {
+                let Record {
+                    key,
+                    value,
+                    expires_at,
+                    version,
+                } = record;
+                (&key[..], &value[..], *expires_at, *version)
+            })
+            .collect();
+        let unversioned = Version::default();
+        assert_eq!(
+            decoded,
+            [
+                (&b"k="[..], &b"multi\nline=value"[..], None, unversioned),
+                (b"", b"", None, unversioned),
+                (b"\xff\x00", b"\xc3\x28", Some(expiry), version),
+            ]
+        );
+
+        // Zeros where a record should be, as a crash can leave behind, are not an empty insert
+        assert_eq!(decode(&[0; 64], |_| panic!("decoded zeros")), (0, 0));
+    }
+
+    #[tokio::test]
+    async fn test_replay_after_restart() -> anyhow::Result<()> {
+        let config = wal_config("replay");
+        let storage = sharded();
+        let (mut wal, _) = Wal::open(&config, &storage).await?;
+        assert!(storage.is_empty());
+        insert(&mut wal, &storage, "foo", "bar").await;
+        insert(&mut wal, &storage, "baz", "1").await;
+        insert(&mut wal, &storage, "foo", "qux").await;
+        drop(wal);
+
+        assert_eq!(
+            replayed(&config).await,
+            expected(&[("foo", "qux"), ("baz", "1")])
+        );
+        std::fs::remove_dir_all(&config.dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_expiry_survives_restart() -> anyhow::Result<()> {
+        let config = wal_config("expiry");
+        let storage = sharded();
+        let (mut wal, _) = Wal::open(&config, &storage).await?;
+        insert(&mut wal, &storage, "gone", "old").await;
+        let now = tokio::time::Instant::now();
+        for (key, value, ttl) in [("gone", "new", 0), ("kept", "1", 60_000)] {
+            let expires_at = Some(now + Duration::from_millis(ttl));
+            storage.insert(key.into(), value.into(), expires_at);
+            wal.append(&storage, &Versions::new(), &write(key, value, expires_at))
+                .await?;
+        }
+        drop(wal);
+
+        // The expired insert still replaced the older value rather than bringing it back
+        let storage = sharded();
+        Wal::open(&config, &storage).await?;
+        assert_eq!(contents(&storage), expected(&[("kept", "1")]));
+        let mut expiries = vec![];
+        storage.for_each(|_, _, expires_at| expiries.push(expires_at));
+        let left = expiries[0].unwrap() - tokio::time::Instant::now();
+        assert!(left > Duration::from_secs(50), "{:?} left", left);
+        std::fs::remove_dir_all(&config.dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_log_truncated_mid_record() -> anyhow::Result<()> {
+        let config = wal_config("torn");
+        let storage = sharded();
+        let (mut wal, _) = Wal::open(&config, &storage).await?;
+        insert(&mut wal, &storage, "a", "1").await;
+        insert(&mut wal, &storage, "b", "2").await;
+        drop(wal);
+
+        let log_path = config.dir.join(LOG_FILE);
+        let whole = std::fs::metadata(&log_path)?.len();
+        for cut in 1..plain(b"b", b"2").len() as u64 {
+            std::fs::OpenOptions::new()
+                .write(true)
+                .open(&log_path)?
+                .set_len(whole - cut)?;
+            assert_eq!(
+                replayed(&config).await,
+                expected(&[("a", "1")]),
+                "cut {} bytes",
+                cut
+            );
+            assert_eq!(
+                std::fs::metadata(&log_path)?.len(),
+                plain(b"a", b"1").len() as u64
+            );
+            // Put the torn tail back for the next cut
+            std::fs::write(&log_path, [plain(b"a", b"1"), plain(b"b", b"2")].concat())?;
+        }
+
+        // New inserts after recovery are readable on the next start
+        std::fs::OpenOptions::new()
+            .write(true)
+            .open(&log_path)?
+            .set_len(whole - 3)?;
+        let storage = sharded();
+        let (mut wal, _) = Wal::open(&config, &storage).await?;
+        insert(&mut wal, &storage, "c", "3").await;
+        drop(wal);
+        assert_eq!(replayed(&config).await, expected(&[("a", "1"), ("c", "3")]));
+        std::fs::remove_dir_all(&config.dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_compacts_log() -> anyhow::Result<()> {
+        let mut config = wal_config("snapshot");
+        config.snapshot_every = Some(3);
+        let storage = sharded();
+        let (mut wal, _) = Wal::open(&config, &storage).await?;
+        for (key, value) in [("a", "1"), ("a", "2"), ("b", "1"), ("c", "1")] {
+            insert(&mut wal, &storage, key, value).await;
+        }
+        drop(wal);
+
+        // Three inserts went into the snapshot, the fourth is the only one left in the log
+        let log_path = config.dir.join(LOG_FILE);
+        assert_eq!(
+            std::fs::read(&log_path)?,
+            plain(b"c", b"1"),
+            "log not compacted"
+        );
+        assert_eq!(
+            replayed(&config).await,
+            expected(&[("a", "2"), ("b", "1"), ("c", "1")])
+        );
+        std::fs::remove_dir_all(&config.dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_keeps_eviction_order() -> anyhow::Result<()> {
+        let config = wal_config("snapshot-lru");
+        // One shard with room for three 2-byte entries
+        let lru = || ShardedStorage::new(1, Some(6));
+        let storage = lru();
+        let (mut wal, _) = Wal::open(&config, &storage).await?;
+        for (key, value) in [("a", "1"), ("b", "2"), ("c", "3")] {
+            insert(&mut wal, &storage, key, value).await;
+        }
+        storage.get(b"a", tokio::time::Instant::now());
+        wal.snapshot(&storage, &Versions::new()).await?;
+        drop(wal);
+
+        let storage = lru();
+        let (mut wal, _) = Wal::open(&config, &storage).await?;
+        insert(&mut wal, &storage, "d", "4").await;
+        assert_eq!(
+            contents(&storage),
+            expected(&[("a", "1"), ("c", "3"), ("d", "4")])
+        );
+        std::fs::remove_dir_all(&config.dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_versions_survive_compaction() -> anyhow::Result<()> {
+        let config = wal_config("versions");
+        let storage = sharded();
+        let (mut wal, _) = Wal::open(&config, &storage).await?;
+        let version = |clock| Version { clock, node: 9 };
+        let mut versions = Versions::new();
+        for (clock, key) in [(1, "kept"), (2, "evicted")] {
+            versions.insert(key.into(), version(clock));
+            let write = Write {
+                version: version(clock),
+                ..write(key, "1", None)
+            };
+            storage.insert(write.key.clone(), write.value.clone(), None);
+            wal.append(&storage, &versions, &write).await?;
+        }
+        storage.remove(b"evicted");
+        wal.snapshot(&storage, &versions).await?;
+        drop(wal);
+
+        // The key no longer stored comes back as a tombstone, version and all
+        let storage = sharded();
+        let (_, replayed) = Wal::open(&config, &storage).await?;
+        assert_eq!(contents(&storage), expected(&[("kept", "1")]));
+        assert_eq!(replayed, versions);
+        std::fs::remove_dir_all(&config.dir)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_crash_before_log_emptied() -> anyhow::Result<()> {
+        let config = wal_config("snapshot-crash");
+        let storage = sharded();
+        let (mut wal, _) = Wal::open(&config, &storage).await?;
+        insert(&mut wal, &storage, "a", "1").await;
+        insert(&mut wal, &storage, "a", "2").await;
+        let log_path = config.dir.join(LOG_FILE);
+        let log = std::fs::read(&log_path)?;
+        wal.snapshot(&storage, &Versions::new()).await?;
+        drop(wal);
+        // As if the process died between renaming the snapshot and emptying the log
+        std::fs::write(&log_path, log)?;
+
+        assert_eq!(replayed(&config).await, expected(&[("a", "2")]));
+        std::fs::remove_dir_all(&config.dir)?;
+        Ok(())
+    }
+}
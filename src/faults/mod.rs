@@ -0,0 +1,126 @@
+pub mod proxy;
+
+use std::time::Duration;
+
+use rand::{Rng, SeedableRng, rngs::StdRng};
+
+use crate::capture::Direction;
+
+pub use proxy::{proxy_tcp, proxy_udp};
+
+/// What the fault proxy does to the traffic it forwards. Every decision comes from an RNG
+/// seeded per connection and direction, so a seed reproduces the same faults however the
+/// proxy's tasks happen to be scheduled.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Faults {
+    pub seed: u64,
+    /// Largest piece TCP data is forwarded in. `None` forwards each read whole.
+    pub max_chunk: Option<usize>,
+    /// Upper bound on the random pause before each chunk or datagram.
+    pub max_delay: Duration,
+    /// Chance that a datagram is dropped.
+    pub drop: f64,
+    /// Chance that a datagram is delivered twice.
+    pub duplicate: f64,
+    /// Chance, per chunk, that the connection is reset instead of forwarding it.
+    pub reset: f64,
+}
+
+impl Faults {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.max_chunk == Some(0) {
+            anyhow::bail!("max chunk must be at least 1 byte");
+        }
+        for (name, p) in [
+            ("drop", self.drop),
+            ("duplicate", self.duplicate),
+            ("reset", self.reset),
+        ] {
+            if !(0.0..=1.0).contains(&p) {
+                anyhow::bail!("{} probability must be between 0 and 1, got {}", name, p);
+            }
+        }
+        Ok(())
+    }
+
+    fn rng(&self, conn: u64, dir: Direction) -> StdRng {
+        let dir = match dir {
+            Direction::In => 0,
+            Direction::Out => 1,
+        };
+        StdRng::seed_from_u64(self.seed ^ conn.wrapping_mul(0x9e37_79b9_7f4a_7c15) ^ dir)
+    }
+
+    fn chunk_len(&self, rng: &mut StdRng, available: usize) -> usize {
+        match self.max_chunk {
+            Some(max) => rng.random_range(1..=max.min(available)),
+            None => available,
+        }
+    }
+
+    /// How many times to deliver a datagram: 0 when dropped, 2 when duplicated.
+    fn copies(&self, rng: &mut StdRng) -> usize {
+        if rng.random_bool(self.drop) {
+            0
+        } else if rng.random_bool(self.duplicate) {
+            2
+        } else {
+            1
+        }
+    }
+
+    /// The pause before the next chunk or datagram, drawn only when delays are on.
+    fn delay(&self, rng: &mut StdRng) -> Duration {
+        if self.max_delay.is_zero() {
+            Duration::ZERO
+        } else {
+            rng.random_range(Duration::ZERO..=self.max_delay)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_chunks() {
+        let faults = Faults {
+            seed: 7,
+            max_chunk: Some(4),
+            ..Faults::default()
+        };
+        let chunks = |conn| {
+            let mut rng = faults.rng(conn, Direction::In);
+            (0..32)
+                .map(|_| faults.chunk_len(&mut rng, 100))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(chunks(1), chunks(1));
+        assert_ne!(chunks(1), chunks(2));
+        assert!(chunks(1).iter().all(|len| (1..=4).contains(len)));
+    }
+
+    #[test]
+    fn test_validate() {
+        assert!(Faults::default().validate().is_ok());
+        let err = Faults {
+            drop: 1.5,
+            ..Faults::default()
+        }
+        .validate()
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "drop probability must be between 0 and 1, got 1.5"
+        );
+        assert!(
+            Faults {
+                max_chunk: Some(0),
+                ..Faults::default()
+            }
+            .validate()
+            .is_err()
+        );
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    net::SocketAddr,
+    sync::{
+        Arc,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use futures::future::{self, BoxFuture};
+use rand::{Rng, rngs::StdRng};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio_util::sync::CancellationToken;
+use tracing::info;
+
+use crate::{
+    capture::Direction,
+    faults::Faults,
+    relay::{self, Hook, Step},
+};
+
+/// Plans faults for what the relay forwards, from one RNG per connection and direction.
+struct Injector {
+    faults: Faults,
+    next_conn: AtomicU64,
+}
+
+impl Injector {
+    fn new(faults: Faults) -> Arc<Self> {
+        Arc::new(Self {
+            faults,
+            next_conn: AtomicU64::new(1),
+        })
+    }
+}
+
+impl Hook for Injector {
+    type Stream = StdRng;
+
+    fn open(&self, _peer: SocketAddr) -> BoxFuture<'_, u64> {
+        Box::pin(future::ready(
+            self.next_conn.fetch_add(1, Ordering::Relaxed),
+        ))
+    }
+
+    fn close(&self, _conn: u64) -> BoxFuture<'_, ()> {
+        Box::pin(future::ready(()))
+    }
+
+    fn stream(&self, conn: u64, dir: Direction) -> StdRng {
+        self.faults.rng(conn, dir)
+    }
+
+    fn chunk<'a>(&'a self, rng: &'a mut StdRng, data: &'a [u8]) -> BoxFuture<'a, Vec<Step>> {
+        let mut steps = vec![];
+        let mut available = data.len();
+        while available > 0 {
+            if rng.random_bool(self.faults.reset) {
+                steps.push(Step::Reset);
+                break;
+            }
+            let len = self.faults.chunk_len(rng, available);
+            let delay = self.faults.delay(rng);
+            if !delay.is_zero() {
+                steps.push(Step::Pause(delay));
+            }
+            steps.push(Step::Write(len));
+            available -= len;
+        }
+        Box::pin(future::ready(steps))
+    }
+
+    fn datagram<'a>(&'a self, rng: &'a mut StdRng, _: &'a [u8]) -> BoxFuture<'a, Vec<Duration>> {
+        // Each copy is delayed after the one before, as if sent one by one
+        let mut at = Duration::ZERO;
+        let delays = (0..self.faults.copies(rng))
+            .map(|_| {
+                at += self.faults.delay(rng);
+                at
+            })
+            .collect();
+        Box::pin(future::ready(delays))
+    }
+}
+
+/// Forwards every connection on `listener` to `upstream`, splitting, delaying and resetting
+/// as `faults` says, until `shutdown` is cancelled. Open connections are cut at that point.
+pub async fn proxy_tcp(
+    listener: TcpListener,
+    upstream: String,
+    faults: Faults,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    info!(addr = %listener.local_addr()?, %upstream, seed = faults.seed, "Injecting TCP faults");
+    relay::proxy_tcp(listener, upstream, Injector::new(faults), shutdown).await
+}
+
+/// Forwards datagrams on `socket` to `upstream`, dropping, duplicating and delaying them in
+/// both directions as `faults` says. A delayed datagram holds up no other traffic.
+pub async fn proxy_udp(
+    socket: UdpSocket,
+    upstream: String,
+    faults: Faults,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    info!(addr = %socket.local_addr()?, %upstream, seed = faults.seed, "Injecting UDP faults");
+    relay::proxy_udp(socket, upstream, Injector::new(faults), shutdown).await
+}
@@ -1,102 +1,462 @@
-use futures::{SinkExt, stream::StreamExt};
+use std::{fmt, ops::RangeInclusive, sync::Arc};
+
+use futures::{SinkExt, future::BoxFuture, stream::StreamExt};
+use num_bigint::BigUint;
 use serde::{Deserialize, Serialize};
-use tokio::net::TcpStream;
-use tokio_util::codec::{Framed, LinesCodec};
+use serde_json::value::RawValue;
+use tokio::{net::TcpStream, sync::Semaphore};
+use tokio_util::{
+    codec::{FramedRead, FramedWrite, LinesCodec, LinesCodecError},
+    sync::CancellationToken,
+};
+use tracing::{info, warn};
 
-#[derive(Deserialize, Debug)]
-#[serde(untagged)]
-enum Number {
-    Int(u64),
-    Float(f64),
+use crate::{
+    config::{ConnectionSettings, DEFAULT_PRIME_MAX_LINE_LENGTH, ServiceConfig},
+    metrics,
+    prime_cache::PrimeCache,
+    primes,
+    services::{ServiceKind, TcpService},
+};
+
+/// Sent once in reply to a malformed request, right before the connection is closed.
+const MALFORMED_RESPONSE: &str = r#"{"error":"malformed"}"#;
+
+/// Largest number `nextPrime` and `prevPrime` search from, in bits.
+const MAX_SEARCH_BITS: u64 = 1024;
+/// Most numbers `primeCount` tests in one request.
+const MAX_COUNT_SPAN: u64 = 1_000_000;
+/// Most numbers in one `isPrimeBatch` request.
+const MAX_BATCH: usize = 1024;
+/// Numbers are only expanded up to this many digits. A longer one written with trailing zeros
+/// or an exponent is a multiple of ten, so `isPrime` can answer without the digits; any other
+/// literal that long is out of range for every method.
+const MAX_EXPANDED_DIGITS: i64 = 4096;
+
+/// Every field is optional here so a missing one, or an explicit `null`, is reported as such
+/// rather than as a generic serde error. Unknown fields are ignored, as the protocol requires.
+/// Numbers are kept exactly as written, so no digits are lost to `u64` or `f64` on the way in.
+#[derive(Deserialize)]
+struct Request<'a> {
+    #[serde(borrow)]
+    method: Option<&'a RawValue>,
+    #[serde(borrow)]
+    number: Option<&'a RawValue>,
+    #[serde(borrow)]
+    numbers: Option<&'a RawValue>,
+    #[serde(borrow)]
+    from: Option<&'a RawValue>,
+    #[serde(borrow)]
+    to: Option<&'a RawValue>,
 }
 
 #[derive(Deserialize)]
-struct Request {
-    method: String,
-    number: Number,
+#[serde(rename_all = "camelCase")]
+enum Method {
+    IsPrime,
+    Factorize,
+    NextPrime,
+    PrevPrime,
+    PrimeCount,
+    IsPrimeBatch,
+}
+
+/// A validated request. `None` stands for a number that cannot be prime.
+#[derive(Debug, PartialEq)]
+enum Call {
+    IsPrime(Option<BigUint>),
+    Factorize(u64),
+    NextPrime(BigUint),
+    PrevPrime(BigUint),
+    PrimeCount(RangeInclusive<u64>),
+    IsPrimeBatch(Vec<Option<BigUint>>),
+}
+
+/// Each method answers with its name plus its own result fields.
+#[derive(Serialize, Debug)]
+#[serde(tag = "method", rename_all = "camelCase")]
+enum Response {
+    IsPrime { prime: bool },
+    Factorize { factors: Vec<u64> },
+    NextPrime { number: Box<RawValue> },
+    PrevPrime { number: Option<Box<RawValue>> },
+    PrimeCount { count: u64 },
+    IsPrimeBatch { primes: Vec<bool> },
 }
 
-#[derive(Serialize)]
-struct Response {
-    method: String,
-    prime: bool,
+/// Why a request line was rejected. Clients all get the same malformed response; the reason
+/// only goes to the log.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Malformed {
+    TooLong,
+    InvalidJson,
+    NotAnObject,
+    DuplicateField,
+    MissingMethod,
+    UnknownMethod,
+    Missing(&'static str),
+    NotANumber,
+    NotAnArray,
+    NotNatural,
+    OutOfRange,
 }
 
-pub async fn handle_is_prime(socket: &mut TcpStream) -> anyhow::Result<()> {
-    let mut framed = Framed::new(socket, LinesCodec::new());
-    while let Some(line) = framed.next().await.transpose()? {
-        let req: Request = serde_json::from_str(&line)?;
-        if req.method != "isPrime" {
-            return Err(anyhow::anyhow!("method is not isPrime"));
+impl fmt::Display for Malformed {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Malformed::TooLong => f.write_str("line too long"),
+            Malformed::InvalidJson => f.write_str("not valid JSON"),
+            Malformed::NotAnObject => f.write_str("not a JSON object"),
+            Malformed::DuplicateField => f.write_str("duplicate field"),
+            Malformed::MissingMethod => f.write_str("missing method"),
+            Malformed::UnknownMethod => f.write_str("unknown method"),
+            Malformed::Missing(field) => write!(f, "missing {}", field),
+            Malformed::NotANumber => f.write_str("not a number"),
+            Malformed::NotAnArray => f.write_str("numbers is not an array"),
+            Malformed::NotNatural => f.write_str("not a non-negative integer"),
+            Malformed::OutOfRange => f.write_str("out of range for the method"),
         }
-        let prime = match req.number {
-            Number::Int(i) => is_prime(i),
-            Number::Float(f) => {
-                if f.fract() == 0.0 {
-                    is_prime(f as u64)
-                } else {
-                    false
-                }
+    }
+}
+
+pub struct PrimeService {
+    settings: ConnectionSettings,
+    cache: Arc<PrimeCache>,
+}
+
+impl PrimeService {
+    pub fn new(config: &ServiceConfig) -> Self {
+        Self {
+            settings: ConnectionSettings {
+                max_line_length: config
+                    .max_line_length
+                    .unwrap_or(DEFAULT_PRIME_MAX_LINE_LENGTH),
+                ..config.connection_settings()
+            },
+            cache: Arc::new(PrimeCache::new(config.cache_capacity())),
+        }
+    }
+}
+
+impl TcpService for PrimeService {
+    fn handle(
+        &self,
+        mut socket: TcpStream,
+        shutdown: CancellationToken,
+    ) -> BoxFuture<'static, anyhow::Result<()>> {
+        let settings = self.settings;
+        let cache = self.cache.clone();
+        Box::pin(async move { handle_is_prime(&mut socket, settings, cache, shutdown).await })
+    }
+}
+
+/// Requests on a connection are evaluated concurrently, at most `max_concurrent` at a time,
+/// but answered strictly in the order they arrived. Reading stops while `max_pipelined`
+/// requests are outstanding, which pushes back on clients that do not read their responses.
+pub async fn handle_is_prime(
+    socket: &mut TcpStream,
+    settings: ConnectionSettings,
+    cache: Arc<PrimeCache>,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let result = serve_requests(socket, settings, &cache, shutdown).await;
+    info!(
+        hits = cache.hits(),
+        misses = cache.misses(),
+        entries = cache.len(),
+        "Prime cache totals"
+    );
+    result
+}
+
+async fn serve_requests(
+    socket: &mut TcpStream,
+    settings: ConnectionSettings,
+    cache: &Arc<PrimeCache>,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let metrics = metrics::service(ServiceKind::Prime);
+    let (reader, writer) = socket.split();
+    let mut writer = FramedWrite::new(writer, LinesCodec::new());
+    let evaluations = Semaphore::new(settings.max_concurrent);
+    let evaluations = &evaluations;
+
+    // Stops reading on shutdown, but whatever was already read is still answered
+    let responses = FramedRead::new(
+        reader,
+        LinesCodec::new_with_max_length(settings.max_line_length),
+    )
+    .take_until(shutdown.cancelled())
+    .map(|line| evaluate(line, evaluations, cache))
+    .buffered(settings.max_pipelined);
+    tokio::pin!(responses);
+
+    while let Some(response) = responses.next().await {
+        let response = match response? {
+            Ok(response) => response,
+            Err(reason) => {
+                metrics.decode_errors.inc();
+                warn!(%reason, "Malformed request, disconnecting");
+                writer.send(MALFORMED_RESPONSE).await?;
+                return Ok(());
             }
         };
-        let response = Response {
-            method: "isPrime".into(),
-            prime,
-        };
+        metrics.messages_decoded.inc();
         let response_json = serde_json::to_string(&response)?;
-        framed.send(response_json).await?;
+        writer.send(response_json).await?;
+        metrics.messages_encoded.inc();
     }
     Ok(())
 }
 
-fn is_prime(i: u64) -> bool {
-    if i < 2 {
-        return false;
+/// Answers one request line. A malformed line is not an error of the connection: it still
+/// has to be reported in order behind the requests before it.
+async fn evaluate(
+    line: Result<String, LinesCodecError>,
+    evaluations: &Semaphore,
+    cache: &Arc<PrimeCache>,
+) -> anyhow::Result<Result<Response, Malformed>> {
+    let call = match line {
+        Ok(line) => parse_request(&line),
+        Err(LinesCodecError::MaxLineLengthExceeded) => Err(Malformed::TooLong),
+        Err(e) => return Err(e.into()),
+    };
+    match call {
+        Ok(call) => Ok(Ok(answer(call, evaluations, cache).await?)),
+        Err(reason) => Ok(Err(reason)),
     }
-    let max: u64 = ((i as f64).sqrt() + 1f64) as u64;
-    for n in 2..max {
-        if i % n == 0 {
-            return false;
-        }
-    }
-    return true;
 }
 
-#[cfg(test)]
-mod tests {
+/// Validates one request line against the fields its method needs.
+fn parse_request(line: &str) -> Result<Call, Malformed> {
+    let value: &RawValue = serde_json::from_str(line).map_err(|_| Malformed::InvalidJson)?;
+    if !value.get().starts_with('{') {
+        return Err(Malformed::NotAnObject);
+    }
+    // The line is known to be an object, so this can only fail on a repeated key
+    let request: Request<'_> =
+        serde_json::from_str(value.get()).map_err(|_| Malformed::DuplicateField)?;
 
-    use futures::{SinkExt, StreamExt};
-    use serde_json::{Value, json};
-    use tokio::net::{TcpListener, TcpStream};
-    use tokio_util::codec::{Framed, LinesCodec};
+    let method = request.method.ok_or(Malformed::MissingMethod)?;
+    let method = serde_json::from_str(method.get()).map_err(|_| Malformed::UnknownMethod)?;
+    let call = match method {
+        Method::IsPrime => Call::IsPrime(prime_candidate(required(request.number, "number")?)?),
+        Method::Factorize => {
+            let n = natural(required(request.number, "number")?, 64)?;
+            match u64::try_from(n) {
+                Ok(n) if n > 0 => Call::Factorize(n),
+                _ => return Err(Malformed::OutOfRange),
+            }
+        }
+        Method::NextPrime => Call::NextPrime(natural(
+            required(request.number, "number")?,
+            MAX_SEARCH_BITS,
+        )?),
+        Method::PrevPrime => Call::PrevPrime(natural(
+            required(request.number, "number")?,
+            MAX_SEARCH_BITS,
+        )?),
+        Method::PrimeCount => {
+            let bound = |value, name| {
+                let n = natural(required(value, name)?, 64)?;
+                u64::try_from(n).map_err(|_| Malformed::OutOfRange)
+            };
+            let (from, to) = (bound(request.from, "from")?, bound(request.to, "to")?);
+            if from > to || to - from >= MAX_COUNT_SPAN {
+                return Err(Malformed::OutOfRange);
+            }
+            Call::PrimeCount(from..=to)
+        }
+        Method::IsPrimeBatch => {
+            let numbers: Vec<&RawValue> =
+                serde_json::from_str(required(request.numbers, "numbers")?.get())
+                    .map_err(|_| Malformed::NotAnArray)?;
+            if numbers.len() > MAX_BATCH {
+                return Err(Malformed::OutOfRange);
+            }
+            Call::IsPrimeBatch(
+                numbers
+                    .into_iter()
+                    .map(prime_candidate)
+                    .collect::<Result<_, _>>()?,
+            )
+        }
+    };
+    Ok(call)
+}
 
-    use crate::handle_is_prime::handle_is_prime;
+fn required<'a>(
+    value: Option<&'a RawValue>,
+    name: &'static str,
+) -> Result<&'a RawValue, Malformed> {
+    value.ok_or(Malformed::Missing(name))
+}
 
-    // Import the function from your main crate or define it here
+/// What a JSON number denotes, worked out from its text so nothing goes through `f64`.
+#[derive(Debug, PartialEq)]
+enum Number {
+    Natural(BigUint),
+    /// Negative, or with a nonzero fractional part.
+    NotNatural,
+    /// A multiple of ten past [`MAX_EXPANDED_DIGITS`].
+    TooLarge,
+}
 
-    async fn run_test(input_number: serde_json::Value, expected: bool) -> anyhow::Result<()> {
-        let listener = TcpListener::bind("127.0.0.1:0").await?;
-        let addr = listener.local_addr()?;
+/// `7`, `7.0` and `70e-1` are all 7.
+fn parse_number(number: &RawValue) -> Result<Number, Malformed> {
+    let text = number.get();
+    let (negative, unsigned) = match text.strip_prefix('-') {
+        Some(unsigned) => (true, unsigned),
+        None => (false, text),
+    };
+    if !unsigned.starts_with(|c: char| c.is_ascii_digit()) {
+        return Err(Malformed::NotANumber);
+    }
 
-        tokio::spawn(async move {
-            let (mut socket, _) = listener.accept().await.unwrap();
-            handle_is_prime(&mut socket).await.unwrap();
+    let (mantissa, exponent) = unsigned.split_once(['e', 'E']).unwrap_or((unsigned, "0"));
+    let (int, frac) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    let digits = format!("{}{}", int, frac);
+    let significant = digits.trim_start_matches('0');
+    let trimmed = significant.trim_end_matches('0');
+    if trimmed.is_empty() {
+        return Ok(Number::Natural(BigUint::ZERO));
+    }
+    if negative {
+        return Ok(Number::NotNatural);
+    }
+    // Can only fail by overflowing, which leaves a tiny fraction or a huge multiple of ten
+    let Ok(exponent) = exponent.parse::<i64>() else {
+        return Ok(if exponent.starts_with('-') {
+            Number::NotNatural
+        } else {
+            Number::TooLarge
         });
+    };
 
-        let mut client = Framed::new(TcpStream::connect(addr).await?, LinesCodec::new());
+    let scale = exponent
+        .saturating_sub(frac.len() as i64)
+        .saturating_add((significant.len() - trimmed.len()) as i64);
+    if scale < 0 {
+        return Ok(Number::NotNatural);
+    }
+    if scale.saturating_add(trimmed.len() as i64) > MAX_EXPANDED_DIGITS {
+        return if scale > 0 {
+            Ok(Number::TooLarge)
+        } else {
+            Err(Malformed::OutOfRange)
+        };
+    }
+    let n: BigUint = trimmed.parse().map_err(|_| Malformed::NotANumber)?;
+    Ok(Number::Natural(n * BigUint::from(10u32).pow(scale as u32)))
+}
 
-        let input = json!({
-            "method": "isPrime",
-            "number": input_number
-        });
+/// The integer to test for `isPrime`, or `None` for numbers that cannot be prime.
+fn prime_candidate(number: &RawValue) -> Result<Option<BigUint>, Malformed> {
+    match parse_number(number)? {
+        Number::Natural(n) => Ok(Some(n)),
+        Number::NotNatural | Number::TooLarge => Ok(None),
+    }
+}
+
+/// A non-negative integer of at most `max_bits` bits, for the methods that need one.
+fn natural(number: &RawValue, max_bits: u64) -> Result<BigUint, Malformed> {
+    match parse_number(number)? {
+        Number::Natural(n) if n.bits() <= max_bits => Ok(n),
+        Number::Natural(_) | Number::TooLarge => Err(Malformed::OutOfRange),
+        Number::NotNatural => Err(Malformed::NotNatural),
+    }
+}
 
-        client.send(input.to_string()).await?;
+/// `isPrime` for sieve and cache hits is answered in place; everything else runs on the
+/// blocking pool so a stream of expensive requests cannot hold up the other connections on
+/// this worker, once one of the connection's `evaluations` permits is free.
+async fn answer(
+    call: Call,
+    evaluations: &Semaphore,
+    cache: &Arc<PrimeCache>,
+) -> anyhow::Result<Response> {
+    let known = match &call {
+        Call::IsPrime(None) => Some(false),
+        Call::IsPrime(Some(n)) => cache.get(n),
+        _ => None,
+    };
+    if let Some(prime) = known {
+        return Ok(Response::IsPrime { prime });
+    }
+    let _permit = evaluations.acquire().await?;
+    let cache = cache.clone();
+    tokio::task::spawn_blocking(move || call.answer(&cache)).await?
+}
 
-        let Some(line) = client.next().await else {
-            panic!("No response received");
+impl Call {
+    fn answer(self, cache: &PrimeCache) -> anyhow::Result<Response> {
+        let response = match self {
+            // `answer` already missed on it in the cache
+            Call::IsPrime(n) => Response::IsPrime {
+                prime: n.is_some_and(|n| cache.compute(n)),
+            },
+            Call::Factorize(n) => Response::Factorize {
+                factors: primes::factorize(n),
+            },
+            Call::NextPrime(n) => Response::NextPrime {
+                number: raw_number(&primes::next_prime(&n))?,
+            },
+            Call::PrevPrime(n) => Response::PrevPrime {
+                number: primes::prev_prime(&n)
+                    .as_ref()
+                    .map(raw_number)
+                    .transpose()?,
+            },
+            Call::PrimeCount(range) => Response::PrimeCount {
+                count: primes::prime_count(*range.start(), *range.end()),
+            },
+            Call::IsPrimeBatch(numbers) => Response::IsPrimeBatch {
+                primes: numbers
+                    .into_iter()
+                    .map(|n| n.is_some_and(|n| cache.is_prime(n)))
+                    .collect(),
+            },
         };
+        Ok(response)
+    }
+}
+
+/// Writes `n` out in full; `serde_json` numbers stop at `u64`.
+fn raw_number(n: &BigUint) -> anyhow::Result<Box<RawValue>> {
+    Ok(RawValue::from_string(n.to_string())?)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use futures::{SinkExt, StreamExt};
+    use serde_json::{Value, json};
+    use tokio::{
+        io::AsyncWriteExt,
+        net::{TcpListener, TcpStream},
+    };
+    use tokio_util::{
+        codec::{Framed, LinesCodec},
+        sync::CancellationToken,
+    };
+
+    use std::num::NonZeroUsize;
 
-        let line = line?;
+    use super::*;
+    use crate::{config::ConnectionSettings, handle_is_prime::handle_is_prime};
+
+    fn test_cache() -> Arc<PrimeCache> {
+        Arc::new(PrimeCache::new(NonZeroUsize::new(16).unwrap()))
+    }
+
+    async fn run_test(input_number: serde_json::Value, expected: bool) -> anyhow::Result<()> {
+        run_test_raw(&input_number.to_string(), expected).await
+    }
+
+    /// Sends `number` exactly as written, for numbers `serde_json::Value` cannot hold.
+    async fn run_test_raw(number: &str, expected: bool) -> anyhow::Result<()> {
+        let input = format!(r#"{{"method":"isPrime","number":{}}}"#, number);
+        let line = exchange(&input).await?;
         let response: Value = serde_json::from_str(&line)?;
         assert_eq!(response["method"], "isPrime");
         assert_eq!(response["prime"], expected);
@@ -126,4 +486,408 @@ mod tests {
         run_test(json!(5.0), true).await?;
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_large_numbers() -> anyhow::Result<()> {
+        run_test(json!(18_446_744_073_709_551_557u64), true).await?;
+        run_test(json!(18_446_744_073_709_551_555u64), false).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_big_numbers() -> anyhow::Result<()> {
+        // 2^127 - 1 and 2^127 + 1
+        run_test_raw("170141183460469231731687303715884105727", true).await?;
+        run_test_raw("170141183460469231731687303715884105729", false).await?;
+        // f64 would round this to 2^64
+        run_test_raw("18446744073709551557.0", true).await?;
+        run_test_raw("1e400", false).await?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_negative_numbers() -> anyhow::Result<()> {
+        run_test(json!(-7), false).await?;
+        run_test_raw("-170141183460469231731687303715884105727", false).await?;
+        Ok(())
+    }
+
+    fn candidate(number: &str) -> Option<BigUint> {
+        prime_candidate(&RawValue::from_string(number.into()).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_prime_candidate() {
+        for seven in ["7", "7.0", "70e-1", "0.7E1", "0.07e+2"] {
+            assert_eq!(candidate(seven), Some(BigUint::from(7u32)), "{}", seven);
+        }
+        for zero in ["0", "0.0", "0e5", "-0"] {
+            assert!(
+                candidate(zero).is_none_or(|n| n == BigUint::ZERO),
+                "{}",
+                zero
+            );
+        }
+        for never in ["-7", "7.5", "1e-400", "70", "7e1", "7e99999999999999999999"] {
+            if let Some(n) = candidate(never) {
+                assert!(!primes::is_probable_prime(&n), "{}", never);
+            }
+        }
+        for not_a_number in [r#""7""#, "true", "null", "[7]"] {
+            let raw = RawValue::from_string(not_a_number.into()).unwrap();
+            assert!(prime_candidate(&raw).is_err(), "{}", not_a_number);
+        }
+    }
+
+    #[test]
+    fn test_malformed_requests() {
+        let cases = [
+            ("", Malformed::InvalidJson),
+            ("{", Malformed::InvalidJson),
+            (
+                r#"{"method":"isPrime","number":7} x"#,
+                Malformed::InvalidJson,
+            ),
+            ("[7]", Malformed::NotAnObject),
+            ("7", Malformed::NotAnObject),
+            (r#""isPrime""#, Malformed::NotAnObject),
+            ("null", Malformed::NotAnObject),
+            (
+                r#"{"method":"isPrime","number":7,"number":8}"#,
+                Malformed::DuplicateField,
+            ),
+            (r#"{"number":7}"#, Malformed::MissingMethod),
+            (
+                r#"{"method":"isprime","number":7}"#,
+                Malformed::UnknownMethod,
+            ),
+            (r#"{"method":7,"number":7}"#, Malformed::UnknownMethod),
+            (r#"{"method":null,"number":7}"#, Malformed::MissingMethod),
+            (r#"{"method":"isPrime"}"#, Malformed::Missing("number")),
+            (
+                r#"{"method":"isPrime","number":"7"}"#,
+                Malformed::NotANumber,
+            ),
+            (
+                r#"{"method":"isPrime","number":true}"#,
+                Malformed::NotANumber,
+            ),
+            (
+                r#"{"method":"isPrime","number":null}"#,
+                Malformed::Missing("number"),
+            ),
+            (
+                r#"{"method":"isPrime","number":[7]}"#,
+                Malformed::NotANumber,
+            ),
+            (
+                r#"{"method":"isPrime","number":{"n":7}}"#,
+                Malformed::NotANumber,
+            ),
+            (
+                r#"{"method":"factorize","number":0}"#,
+                Malformed::OutOfRange,
+            ),
+            (
+                r#"{"method":"factorize","number":18446744073709551616}"#,
+                Malformed::OutOfRange,
+            ),
+            (
+                r#"{"method":"factorize","number":-4}"#,
+                Malformed::NotNatural,
+            ),
+            (
+                r#"{"method":"factorize","number":4.5}"#,
+                Malformed::NotNatural,
+            ),
+            (
+                r#"{"method":"nextPrime","number":1e400}"#,
+                Malformed::OutOfRange,
+            ),
+            (
+                &format!(r#"{{"method":"isPrime","number":{}}}"#, "7".repeat(5000)),
+                Malformed::OutOfRange,
+            ),
+            (
+                r#"{"method":"prevPrime","numbers":[7]}"#,
+                Malformed::Missing("number"),
+            ),
+            (
+                r#"{"method":"primeCount","from":10}"#,
+                Malformed::Missing("to"),
+            ),
+            (
+                r#"{"method":"primeCount","from":10,"to":9}"#,
+                Malformed::OutOfRange,
+            ),
+            (
+                r#"{"method":"primeCount","from":0,"to":1000000}"#,
+                Malformed::OutOfRange,
+            ),
+            (
+                r#"{"method":"isPrimeBatch","numbers":7}"#,
+                Malformed::NotAnArray,
+            ),
+            (
+                r#"{"method":"isPrimeBatch","numbers":[7,"8"]}"#,
+                Malformed::NotANumber,
+            ),
+        ];
+        for (line, reason) in cases {
+            assert_eq!(parse_request(line), Err(reason), "{}", line);
+        }
+    }
+
+    #[test]
+    fn test_well_formed_requests() {
+        let seven = || Call::IsPrime(Some(BigUint::from(7u32)));
+        let cases = [
+            (r#"{"method":"isPrime","number":7}"#, seven()),
+            (r#" { "number" : 7 , "method" : "isPrime" } "#, seven()),
+            (
+                r#"{"method":"isPrime","number":7,"extra":{"nested":[1,2]}}"#,
+                seven(),
+            ),
+            (r#"{"method":"is\u0050rime","number":7}"#, seven()),
+            (r#"{"method":"isPrime","number":-7}"#, Call::IsPrime(None)),
+            (
+                r#"{"method":"factorize","number":1.2e1}"#,
+                Call::Factorize(12),
+            ),
+            (
+                r#"{"method":"nextPrime","number":0}"#,
+                Call::NextPrime(BigUint::ZERO),
+            ),
+            (
+                r#"{"method":"primeCount","from":0,"to":999999}"#,
+                Call::PrimeCount(0..=999_999),
+            ),
+            (
+                r#"{"method":"isPrimeBatch","numbers":[7,-7]}"#,
+                Call::IsPrimeBatch(vec![Some(BigUint::from(7u32)), None]),
+            ),
+        ];
+        for (line, call) in cases {
+            assert_eq!(parse_request(line), Ok(call), "{}", line);
+        }
+    }
+
+    /// Sends one request line to a fresh connection and returns the response line.
+    async fn exchange(request: &str) -> anyhow::Result<String> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await?;
+            handle_is_prime(
+                &mut socket,
+                ConnectionSettings::default(),
+                test_cache(),
+                CancellationToken::new(),
+            )
+            .await
+        });
+
+        let mut client = Framed::new(TcpStream::connect(addr).await?, LinesCodec::new());
+        client.send(request).await?;
+        Ok(client.next().await.expect("No response received")?)
+    }
+
+    #[tokio::test]
+    async fn test_other_methods() -> anyhow::Result<()> {
+        let cases = [
+            (
+                r#"{"method":"factorize","number":360}"#,
+                r#"{"method":"factorize","factors":[2,2,2,3,3,5]}"#,
+            ),
+            (
+                r#"{"method":"factorize","number":1}"#,
+                r#"{"method":"factorize","factors":[]}"#,
+            ),
+            (
+                r#"{"method":"nextPrime","number":18446744073709551557}"#,
+                r#"{"method":"nextPrime","number":18446744073709551629}"#,
+            ),
+            (
+                r#"{"method":"prevPrime","number":14}"#,
+                r#"{"method":"prevPrime","number":13}"#,
+            ),
+            (
+                r#"{"method":"prevPrime","number":2}"#,
+                r#"{"method":"prevPrime","number":null}"#,
+            ),
+            (
+                r#"{"method":"primeCount","from":0,"to":100}"#,
+                r#"{"method":"primeCount","count":25}"#,
+            ),
+            (
+                r#"{"method":"isPrimeBatch","numbers":[2,4,7.0,-7,170141183460469231731687303715884105727]}"#,
+                r#"{"method":"isPrimeBatch","primes":[true,false,true,false,true]}"#,
+            ),
+            (
+                r#"{"method":"isPrimeBatch","numbers":[]}"#,
+                r#"{"method":"isPrimeBatch","primes":[]}"#,
+            ),
+        ];
+        for (request, response) in cases {
+            assert_eq!(exchange(request).await?, response, "{}", request);
+        }
+        Ok(())
+    }
+
+    async fn malformed_then_closed(settings: ConnectionSettings, line: &str) -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await?;
+            handle_is_prime(
+                &mut socket,
+                settings,
+                test_cache(),
+                CancellationToken::new(),
+            )
+            .await
+        });
+
+        let mut client = Framed::new(TcpStream::connect(addr).await?, LinesCodec::new());
+        client.send(r#"{"method":"isPrime","number":7}"#).await?;
+        client.send(line).await?;
+        client.send(r#"{"method":"isPrime","number":7}"#).await?;
+
+        let first: Value = serde_json::from_str(&client.next().await.unwrap()?)?;
+        assert_eq!(first["prime"], true);
+        assert_eq!(client.next().await.unwrap()?, MALFORMED_RESPONSE);
+        assert!(client.next().await.is_none(), "connection left open");
+        server.await??;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_malformed_response_then_disconnect() -> anyhow::Result<()> {
+        malformed_then_closed(ConnectionSettings::default(), r#"{"number":7}"#).await?;
+        malformed_then_closed(ConnectionSettings::default(), "not json").await?;
+        let short = ConnectionSettings {
+            max_line_length: 40,
+            ..ConnectionSettings::default()
+        };
+        malformed_then_closed(
+            short,
+            &format!("{{\"method\":\"isPrime\",\"number\":{}}}", "1".repeat(64)),
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// 2^521 - 1, a prime that takes every Miller-Rabin round to confirm.
+    const M521: &str = "6864797660130609714981900799081393217269435300143305409394463459185543183397656052122559640661454554977296311391480858037121987999716643812574028291115057151";
+
+    /// Writes all `lines` at once, closes the sending side and collects every response.
+    async fn pipeline(
+        settings: ConnectionSettings,
+        cache: Arc<PrimeCache>,
+        lines: &[String],
+    ) -> anyhow::Result<Vec<String>> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await?;
+            handle_is_prime(&mut socket, settings, cache, CancellationToken::new()).await
+        });
+
+        let mut stream = TcpStream::connect(addr).await?;
+        let mut requests = lines.join("\n");
+        requests.push('\n');
+        stream.write_all(requests.as_bytes()).await?;
+        stream.shutdown().await?;
+        let responses: Vec<String> = Framed::new(stream, LinesCodec::new())
+            .collect::<Vec<_>>()
+            .await
+            .into_iter()
+            .collect::<Result<_, _>>()?;
+        server.await??;
+        Ok(responses)
+    }
+
+    fn is_prime_request(number: impl fmt::Display) -> String {
+        format!(r#"{{"method":"isPrime","number":{}}}"#, number)
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_responses_keep_order() -> anyhow::Result<()> {
+        // Slow checks of a big prime scattered among trivial ones
+        let numbers: Vec<String> = (0..200)
+            .map(|i| match i % 50 {
+                0 => M521.to_string(),
+                _ => i.to_string(),
+            })
+            .collect();
+        let requests: Vec<String> = numbers.iter().map(is_prime_request).collect();
+        let expected: Vec<String> = numbers
+            .iter()
+            .map(|n| {
+                let prime = n == M521 || primes::is_prime(n.parse().unwrap());
+                format!(r#"{{"method":"isPrime","prime":{}}}"#, prime)
+            })
+            .collect();
+
+        for (max_pipelined, max_concurrent) in [(1000, 8), (16, 2), (1, 1)] {
+            let settings = ConnectionSettings {
+                max_pipelined,
+                max_concurrent,
+                ..ConnectionSettings::default()
+            };
+            assert_eq!(
+                pipeline(settings, test_cache(), &requests).await?,
+                expected,
+                "max_pipelined {}, max_concurrent {}",
+                max_pipelined,
+                max_concurrent
+            );
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_pipelined_malformed_answered_in_order() -> anyhow::Result<()> {
+        let requests = [
+            is_prime_request(M521),
+            is_prime_request(4),
+            "{".to_string(),
+            is_prime_request(7),
+        ];
+        let responses = pipeline(ConnectionSettings::default(), test_cache(), &requests).await?;
+        assert_eq!(
+            responses,
+            [
+                r#"{"method":"isPrime","prime":true}"#,
+                r#"{"method":"isPrime","prime":false}"#,
+                MALFORMED_RESPONSE,
+            ]
+        );
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_cache_shared_across_connections() -> anyhow::Result<()> {
+        let cache = test_cache();
+        let first = [is_prime_request(M521), is_prime_request(7)];
+        let responses = pipeline(ConnectionSettings::default(), cache.clone(), &first).await?;
+        assert_eq!(responses[0], r#"{"method":"isPrime","prime":true}"#);
+        // 7 is answered from the sieve and never reaches the cache
+        assert_eq!((cache.hits(), cache.misses(), cache.len()), (0, 1, 1));
+
+        let second = [
+            is_prime_request(M521),
+            format!(r#"{{"method":"isPrimeBatch","numbers":[{}, 8]}}"#, M521),
+        ];
+        let responses = pipeline(ConnectionSettings::default(), cache.clone(), &second).await?;
+        assert_eq!(
+            responses,
+            [
+                r#"{"method":"isPrime","prime":true}"#,
+                r#"{"method":"isPrimeBatch","primes":[true,false]}"#
+            ]
+        );
+        assert_eq!((cache.hits(), cache.misses(), cache.len()), (2, 1, 1));
+        Ok(())
+    }
 }
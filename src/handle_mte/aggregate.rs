@@ -0,0 +1,169 @@
+use crate::handle_mte::Prices;
+
+/// Opcodes `0x80..=0xe4` ask for a percentile, `opcode - 0x80`.
+const PERCENTILE_OPCODES: std::ops::RangeInclusive<u8> = 0x80..=0xe4;
+
+/// A query over the prices inserted at timestamps in `[mintime, maxtime]`. An empty or
+/// inverted range answers 0, whatever the aggregate.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Aggregate {
+    /// `Q`, the protocol's own query: the integer mean.
+    Mean,
+    /// `L`, the lowest price.
+    Min,
+    /// `H`, the highest price.
+    Max,
+    /// `C`, how many prices there are.
+    Count,
+    /// `U`, the total of the prices.
+    Sum,
+    /// `D` for the median, or `0x80 + p` for the p-th percentile. Nearest rank, so the
+    /// answer is always one of the prices and the median of an even count is the lower one.
+    Percentile(u8),
+}
+
+/// Answers are written big-endian: prices as `i32`, counts as `u32` and sums as `i64`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Answer {
+    Price(i32),
+    Count(u32),
+    Sum(i64),
+}
+
+impl Answer {
+    pub fn to_be_bytes(self) -> Vec<u8> {
+        match self {
+            Answer::Price(price) => price.to_be_bytes().to_vec(),
+            Answer::Count(count) => count.to_be_bytes().to_vec(),
+            Answer::Sum(sum) => sum.to_be_bytes().to_vec(),
+        }
+    }
+}
+
+impl Aggregate {
+    pub fn from_opcode(opcode: u8) -> Option<Self> {
+        let aggregate = match opcode {
+            b'Q' => Aggregate::Mean,
+            b'L' => Aggregate::Min,
+            b'H' => Aggregate::Max,
+            b'C' => Aggregate::Count,
+            b'U' => Aggregate::Sum,
+            b'D' => Aggregate::Percentile(50),
+            _ if PERCENTILE_OPCODES.contains(&opcode) => {
+                Aggregate::Percentile(opcode - PERCENTILE_OPCODES.start())
+            }
+            _ => return None,
+        };
+        Some(aggregate)
+    }
+
+    pub fn answer(self, prices: &Prices, mintime: i32, maxtime: i32) -> Answer {
+        let in_range = || prices.range(mintime, maxtime);
+        match self {
+            Aggregate::Mean => match prices.count_and_sum(mintime, maxtime) {
+                (0, _) => Answer::Price(0),
+                (count, sum) => Answer::Price((sum / count as i64) as i32),
+            },
+            Aggregate::Min => Answer::Price(in_range().min().unwrap_or(0)),
+            Aggregate::Max => Answer::Price(in_range().max().unwrap_or(0)),
+            Aggregate::Count => Answer::Count(prices.count_and_sum(mintime, maxtime).0),
+            Aggregate::Sum => Answer::Sum(prices.count_and_sum(mintime, maxtime).1),
+            Aggregate::Percentile(p) => {
+                let mut in_range: Vec<i32> = in_range().collect();
+                if in_range.is_empty() {
+                    return Answer::Price(0);
+                }
+                let rank = (p as usize * in_range.len()).div_ceil(100).max(1);
+                let (_, price, _) = in_range.select_nth_unstable(rank - 1);
+                Answer::Price(*price)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn prices() -> Prices {
+        // Inserted out of order on purpose; the map keeps them by timestamp
+        Prices::from([(5, 50), (1, 10), (4, -40), (2, 20), (3, 30)])
+    }
+
+    #[test]
+    fn test_opcodes() {
+        assert_eq!(Aggregate::from_opcode(b'Q'), Some(Aggregate::Mean));
+        assert_eq!(
+            Aggregate::from_opcode(b'D'),
+            Some(Aggregate::Percentile(50))
+        );
+        assert_eq!(Aggregate::from_opcode(0x80), Some(Aggregate::Percentile(0)));
+        assert_eq!(
+            Aggregate::from_opcode(0xe4),
+            Some(Aggregate::Percentile(100))
+        );
+        assert_eq!(Aggregate::from_opcode(0xe5), None);
+        assert_eq!(Aggregate::from_opcode(b'I'), None);
+    }
+
+    #[test]
+    fn test_aggregates() {
+        let prices = prices();
+        let cases = [
+            (Aggregate::Mean, Answer::Price(14)),
+            (Aggregate::Min, Answer::Price(-40)),
+            (Aggregate::Max, Answer::Price(50)),
+            (Aggregate::Count, Answer::Count(5)),
+            (Aggregate::Sum, Answer::Sum(70)),
+            (Aggregate::Percentile(50), Answer::Price(20)),
+            (Aggregate::Percentile(0), Answer::Price(-40)),
+            (Aggregate::Percentile(20), Answer::Price(-40)),
+            (Aggregate::Percentile(21), Answer::Price(10)),
+            (Aggregate::Percentile(100), Answer::Price(50)),
+        ];
+        for (aggregate, answer) in cases {
+            assert_eq!(aggregate.answer(&prices, 1, 5), answer, "{:?}", aggregate);
+        }
+        // Lower median of an even count
+        assert_eq!(
+            Aggregate::Percentile(50).answer(&prices, 1, 4),
+            Answer::Price(10)
+        );
+    }
+
+    #[test]
+    fn test_empty_and_inverted_ranges() {
+        let prices = prices();
+        for aggregate in [
+            Aggregate::Mean,
+            Aggregate::Min,
+            Aggregate::Max,
+            Aggregate::Percentile(50),
+        ] {
+            assert_eq!(aggregate.answer(&prices, 6, 9), Answer::Price(0));
+            assert_eq!(aggregate.answer(&prices, 5, 1), Answer::Price(0));
+        }
+        assert_eq!(Aggregate::Count.answer(&prices, 5, 1), Answer::Count(0));
+        assert_eq!(Aggregate::Sum.answer(&prices, 6, 9), Answer::Sum(0));
+    }
+
+    #[test]
+    fn test_sum_does_not_overflow() {
+        let prices = Prices::from([(1, i32::MAX), (2, i32::MAX)]);
+        assert_eq!(
+            Aggregate::Sum.answer(&prices, 1, 2),
+            Answer::Sum(2 * i32::MAX as i64)
+        );
+        assert_eq!(
+            Aggregate::Mean.answer(&prices, 1, 2),
+            Answer::Price(i32::MAX)
+        );
+    }
+
+    #[test]
+    fn test_widths() {
+        assert_eq!(Answer::Price(-1).to_be_bytes(), vec![0xff; 4]);
+        assert_eq!(Answer::Count(1).to_be_bytes(), vec![0, 0, 0, 1]);
+        assert_eq!(Answer::Sum(1).to_be_bytes(), vec![0, 0, 0, 0, 0, 0, 0, 1]);
+    }
+}
@@ -0,0 +1,201 @@
+type Link = Option<usize>;
+
+struct Node {
+    timestamp: i32,
+    price: i32,
+    priority: u32,
+    left: Link,
+    right: Link,
+    /// Entries in the subtree rooted here, this one included.
+    count: u32,
+    /// Total price of the same entries.
+    sum: i64,
+}
+
+/// Prices by timestamp in a treap whose nodes carry the count and total of their subtree, so
+/// both are known for any timestamp range after O(log n) steps rather than a walk over it.
+/// Random priorities keep the expected depth logarithmic whatever order the timestamps come in.
+#[derive(Default)]
+pub struct RangeIndex {
+    nodes: Vec<Node>,
+    root: Link,
+}
+
+impl RangeIndex {
+    pub fn len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    /// Sets the price at `timestamp`, replacing the one already there.
+    pub fn insert(&mut self, timestamp: i32, price: i32) {
+        let (below, rest) = self.split(self.root, |t| t < timestamp);
+        let (at, above) = self.split(rest, |t| t <= timestamp);
+        let at = match at {
+            Some(i) => {
+                self.nodes[i].price = price;
+                self.update(i);
+                i
+            }
+            None => {
+                self.nodes.push(Node {
+                    timestamp,
+                    price,
+                    priority: rand::random(),
+                    left: None,
+                    right: None,
+                    count: 1,
+                    sum: price as i64,
+                });
+                self.nodes.len() - 1
+            }
+        };
+        let below = self.merge(below, Some(at));
+        self.root = self.merge(below, above);
+    }
+
+    /// How many prices there are at timestamps in `[mintime, maxtime]`, and their total.
+    pub fn count_and_sum(&self, mintime: i32, maxtime: i32) -> (u32, i64) {
+        if mintime > maxtime {
+            return (0, 0);
+        }
+        let (count_to, sum_to) = self.prefix(|t| t <= maxtime);
+        let (count_before, sum_before) = self.prefix(|t| t < mintime);
+        (count_to - count_before, sum_to - sum_before)
+    }
+
+    /// Count and total of the entries whose timestamp is `below`, which holds for a prefix.
+    fn prefix(&self, below: impl Fn(i32) -> bool) -> (u32, i64) {
+        let (mut count, mut sum) = (0, 0);
+        let mut link = self.root;
+        while let Some(i) = link {
+            let node = &self.nodes[i];
+            if below(node.timestamp) {
+                let (left_count, left_sum) = self.totals(node.left);
+                count += left_count + 1;
+                sum += left_sum + node.price as i64;
+                link = node.right;
+            } else {
+                link = node.left;
+            }
+        }
+        (count, sum)
+    }
+
+    fn totals(&self, link: Link) -> (u32, i64) {
+        link.map_or((0, 0), |i| (self.nodes[i].count, self.nodes[i].sum))
+    }
+
+    fn update(&mut self, i: usize) {
+        let (left_count, left_sum) = self.totals(self.nodes[i].left);
+        let (right_count, right_sum) = self.totals(self.nodes[i].right);
+        let node = &mut self.nodes[i];
+        node.count = left_count + 1 + right_count;
+        node.sum = left_sum + node.price as i64 + right_sum;
+    }
+
+    /// Splits the subtree at `link` into the timestamps that are `below`, a prefix, and the rest.
+    fn split(&mut self, link: Link, below: impl Fn(i32) -> bool + Copy) -> (Link, Link) {
+        let Some(i) = link else {
+            return (None, None);
+        };
+        if below(self.nodes[i].timestamp) {
+            let (left, right) = self.split(self.nodes[i].right, below);
+            self.nodes[i].right = left;
+            self.update(i);
+            (Some(i), right)
+        } else {
+            let (left, right) = self.split(self.nodes[i].left, below);
+            self.nodes[i].left = right;
+            self.update(i);
+            (left, Some(i))
+        }
+    }
+
+    /// Joins two subtrees where every timestamp in `left` comes before those in `right`.
+    fn merge(&mut self, left: Link, right: Link) -> Link {
+        let (l, r) = match (left, right) {
+            (None, link) | (link, None) => return link,
+            (Some(l), Some(r)) => (l, r),
+        };
+        if self.nodes[l].priority > self.nodes[r].priority {
+            self.nodes[l].right = self.merge(self.nodes[l].right, right);
+            self.update(l);
+            left
+        } else {
+            self.nodes[r].left = self.merge(left, self.nodes[r].left);
+            self.update(r);
+            right
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, ops::Bound::Included};
+
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn naive(prices: &BTreeMap<i32, i32>, mintime: i32, maxtime: i32) -> (u32, i64) {
+        if mintime > maxtime {
+            return (0, 0);
+        }
+        prices
+            .range((Included(mintime), Included(maxtime)))
+            .fold((0, 0), |(count, sum), (_timestamp, price)| {
+                (count + 1, sum + *price as i64)
+            })
+    }
+
+    #[test]
+    fn test_count_and_sum() {
+        let mut index = RangeIndex::default();
+        for (timestamp, price) in [(30, 3), (10, 1), (20, 2), (i32::MIN, -5), (i32::MAX, 7)] {
+            index.insert(timestamp, price);
+        }
+        assert_eq!(index.len(), 5);
+        assert_eq!(index.count_and_sum(10, 30), (3, 6));
+        assert_eq!(index.count_and_sum(11, 29), (1, 2));
+        assert_eq!(index.count_and_sum(i32::MIN, i32::MAX), (5, 8));
+        assert_eq!(index.count_and_sum(31, i32::MAX - 1), (0, 0));
+        assert_eq!(index.count_and_sum(30, 10), (0, 0));
+    }
+
+    #[test]
+    fn test_insert_replaces() {
+        let mut index = RangeIndex::default();
+        index.insert(1, 10);
+        index.insert(2, 20);
+        index.insert(1, -10);
+        assert_eq!(index.len(), 2);
+        assert_eq!(index.count_and_sum(1, 2), (2, 10));
+    }
+
+    proptest! {
+        #[test]
+        fn prop_matches_naive_fold(
+            // A narrow timestamp range so replacements are common
+            inserts in prop::collection::vec((-200i32..200, any::<i32>()), 0..500),
+            queries in prop::collection::vec((-250i32..250, -250i32..250), 1..50),
+        ) {
+            let mut index = RangeIndex::default();
+            let mut prices = BTreeMap::new();
+            for (timestamp, price) in inserts {
+                index.insert(timestamp, price);
+                prices.insert(timestamp, price);
+            }
+            prop_assert_eq!(index.len(), prices.len());
+            for (mintime, maxtime) in queries {
+                prop_assert_eq!(
+                    index.count_and_sum(mintime, maxtime),
+                    naive(&prices, mintime, maxtime)
+                );
+            }
+        }
+    }
+}
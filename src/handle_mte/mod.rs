@@ -0,0 +1,541 @@
+use std::{
+    io::ErrorKind,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use anyhow::bail;
+use futures::future::BoxFuture;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, error};
+
+use crate::{
+    config::{ConnectionSettings, ServiceConfig},
+    metrics,
+    services::{ServiceKind, TcpService},
+};
+
+mod aggregate;
+mod index;
+mod prices;
+mod sessions;
+
+pub use aggregate::{Aggregate, Answer};
+pub use index::RangeIndex;
+pub use prices::{InsertRules, Prices, Rejected};
+pub use sessions::Sessions;
+
+pub struct MteService {
+    settings: ConnectionSettings,
+    sessions: Arc<Sessions>,
+}
+
+impl MteService {
+    pub fn new(config: &ServiceConfig) -> anyhow::Result<Self> {
+        Ok(Self {
+            settings: config.connection_settings(),
+            sessions: Arc::new(Sessions::load(config.session_log.as_deref())?),
+        })
+    }
+}
+
+impl TcpService for MteService {
+    fn handle(
+        &self,
+        mut socket: TcpStream,
+        shutdown: CancellationToken,
+    ) -> BoxFuture<'static, anyhow::Result<()>> {
+        let settings = self.settings;
+        let sessions = self.sessions.clone();
+        Box::pin(async move { handle_is_mte(&mut socket, settings, sessions, shutdown).await })
+    }
+}
+
+/// Where a connection's inserts go: prices of its own that vanish with it, or the session of
+/// the client id it opened, shared with that client's other connections.
+enum Store {
+    Connection(Prices),
+    Session { id: i32, prices: Arc<Mutex<Prices>> },
+}
+
+/// Serves one connection until the client disconnects, goes idle for longer than the idle
+/// timeout, or shutdown. A message the protocol has no answer for, or an insert the duplicate
+/// policy or price limit refuses, ends it with an error.
+pub async fn handle_is_mte(
+    stream: &mut TcpStream,
+    settings: ConnectionSettings,
+    sessions: Arc<Sessions>,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let metrics = metrics::service(ServiceKind::MeansToEnd);
+    let rules = InsertRules {
+        duplicates: settings.duplicate_timestamps,
+        max_prices: settings.max_prices,
+    };
+    let mut buf = [0u8; 9];
+    let mut store = Store::Connection(Prices::default());
+    let mut started = false;
+    // A half-read message is simply dropped on shutdown or timeout, there is no reply owed for it
+    while let Some(read) = shutdown
+        .run_until_cancelled(read_message(stream, &mut buf, settings.idle_timeout))
+        .await
+    {
+        match read {
+            Ok(_req) => {
+                let method_type = buf[0];
+                let arg_1 = i32::from_be_bytes(buf[1..5].try_into()?);
+                let arg_2 = i32::from_be_bytes(buf[5..9].try_into()?);
+                match method_type {
+                    0x49 => {
+                        metrics.messages_decoded.inc();
+                        match &mut store {
+                            Store::Connection(prices) => {
+                                prices.try_insert(arg_1, arg_2, rules)?;
+                            }
+                            Store::Session { id, .. } => {
+                                sessions.insert(*id, arg_1, arg_2, rules).await?
+                            }
+                        }
+                    }
+                    // Session: arg_1 is the client id, arg_2 is ignored
+                    0x53 => {
+                        if started {
+                            metrics.decode_errors.inc();
+                            error!(id = arg_1, "Session opened after the first insert or query");
+                            bail!("session must be opened before any insert or query");
+                        }
+                        metrics.messages_decoded.inc();
+                        debug!(id = arg_1, "Session opened");
+                        store = Store::Session {
+                            id: arg_1,
+                            prices: sessions.get(arg_1),
+                        };
+                    }
+                    opcode => {
+                        let Some(aggregate) = Aggregate::from_opcode(opcode) else {
+                            metrics.decode_errors.inc();
+                            error!(opcode, "Unknown request");
+                            return Err(anyhow::anyhow!("Unknown request"));
+                        };
+                        metrics.messages_decoded.inc();
+                        let answer = match &store {
+                            Store::Connection(prices) => aggregate.answer(prices, arg_1, arg_2),
+                            Store::Session { prices, .. } => {
+                                aggregate.answer(&prices.lock().unwrap(), arg_1, arg_2)
+                            }
+                        };
+                        stream.write_all(&answer.to_be_bytes()).await?;
+                        metrics.messages_encoded.inc();
+                    }
+                }
+                started = true;
+            }
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => {
+                debug!("Client disconnected");
+                break;
+            }
+            Err(e) if e.kind() == ErrorKind::TimedOut => {
+                debug!(timeout = ?settings.idle_timeout, "Closing idle connection");
+                break;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    Ok(())
+}
+
+/// Fills `buf` with the next message, failing with `TimedOut` if none completes within
+/// `idle_timeout`.
+async fn read_message(
+    stream: &mut TcpStream,
+    buf: &mut [u8; 9],
+    idle_timeout: Option<Duration>,
+) -> std::io::Result<usize> {
+    match idle_timeout {
+        Some(idle_timeout) => tokio::time::timeout(idle_timeout, stream.read_exact(buf))
+            .await
+            .unwrap_or_else(|_| Err(ErrorKind::TimedOut.into())),
+        None => stream.read_exact(buf).await,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpListener,
+        net::TcpStream,
+    };
+
+    // Builds a 9-byte insert message
+    fn build_insert(timestamp: i32, price: i32) -> [u8; 9] {
+        let mut buf = [0u8; 9];
+        buf[0] = b'I';
+        buf[1..5].copy_from_slice(&timestamp.to_be_bytes());
+        buf[5..9].copy_from_slice(&price.to_be_bytes());
+        buf
+    }
+
+    // Builds a 9-byte query message
+    fn build_query(mintime: i32, maxtime: i32) -> [u8; 9] {
+        let mut buf = [0u8; 9];
+        buf[0] = b'Q';
+        buf[1..5].copy_from_slice(&mintime.to_be_bytes());
+        buf[5..9].copy_from_slice(&maxtime.to_be_bytes());
+        buf
+    }
+
+    // Builds a 9-byte aggregate query message for `opcode`
+    fn build_aggregate(opcode: u8, mintime: i32, maxtime: i32) -> [u8; 9] {
+        let mut buf = build_query(mintime, maxtime);
+        buf[0] = opcode;
+        buf
+    }
+
+    // Builds a 9-byte session message
+    fn build_session(id: i32) -> [u8; 9] {
+        let mut buf = [0u8; 9];
+        buf[0] = b'S';
+        buf[1..5].copy_from_slice(&id.to_be_bytes());
+        buf
+    }
+
+    // Parse the 4-byte response as i32
+    fn parse_response(resp: &[u8]) -> i32 {
+        let arr: [u8; 4] = resp.try_into().unwrap();
+        i32::from_be_bytes(arr)
+    }
+
+    // Helper to start the server in background on ephemeral port and return address
+    async fn start_server() -> anyhow::Result<std::net::SocketAddr> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let sessions = Arc::new(Sessions::default());
+
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                let sessions = sessions.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle_is_mte(
+                        &mut socket,
+                        ConnectionSettings::default(),
+                        sessions,
+                        CancellationToken::new(),
+                    )
+                    .await
+                    {
+                        eprintln!("server error: {:?}", e);
+                    }
+                });
+            }
+        });
+
+        // Give server a tiny moment to start
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        Ok(addr)
+    }
+
+    #[tokio::test]
+    async fn test_insert_and_query_basic() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let mut stream = TcpStream::connect(addr).await?;
+
+        // Insert timestamp=12345 price=101
+        stream.write_all(&build_insert(12345, 101)).await?;
+
+        // Insert timestamp=12346 price=102
+        stream.write_all(&build_insert(12346, 102)).await?;
+
+        // Query average from 12345 to 12346 inclusive
+        stream.write_all(&build_query(12345, 12346)).await?;
+
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await?;
+
+        let avg = parse_response(&buf);
+
+        assert!(avg == 101 || avg == 102);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_empty_returns_zero() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+        let mut stream = TcpStream::connect(addr).await?;
+
+        // Query with no inserts should return 0
+        stream.write_all(&build_query(0, 1000)).await?;
+
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await?;
+
+        let avg = parse_response(&buf);
+
+        assert_eq!(avg, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_query_invalid_range_returns_zero() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+        let mut stream = TcpStream::connect(addr).await?;
+
+        // Insert one data point
+        stream.write_all(&build_insert(10, 50)).await?;
+
+        // Query where mintime > maxtime
+        stream.write_all(&build_query(100, 50)).await?;
+
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await?;
+
+        let avg = parse_response(&buf);
+        assert_eq!(avg, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_insert_negative_price_and_query() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+        let mut stream = TcpStream::connect(addr).await?;
+
+        stream.write_all(&build_insert(500, -20)).await?;
+        stream.write_all(&build_insert(600, 40)).await?;
+
+        stream.write_all(&build_query(400, 700)).await?;
+
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await?;
+
+        let avg = parse_response(&buf);
+        assert_eq!(avg, 10);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_multiple_queries_after_inserts() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+        let mut stream = TcpStream::connect(addr).await?;
+
+        for (ts, price) in &[(1, 100), (2, 200), (3, 300)] {
+            stream.write_all(&build_insert(*ts, *price)).await?;
+        }
+
+        // Query full range
+        stream.write_all(&build_query(1, 3)).await?;
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await?;
+        let avg = parse_response(&buf);
+        assert_eq!(avg, 200);
+
+        // Query partial range
+        stream.write_all(&build_query(2, 2)).await?;
+        stream.read_exact(&mut buf).await?;
+        let avg2 = parse_response(&buf);
+        assert_eq!(avg2, 200);
+
+        // Query out of range
+        stream.write_all(&build_query(10, 20)).await?;
+        stream.read_exact(&mut buf).await?;
+        let avg3 = parse_response(&buf);
+        assert_eq!(avg3, 0);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_session_survives_reconnect() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+
+        let mut stream = TcpStream::connect(addr).await?;
+        stream.write_all(&build_session(42)).await?;
+        stream.write_all(&build_insert(1, 100)).await?;
+        stream.write_all(&build_insert(2, 300)).await?;
+        stream.write_all(&build_query(1, 2)).await?;
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(parse_response(&buf), 200);
+        drop(stream);
+
+        // Same client again, from a new connection
+        let mut stream = TcpStream::connect(addr).await?;
+        stream.write_all(&build_session(42)).await?;
+        stream.write_all(&build_insert(3, 500)).await?;
+        stream.write_all(&build_query(1, 3)).await?;
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(parse_response(&buf), 300);
+
+        // Another client and an anonymous connection see none of it
+        for first in [Some(build_session(7)), None] {
+            let mut stream = TcpStream::connect(addr).await?;
+            if let Some(session) = first {
+                stream.write_all(&session).await?;
+            }
+            stream.write_all(&build_query(1, 3)).await?;
+            stream.read_exact(&mut buf).await?;
+            assert_eq!(parse_response(&buf), 0);
+        }
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_session_after_insert_rejected() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+        let mut stream = TcpStream::connect(addr).await?;
+
+        stream.write_all(&build_insert(1, 100)).await?;
+        stream.write_all(&build_session(42)).await?;
+
+        let mut buf = [0u8; 4];
+        assert_eq!(stream.read(&mut buf).await?, 0, "connection left open");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_aggregates() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+        let mut stream = TcpStream::connect(addr).await?;
+
+        for (ts, price) in [(4, 40), (1, -10), (3, 30), (2, 20), (5, i32::MAX)] {
+            stream.write_all(&build_insert(ts, price)).await?;
+        }
+
+        // Empty and inverted ranges answer zeros of the same width as the full one
+        let cases = [
+            (b'L', Answer::Price(-10)),
+            (b'H', Answer::Price(40)),
+            (b'C', Answer::Count(4)),
+            (b'U', Answer::Sum(80)),
+            (b'D', Answer::Price(20)),
+            (0x80, Answer::Price(-10)),
+            (0x80 + 75, Answer::Price(30)),
+            (0x80 + 100, Answer::Price(40)),
+        ];
+        for (opcode, answer) in cases {
+            let full = answer.to_be_bytes();
+            let zeros = vec![0u8; full.len()];
+            for (mintime, maxtime, expected) in [(1, 4, &full), (10, 20, &zeros), (4, 1, &zeros)] {
+                stream
+                    .write_all(&build_aggregate(opcode, mintime, maxtime))
+                    .await?;
+                let mut buf = vec![0u8; expected.len()];
+                stream.read_exact(&mut buf).await?;
+                assert_eq!(
+                    &buf, expected,
+                    "opcode {:#x} over {}..={}",
+                    opcode, mintime, maxtime
+                );
+            }
+        }
+
+        // The sum is 64 bits wide where a 32-bit one would overflow
+        stream.write_all(&build_aggregate(b'U', 1, 5)).await?;
+        let mut buf = [0u8; 8];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(i64::from_be_bytes(buf), 80 + i32::MAX as i64);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_aggregates_in_session() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+        let mut stream = TcpStream::connect(addr).await?;
+        stream.write_all(&build_session(9)).await?;
+        stream.write_all(&build_insert(1, 5)).await?;
+        stream.write_all(&build_insert(2, 7)).await?;
+        drop(stream);
+
+        let mut stream = TcpStream::connect(addr).await?;
+        stream.write_all(&build_session(9)).await?;
+        stream.write_all(&build_aggregate(b'C', 1, 2)).await?;
+        let mut buf = [0u8; 4];
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(u32::from_be_bytes(buf), 2);
+        stream.write_all(&build_aggregate(b'H', 1, 2)).await?;
+        stream.read_exact(&mut buf).await?;
+        assert_eq!(parse_response(&buf), 7);
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_unknown_opcode_closes_connection() -> anyhow::Result<()> {
+        let addr = start_server().await?;
+        let mut stream = TcpStream::connect(addr).await?;
+
+        stream.write_all(&build_aggregate(0xe5, 1, 2)).await?;
+
+        let mut buf = [0u8; 4];
+        assert_eq!(stream.read(&mut buf).await?, 0, "connection left open");
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_disconnect_ends_handler() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client = TcpStream::connect(listener.local_addr()?).await?;
+        let (mut socket, _) = listener.accept().await?;
+        let handler = tokio::spawn(async move {
+            handle_is_mte(
+                &mut socket,
+                ConnectionSettings::default(),
+                Arc::new(Sessions::default()),
+                CancellationToken::new(),
+            )
+            .await
+        });
+
+        client.write_all(&build_insert(1, 100)).await?;
+        // Half a message, then gone
+        client.write_all(&build_query(1, 1)[..4]).await?;
+        drop(client);
+
+        tokio::time::timeout(Duration::from_secs(5), handler).await???;
+        Ok(())
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_idle_connection_closed() -> anyhow::Result<()> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let mut client = TcpStream::connect(listener.local_addr()?).await?;
+        let (mut socket, _) = listener.accept().await?;
+        let settings = ConnectionSettings {
+            idle_timeout: Some(Duration::from_secs(30)),
+            ..ConnectionSettings::default()
+        };
+        let handler = tokio::spawn(async move {
+            handle_is_mte(
+                &mut socket,
+                settings,
+                Arc::new(Sessions::default()),
+                CancellationToken::new(),
+            )
+            .await
+        });
+
+        client.write_all(&build_insert(1, 100)).await?;
+        tokio::time::sleep(Duration::from_secs(20)).await;
+        // Still open: activity restarts the clock
+        client.write_all(&build_query(1, 1)).await?;
+        let mut buf = [0u8; 4];
+        client.read_exact(&mut buf).await?;
+        assert_eq!(parse_response(&buf), 100);
+
+        tokio::time::sleep(Duration::from_secs(31)).await;
+        handler.await??;
+        assert_eq!(client.read(&mut buf).await?, 0, "connection left open");
+        Ok(())
+    }
+}
@@ -0,0 +1,174 @@
+use std::{collections::BTreeMap, fmt, ops::Bound::Included};
+
+use super::index::RangeIndex;
+use crate::config::DuplicatePolicy;
+
+/// How [`Prices::try_insert`] treats a timestamp it already has, and how many it may hold.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct InsertRules {
+    pub duplicates: DuplicatePolicy,
+    pub max_prices: Option<usize>,
+}
+
+/// Why [`Prices::try_insert`] refused an insert.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rejected {
+    Duplicate(i32),
+    Full(usize),
+}
+
+impl fmt::Display for Rejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejected::Duplicate(timestamp) => write!(f, "duplicate timestamp {}", timestamp),
+            Rejected::Full(max) => write!(f, "price limit of {} reached", max),
+        }
+    }
+}
+
+impl std::error::Error for Rejected {}
+
+/// Prices by timestamp. Counts and totals over a range come from the index; everything that
+/// needs the prices themselves walks the map.
+#[derive(Default)]
+pub struct Prices {
+    by_timestamp: BTreeMap<i32, i32>,
+    index: RangeIndex,
+}
+
+impl Prices {
+    /// Sets the price at `timestamp`, replacing the one already there.
+    pub fn insert(&mut self, timestamp: i32, price: i32) {
+        self.by_timestamp.insert(timestamp, price);
+        self.index.insert(timestamp, price);
+    }
+
+    /// Inserts following `rules`. `Ok(false)` when a duplicate was ignored under keep-first,
+    /// so there is nothing to record.
+    pub fn try_insert(
+        &mut self,
+        timestamp: i32,
+        price: i32,
+        rules: InsertRules,
+    ) -> Result<bool, Rejected> {
+        let stored = self.admits(timestamp, rules)?;
+        if stored {
+            self.insert(timestamp, price);
+        }
+        Ok(stored)
+    }
+
+    /// What [`Prices::try_insert`] at `timestamp` would return, without inserting.
+    pub fn admits(&self, timestamp: i32, rules: InsertRules) -> Result<bool, Rejected> {
+        if self.by_timestamp.contains_key(&timestamp) {
+            match rules.duplicates {
+                DuplicatePolicy::Reject => return Err(Rejected::Duplicate(timestamp)),
+                DuplicatePolicy::KeepFirst => return Ok(false),
+                DuplicatePolicy::Overwrite => {}
+            }
+        } else if let Some(max) = rules.max_prices
+            && self.len() >= max
+        {
+            return Err(Rejected::Full(max));
+        }
+        Ok(true)
+    }
+
+    /// The prices at timestamps in `[mintime, maxtime]`, none when the range is inverted.
+    pub fn range(&self, mintime: i32, maxtime: i32) -> impl Iterator<Item = i32> + '_ {
+        (mintime <= maxtime)
+            .then(|| {
+                self.by_timestamp
+                    .range((Included(mintime), Included(maxtime)))
+            })
+            .into_iter()
+            .flatten()
+            .map(|(_timestamp, price)| *price)
+    }
+
+    /// How many prices [`Prices::range`] would yield, and their total, in O(log n).
+    pub fn count_and_sum(&self, mintime: i32, maxtime: i32) -> (u32, i64) {
+        self.index.count_and_sum(mintime, maxtime)
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_timestamp.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_timestamp.is_empty()
+    }
+}
+
+impl<const N: usize> From<[(i32, i32); N]> for Prices {
+    fn from(entries: [(i32, i32); N]) -> Self {
+        let mut prices = Self::default();
+        for (timestamp, price) in entries {
+            prices.insert(timestamp, price);
+        }
+        prices
+    }
+}
+
+impl PartialEq for Prices {
+    fn eq(&self, other: &Self) -> bool {
+        self.by_timestamp == other.by_timestamp
+    }
+}
+
+impl fmt::Debug for Prices {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.by_timestamp.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(duplicates: DuplicatePolicy, max_prices: Option<usize>) -> InsertRules {
+        InsertRules {
+            duplicates,
+            max_prices,
+        }
+    }
+
+    #[test]
+    fn test_duplicate_policies() {
+        let mut prices = Prices::from([(1, 10)]);
+        let overwrite = rules(DuplicatePolicy::Overwrite, None);
+        assert_eq!(prices.try_insert(1, 11, overwrite), Ok(true));
+        assert_eq!(prices, Prices::from([(1, 11)]));
+
+        let keep_first = rules(DuplicatePolicy::KeepFirst, None);
+        assert_eq!(prices.try_insert(1, 12, keep_first), Ok(false));
+        assert_eq!(prices.count_and_sum(1, 1), (1, 11));
+
+        let reject = rules(DuplicatePolicy::Reject, None);
+        assert_eq!(
+            prices.try_insert(1, 13, reject),
+            Err(Rejected::Duplicate(1))
+        );
+        assert_eq!(prices.try_insert(2, 20, reject), Ok(true));
+        assert_eq!(prices, Prices::from([(1, 11), (2, 20)]));
+    }
+
+    #[test]
+    fn test_max_prices() {
+        let mut prices = Prices::default();
+        let limited = rules(DuplicatePolicy::Overwrite, Some(2));
+        assert_eq!(prices.try_insert(1, 10, limited), Ok(true));
+        assert_eq!(prices.try_insert(2, 20, limited), Ok(true));
+        assert_eq!(prices.try_insert(3, 30, limited), Err(Rejected::Full(2)));
+        // Replacing a price does not take another slot
+        assert_eq!(prices.try_insert(2, 21, limited), Ok(true));
+        assert_eq!(prices, Prices::from([(1, 10), (2, 21)]));
+    }
+
+    #[test]
+    fn test_inverted_range_is_empty() {
+        let prices = Prices::from([(1, 10), (2, 20)]);
+        assert_eq!(prices.range(2, 1).count(), 0);
+        assert_eq!(prices.count_and_sum(2, 1), (0, 0));
+    }
+}
@@ -0,0 +1,242 @@
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io::Read,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use anyhow::Context;
+use tokio::{fs::File, io::AsyncWriteExt};
+use tracing::{info, warn};
+
+use super::{InsertRules, Prices};
+
+/// Client id, timestamp and price, each a big-endian `i32`.
+const RECORD_LEN: usize = 12;
+
+/// Price stores of clients that opened a session, shared by all their connections. With a log
+/// every insert is appended to it, and the stores are rebuilt from it on startup.
+#[derive(Default)]
+pub struct Sessions {
+    stores: Mutex<HashMap<i32, Arc<Mutex<Prices>>>>,
+    log: Option<tokio::sync::Mutex<File>>,
+}
+
+impl Sessions {
+    /// Replays the log at `path`, creating it if needed. A record cut short by a crash is
+    /// dropped and the file truncated back to the last whole record, so appends stay aligned.
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let context = || format!("failed to open session log {}", path.display());
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)
+            .with_context(context)?;
+        let mut bytes = vec![];
+        file.read_to_end(&mut bytes).with_context(context)?;
+
+        let mut stores: HashMap<i32, Prices> = HashMap::new();
+        let records = bytes.chunks_exact(RECORD_LEN);
+        let torn = records.remainder().len();
+        for record in records {
+            let field = |i: usize| i32::from_be_bytes(record[i * 4..i * 4 + 4].try_into().unwrap());
+            stores
+                .entry(field(0))
+                .or_default()
+                .insert(field(1), field(2));
+        }
+        if torn > 0 {
+            warn!(path = %path.display(), bytes = torn, "Dropping torn record at end of session log");
+            file.set_len((bytes.len() - torn) as u64)
+                .with_context(context)?;
+        }
+        info!(
+            path = %path.display(),
+            sessions = stores.len(),
+            records = bytes.len() / RECORD_LEN,
+            "Replayed session log"
+        );
+
+        Ok(Self {
+            stores: Mutex::new(
+                stores
+                    .into_iter()
+                    .map(|(id, prices)| (id, Arc::new(Mutex::new(prices))))
+                    .collect(),
+            ),
+            log: Some(tokio::sync::Mutex::new(File::from_std(file))),
+        })
+    }
+
+    /// Sessions that only live as long as the process, or replayed from `path` when given.
+    pub fn load(path: Option<&Path>) -> anyhow::Result<Self> {
+        match path {
+            Some(path) => Self::open(path),
+            None => Ok(Self::default()),
+        }
+    }
+
+    /// The prices of client `id`, empty for a client not seen before.
+    pub fn get(&self, id: i32) -> Arc<Mutex<Prices>> {
+        self.stores.lock().unwrap().entry(id).or_default().clone()
+    }
+
+    /// Inserts into client `id`'s prices following `rules`. With a log, an insert that changes
+    /// anything is appended first and applied only once written, all under the log's lock, so
+    /// the log holds inserts in the order they were applied and every one that was.
+    pub async fn insert(
+        &self,
+        id: i32,
+        timestamp: i32,
+        price: i32,
+        rules: InsertRules,
+    ) -> anyhow::Result<()> {
+        let prices = self.get(id);
+        let Some(log) = &self.log else {
+            prices.lock().unwrap().try_insert(timestamp, price, rules)?;
+            return Ok(());
+        };
+        let mut log = log.lock().await;
+        // Session prices only change under the log lock, so the check still holds once written
+        if !prices.lock().unwrap().admits(timestamp, rules)? {
+            return Ok(());
+        }
+        let mut record = [0u8; RECORD_LEN];
+        for (i, value) in [id, timestamp, price].into_iter().enumerate() {
+            record[i * 4..i * 4 + 4].copy_from_slice(&value.to_be_bytes());
+        }
+        log.write_all(&record).await?;
+        log.flush().await?;
+        prices.lock().unwrap().insert(timestamp, price);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::config::DuplicatePolicy;
+
+    fn log_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!(
+            "prime_time-sessions-{}-{}.log",
+            name,
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[tokio::test]
+    async fn test_replay_after_restart() -> anyhow::Result<()> {
+        let path = log_path("replay");
+        let sessions = Sessions::open(&path)?;
+        sessions.insert(1, 10, 100, InsertRules::default()).await?;
+        sessions.insert(1, 20, 200, InsertRules::default()).await?;
+        sessions.insert(2, 10, 5, InsertRules::default()).await?;
+        sessions.insert(1, 10, 150, InsertRules::default()).await?;
+        drop(sessions);
+
+        let sessions = Sessions::open(&path)?;
+        assert_eq!(
+            *sessions.get(1).lock().unwrap(),
+            Prices::from([(10, 150), (20, 200)])
+        );
+        assert_eq!(*sessions.get(2).lock().unwrap(), Prices::from([(10, 5)]));
+        assert!(sessions.get(3).lock().unwrap().is_empty());
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_torn_record_dropped() -> anyhow::Result<()> {
+        let path = log_path("torn");
+        let sessions = Sessions::open(&path)?;
+        sessions.insert(7, 1, 1, InsertRules::default()).await?;
+        sessions.insert(7, 2, 2, InsertRules::default()).await?;
+        drop(sessions);
+        let len = std::fs::metadata(&path)?.len();
+        OpenOptions::new()
+            .write(true)
+            .open(&path)?
+            .set_len(len - 5)?;
+
+        let sessions = Sessions::open(&path)?;
+        assert_eq!(*sessions.get(7).lock().unwrap(), Prices::from([(1, 1)]));
+        // Later records land right after the last whole one
+        sessions.insert(7, 3, 3, InsertRules::default()).await?;
+        drop(sessions);
+        let sessions = Sessions::open(&path)?;
+        assert_eq!(
+            *sessions.get(7).lock().unwrap(),
+            Prices::from([(1, 1), (3, 3)])
+        );
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_ignored_duplicates_not_logged() -> anyhow::Result<()> {
+        let path = log_path("keep-first");
+        let keep_first = InsertRules {
+            duplicates: DuplicatePolicy::KeepFirst,
+            max_prices: None,
+        };
+        let sessions = Sessions::open(&path)?;
+        sessions.insert(1, 10, 100, keep_first).await?;
+        sessions.insert(1, 10, 999, keep_first).await?;
+        drop(sessions);
+        assert_eq!(std::fs::metadata(&path)?.len(), RECORD_LEN as u64);
+
+        // Replayed without the policy, the log still holds the first price
+        let sessions = Sessions::open(&path)?;
+        assert_eq!(*sessions.get(1).lock().unwrap(), Prices::from([(10, 100)]));
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_concurrent_overwrites_replay_last_applied() -> anyhow::Result<()> {
+        let path = log_path("concurrent");
+        let sessions = Arc::new(Sessions::open(&path)?);
+        let writers: Vec<_> = (0..4)
+            .map(|writer| {
+                let sessions = Arc::clone(&sessions);
+                tokio::spawn(async move {
+                    for n in 0..50 {
+                        let price = writer * 1000 + n;
+                        sessions
+                            .insert(1, 10, price, InsertRules::default())
+                            .await?;
+                    }
+                    anyhow::Ok(())
+                })
+            })
+            .collect();
+        for writer in writers {
+            writer.await??;
+        }
+        let applied = sessions.get(1).lock().unwrap().range(10, 10).next();
+        drop(sessions);
+
+        let sessions = Sessions::open(&path)?;
+        assert_eq!(
+            sessions.get(1).lock().unwrap().range(10, 10).next(),
+            applied
+        );
+        std::fs::remove_file(&path)?;
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_in_memory() -> anyhow::Result<()> {
+        let sessions = Sessions::load(None)?;
+        sessions.insert(1, 10, 100, InsertRules::default()).await?;
+        assert_eq!(*sessions.get(1).lock().unwrap(), Prices::from([(10, 100)]));
+        Ok(())
+    }
+}
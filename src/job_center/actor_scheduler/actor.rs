@@ -3,11 +3,11 @@ use std::net::SocketAddr;
 use anyhow::{Ok};
 use serde_json::Value;
 use tokio::sync::oneshot::Sender;
-use tracing::warn;
+use tracing::debug;
 
 use crate::job_center::actor_scheduler::{
     job::{Job, JobState},
-    manager::JobManager,
+    manager::{JobManager, JobStats},
 };
 
 pub enum JobCommand {
@@ -32,6 +32,9 @@ pub enum JobCommand {
         addr: SocketAddr,
         resp: Sender<anyhow::Result<bool>>,
     },
+    Stats {
+        resp: Sender<JobStats>,
+    },
 }
 
 impl JobManager {
@@ -61,6 +64,7 @@ impl JobManager {
                         respond(resp, Ok(false));
                     }
                 },
+                JobCommand::Stats { resp } => respond(resp, self.stats()),
                 JobCommand::Abort { id, addr, resp } => {
                     let job_to_add_back = match self.jobs.get_mut(&id) {
                         None => {
@@ -69,7 +73,6 @@ impl JobManager {
                         }
                         Some(job) => match job.state {
                             JobState::Given(given_to) => {
-                                warn!("Found Job {:?}", job);
                                 if given_to == addr {
                                     job.state = JobState::Ready;
                                     respond(resp, Ok(true));
@@ -81,14 +84,22 @@ impl JobManager {
                                 }
                             }
                             _ => {
-                                warn!("Found a job {:?}", job);
+                                debug!(
+                                    job_id = id,
+                                    state = job.state.name(),
+                                    "Abort of a job that was not given"
+                                );
                                 respond(resp, Ok(false));
                                 None
                             }
                         },
                     };
                     if let Some(mut job) = job_to_add_back {
-                        warn!("ADDING BACK {:?}", job);
+                        debug!(
+                            job_id = id,
+                            queue = %job.queue,
+                            "Aborted job returned to its queue"
+                        );
                         job.state = JobState::Ready;
                         self.add_to_queue(&job.queue, id, job.priority);
                     }
@@ -104,6 +115,6 @@ where
     T: std::fmt::Debug,
 {
     if let Err(e) = tx.send(val) {
-        tracing::warn!("Failed to send response: {:?}", e);
+        tracing::warn!(response = ?e, "Failed to send response, requester is gone");
     }
 }
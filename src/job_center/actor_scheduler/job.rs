@@ -27,6 +27,16 @@ pub enum JobState {
     Deleted,
 }
 
+impl JobState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            JobState::Ready => "ready",
+            JobState::Given(_) => "given",
+            JobState::Deleted => "deleted",
+        }
+    }
+}
+
 impl Job {
     pub fn new(queue: String, value: Value, priority: usize) -> Self {
         Self {
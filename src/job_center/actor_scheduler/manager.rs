@@ -1,5 +1,5 @@
 use std::{
-    collections::HashMap,
+    collections::{BTreeMap, HashMap},
     net::SocketAddr,
     sync::{Arc, Mutex},
 };
@@ -12,6 +12,15 @@ use crate::job_center::actor_scheduler::{
     queue::{Entry, Queue},
 };
 
+/// Snapshot of the manager for the metrics endpoint.
+#[derive(Debug, Default, PartialEq)]
+pub struct JobStats {
+    /// Job count keyed by queue and state name.
+    pub jobs: BTreeMap<(String, &'static str), usize>,
+    /// Clients blocked in a waiting `get`, per queue.
+    pub waiting: BTreeMap<String, usize>,
+}
+
 pub struct JobManager {
     pub jobs: HashMap<usize, Job>,
     queues: HashMap<String, Queue>,
@@ -38,26 +47,55 @@ impl JobManager {
         id
     }
 
-    pub fn add_to_queue(&mut self, queue: &str, id: usize, priority: usize) {
-        let queue = self.queues.entry(queue.to_string()).or_default();
+    pub fn add_to_queue(&mut self, name: &str, id: usize, priority: usize) {
+        let queue = self.queues.entry(name.to_string()).or_default();
         while let Some((addr, resp)) = queue.senders.pop() {
             let resp_guard = resp.lock().unwrap().take().unwrap();
             if let Some(job) = self.jobs.get_mut(&id) {
-                if let Err(_) = resp_guard.send(Ok(Some((id, job.clone())))) {
+                if resp_guard.send(Ok(Some((id, job.clone())))).is_err() {
                     continue;
                 }
                 job.state = JobState::Given(addr);
                 return;
             }
         }
-        let entry = Entry {
-            id,
-            priority: priority,
-        };
-        warn!("ADDING ENTRY, {:?}", entry);
+        let entry = Entry { id, priority };
+        debug!(
+            job_id = entry.id,
+            queue = name,
+            priority = entry.priority,
+            "Job queued"
+        );
         queue.jobs.push(entry);
     }
 
+    pub fn stats(&self) -> JobStats {
+        let mut stats = JobStats::default();
+        for job in self.jobs.values() {
+            *stats
+                .jobs
+                .entry((job.queue.clone(), job.state.name()))
+                .or_default() += 1;
+        }
+        for (name, queue) in &self.queues {
+            // A waiting client sits in every queue it asked for until one of them answers
+            let waiting = queue
+                .senders
+                .iter()
+                .filter(|(_, resp)| {
+                    resp.lock()
+                        .unwrap()
+                        .as_ref()
+                        .is_some_and(|tx| !tx.is_closed())
+                })
+                .count();
+            if waiting > 0 {
+                stats.waiting.insert(name.clone(), waiting);
+            }
+        }
+        stats
+    }
+
     pub fn get_job(&self, id: usize) -> Option<&Job> {
         match self.jobs.get(&id) {
             None => None,
@@ -70,7 +108,7 @@ impl JobManager {
 
     pub fn mark_job(&mut self, id: usize) {
         if let Some(job) = self.jobs.get_mut(&id) {
-            debug!("Marking job {:?}", job);
+            debug!(job_id = id, queue = %job.queue, "Job deleted");
             job.state = JobState::Deleted
         };
     }
@@ -97,7 +135,7 @@ impl JobManager {
                     if self
                         .jobs
                         .get(&entry.id)
-                        .map_or(true, |j| j.state != JobState::Ready)
+                        .is_none_or(|j| j.state != JobState::Ready)
                     {
                         // Job is gone or not ready, pop it.
                         queue.jobs.pop();
@@ -108,13 +146,12 @@ impl JobManager {
                 }
 
                 // If a valid job is now at the top, consider it.
-                if let Some(entry) = queue.jobs.peek() {
-                    if top_job_entry
+                if let Some(entry) = queue.jobs.peek()
+                    && top_job_entry
                         .as_ref()
-                        .map_or(true, |(_, top)| entry.priority > top.priority)
-                    {
-                        top_job_entry = Some((queue_name.clone(), entry.clone()));
-                    }
+                        .is_none_or(|(_, top)| entry.priority > top.priority)
+                {
+                    top_job_entry = Some((queue_name.clone(), entry.clone()));
                 }
             }
         }
@@ -145,6 +182,6 @@ where
     T: std::fmt::Debug,
 {
     if let Err(e) = tx.send(val) {
-        tracing::warn!("Failed to send response: {:?}", e);
+        warn!(response = ?e, "Failed to send response, requester is gone");
     }
 }
@@ -19,7 +19,7 @@ impl Eq for Entry {}
 
 impl PartialOrd for Entry {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        Some(self.priority.cmp(&other.priority))
+        Some(self.cmp(other))
     }
 }
 impl Ord for Entry {
@@ -1,29 +1,47 @@
 
 use futures::StreamExt;
 use tokio::{net::TcpStream, sync::mpsc::{self, Sender}};
-use tokio_util::codec::{Framed, LinesCodec};
+use tokio_util::{
+    codec::{Framed, LinesCodec},
+    sync::CancellationToken,
+};
+use tracing::{Instrument, error, warn};
 
-use crate::job_center::{actor_scheduler::actor::JobCommand, handle_request::handle_request, handle_response::response_handler};
+use crate::{
+    config::ConnectionSettings,
+    metrics,
+    services::{RateLimited, ServiceKind},
+    job_center::{actor_scheduler::actor::JobCommand, handle_request::handle_request, handle_response::response_handler},
+};
 
 
 pub async fn handle_job_center(
     socket: TcpStream,
-    job_command_sender: Sender<JobCommand>
+    job_command_sender: Sender<JobCommand>,
+    settings: ConnectionSettings,
+    shutdown: CancellationToken,
 ) -> anyhow::Result<()> {
     let addr = socket.peer_addr()?;
 
-    let framed: Framed<TcpStream, LinesCodec> = Framed::new(socket, LinesCodec::new());
+    let framed: Framed<TcpStream, LinesCodec> = Framed::new(
+        socket,
+        LinesCodec::new_with_max_length(settings.max_line_length),
+    );
     let (writer, reader) = framed.split();
+    let reader = RateLimited::new(
+        reader,
+        settings.rate_limit,
+        metrics::service(ServiceKind::JobCenter),
+    );
 
-    let (mut tx, rx) = mpsc::channel(1_000);
+    let (mut tx, rx) = mpsc::channel(settings.channel_capacity);
 
-    let writer_task = { tokio::spawn(async move { response_handler(writer,  rx).await }) };
+    let writer_task = tokio::spawn(response_handler(writer, rx).in_current_span());
 
-    let reader_task = {
-        tokio::spawn(async move {
-            handle_request(reader, &addr, job_command_sender, &mut tx).await
-        })
-    };
+    let reader_task = tokio::spawn(
+        async move { handle_request(reader, &addr, job_command_sender, &mut tx, shutdown).await }
+            .in_current_span(),
+    );
 
     // FIXED: Wait for BOTH tasks to complete before exiting
     let (reader_result, writer_result) = tokio::join!(reader_task, writer_task);
@@ -31,14 +49,14 @@ pub async fn handle_job_center(
     // Handle results properly
     match reader_result {
         Ok(Ok(())) => {}
-        Ok(Err(e)) => eprintln!("Reader error: {:?} for {}", e, addr),
-        Err(e) => eprintln!("Reader task panicked: {:?}", e),
+        Ok(Err(e)) => warn!(error = format!("{:#}", e), "Reader failed"),
+        Err(e) => error!(error = %e, "Reader task panicked"),
     }
 
     match writer_result {
         Ok(Ok(())) => {}
-        Ok(Err(e)) => eprintln!("Writer error: {:?} for {}", e, addr),
-        Err(e) => eprintln!("Writer task panicked: {:?}", e),
+        Ok(Err(e)) => warn!(error = format!("{:#}", e), "Writer failed"),
+        Err(e) => error!(error = %e, "Writer task panicked"),
     }
 
     Ok(())
@@ -5,27 +5,50 @@ use tokio::{
     net::TcpStream,
     sync::{mpsc::Sender, oneshot},
 };
-use tokio_util::codec::{Framed, LinesCodec};
-use tracing::{info, warn};
+use tokio_util::{
+    codec::{Framed, LinesCodec},
+    sync::CancellationToken,
+};
+use tracing::{debug, trace, warn};
 
-use crate::job_center::{
-    Request, Response,
-    actor_scheduler::{actor::JobCommand, job::Job},
+use crate::{
+    job_center::{
+        Request, Response,
+        actor_scheduler::{actor::JobCommand, job::Job},
+    },
+    metrics,
+    services::{RateLimited, ServiceKind, Throttled},
 };
 
 pub async fn handle_request(
-    mut reader: SplitStream<Framed<TcpStream, LinesCodec>>,
+    mut reader: RateLimited<SplitStream<Framed<TcpStream, LinesCodec>>>,
     peer_address: &SocketAddr,
     job_command_sender: Sender<JobCommand>,
     writer_tx: &mut Sender<Response>,
+    shutdown: CancellationToken,
 ) -> anyhow::Result<()> {
+    let metrics = metrics::service(ServiceKind::JobCenter);
     let mut working_on = vec![];
-    while let Some(line) = reader.next().await.transpose()? {
-        info!("Received {}", line);
+    while let Some(next) = shutdown.run_until_cancelled(reader.next()).await.flatten() {
+        let Ok(line) = next else {
+            warn!("Message throttled");
+            writer_tx
+                .send(Response::Error {
+                    error: Throttled::MESSAGE.to_string(),
+                })
+                .await?;
+            continue;
+        };
+        let line = line?;
+        trace!(%line, "Received line");
         let req: Request = match serde_json::from_str(&line) {
-            Ok(r) => r,
+            Ok(r) => {
+                metrics.messages_decoded.inc();
+                r
+            }
             Err(e) => {
-                warn!("Failed to parse request from {}: {}", peer_address, e);
+                metrics.decode_errors.inc();
+                warn!(error = %e, "Failed to parse request");
                 writer_tx
                     .send(Response::Error {
                         error: format!("Invalid request: {}", e),
@@ -34,9 +57,10 @@ pub async fn handle_request(
                 continue; // skip to next line
             }
         };
+        debug!(command = req.command(), "Received request");
         match &req {
             Request::Get { queues, wait } => {
-                let (tx, rx) = oneshot::channel::<anyhow::Result<Option<(usize, Job)>>>();
+                let (tx, mut rx) = oneshot::channel::<anyhow::Result<Option<(usize, Job)>>>();
                 job_command_sender
                     .send(JobCommand::Get {
                         queues: queues.clone(),
@@ -46,7 +70,18 @@ pub async fn handle_request(
                     })
                     .await
                     .expect("Failed to send job command");
-                if let Some((id, job)) = rx.await?? {
+                let given = tokio::select! {
+                    given = &mut rx => given??,
+                    _ = shutdown.cancelled() => {
+                        // A job handed out while we were giving up still has to be aborted below
+                        rx.close();
+                        if let Ok(Ok(Some((id, _)))) = rx.try_recv() {
+                            working_on.push(id);
+                        }
+                        break;
+                    }
+                };
+                if let Some((id, job)) = given {
                     working_on.push(id);
                     writer_tx
                         .send(Response::Ok {
@@ -64,7 +99,7 @@ pub async fn handle_request(
                     .send(JobCommand::Put {
                         queue: queue.clone(),
                         job: job.clone(),
-                        pri: pri.clone(),
+                        pri: *pri,
                         resp: tx,
                     })
                     .await
@@ -82,10 +117,7 @@ pub async fn handle_request(
                 let (tx, rx) = oneshot::channel::<anyhow::Result<bool>>();
 
                 job_command_sender
-                    .send(JobCommand::Delete {
-                        id: id.clone(),
-                        resp: tx,
-                    })
+                    .send(JobCommand::Delete { id: *id, resp: tx })
                     .await
                     .expect("Failed to send job command");
 
@@ -106,7 +138,7 @@ pub async fn handle_request(
 
                 job_command_sender
                     .send(JobCommand::Abort {
-                        id: id.clone(),
+                        id: *id,
                         addr: *peer_address,
                         resp: tx,
                     })
@@ -142,7 +174,7 @@ pub async fn handle_request(
 
         job_command_sender
             .send(JobCommand::Abort {
-                id: id,
+                id,
                 addr: *peer_address,
                 resp: tx,
             })
@@ -3,16 +3,16 @@ use futures::SinkExt;
 use futures::stream::SplitSink;
 use tokio::{net::TcpStream, sync::mpsc::Receiver}; 
 use tokio_util::codec::{Framed, LinesCodec};
-use tracing::info;
+use tracing::{debug, warn};
 
-use crate::job_center::Response;
+use crate::{job_center::Response, metrics, services::ServiceKind};
 
 pub async fn response_handler(
     mut writer: SplitSink<Framed<TcpStream, LinesCodec>, String>,
     mut rx: Receiver<Response>,
 ) -> anyhow::Result<()> {
     while let Some(msg) = rx.recv().await {
-        info!("Sending {:?}", msg);
+        debug!(response = ?msg, "Sending response");
         let string_message = serde_json::to_string(&msg)?;
 
         if let Err(e) = writer
@@ -20,14 +20,18 @@ pub async fn response_handler(
             .await
             .context("Failed to send response")
         {
-            eprintln!(
-                "Writer error ({}), shutting down response handler for msg: {:?}",
-                e, msg
+            warn!(
+                error = format!("{:#}", e),
+                response = ?msg,
+                "Writer failed, closing response handler"
             );
             break;
         }
+        metrics::service(ServiceKind::JobCenter)
+            .messages_encoded
+            .inc();
     }
 
-    println!("Response handler exiting");
+    debug!("Response handler exiting");
     Ok(())
 }
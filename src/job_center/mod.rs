@@ -9,6 +9,7 @@ pub mod handle_jobs_center;
 mod handle_request;
 mod handle_response;
 pub mod actor_scheduler;
+pub mod service;
 
 
 #[derive(Debug, Deserialize, Serialize, Clone, PartialEq)]
@@ -56,3 +57,15 @@ pub enum Request {
     #[serde(rename = "abort", alias = "ABORT", alias = "Abort")]
     Abort { id: usize },
 }
+
+impl Request {
+    /// The `request` tag, used as the `command` field in logs.
+    pub fn command(&self) -> &'static str {
+        match self {
+            Request::Put { .. } => "put",
+            Request::Get { .. } => "get",
+            Request::Delete { .. } => "delete",
+            Request::Abort { .. } => "abort",
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use futures::{SinkExt, future::BoxFuture};
+use tokio::{
+    net::TcpStream,
+    sync::{
+        mpsc::{self, Sender, WeakSender},
+        oneshot,
+    },
+    task::JoinHandle,
+};
+use tokio_util::{
+    codec::{Framed, LinesCodec},
+    sync::CancellationToken,
+};
+use tracing::{error, warn};
+
+use crate::{
+    config::{ConnectionSettings, ServiceConfig},
+    job_center::{
+        Response,
+        actor_scheduler::{actor::JobCommand, manager::JobManager},
+        handle_jobs_center::handle_job_center,
+    },
+    metrics::{self, CollectorGuard, Sample},
+    services::{Rejection, ServiceKind, TcpService},
+};
+
+pub struct JobCenterService {
+    sender: Sender<JobCommand>,
+    actor: JoinHandle<JobManager>,
+    settings: ConnectionSettings,
+    _collector: CollectorGuard,
+}
+
+impl JobCenterService {
+    pub fn new(config: &ServiceConfig) -> Self {
+        let (sender, rx) = mpsc::channel(config.actor_channel_capacity());
+        let mut job_manager = JobManager::new(rx);
+        let actor = tokio::spawn(async move {
+            if let Err(e) = job_manager.job_actor().await {
+                error!("JobManager exited with error: {:?}", e);
+            }
+            job_manager
+        });
+        let collector = metrics::add_collector(ServiceKind::JobCenter, {
+            // Weak so the metrics endpoint never keeps the actor alive past shutdown
+            let sender = sender.downgrade();
+            Box::new(move || Box::pin(collect(sender.clone())))
+        });
+        Self {
+            sender,
+            actor,
+            settings: config.connection_settings(),
+            _collector: collector,
+        }
+    }
+}
+
+async fn collect(sender: WeakSender<JobCommand>) -> Vec<Sample> {
+    let Some(sender) = sender.upgrade() else {
+        return vec![];
+    };
+    let (resp, rx) = oneshot::channel();
+    if sender.send(JobCommand::Stats { resp }).await.is_err() {
+        return vec![];
+    }
+    let Ok(stats) = rx.await else {
+        return vec![];
+    };
+
+    let jobs = stats.jobs.into_iter().map(|((queue, state), count)| {
+        Sample::gauge("protohack_jobs", "Jobs by queue and state.", count as f64)
+            .label("queue", queue)
+            .label("state", state)
+    });
+    let waiting = stats.waiting.into_iter().map(|(queue, count)| {
+        Sample::gauge(
+            "protohack_waiting_clients",
+            "Clients blocked in a waiting get.",
+            count as f64,
+        )
+        .label("queue", queue)
+    });
+    jobs.chain(waiting).collect()
+}
+
+impl TcpService for JobCenterService {
+    fn handle(
+        &self,
+        socket: TcpStream,
+        shutdown: CancellationToken,
+    ) -> BoxFuture<'static, anyhow::Result<()>> {
+        Box::pin(handle_job_center(
+            socket,
+            self.sender.clone(),
+            self.settings,
+            shutdown,
+        ))
+    }
+
+    fn reject(&self, socket: TcpStream, rejection: Rejection) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            let response = Response::Error {
+                error: rejection.to_string(),
+            };
+            if let Ok(line) = serde_json::to_string(&response) {
+                let _ = Framed::new(socket, LinesCodec::new()).send(line).await;
+            }
+        })
+    }
+
+    fn finish(self: Box<Self>) -> BoxFuture<'static, anyhow::Result<()>> {
+        let JobCenterService { sender, actor, .. } = *self;
+        // The actor drains its backlog and exits once the last sender is gone
+        drop(sender);
+        Box::pin(async move {
+            let job_manager = actor.await?;
+            if !job_manager.jobs.is_empty() {
+                warn!(pending = job_manager.jobs.len(), "Jobs left in queues");
+            }
+            Ok(())
+        })
+    }
+}
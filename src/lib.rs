@@ -1,5 +1,18 @@
-pub mod database_server;
-pub mod road;
+pub mod bench;
+pub mod capture;
+pub mod chat;
+pub mod config;
 pub mod crypto;
+pub mod database_server;
+pub mod faults;
+pub mod handle_is_prime;
+pub mod handle_mte;
 pub mod job_center;
-
+pub mod logging;
+pub mod metrics;
+pub mod prime_cache;
+pub mod primes;
+pub mod relay;
+pub mod road;
+pub mod services;
+pub mod version_control;
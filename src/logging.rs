@@ -0,0 +1,131 @@
+use tracing::Subscriber;
+use tracing_subscriber::{EnvFilter, fmt::MakeWriter, fmt::format::FmtSpan};
+
+use crate::config::LogFormat;
+
+/// Installs the global subscriber. `RUST_LOG` still wins over `level` so a single run can be
+/// turned up without editing the config.
+pub fn init(level: &str, format: LogFormat) -> anyhow::Result<()> {
+    let filter = EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new(level));
+    tracing::subscriber::set_global_default(subscriber(filter, format, std::io::stdout))?;
+    Ok(())
+}
+
+fn subscriber<W>(
+    filter: EnvFilter,
+    format: LogFormat,
+    writer: W,
+) -> Box<dyn Subscriber + Send + Sync>
+where
+    W: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+{
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(writer)
+        .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE);
+
+    match format {
+        LogFormat::Pretty => Box::new(builder.pretty().finish()),
+        // Event fields at the top level and every enclosing span listed, so each line carries
+        // the service, conn_id and peer of the connection it came from
+        LogFormat::Json => Box::new(
+            builder
+                .json()
+                .flatten_event(true)
+                .with_current_span(false)
+                .with_span_list(true)
+                .finish(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+        time::Duration,
+    };
+
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpStream,
+    };
+    use tokio_util::sync::CancellationToken;
+
+    use super::*;
+    use crate::{
+        config::ServiceConfig,
+        services::{Registry, ServiceKind},
+    };
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl<'a> MakeWriter<'a> for Captured {
+        type Writer = Captured;
+
+        fn make_writer(&'a self) -> Self::Writer {
+            self.clone()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_json_lines_carry_conn_span() {
+        let captured = Captured::default();
+        let _guard = tracing::subscriber::set_default(subscriber(
+            EnvFilter::new("debug"),
+            LogFormat::Json,
+            captured.clone(),
+        ));
+
+        let config = ServiceConfig::new("127.0.0.1:0");
+        let service = Registry::default()
+            .build(ServiceKind::JobCenter, &config)
+            .unwrap();
+        let listener = service.bind(&config.bind).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let serving =
+            tokio::spawn(service.serve(listener, shutdown.clone(), Duration::from_secs(1)));
+
+        let mut client = BufReader::new(TcpStream::connect(addr).await.unwrap());
+        client
+            .write_all(b"{\"request\":\"put\",\"queue\":\"q\",\"job\":{},\"pri\":1}\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        client.read_line(&mut response).await.unwrap();
+        drop(client);
+        shutdown.cancel();
+        serving.await.unwrap().unwrap();
+
+        let output = String::from_utf8(captured.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<serde_json::Value> = output
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        // Logged from the reader task, which only has the span if it was instrumented
+        let received = lines
+            .iter()
+            .find(|line| line["message"] == "Received request")
+            .unwrap_or_else(|| panic!("no request logged in {}", output));
+        assert_eq!(received["command"], "put");
+        let conn = &received["spans"][0];
+        assert_eq!(conn["name"], "conn");
+        assert_eq!(conn["service"], "job_center");
+        assert!(conn["conn_id"].is_u64());
+        assert!(conn["peer"].as_str().unwrap().starts_with("127.0.0.1:"));
+    }
+}
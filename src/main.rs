@@ -1,57 +1,388 @@
-pub mod chat;
-pub mod database_server;
-pub mod handle_is_prime;
-pub mod handle_mte;
-pub mod job_center;
+use std::{path::PathBuf, time::Duration};
 
-use std::sync::atomic::{AtomicUsize, Ordering};
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand};
+use prime_time::{
+    capture::{self, Capture, Recorder, Transport},
+    config::{Config, LogFormat, MetricsConfig, ServiceConfig},
+    faults::{self, Faults},
+    logging,
+    metrics::serve_metrics,
+    services::{DrainSummary, Registry, ServiceKind, ServiceSpec},
+};
+use tokio::{
+    net::{TcpListener, UdpSocket},
+    signal::unix::{SignalKind, signal},
+    task::JoinSet,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{error, info};
 
-use tokio::{net::TcpListener, sync::mpsc};
-use tracing::{Instrument, info, info_span};
-use tracing_subscriber::{EnvFilter, fmt::format::FmtSpan};
+#[derive(Parser, Debug)]
+#[command(
+    about = "Runs any subset of the protohackers services",
+    args_conflicts_with_subcommands = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
 
-use crate::job_center::{
-    actor_scheduler::manager::JobManager, handle_jobs_center::handle_job_center,
-};
+    #[command(flatten)]
+    run: RunArgs,
+
+    /// `pretty` or `json`. Overrides `log.format` from `--config`.
+    #[arg(long, global = true, value_name = "FORMAT")]
+    log_format: Option<LogFormat>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Proxy a running service and record every byte in both directions to a capture file.
+    Capture(CaptureArgs),
+    /// Play a capture's client traffic against a service and diff the responses.
+    Replay(ReplayArgs),
+    /// Proxy a running service, splitting, delaying, dropping or resetting traffic on the way.
+    Faults(FaultArgs),
+}
+
+#[derive(Args, Debug)]
+struct RunArgs {
+    /// TOML or JSON file describing services, channel capacities and log levels.
+    #[arg(long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Service to start as `name=addr`, e.g. `--service road=0.0.0.0:4000`. May be repeated.
+    /// Overrides the bind address of a service from `--config`. Defaults to
+    /// `job_center=0.0.0.0:3030` when neither is given.
+    #[arg(long = "service", value_name = "NAME=ADDR")]
+    services: Vec<ServiceSpec>,
+
+    /// Serve Prometheus metrics on `GET /metrics` at this address. Overrides `[metrics]`.
+    #[arg(long, value_name = "ADDR")]
+    metrics: Option<String>,
+}
+
+#[derive(Args, Debug)]
+struct CaptureArgs {
+    /// Address clients connect to instead of the service.
+    #[arg(long, value_name = "ADDR")]
+    listen: String,
+
+    /// Address of the service being captured.
+    #[arg(long, value_name = "ADDR")]
+    upstream: String,
+
+    /// File to write the capture to.
+    #[arg(long, value_name = "PATH")]
+    out: PathBuf,
+
+    /// Proxy datagrams instead of TCP connections, e.g. for the database service.
+    #[arg(long)]
+    udp: bool,
+}
+
+#[derive(Args, Debug)]
+struct ReplayArgs {
+    /// Capture file written by `capture`.
+    capture: PathBuf,
+
+    /// Service to replay against. Defaults to the upstream the capture was taken from.
+    #[arg(long, value_name = "ADDR")]
+    target: Option<String>,
+
+    /// How long to wait for each recorded response.
+    #[arg(long, value_name = "MS", default_value_t = 1_000)]
+    read_timeout_ms: u64,
+}
+
+#[derive(Args, Debug)]
+struct FaultArgs {
+    /// Address clients connect to instead of the service.
+    #[arg(long, value_name = "ADDR")]
+    listen: String,
+
+    /// Address of the service to put faults in front of.
+    #[arg(long, value_name = "ADDR")]
+    upstream: String,
+
+    /// Proxy datagrams instead of TCP connections.
+    #[arg(long)]
+    udp: bool,
+
+    /// Seed for every random decision. A fresh one is picked and logged when not given.
+    #[arg(long)]
+    seed: Option<u64>,
+
+    /// Forward TCP data in random chunks of 1 to N bytes.
+    #[arg(long, value_name = "N")]
+    max_chunk: Option<usize>,
+
+    /// Pause for up to this long before each chunk or datagram.
+    #[arg(long, value_name = "MS", default_value_t = 0)]
+    max_delay_ms: u64,
+
+    /// Probability of dropping each datagram.
+    #[arg(long, value_name = "P", default_value_t = 0.0)]
+    drop: f64,
+
+    /// Probability of delivering each datagram twice.
+    #[arg(long, value_name = "P", default_value_t = 0.0)]
+    duplicate: f64,
+
+    /// Probability, per forwarded chunk, of resetting the TCP connection instead.
+    #[arg(long, value_name = "P", default_value_t = 0.0)]
+    reset: f64,
+}
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    let listener: TcpListener = TcpListener::bind("0.0.0.0:3030").await?;
-
-    let subscriber = tracing_subscriber::fmt()
-        .with_env_filter(
-            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")), // Default to info level
-        )
-        .with_span_events(FmtSpan::NEW | FmtSpan::CLOSE)
-        .pretty()
-        .finish();
-
-    tracing::subscriber::set_global_default(subscriber)?;
-    static CONN_COUNTER: AtomicUsize = AtomicUsize::new(1);
-    let (tx, rx) = mpsc::channel(32);
-    let mut job_manager = JobManager::new(rx);
-    tokio::spawn(async move {
-        if let Err(e) = job_manager.job_actor().await {
-            tracing::error!("JobManager exited with error: {:?}", e);
-        }
-    });
+    let cli = Cli::parse();
+    let log_format = cli.log_format.unwrap_or_default();
+    match cli.command {
+        Some(Command::Capture(args)) => {
+            logging::init("info", log_format)?;
+            run_capture(args).await
+        }
+        Some(Command::Replay(args)) => {
+            logging::init("warn", log_format)?;
+            run_replay(args).await
+        }
+        Some(Command::Faults(args)) => {
+            logging::init("info", log_format)?;
+            run_faults(args).await
+        }
+        None => {
+            let config = build_config(&cli.run)?;
+            logging::init(
+                &config.log.level,
+                cli.log_format.unwrap_or(config.log.format),
+            )?;
+            run_services(config).await
+        }
+    }
+}
 
+async fn run_services(config: Config) -> anyhow::Result<()> {
+    let shutdown = CancellationToken::new();
+    let drain_timeout = config.shutdown.drain_timeout();
+    let registry = Registry::default();
+    let mut running = JoinSet::new();
+    for (kind, config) in config.enabled_services() {
+        let service = registry.build(kind, config)?;
+        let listener = service
+            .bind(&config.bind)
+            .await
+            .with_context(|| format!("service {} failed to bind {}", kind, config.bind))?;
+        let serving = service.serve(listener, shutdown.clone(), drain_timeout);
+        running.spawn(async move { (kind, serving.await) });
+    }
+
+    let metrics = match &config.metrics {
+        Some(metrics) => {
+            let listener = TcpListener::bind(&metrics.bind)
+                .await
+                .with_context(|| format!("metrics failed to bind {}", metrics.bind))?;
+            Some(tokio::spawn(serve_metrics(listener, shutdown.clone())))
+        }
+        None => None,
+    };
+
+    let signal = shutdown_signal();
+    tokio::pin!(signal);
+    let mut total = DrainSummary::default();
+    let mut failure = None;
     loop {
-        let (socket, addr) = listener.accept().await?;
-        let conn_id = CONN_COUNTER.fetch_add(1, Ordering::Relaxed);
-
-        let span = info_span!("conn", %addr, conn_id);
-        let tx_clone = tx.clone();
-
-        // Spawn a new async task to handle the connection
-        let _ = tokio::spawn(async move {
-            info!("New connection established");
-            if let Err(e) = handle_job_center(socket, tx_clone).await {
-                tracing::error!("Connection {} ended with error: {}", addr, e);
-            } else {
-                println!("Connection {} ended cleanly", addr);
+        tokio::select! {
+            result = &mut signal, if !shutdown.is_cancelled() => {
+                result?;
+                info!("Shutdown requested, draining connections");
+                shutdown.cancel();
+            }
+            joined = running.join_next() => {
+                let Some(joined) = joined else { break };
+                let (kind, result) = joined?;
+                match result {
+                    Ok(summary) => {
+                        info!(service = %kind, closed = summary.closed, forced = summary.forced, "Service stopped");
+                        total.closed += summary.closed;
+                        total.forced += summary.forced;
+                    }
+                    // One failed service takes the others down with it, after they drain
+                    Err(e) => {
+                        error!("Service {} exited with error: {:?}", kind, e);
+                        failure.get_or_insert(e.context(format!("service {} failed", kind)));
+                        shutdown.cancel();
+                    }
+                }
             }
-        })
-        .instrument(span);
+        }
+    }
+
+    if let Some(metrics) = metrics {
+        metrics.await??;
+    }
+
+    info!(
+        closed = total.closed,
+        forced = total.forced,
+        "Shutdown complete"
+    );
+    failure.map_or(Ok(()), Err)
+}
+
+async fn run_capture(args: CaptureArgs) -> anyhow::Result<()> {
+    let transport = if args.udp {
+        Transport::Udp
+    } else {
+        Transport::Tcp
+    };
+    let (recorder, writer) = Recorder::create(&args.out, transport, &args.upstream).await?;
+    let shutdown = CancellationToken::new();
+    let proxy = match transport {
+        Transport::Tcp => {
+            let listener = TcpListener::bind(&args.listen)
+                .await
+                .with_context(|| format!("capture failed to bind {}", args.listen))?;
+            tokio::spawn(capture::proxy_tcp(
+                listener,
+                args.upstream,
+                recorder,
+                shutdown.clone(),
+            ))
+        }
+        Transport::Udp => {
+            let socket = UdpSocket::bind(&args.listen)
+                .await
+                .with_context(|| format!("capture failed to bind {}", args.listen))?;
+            tokio::spawn(capture::proxy_udp(
+                socket,
+                args.upstream,
+                recorder,
+                shutdown.clone(),
+            ))
+        }
+    };
+
+    shutdown_signal().await?;
+    shutdown.cancel();
+    proxy.await??;
+    let events = writer.await??;
+    info!(events, path = %args.out.display(), "Capture written");
+    Ok(())
+}
+
+async fn run_replay(args: ReplayArgs) -> anyhow::Result<()> {
+    let capture = Capture::load(&args.capture)?;
+    let target = args
+        .target
+        .unwrap_or_else(|| capture.header.upstream.clone());
+    let report = capture::replay(
+        &capture,
+        &target,
+        Duration::from_millis(args.read_timeout_ms),
+    )
+    .await?;
+
+    for mismatch in &report.mismatches {
+        println!(
+            "conn {} (line {}): expected {} bytes, got {}",
+            mismatch.conn,
+            mismatch.line,
+            mismatch.expected.len(),
+            mismatch.actual.len()
+        );
+        println!("{}", mismatch.diff());
+    }
+    println!(
+        "{} connections, {} responses, {} mismatched",
+        report.connections,
+        report.responses,
+        report.mismatches.len()
+    );
+    if !report.mismatches.is_empty() {
+        anyhow::bail!("replay against {} diverged from the capture", target);
+    }
+    Ok(())
+}
+
+async fn run_faults(args: FaultArgs) -> anyhow::Result<()> {
+    let faults = Faults {
+        seed: args.seed.unwrap_or_else(rand::random),
+        max_chunk: args.max_chunk,
+        max_delay: Duration::from_millis(args.max_delay_ms),
+        drop: args.drop,
+        duplicate: args.duplicate,
+        reset: args.reset,
+    };
+    faults.validate()?;
+
+    let shutdown = CancellationToken::new();
+    let proxy = if args.udp {
+        let socket = UdpSocket::bind(&args.listen)
+            .await
+            .with_context(|| format!("fault proxy failed to bind {}", args.listen))?;
+        tokio::spawn(faults::proxy_udp(
+            socket,
+            args.upstream,
+            faults,
+            shutdown.clone(),
+        ))
+    } else {
+        let listener = TcpListener::bind(&args.listen)
+            .await
+            .with_context(|| format!("fault proxy failed to bind {}", args.listen))?;
+        tokio::spawn(faults::proxy_tcp(
+            listener,
+            args.upstream,
+            faults,
+            shutdown.clone(),
+        ))
+    };
+
+    shutdown_signal().await?;
+    shutdown.cancel();
+    proxy.await?
+}
+
+async fn shutdown_signal() -> anyhow::Result<()> {
+    let mut terminate = signal(SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = terminate.recv() => {}
+    }
+    Ok(())
+}
+
+fn build_config(cli: &RunArgs) -> anyhow::Result<Config> {
+    let mut config = match &cli.config {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+
+    if let Some(bind) = &cli.metrics {
+        config.metrics = Some(MetricsConfig { bind: bind.clone() });
+    }
+
+    for spec in &cli.services {
+        config
+            .services
+            .entry(spec.kind)
+            .and_modify(|service| {
+                service.bind = spec.addr.clone();
+                service.enabled = true;
+            })
+            .or_insert_with(|| ServiceConfig::new(spec.addr.clone()));
+    }
+
+    if cli.config.is_none() && config.services.is_empty() {
+        config
+            .services
+            .insert(ServiceKind::JobCenter, ServiceConfig::new("0.0.0.0:3030"));
+    }
+
+    config.validate()?;
+    if config.enabled_services().next().is_none() {
+        anyhow::bail!("no services enabled");
     }
+    Ok(config)
 }
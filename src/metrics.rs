@@ -0,0 +1,515 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    sync::{
+        LazyLock, Mutex,
+        atomic::{AtomicI64, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use futures::future::{BoxFuture, join_all};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    time::timeout,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{debug, info, warn};
+
+use crate::services::{ACCEPT_BACKOFF, ServiceKind};
+
+/// Longest a scrape connection may take to send its request and read the response.
+const SCRAPE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Debug, Default)]
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    pub const fn new() -> Self {
+        Self(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.add(1);
+    }
+
+    pub fn add(&self, n: u64) {
+        self.0.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    pub const fn new() -> Self {
+        Self(AtomicI64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub fn get(&self) -> i64 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Increments now and decrements when the guard drops, even if the task is aborted.
+    pub fn track(&'static self) -> GaugeGuard {
+        self.inc();
+        GaugeGuard(self)
+    }
+}
+
+pub struct GaugeGuard(&'static Gauge);
+
+impl Drop for GaugeGuard {
+    fn drop(&mut self) {
+        self.0.dec();
+    }
+}
+
+/// Counters every service has, labelled with `service` when rendered. They total every
+/// instance of the service in the process.
+#[derive(Debug, Default)]
+pub struct ServiceMetrics {
+    pub connections_active: Gauge,
+    pub connections_total: Counter,
+    pub messages_decoded: Counter,
+    pub messages_encoded: Counter,
+    pub decode_errors: Counter,
+    pub connections_rejected: Counter,
+    pub messages_throttled: Counter,
+}
+
+impl ServiceMetrics {
+    pub const fn new() -> Self {
+        Self {
+            connections_active: Gauge::new(),
+            connections_total: Counter::new(),
+            messages_decoded: Counter::new(),
+            messages_encoded: Counter::new(),
+            decode_errors: Counter::new(),
+            connections_rejected: Counter::new(),
+            messages_throttled: Counter::new(),
+        }
+    }
+
+    /// Counts the outcome of a `Decoder::decode` call; `Ok(None)` only means more bytes are needed.
+    pub fn record_decode<T, E>(&self, result: &Result<Option<T>, E>) {
+        match result {
+            Ok(Some(_)) => self.messages_decoded.inc(),
+            Ok(None) => {}
+            Err(_) => self.decode_errors.inc(),
+        }
+    }
+
+    fn samples(&self) -> [Sample; 7] {
+        [
+            Sample::gauge(
+                "protohack_connections_active",
+                "Connections currently open.",
+                self.connections_active.get() as f64,
+            ),
+            Sample::counter(
+                "protohack_connections_total",
+                "Connections accepted since startup.",
+                self.connections_total.get() as f64,
+            ),
+            Sample::counter(
+                "protohack_messages_decoded_total",
+                "Requests decoded from clients.",
+                self.messages_decoded.get() as f64,
+            ),
+            Sample::counter(
+                "protohack_messages_encoded_total",
+                "Responses encoded to clients.",
+                self.messages_encoded.get() as f64,
+            ),
+            Sample::counter(
+                "protohack_decode_errors_total",
+                "Requests that failed to decode.",
+                self.decode_errors.get() as f64,
+            ),
+            Sample::counter(
+                "protohack_connections_rejected_total",
+                "Connections turned away by the connection limits.",
+                self.connections_rejected.get() as f64,
+            ),
+            Sample::counter(
+                "protohack_messages_throttled_total",
+                "Messages dropped by the per-connection rate limit.",
+                self.messages_throttled.get() as f64,
+            ),
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MetricType {
+    Counter,
+    Gauge,
+}
+
+impl MetricType {
+    fn as_str(&self) -> &'static str {
+        match self {
+            MetricType::Counter => "counter",
+            MetricType::Gauge => "gauge",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    pub name: &'static str,
+    pub help: &'static str,
+    pub metric_type: MetricType,
+    pub labels: Vec<(&'static str, String)>,
+    pub value: f64,
+}
+
+impl Sample {
+    pub fn counter(name: &'static str, help: &'static str, value: f64) -> Self {
+        Self {
+            name,
+            help,
+            metric_type: MetricType::Counter,
+            labels: vec![],
+            value,
+        }
+    }
+
+    pub fn gauge(name: &'static str, help: &'static str, value: f64) -> Self {
+        Self {
+            name,
+            help,
+            metric_type: MetricType::Gauge,
+            labels: vec![],
+            value,
+        }
+    }
+
+    pub fn label(mut self, name: &'static str, value: impl Into<String>) -> Self {
+        self.labels.push((name, value.into()));
+        self
+    }
+}
+
+/// Reads service state that lives behind a lock or an actor at scrape time.
+pub type Collector = Box<dyn Fn() -> BoxFuture<'static, Vec<Sample>> + Send + Sync>;
+
+/// A process-wide counter rendered once for its service, however many instances run.
+struct Registered {
+    kind: ServiceKind,
+    name: &'static str,
+    help: &'static str,
+    counter: &'static Counter,
+}
+
+pub struct Metrics {
+    services: BTreeMap<ServiceKind, ServiceMetrics>,
+    counters: Mutex<Vec<Registered>>,
+    collectors: Mutex<BTreeMap<(ServiceKind, u64), Collector>>,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(|| Metrics {
+    services: ServiceKind::ALL
+        .into_iter()
+        .map(|kind| (kind, ServiceMetrics::default()))
+        .collect(),
+    counters: Mutex::new(vec![]),
+    collectors: Mutex::new(BTreeMap::new()),
+});
+
+static NEXT_COLLECTOR: AtomicU64 = AtomicU64::new(0);
+
+pub fn service(kind: ServiceKind) -> &'static ServiceMetrics {
+    &METRICS.services[&kind]
+}
+
+/// Renders a static counter under `kind`. Registering the same name again is a no-op.
+pub fn register_counter(
+    kind: ServiceKind,
+    name: &'static str,
+    help: &'static str,
+    counter: &'static Counter,
+) {
+    let mut counters = METRICS.counters.lock().unwrap();
+    if !counters.iter().any(|c| c.kind == kind && c.name == name) {
+        counters.push(Registered {
+            kind,
+            name,
+            help,
+            counter,
+        });
+    }
+}
+
+/// Unregisters its collector when dropped, so a stopped instance is no longer scraped.
+#[must_use]
+pub struct CollectorGuard {
+    key: (ServiceKind, u64),
+}
+
+impl Drop for CollectorGuard {
+    fn drop(&mut self) {
+        METRICS.collectors.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// Registers the collector of one instance of `kind`. Samples with the same name and labels
+/// from every live instance are summed, as the [`ServiceMetrics`] counters are.
+pub fn add_collector(kind: ServiceKind, collector: Collector) -> CollectorGuard {
+    let key = (kind, NEXT_COLLECTOR.fetch_add(1, Ordering::Relaxed));
+    METRICS.collectors.lock().unwrap().insert(key, collector);
+    CollectorGuard { key }
+}
+
+/// Renders every metric in the Prometheus text exposition format.
+pub async fn render() -> String {
+    let mut samples = vec![];
+    for (kind, metrics) in &METRICS.services {
+        samples.extend(
+            metrics
+                .samples()
+                .into_iter()
+                .map(|sample| for_service(sample, *kind)),
+        );
+    }
+
+    samples.extend(METRICS.counters.lock().unwrap().iter().map(|registered| {
+        for_service(
+            Sample::counter(
+                registered.name,
+                registered.help,
+                registered.counter.get() as f64,
+            ),
+            registered.kind,
+        )
+    }));
+
+    let pending: Vec<_> = {
+        let collectors = METRICS.collectors.lock().unwrap();
+        collectors
+            .iter()
+            .map(|(&(kind, _), collect)| {
+                let collected = collect();
+                async move {
+                    collected
+                        .await
+                        .into_iter()
+                        .map(|sample| for_service(sample, kind))
+                        .collect::<Vec<_>>()
+                }
+            })
+            .collect()
+    };
+    samples.extend(sum_instances(
+        join_all(pending).await.into_iter().flatten().collect(),
+    ));
+
+    format_samples(samples)
+}
+
+/// Folds samples that differ only in which instance reported them into one.
+fn sum_instances(samples: Vec<Sample>) -> Vec<Sample> {
+    let mut summed: Vec<Sample> = vec![];
+    for sample in samples {
+        match summed
+            .iter_mut()
+            .find(|seen| seen.name == sample.name && seen.labels == sample.labels)
+        {
+            Some(seen) => seen.value += sample.value,
+            None => summed.push(sample),
+        }
+    }
+    summed
+}
+
+fn for_service(mut sample: Sample, kind: ServiceKind) -> Sample {
+    sample
+        .labels
+        .insert(0, ("service", kind.name().to_string()));
+    sample
+}
+
+fn format_samples(samples: Vec<Sample>) -> String {
+    // HELP/TYPE must appear once per metric with all its samples grouped underneath
+    let mut families: Vec<(&'static str, Vec<Sample>)> = vec![];
+    for sample in samples {
+        match families.iter_mut().find(|(name, _)| *name == sample.name) {
+            Some((_, family)) => family.push(sample),
+            None => families.push((sample.name, vec![sample])),
+        }
+    }
+
+    let mut out = String::new();
+    for (name, family) in families {
+        let _ = writeln!(out, "# HELP {} {}", name, family[0].help);
+        let _ = writeln!(out, "# TYPE {} {}", name, family[0].metric_type.as_str());
+        for sample in family {
+            out.push_str(name);
+            if !sample.labels.is_empty() {
+                let labels: Vec<String> = sample
+                    .labels
+                    .iter()
+                    .map(|(key, value)| format!("{}=\"{}\"", key, escape_label(value)))
+                    .collect();
+                let _ = write!(out, "{{{}}}", labels.join(","));
+            }
+            let _ = writeln!(out, " {}", sample.value);
+        }
+    }
+    out
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Serves `GET /metrics` over bare HTTP/1.1 until `shutdown` is cancelled.
+pub async fn serve_metrics(
+    listener: TcpListener,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    info!(addr = %listener.local_addr()?, "Metrics listening");
+    while let Some(accepted) = shutdown.run_until_cancelled(listener.accept()).await {
+        let (socket, addr) = match accepted {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!(error = %e, "Metrics accept failed, retrying");
+                shutdown
+                    .run_until_cancelled(tokio::time::sleep(ACCEPT_BACKOFF))
+                    .await;
+                continue;
+            }
+        };
+        tokio::spawn(async move {
+            match timeout(SCRAPE_TIMEOUT, handle_scrape(socket)).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => debug!("Metrics request from {} failed: {}", addr, e),
+                Err(_) => debug!("Metrics request from {} timed out", addr),
+            }
+        });
+    }
+    Ok(())
+}
+
+async fn handle_scrape(mut socket: TcpStream) -> anyhow::Result<()> {
+    let mut request = vec![];
+    let mut buf = [0u8; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = socket.read(&mut buf).await?;
+        if n == 0 {
+            anyhow::bail!("connection closed before the request ended");
+        }
+        request.extend_from_slice(&buf[..n]);
+        if request.len() > 8 * 1024 {
+            anyhow::bail!("request head too large");
+        }
+    }
+
+    let request_line = request.split(|b| *b == b'\r').next().unwrap_or_default();
+    let mut parts = request_line.split(|b| *b == b' ');
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some(b"GET"), Some(b"/metrics")) => ("200 OK", render().await),
+        _ => ("404 Not Found", "not found\n".to_string()),
+    };
+
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_groups_families() {
+        let text = format_samples(vec![
+            Sample::gauge("jobs", "Jobs.", 2.0).label("queue", "a"),
+            Sample::counter("hits_total", "Hits.", 7.0),
+            Sample::gauge("jobs", "Jobs.", 1.0).label("queue", "b\"c"),
+        ]);
+        assert_eq!(
+            text,
+            "# HELP jobs Jobs.\n\
+             # TYPE jobs gauge\n\
+             jobs{queue=\"a\"} 2\n\
+             jobs{queue=\"b\\\"c\"} 1\n\
+             # HELP hits_total Hits.\n\
+             # TYPE hits_total counter\n\
+             hits_total 7\n"
+        );
+    }
+
+    #[test]
+    fn test_sum_instances() {
+        let summed = sum_instances(vec![
+            Sample::gauge("keys", "Keys.", 2.0).label("service", "kv"),
+            Sample::gauge("keys", "Keys.", 3.0).label("service", "kv"),
+            Sample::gauge("keys", "Keys.", 1.0).label("service", "other"),
+        ]);
+        assert_eq!(
+            summed,
+            [
+                Sample::gauge("keys", "Keys.", 5.0).label("service", "kv"),
+                Sample::gauge("keys", "Keys.", 1.0).label("service", "other"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_collectors_per_instance() {
+        let collector = |value| -> Collector {
+            Box::new(move || {
+                Box::pin(async move { vec![Sample::gauge("test_instances", "Test.", value)] })
+            })
+        };
+        let first = add_collector(ServiceKind::Crypto, collector(2.0));
+        let second = add_collector(ServiceKind::Crypto, collector(3.0));
+        assert!(
+            render()
+                .await
+                .contains("test_instances{service=\"crypto\"} 5\n")
+        );
+        drop(first);
+        assert!(
+            render()
+                .await
+                .contains("test_instances{service=\"crypto\"} 3\n")
+        );
+        drop(second);
+        assert!(!render().await.contains("test_instances"));
+    }
+
+    #[test]
+    fn test_record_decode() {
+        let metrics = ServiceMetrics::default();
+        metrics.record_decode::<(), ()>(&Ok(Some(())));
+        metrics.record_decode::<(), ()>(&Ok(None));
+        metrics.record_decode::<(), ()>(&Err(()));
+        assert_eq!(metrics.messages_decoded.get(), 1);
+        assert_eq!(metrics.decode_errors.get(), 1);
+    }
+}
@@ -0,0 +1,117 @@
+use std::{num::NonZeroUsize, sync::Mutex};
+
+use lru::LruCache;
+use num_bigint::BigUint;
+
+use crate::{
+    metrics::Counter,
+    primes::{self, SIEVE_LIMIT},
+};
+
+/// Primality results shared by every prime connection, evicting the least recently asked
+/// number once full. Numbers below the sieve limit are cheaper to look up in the sieve and
+/// never take a slot.
+///
+/// The lock is only held to read or store an entry, never while testing a number, so two
+/// connections missing on the same number at once may both compute it.
+pub struct PrimeCache {
+    entries: Mutex<LruCache<BigUint, bool>>,
+    hits: Counter,
+    misses: Counter,
+}
+
+impl PrimeCache {
+    pub fn new(capacity: NonZeroUsize) -> Self {
+        Self {
+            entries: Mutex::new(LruCache::new(capacity)),
+            hits: Counter::new(),
+            misses: Counter::new(),
+        }
+    }
+
+    /// The known answer for `n`, if any. Only lookups past the sieve count as hits or misses.
+    pub fn get(&self, n: &BigUint) -> Option<bool> {
+        if let Ok(small) = u64::try_from(n)
+            && small < SIEVE_LIMIT
+        {
+            return Some(primes::is_prime(small));
+        }
+        let prime = self.entries.lock().unwrap().get(n).copied();
+        match prime {
+            Some(_) => self.hits.inc(),
+            None => self.misses.inc(),
+        }
+        prime
+    }
+
+    /// Tests `n` and remembers the answer. For numbers [`PrimeCache::get`] just missed on.
+    pub fn compute(&self, n: BigUint) -> bool {
+        let prime = primes::is_probable_prime(&n);
+        self.entries.lock().unwrap().put(n, prime);
+        prime
+    }
+
+    pub fn is_prime(&self, n: BigUint) -> bool {
+        self.get(&n).unwrap_or_else(|| self.compute(n))
+    }
+
+    pub fn hits(&self) -> u64 {
+        self.hits.get()
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.get()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cache(capacity: usize) -> PrimeCache {
+        PrimeCache::new(NonZeroUsize::new(capacity).unwrap())
+    }
+
+    #[test]
+    fn test_hits_and_misses() {
+        let cache = cache(4);
+        let big = BigUint::from(18_446_744_073_709_551_557u64);
+        assert!(cache.is_prime(big.clone()));
+        assert!(cache.is_prime(big.clone()));
+        assert_eq!((cache.hits(), cache.misses()), (1, 1));
+        assert_eq!(cache.get(&big), Some(true));
+        assert_eq!(cache.hits(), 2);
+    }
+
+    #[test]
+    fn test_sieve_numbers_not_stored() {
+        let cache = cache(4);
+        assert!(cache.is_prime(BigUint::from(13u32)));
+        assert!(!cache.is_prime(BigUint::from(SIEVE_LIMIT - 1)));
+        assert!(cache.is_empty());
+        assert_eq!((cache.hits(), cache.misses()), (0, 0));
+    }
+
+    #[test]
+    fn test_least_recently_used_evicted() {
+        let cache = cache(2);
+        let [a, b, c] = [SIEVE_LIMIT + 1, SIEVE_LIMIT + 3, SIEVE_LIMIT + 5].map(BigUint::from);
+        cache.is_prime(a.clone());
+        cache.is_prime(b.clone());
+        // Touching `a` leaves `b` as the one to go
+        cache.get(&a);
+        cache.is_prime(c.clone());
+        assert_eq!(cache.len(), 2);
+        assert!(cache.get(&a).is_some());
+        assert!(cache.get(&b).is_none());
+        assert!(cache.get(&c).is_some());
+    }
+}
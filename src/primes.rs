@@ -0,0 +1,337 @@
+use std::sync::LazyLock;
+
+use num_bigint::BigUint;
+use rand::Rng;
+
+/// Numbers below this are answered from a sieve without any arithmetic.
+pub const SIEVE_LIMIT: u64 = 1 << 16;
+
+/// The first twelve primes as Miller-Rabin witnesses are enough to decide every n < 2^64
+/// (Sorenson & Webster, 2015).
+const WITNESSES: [u64; 12] = [2, 3, 5, 7, 11, 13, 17, 19, 23, 29, 31, 37];
+
+/// Random Miller-Rabin rounds on top of the fixed witnesses for numbers past `u64`. Each
+/// round lets a composite through with probability at most 1/4.
+const RANDOM_ROUNDS: usize = 32;
+
+static SIEVE: LazyLock<Vec<bool>> = LazyLock::new(|| {
+    let limit = SIEVE_LIMIT as usize;
+    let mut sieve = vec![true; limit];
+    sieve[0] = false;
+    sieve[1] = false;
+    let mut i = 2;
+    while i * i < limit {
+        if sieve[i] {
+            for multiple in (i * i..limit).step_by(i) {
+                sieve[multiple] = false;
+            }
+        }
+        i += 1;
+    }
+    sieve
+});
+
+/// Deterministic for the whole `u64` range: a sieve lookup for small numbers, trial division
+/// by the witnesses to reject most composites cheaply, then Miller-Rabin.
+pub fn is_prime(n: u64) -> bool {
+    if n < SIEVE_LIMIT {
+        return SIEVE[n as usize];
+    }
+    if WITNESSES.iter().any(|&p| n.is_multiple_of(p)) {
+        return false;
+    }
+
+    let mut d = n - 1;
+    let s = d.trailing_zeros();
+    d >>= s;
+    WITNESSES.iter().all(|&a| {
+        let mut x = pow_mod(a, d, n);
+        if x == 1 || x == n - 1 {
+            return true;
+        }
+        for _ in 1..s {
+            x = mul_mod(x, x, n);
+            if x == n - 1 {
+                return true;
+            }
+        }
+        false
+    })
+}
+
+/// Exact for anything that fits in a `u64`. Beyond that Miller-Rabin is only probabilistic:
+/// a prime is never rejected, and a composite passes with probability below 4^-32.
+pub fn is_probable_prime(n: &BigUint) -> bool {
+    if let Ok(n) = u64::try_from(n) {
+        return is_prime(n);
+    }
+    if WITNESSES.iter().any(|&p| n % p == BigUint::ZERO) {
+        return false;
+    }
+
+    let n_minus_one = n - 1u32;
+    let s = n_minus_one.trailing_zeros().unwrap_or(0);
+    let d = &n_minus_one >> s;
+    let mut rng = rand::rng();
+    let random = (0..RANDOM_ROUNDS).map(|_| BigUint::from(rng.random::<u64>()) + 2u32);
+    WITNESSES
+        .iter()
+        .map(|&a| BigUint::from(a))
+        .chain(random)
+        .all(|a| {
+            let mut x = a.modpow(&d, n);
+            if x == BigUint::from(1u32) || x == n_minus_one {
+                return true;
+            }
+            for _ in 1..s {
+                x = x.modpow(&BigUint::from(2u32), n);
+                if x == n_minus_one {
+                    return true;
+                }
+            }
+            false
+        })
+}
+
+/// Prime factors of `n` in ascending order, repeated by multiplicity; empty for 0 and 1.
+pub fn factorize(mut n: u64) -> Vec<u64> {
+    if n == 0 {
+        return vec![];
+    }
+    let mut factors = vec![];
+    for p in WITNESSES {
+        while n.is_multiple_of(p) {
+            factors.push(p);
+            n /= p;
+        }
+    }
+    let mut rest = vec![n];
+    while let Some(m) = rest.pop() {
+        if m == 1 {
+            continue;
+        }
+        if is_prime(m) {
+            factors.push(m);
+            continue;
+        }
+        let d = pollard_rho(m);
+        rest.extend([d, m / d]);
+    }
+    factors.sort_unstable();
+    factors
+}
+
+/// A nontrivial divisor of a composite `n` with no factor among the witnesses.
+fn pollard_rho(n: u64) -> u64 {
+    let step = |x: u64, c: u64| ((x as u128 * x as u128 + c as u128) % n as u128) as u64;
+    // A cycle that closes on `n` itself just means this `c` was unlucky
+    for c in 1.. {
+        let (mut x, mut y, mut d) = (2, 2, 1);
+        while d == 1 {
+            x = step(x, c);
+            y = step(step(y, c), c);
+            d = gcd(x.abs_diff(y), n);
+        }
+        if d != n {
+            return d;
+        }
+    }
+    unreachable!("Pollard's rho ran out of constants for {}", n)
+}
+
+fn gcd(mut a: u64, mut b: u64) -> u64 {
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a
+}
+
+/// The smallest prime greater than `n`, probabilistic past `u64` like [`is_probable_prime`].
+pub fn next_prime(n: &BigUint) -> BigUint {
+    let two = BigUint::from(2u32);
+    if *n < two {
+        return two;
+    }
+    let mut candidate = n + 1u32;
+    if !candidate.bit(0) {
+        candidate += 1u32;
+    }
+    while !is_probable_prime(&candidate) {
+        candidate += 2u32;
+    }
+    candidate
+}
+
+/// The largest prime less than `n`, or `None` when there is none.
+pub fn prev_prime(n: &BigUint) -> Option<BigUint> {
+    if *n <= BigUint::from(3u32) {
+        return (*n == BigUint::from(3u32)).then(|| BigUint::from(2u32));
+    }
+    let mut candidate = n - 1u32;
+    if !candidate.bit(0) {
+        candidate -= 1u32;
+    }
+    // Odd and at least 3, so this stops at 3 at the latest
+    while !is_probable_prime(&candidate) {
+        candidate -= 2u32;
+    }
+    Some(candidate)
+}
+
+/// How many primes lie in `from..=to`.
+pub fn prime_count(from: u64, to: u64) -> u64 {
+    (from..=to).filter(|&n| is_prime(n)).count() as u64
+}
+
+/// The original trial division up to sqrt(n). Kept as the reference `is_prime` is checked and
+/// benchmarked against; far too slow for large primes.
+pub fn is_prime_trial_division(i: u64) -> bool {
+    if i < 2 {
+        return false;
+    }
+    let max: u64 = ((i as f64).sqrt() + 1f64) as u64;
+    for n in 2..max {
+        if i.is_multiple_of(n) {
+            return false;
+        }
+    }
+    true
+}
+
+fn mul_mod(a: u64, b: u64, m: u64) -> u64 {
+    ((a as u128 * b as u128) % m as u128) as u64
+}
+
+fn pow_mod(mut base: u64, mut exp: u64, m: u64) -> u64 {
+    let mut result = 1;
+    base %= m;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = mul_mod(result, base, m);
+        }
+        base = mul_mod(base, base, m);
+        exp >>= 1;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_sieve_matches_trial_division() {
+        for n in 0..SIEVE_LIMIT + 1_000 {
+            assert_eq!(is_prime(n), is_prime_trial_division(n), "{}", n);
+        }
+    }
+
+    #[test]
+    fn test_large_primes() {
+        for n in [
+            4_294_967_291,              // largest prime below 2^32
+            1_000_000_000_000_000_003,  // 10^18 + 3
+            18_446_744_073_709_551_557, // largest prime below 2^64
+        ] {
+            assert!(is_prime(n), "{}", n);
+        }
+    }
+
+    #[test]
+    fn test_pseudoprimes_rejected() {
+        for n in [
+            3_215_031_751,             // strong pseudoprime to bases 2, 3, 5 and 7
+            3_825_123_056_546_413_051, // strong pseudoprime to every base up to 23
+            4_294_967_297,             // 2^32 + 1 = 641 * 6700417
+            18_446_744_073_709_551_615,
+            4_611_686_014_132_420_609, // (2^31 - 1)^2
+        ] {
+            assert!(!is_prime(n), "{}", n);
+        }
+    }
+
+    #[test]
+    fn test_big_primes() {
+        let mersenne_127 = (BigUint::from(1u32) << 127u32) - 1u32;
+        assert!(is_probable_prime(&mersenne_127));
+        // 2^127 + 1 is divisible by 3, 2^128 + 1 is the composite Fermat number F7
+        assert!(!is_probable_prime(
+            &((BigUint::from(1u32) << 127u32) + 1u32)
+        ));
+        assert!(!is_probable_prime(
+            &((BigUint::from(1u32) << 128u32) + 1u32)
+        ));
+        assert!(!is_probable_prime(&(&mersenne_127 * &mersenne_127)));
+        assert!(is_probable_prime(&BigUint::from(
+            18_446_744_073_709_551_557u64
+        )));
+    }
+
+    #[test]
+    fn test_factorize() {
+        assert_eq!(factorize(0), Vec::<u64>::new());
+        assert_eq!(factorize(1), Vec::<u64>::new());
+        assert_eq!(factorize(360), vec![2, 2, 2, 3, 3, 5]);
+        assert_eq!(factorize(4_294_967_297), vec![641, 6_700_417]);
+        assert_eq!(factorize(4_611_686_014_132_420_609), vec![2_147_483_647; 2]);
+        assert_eq!(
+            factorize(18_446_744_073_709_551_615),
+            vec![3, 5, 17, 257, 641, 65_537, 6_700_417]
+        );
+        assert_eq!(
+            factorize(18_446_744_073_709_551_557),
+            vec![18_446_744_073_709_551_557]
+        );
+    }
+
+    #[test]
+    fn test_next_and_prev_prime() {
+        let next = |n: u64| next_prime(&BigUint::from(n));
+        let prev = |n: u64| prev_prime(&BigUint::from(n));
+        assert_eq!(next(0), BigUint::from(2u32));
+        assert_eq!(next(2), BigUint::from(3u32));
+        assert_eq!(next(13), BigUint::from(17u32));
+        assert_eq!(prev(2), None);
+        assert_eq!(prev(3), Some(BigUint::from(2u32)));
+        assert_eq!(prev(4), Some(BigUint::from(3u32)));
+        assert_eq!(prev(17), Some(BigUint::from(13u32)));
+        // The first prime past u64 is 2^64 + 13
+        assert_eq!(
+            next(18_446_744_073_709_551_557),
+            (BigUint::from(1u32) << 64u32) + 13u32
+        );
+        assert_eq!(
+            prev_prime(&(BigUint::from(1u32) << 64u32)),
+            Some(BigUint::from(18_446_744_073_709_551_557u64))
+        );
+    }
+
+    #[test]
+    fn test_prime_count() {
+        assert_eq!(prime_count(0, 100), 25);
+        assert_eq!(prime_count(2, 2), 1);
+        assert_eq!(prime_count(14, 16), 0);
+        assert_eq!(prime_count(0, 1_000_000), 78_498);
+    }
+
+    proptest! {
+        #[test]
+        fn prop_matches_trial_division(n in 0u64..10_000_000_000) {
+            prop_assert_eq!(is_prime(n), is_prime_trial_division(n));
+        }
+
+        #[test]
+        fn prop_factors_multiply_back(n in 1u64..) {
+            let factors = factorize(n);
+            prop_assert_eq!(factors.iter().product::<u64>(), n);
+            prop_assert!(factors.iter().all(|&p| is_prime(p)));
+        }
+
+        #[test]
+        fn prop_products_are_composite(a in 2u64..=u32::MAX as u64, b in 2u64..=u32::MAX as u64) {
+            prop_assert!(!is_prime(a * b));
+        }
+    }
+}
@@ -0,0 +1,428 @@
+use std::{
+    collections::{HashMap, hash_map::Entry},
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+
+use futures::future::BoxFuture;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream, UdpSocket},
+    task::{AbortHandle, JoinSet},
+    time::Instant,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, debug, info_span, warn};
+
+use crate::capture::Direction;
+
+const BUFFER_SIZE: usize = 8 * 1024;
+const MAX_DATAGRAM: usize = 64 * 1024;
+/// UDP clients that send nothing for this long are forgotten, along with their upstream socket.
+pub const UDP_IDLE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// One step in forwarding a chunk read from one side of a TCP connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Step {
+    Pause(Duration),
+    /// Writes the next this many bytes of the chunk.
+    Write(usize),
+    /// Resets both sides of the connection instead of writing the rest.
+    Reset,
+}
+
+/// What a relay does to the traffic it forwards. Every chunk read from either side of a TCP
+/// connection, and every datagram in either direction, goes through the hook first.
+pub trait Hook: Send + Sync + 'static {
+    /// Kept for one direction of one connection or UDP client.
+    type Stream: Send + 'static;
+
+    /// Numbers a new connection, or a UDP client not seen since it last went idle.
+    fn open(&self, peer: SocketAddr) -> BoxFuture<'_, u64>;
+
+    fn close(&self, conn: u64) -> BoxFuture<'_, ()>;
+
+    fn stream(&self, conn: u64, dir: Direction) -> Self::Stream;
+
+    /// How to forward `data`. Bytes no step wrote are written once the steps run out.
+    fn chunk<'a>(
+        &'a self,
+        stream: &'a mut Self::Stream,
+        data: &'a [u8],
+    ) -> BoxFuture<'a, Vec<Step>>;
+
+    /// When to send a copy of `datagram` on, counted from its arrival. None drops it.
+    fn datagram<'a>(
+        &'a self,
+        stream: &'a mut Self::Stream,
+        datagram: &'a [u8],
+    ) -> BoxFuture<'a, Vec<Duration>>;
+}
+
+/// Forwards every connection on `listener` to `upstream` through `hook` until `shutdown` is
+/// cancelled. Open connections are cut at that point.
+pub async fn proxy_tcp<H: Hook>(
+    listener: TcpListener,
+    upstream: String,
+    hook: Arc<H>,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let mut connections = JoinSet::new();
+    while let Some(accepted) = shutdown.run_until_cancelled(listener.accept()).await {
+        let (client, peer) = accepted?;
+        let conn = hook.open(peer).await;
+        let upstream = upstream.clone();
+        let hook = Arc::clone(&hook);
+        connections.spawn(
+            async move {
+                if let Err(e) = relay_tcp(client, &upstream, conn, &*hook).await {
+                    warn!("Relay failed: {:#}", e);
+                }
+                hook.close(conn).await;
+            }
+            .instrument(info_span!("relay", conn, %peer)),
+        );
+        while connections.try_join_next().is_some() {}
+    }
+    connections.shutdown().await;
+    Ok(())
+}
+
+async fn relay_tcp<H: Hook>(
+    mut client: TcpStream,
+    upstream: &str,
+    conn: u64,
+    hook: &H,
+) -> anyhow::Result<()> {
+    let mut server = TcpStream::connect(upstream).await?;
+    // Each write should leave as its own segment rather than be coalesced again
+    client.set_nodelay(true)?;
+    server.set_nodelay(true)?;
+
+    let reset = CancellationToken::new();
+    let relayed = {
+        let (client_read, client_write) = client.split();
+        let (server_read, server_write) = server.split();
+        // Each side closes its half independently, so a client that shuts down writing still
+        // gets the rest of the responses
+        let inbound = pump(client_read, server_write, conn, Direction::In, hook, &reset);
+        let outbound = pump(
+            server_read,
+            client_write,
+            conn,
+            Direction::Out,
+            hook,
+            &reset,
+        );
+        reset
+            .run_until_cancelled(async {
+                let (inbound, outbound) = tokio::join!(inbound, outbound);
+                inbound.and(outbound)
+            })
+            .await
+    };
+
+    match relayed {
+        Some(result) => result,
+        None => {
+            // With a zero linger, dropping the sockets sends RST to both ends
+            client.set_linger(Some(Duration::ZERO))?;
+            server.set_linger(Some(Duration::ZERO))?;
+            Ok(())
+        }
+    }
+}
+
+async fn pump<H: Hook>(
+    mut reader: impl AsyncRead + Unpin,
+    mut writer: impl AsyncWrite + Unpin,
+    conn: u64,
+    dir: Direction,
+    hook: &H,
+    reset: &CancellationToken,
+) -> anyhow::Result<()> {
+    let mut stream = hook.stream(conn, dir);
+    let mut buf = vec![0u8; BUFFER_SIZE];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 {
+            debug!(?dir, "EOF");
+            writer.shutdown().await?;
+            return Ok(());
+        }
+        let steps = hook.chunk(&mut stream, &buf[..n]).await;
+        let mut rest = &buf[..n];
+        for step in steps {
+            match step {
+                Step::Pause(delay) => tokio::time::sleep(delay).await,
+                Step::Write(len) => {
+                    let (now, later) = rest.split_at(len.min(rest.len()));
+                    writer.write_all(now).await?;
+                    rest = later;
+                }
+                Step::Reset => {
+                    debug!(?dir, "Resetting connection");
+                    reset.cancel();
+                    return Ok(());
+                }
+            }
+        }
+        writer.write_all(rest).await?;
+    }
+}
+
+struct UdpClient<S> {
+    conn: u64,
+    server: Arc<UdpSocket>,
+    stream: S,
+    last_seen: Instant,
+    responder: AbortHandle,
+}
+
+/// Forwards datagrams on `socket` to `upstream` through `hook` until `shutdown` is cancelled,
+/// from one upstream socket per client address so the service sees distinct peers just as it
+/// would without the proxy.
+pub async fn proxy_udp<H: Hook>(
+    socket: UdpSocket,
+    upstream: String,
+    hook: Arc<H>,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    relay_udp(socket, upstream, hook, UDP_IDLE_TIMEOUT, shutdown).await
+}
+
+async fn relay_udp<H: Hook>(
+    socket: UdpSocket,
+    upstream: String,
+    hook: Arc<H>,
+    idle_timeout: Duration,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let socket = Arc::new(socket);
+    let mut clients: HashMap<SocketAddr, UdpClient<H::Stream>> = HashMap::new();
+    // Responders and delayed sends, all cut at shutdown
+    let mut tasks = JoinSet::new();
+    let mut sweeps = tokio::time::interval(idle_timeout / 2);
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    loop {
+        let (n, peer) = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            _ = sweeps.tick() => {
+                evict_idle(&mut clients, &*hook, idle_timeout).await;
+                while tasks.try_join_next().is_some() {}
+                continue;
+            }
+            received = socket.recv_from(&mut buf) => received?,
+        };
+        let client = match clients.entry(peer) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                let server = Arc::new(UdpSocket::bind("0.0.0.0:0").await?);
+                server.connect(&upstream).await?;
+                let conn = hook.open(peer).await;
+                let responder = tasks.spawn(
+                    respond_udp(
+                        Arc::clone(&server),
+                        Arc::clone(&socket),
+                        peer,
+                        hook.stream(conn, Direction::Out),
+                        Arc::clone(&hook),
+                    )
+                    .instrument(info_span!("relay", conn, %peer)),
+                );
+                entry.insert(UdpClient {
+                    conn,
+                    server,
+                    stream: hook.stream(conn, Direction::In),
+                    last_seen: Instant::now(),
+                    responder,
+                })
+            }
+        };
+        client.last_seen = Instant::now();
+        let delays = hook.datagram(&mut client.stream, &buf[..n]).await;
+        debug!(conn = client.conn, %peer, copies = delays.len(), "Forwarding datagram");
+        send_copies(&mut tasks, &client.server, None, &buf[..n], delays).await;
+    }
+    tasks.shutdown().await;
+    Ok(())
+}
+
+/// Forgets clients that have sent nothing for `idle_timeout`, stopping their responders.
+async fn evict_idle<H: Hook>(
+    clients: &mut HashMap<SocketAddr, UdpClient<H::Stream>>,
+    hook: &H,
+    idle_timeout: Duration,
+) {
+    let mut evicted = vec![];
+    clients.retain(|peer, client| {
+        let idle = client.last_seen.elapsed() >= idle_timeout;
+        if idle {
+            debug!(conn = client.conn, %peer, "Forgetting idle client");
+            client.responder.abort();
+            evicted.push(client.conn);
+        }
+        !idle
+    });
+    for conn in evicted {
+        hook.close(conn).await;
+    }
+}
+
+async fn respond_udp<H: Hook>(
+    server: Arc<UdpSocket>,
+    client: Arc<UdpSocket>,
+    peer: SocketAddr,
+    mut stream: H::Stream,
+    hook: Arc<H>,
+) {
+    // Dropped with the responder, so an evicted client's delayed datagrams go too
+    let mut delayed = JoinSet::new();
+    let mut buf = vec![0u8; MAX_DATAGRAM];
+    loop {
+        let n = match server.recv(&mut buf).await {
+            Ok(n) => n,
+            Err(e) => {
+                warn!("Upstream receive failed: {}", e);
+                continue;
+            }
+        };
+        while delayed.try_join_next().is_some() {}
+        let delays = hook.datagram(&mut stream, &buf[..n]).await;
+        debug!(copies = delays.len(), "Returning datagram");
+        send_copies(&mut delayed, &client, Some(peer), &buf[..n], delays).await;
+    }
+}
+
+/// Sends a copy of `datagram` after each delay, to `to` or the address `socket` is connected
+/// to. Copies due now go out in order; later ones are sent from tasks in `delayed`, so a delay
+/// holds up no other traffic.
+async fn send_copies(
+    delayed: &mut JoinSet<()>,
+    socket: &Arc<UdpSocket>,
+    to: Option<SocketAddr>,
+    datagram: &[u8],
+    delays: Vec<Duration>,
+) {
+    for delay in delays {
+        if delay.is_zero() {
+            send(socket, to, datagram).await;
+            continue;
+        }
+        let (socket, datagram) = (Arc::clone(socket), datagram.to_vec());
+        delayed.spawn(
+            async move {
+                tokio::time::sleep(delay).await;
+                send(&socket, to, &datagram).await;
+            }
+            .in_current_span(),
+        );
+    }
+}
+
+async fn send(socket: &UdpSocket, to: Option<SocketAddr>, datagram: &[u8]) {
+    let sent = match to {
+        Some(to) => socket.send_to(datagram, to).await,
+        None => socket.send(datagram).await,
+    };
+    if let Err(e) = sent {
+        warn!("Sending datagram failed: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use tokio::time::timeout;
+
+    use super::*;
+
+    /// Forwards everything untouched and remembers which connections were closed.
+    #[derive(Default)]
+    struct Passthrough {
+        opened: Mutex<u64>,
+        closed: Mutex<Vec<u64>>,
+    }
+
+    impl Hook for Passthrough {
+        type Stream = ();
+
+        fn open(&self, _peer: SocketAddr) -> BoxFuture<'_, u64> {
+            let mut opened = self.opened.lock().unwrap();
+            *opened += 1;
+            Box::pin(std::future::ready(*opened))
+        }
+
+        fn close(&self, conn: u64) -> BoxFuture<'_, ()> {
+            self.closed.lock().unwrap().push(conn);
+            Box::pin(std::future::ready(()))
+        }
+
+        fn stream(&self, _conn: u64, _dir: Direction) {}
+
+        fn chunk<'a>(&'a self, _stream: &'a mut (), data: &'a [u8]) -> BoxFuture<'a, Vec<Step>> {
+            Box::pin(std::future::ready(vec![Step::Write(data.len())]))
+        }
+
+        fn datagram<'a>(
+            &'a self,
+            _stream: &'a mut (),
+            _: &'a [u8],
+        ) -> BoxFuture<'a, Vec<Duration>> {
+            Box::pin(std::future::ready(vec![Duration::ZERO]))
+        }
+    }
+
+    async fn echo() -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 100];
+            while let Ok((n, peer)) = socket.recv_from(&mut buf).await {
+                let _ = socket.send_to(&buf[..n], peer).await;
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn test_idle_udp_clients_evicted() {
+        let upstream = echo().await;
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let proxy = socket.local_addr().unwrap();
+        let hook = Arc::new(Passthrough::default());
+        let shutdown = CancellationToken::new();
+        tokio::spawn(relay_udp(
+            socket,
+            upstream.to_string(),
+            Arc::clone(&hook),
+            Duration::from_millis(100),
+            shutdown.clone(),
+        ));
+
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        client.connect(proxy).await.unwrap();
+        let mut buf = [0u8; 100];
+        client.send(b"one").await.unwrap();
+        let n = timeout(Duration::from_secs(1), client.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..n], b"one");
+
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert_eq!(*hook.closed.lock().unwrap(), [1]);
+
+        // Coming back later is a new client, answered as before
+        client.send(b"two").await.unwrap();
+        let n = timeout(Duration::from_secs(1), client.recv(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(&buf[..n], b"two");
+        assert_eq!(*hook.opened.lock().unwrap(), 2);
+        shutdown.cancel();
+    }
+}
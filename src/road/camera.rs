@@ -1,3 +1,5 @@
+use tracing::{debug, trace};
+
 #[derive(Debug, Clone, Copy)]
 pub struct Camera {
     pub road: u16,
@@ -18,12 +20,18 @@ impl Camera {
         let distance_miles = (other.location as i32 - self.location as i32).abs() as f64;
         let duration_seconds = (other_timestamp - own_timestamp) as f64;
         let speed_mph = distance_miles / (duration_seconds / 3600.0);
-        println!("Self limit: {}, Other limit: {}, Speed: {}", self.limit, other.limit, speed_mph);
+        trace!(road = self.road, limit = self.limit, speed_mph, "Checking speed");
         if speed_mph > self.limit as f64 {
             let speed_rounded_mph = speed_mph.round() as u16;
-            println!("Car found speeding for camera {:?} at speed {}", self, speed_rounded_mph);
+            debug!(
+                road = self.road,
+                mile = self.location,
+                limit = self.limit,
+                speed = speed_rounded_mph,
+                "Car found speeding"
+            );
             return Some(speed_rounded_mph)
         }
-        return None
+        None
     }
 }
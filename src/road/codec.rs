@@ -4,8 +4,9 @@ use tokio_util::{
     bytes::{Buf, BufMut, BytesMut},
     codec::{Decoder, Encoder},
 };
+use tracing::trace;
 
-use crate::road::ticket::Ticket;
+use crate::{metrics, road::ticket::Ticket, services::ServiceKind};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RespValue {
@@ -14,6 +15,16 @@ pub enum RespValue {
     Heartbeat,
 }
 
+impl RespValue {
+    pub fn opcode(&self) -> u8 {
+        match self {
+            RespValue::Error(_) => 0x10,
+            RespValue::Ticket(_) => 0x21,
+            RespValue::Heartbeat => 0x41,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq)]
 pub enum ReqValue {
     Plate(String, u32),
@@ -23,6 +34,18 @@ pub enum ReqValue {
     ReqError(String),
 }
 
+impl ReqValue {
+    pub fn opcode(&self) -> u8 {
+        match self {
+            ReqValue::Plate(..) => 0x20,
+            ReqValue::WantHeartbeat(_) => 0x40,
+            ReqValue::IAmCamera(..) => 0x80,
+            ReqValue::IAmDispatcher(_) => 0x81,
+            ReqValue::ReqError(_) => 0x10,
+        }
+    }
+}
+
 pub struct Codec;
 
 impl Decoder for Codec {
@@ -30,11 +53,19 @@ impl Decoder for Codec {
     type Error = anyhow::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>> {
+        let decoded = self.decode_frame(src);
+        metrics::service(ServiceKind::Road).record_decode(&decoded);
+        decoded
+    }
+}
+
+impl Codec {
+    fn decode_frame(&mut self, src: &mut BytesMut) -> Result<Option<ReqValue>> {
         if src.is_empty() {
             return Ok(None);
         }
 
-        println!("Decoding {:?}", src);
+        trace!(buffered = src.len(), opcode = src[0], "Decoding");
 
         // Check if we have a full frame
         if !frame_finished(src) {
@@ -95,7 +126,7 @@ fn frame_finished(src: &BytesMut) -> bool {
 }
 
 fn parse_length_prefixed_str(src: &mut BytesMut) -> Result<String> {
-    if src.len() < 1 {
+    if src.is_empty() {
         bail!("Insufficient data for string length");
     }
 
@@ -126,7 +157,7 @@ fn parse_u16_slice(src: &mut BytesMut, count: usize) -> Result<Vec<u16>> {
 }
 
 fn parse_dispatcher(src: &mut BytesMut) -> Result<Option<ReqValue>> {
-    if src.len() < 1 {
+    if src.is_empty() {
         return Ok(None);
     }
     let count = src[0] as usize;
@@ -172,6 +203,14 @@ impl Encoder<RespValue> for Codec {
     type Error = anyhow::Error;
 
     fn encode(&mut self, item: RespValue, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode_frame(item, dst)?;
+        metrics::service(ServiceKind::Road).messages_encoded.inc();
+        Ok(())
+    }
+}
+
+impl Codec {
+    fn encode_frame(&mut self, item: RespValue, dst: &mut BytesMut) -> Result<()> {
         match item {
             RespValue::Error(e) => write_str(dst, 0x10, e.as_bytes()),
             RespValue::Heartbeat => {
@@ -378,7 +417,7 @@ mod tests {
         assert!(decoded.is_some(), "decode should return Some on full frame");
         assert_eq!(
             decoded.unwrap(),
-            Some(ReqValue::Plate(plate.to_string(), timestamp)).unwrap()
+            ReqValue::Plate(plate.to_string(), timestamp)
         );
         assert_eq!(
             after_len, 0,
@@ -1,31 +1,46 @@
 use futures::StreamExt;
 use tokio::{net::TcpStream, sync::mpsc};
-use tokio_util::codec::Framed;
-
-use crate::road::{
-    Plates, RoadDispatchers, codec::Codec, request_handler::handle_request,
-    response_handler::response_handler,
+use tokio_util::{codec::Framed, sync::CancellationToken};
+use tracing::{Instrument, error, warn};
+
+use crate::{
+    config::ConnectionSettings,
+    metrics,
+    road::{
+        Plates, RoadDispatchers, codec::Codec, request_handler::handle_request,
+        response_handler::response_handler,
+    },
+    services::{RateLimited, ServiceKind},
 };
 
 pub async fn handle_road(
     socket: TcpStream,
     mut dispatchers: RoadDispatchers,
     plate_storage: Plates,
+    settings: ConnectionSettings,
+    shutdown: CancellationToken,
 ) -> anyhow::Result<()> {
     let addr = socket.peer_addr()?;
 
     let framed: Framed<TcpStream, Codec> = Framed::new(socket, Codec);
     let (writer, reader) = framed.split();
+    let reader = RateLimited::new(
+        reader,
+        settings.rate_limit,
+        metrics::service(ServiceKind::Road),
+    );
 
-    let (tx, rx) = mpsc::channel(1_000);
+    let (tx, rx) = mpsc::channel(settings.channel_capacity);
 
-    let writer_task = { tokio::spawn(async move { response_handler(writer, rx).await }) };
+    let writer_task = tokio::spawn(response_handler(writer, rx).in_current_span());
 
-    let reader_task = {
-        tokio::spawn(async move {
-            handle_request(reader, addr, &mut dispatchers, plate_storage, tx).await
-        })
-    };
+    let reader_task =
+        tokio::spawn(
+            async move {
+                handle_request(reader, addr, &mut dispatchers, plate_storage, tx, shutdown).await
+            }
+            .in_current_span(),
+        );
 
     // FIXED: Wait for BOTH tasks to complete before exiting
     let (reader_result, writer_result) = tokio::join!(reader_task, writer_task);
@@ -33,14 +48,14 @@ pub async fn handle_road(
     // Handle results properly
     match reader_result {
         Ok(Ok(())) => {}
-        Ok(Err(e)) => eprintln!("Reader error: {:?} for {}", e, addr),
-        Err(e) => eprintln!("Reader task panicked: {:?}", e),
+        Ok(Err(e)) => warn!(error = format!("{:#}", e), "Reader failed"),
+        Err(e) => error!(error = %e, "Reader task panicked"),
     }
 
     match writer_result {
         Ok(Ok(())) => {}
-        Ok(Err(e)) => eprintln!("Writer error: {:?} for {}", e, addr),
-        Err(e) => eprintln!("Writer task panicked: {:?}", e),
+        Ok(Err(e)) => warn!(error = format!("{:#}", e), "Writer failed"),
+        Err(e) => error!(error = %e, "Writer task panicked"),
     }
 
     Ok(())
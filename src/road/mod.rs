@@ -13,6 +13,7 @@ pub mod codec;
 pub mod ticket;
 pub mod heartbeat;
 pub mod road_dispatcher;
+pub mod service;
 
 //type FramedType = Framed<TcpListener>;
 pub type RoadDispatchers = Arc<Mutex<HashMap<u16, RoadDispatcher>>>;
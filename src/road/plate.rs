@@ -1,6 +1,7 @@
 use std::collections::{BTreeMap, HashMap, HashSet};
 
 use chrono::{DateTime, NaiveDate};
+use tracing::trace;
 
 use crate::road::{camera::Camera, ticket::Ticket};
 
@@ -10,6 +11,12 @@ pub struct PlateStorage {
     plates: HashMap<String, PlateState>,
 }
 
+impl Default for PlateStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl PlateStorage {
     pub fn new() -> Self {
         PlateStorage {
@@ -35,7 +42,7 @@ impl PlateState {
     pub fn new(name: &str, timestamp: u32, camera: &Camera) -> Self {
         let mut sightings = HashMap::new();
         let mut b_tree = BTreeMap::new();
-        b_tree.insert(timestamp, camera.clone());
+        b_tree.insert(timestamp, *camera);
         sightings.insert(camera.road, b_tree);
         Self {
             name: name.to_string(),
@@ -45,15 +52,15 @@ impl PlateState {
     }
 
     pub fn update(&mut self, timestamp: u32, camera: &Camera) -> anyhow::Result<Vec<Ticket>> {
-        println!("adding sighting {} for {:?}", timestamp, camera);
-        let sightings_on_road = self.sightings.entry(camera.road).or_insert(BTreeMap::new());
-        sightings_on_road.insert(timestamp, camera.clone());
+        trace!(plate = %self.name, timestamp, road = camera.road, mile = camera.location, "Adding sighting");
+        let sightings_on_road = self.sightings.entry(camera.road).or_default();
+        sightings_on_road.insert(timestamp, *camera);
         let tickets = self.has_ticket_for_road(&camera.road, &timestamp)?;
         let actionable_tickets = tickets
             .iter()
             .filter_map(|ticket| {
-                let start_date = to_date(ticket.timestamp1.clone());
-                let end_date = to_date(ticket.timestamp2.clone());
+                let start_date = to_date(ticket.timestamp1);
+                let end_date = to_date(ticket.timestamp2);
 
                 if self.ticketed_dates.contains(&start_date)
                     || self.ticketed_dates.contains(&end_date)
@@ -77,49 +84,51 @@ impl PlateState {
     ) -> anyhow::Result<Vec<Ticket>> {
         let mut tickets = vec![];
 
-        if let Some(sightings) = self.sightings.get(&road) {
+        if let Some(sightings) = self.sightings.get(road) {
             let sighting = match sightings.get(timestamp) {
                 Some(cam) => cam,
                 None => return Ok(vec![]), // or error?
             };
-            let (prev, next) = neighbors(sightings, timestamp.clone());
-            if let Some((prev_timestamp, camera)) = prev {
-                if let Some(speed) = camera.speeding(prev_timestamp, &sighting, &timestamp) {
-                    let ticket = Ticket {
-                        plate: self.name.clone(),
-                        road: *road,
-                        timestamp1: *prev_timestamp,
-                        mile1: camera.location,
-                        timestamp2: *timestamp,
-                        mile2: sighting.location,
-                        speed: speed * 100,
-                    };
-                    tickets.push(ticket);
-                }
+            let (prev, next) = neighbors(sightings, *timestamp);
+            if let Some((prev_timestamp, camera)) = prev
+                && let Some(speed) = camera.speeding(prev_timestamp, sighting, timestamp)
+            {
+                let ticket = Ticket {
+                    plate: self.name.clone(),
+                    road: *road,
+                    timestamp1: *prev_timestamp,
+                    mile1: camera.location,
+                    timestamp2: *timestamp,
+                    mile2: sighting.location,
+                    speed: speed * 100,
+                };
+                tickets.push(ticket);
             }
-            if let Some((next_timestamp, camera)) = next {
-                if let Some(speed) = sighting.speeding(timestamp, camera, next_timestamp) {
-                    let ticket = Ticket {
-                        plate: self.name.clone(),
-                        road: *road,
-                        timestamp1: *timestamp,
-                        mile1: sighting.location,
-                        timestamp2: *next_timestamp,
-                        mile2: camera.location,
-                        speed: speed * 100,
-                    };
-                    tickets.push(ticket);
-                }
+            if let Some((next_timestamp, camera)) = next
+                && let Some(speed) = sighting.speeding(timestamp, camera, next_timestamp)
+            {
+                let ticket = Ticket {
+                    plate: self.name.clone(),
+                    road: *road,
+                    timestamp1: *timestamp,
+                    mile1: sighting.location,
+                    timestamp2: *next_timestamp,
+                    mile2: camera.location,
+                    speed: speed * 100,
+                };
+                tickets.push(ticket);
             }
         }
         Ok(tickets)
     }
 }
 
+type Neighbor<'a, K, V> = Option<(&'a K, &'a V)>;
+
 fn neighbors<K: Ord + Copy, V>(
     map: &BTreeMap<K, V>,
     key: K,
-) -> (Option<(&K, &V)>, Option<(&K, &V)>) {
+) -> (Neighbor<'_, K, V>, Neighbor<'_, K, V>) {
     let prev = map.range(..key).next_back();
     let next = map
         .range((std::ops::Bound::Excluded(key), std::ops::Bound::Unbounded))
@@ -5,13 +5,17 @@ use anyhow::{self, bail};
 use futures::{StreamExt, stream::SplitStream};
 use tokio::{net::TcpStream, sync::mpsc::Sender};
 use tokio_util::{codec::Framed, sync::CancellationToken};
+use tracing::{debug, warn};
 
-use crate::road::{
-    Plates, RoadDispatchers,
-    camera::Camera,
-    codec::{Codec, ReqValue, RespValue},
-    heartbeat::spawn_heartbeat_task,
-    road_dispatcher::RoadDispatcher,
+use crate::{
+    road::{
+        Plates, RoadDispatchers,
+        camera::Camera,
+        codec::{Codec, ReqValue, RespValue},
+        heartbeat::spawn_heartbeat_task,
+        road_dispatcher::RoadDispatcher,
+    },
+    services::{RateLimited, Throttled},
 };
 
 enum IAm {
@@ -21,19 +25,26 @@ enum IAm {
 }
 
 pub async fn handle_request(
-    mut reader: SplitStream<Framed<TcpStream, Codec>>,
+    mut reader: RateLimited<SplitStream<Framed<TcpStream, Codec>>>,
     peer_address: SocketAddr,
     dispatchers: &mut RoadDispatchers,
     plate_storage: Plates,
     tx: Sender<RespValue>,
+    shutdown: CancellationToken,
 ) -> anyhow::Result<()> {
     let mut i_am = IAm::None;
     let mut heartbeat: Option<u32> = None;
     let cancel_token = CancellationToken::new();
-    while let Some(result) = reader.next().await {
+    // Stop reading on shutdown; dropping `tx` below lets the response handler flush what is queued
+    while let Some(next) = shutdown.run_until_cancelled(reader.next()).await.flatten() {
+        let Ok(result) = next else {
+            warn!("Message throttled");
+            tx.send(RespValue::Error(Throttled::MESSAGE.to_string())).await?;
+            continue;
+        };
         match result {
             Ok(data) => {
-                println!("received request: {:?}", data);
+                debug!(opcode = data.opcode(), request = ?data, "Received request");
                 let resp = match data {
                     ReqValue::WantHeartbeat(interval) => set_heartbeat(
                         &mut heartbeat,
@@ -49,16 +60,18 @@ pub async fn handle_request(
                             roads, tx.clone(), dispatchers).await
                     }
                     ReqValue::Plate(plate, time) => {
-                        update_plate(&i_am, &plate_storage, &dispatchers, plate, time).await
+                        update_plate(&i_am, &plate_storage, dispatchers, plate, time).await
                     }
                     _ => Ok(()),
                 };
-                if resp.is_err() {
-                    let msg = RespValue::Error(resp.unwrap_err().to_string());
+                if let Err(e) = resp {
+                    debug!(error = %e, "Request rejected");
+                    let msg = RespValue::Error(e.to_string());
                     tx.send(msg).await?;
                 }
             }
             Err(e) => {
+                warn!(error = %e, "Failed to decode request");
                 let msg = RespValue::Error(e.to_string());
                 tx.send(msg).await?;
             }
@@ -68,7 +81,7 @@ pub async fn handle_request(
     if let IAm::Dispatcher(roads) = &i_am {
         let mut guard = dispatchers.lock().await;
         for road in roads {
-            guard.remove(&road);
+            guard.remove(road);
         }
     }
     Ok(())
@@ -2,6 +2,7 @@ use anyhow::Context;
 use futures::{stream::SplitSink, SinkExt};
 use tokio::{net::TcpStream, sync::mpsc::Receiver};
 use tokio_util::codec::Framed;
+use tracing::{debug, warn};
 
 use crate::road::codec::{Codec, RespValue};
 
@@ -11,10 +12,14 @@ pub async fn response_handler(
 ) -> anyhow::Result<()> {
     while let Some(msg) = rx.recv().await {
         if let Err(e) = writer.send(msg.clone()).await.context("Failed to send response") {
-            eprintln!("Writer error ({}), shutting down response handler for msg: {:?}", e, msg);
+            warn!(
+                error = format!("{:#}", e),
+                opcode = msg.opcode(),
+                "Writer failed, closing response handler"
+            );
             break;
         }
     }
-    println!("Response handler exiting");
+    debug!("Response handler exiting");
     Ok(())
 }
\ No newline at end of file
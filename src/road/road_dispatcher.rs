@@ -2,13 +2,22 @@ use std::{collections::HashMap, net::SocketAddr};
 
 use tokio::sync::mpsc::Sender;
 
-use crate::road::codec::RespValue;
+use crate::{metrics::Counter, road::codec::RespValue};
+
+/// Every ticket handed to a dispatcher or its queue since startup.
+pub static TICKETS_ISSUED: Counter = Counter::new();
 
 pub struct RoadDispatcher {
     senders: HashMap<SocketAddr, Sender<RespValue>>,
     queue: Vec<RespValue>,
 }
 
+impl Default for RoadDispatcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl RoadDispatcher {
     pub fn new() -> Self {
         Self {
@@ -18,16 +27,17 @@ impl RoadDispatcher {
     }
 
     pub async fn add_ticket(&mut self, ticket: RespValue) {
+        TICKETS_ISSUED.inc();
         let maybe_key = self.senders.keys().next().cloned(); // avoid borrowing the whole map
 
         match maybe_key {
             Some(key) => {
-                if let Some(tx) = self.senders.get(&key) {
-                    if tx.send(ticket.clone()).await.is_err() {
-                        // Receiver gone, re-queue and clear sender
-                        self.queue.push(ticket);
-                        self.senders.remove(&key); // safe, no active borrow
-                    }
+                if let Some(tx) = self.senders.get(&key)
+                    && tx.send(ticket.clone()).await.is_err()
+                {
+                    // Receiver gone, re-queue and clear sender
+                    self.queue.push(ticket);
+                    self.senders.remove(&key); // safe, no active borrow
                 }
             }
             None => {
@@ -47,6 +57,11 @@ impl RoadDispatcher {
         self.senders.insert(address, tx);
     }
 
+    /// Tickets waiting for a dispatcher to connect.
+    pub fn queued(&self) -> usize {
+        self.queue.len()
+    }
+
     pub async fn remove_sender(&mut self, key: SocketAddr) {
         self.senders.remove(&key);
     }
@@ -0,0 +1,100 @@
+use std::{collections::HashMap, sync::Arc};
+
+use futures::{SinkExt, future::BoxFuture};
+use tokio::{net::TcpStream, sync::Mutex};
+use tokio_util::{codec::Framed, sync::CancellationToken};
+use tracing::warn;
+
+use crate::{
+    config::{ConnectionSettings, ServiceConfig},
+    metrics::{self, CollectorGuard, Sample},
+    road::{
+        Plates, RoadDispatchers,
+        codec::{Codec, RespValue},
+        handle_road::handle_road,
+        plate::PlateStorage,
+        road_dispatcher::TICKETS_ISSUED,
+    },
+    services::{Rejection, ServiceKind, TcpService},
+};
+
+pub struct RoadService {
+    dispatchers: RoadDispatchers,
+    plate_storage: Plates,
+    settings: ConnectionSettings,
+    _collector: CollectorGuard,
+}
+
+impl RoadService {
+    pub fn new(config: &ServiceConfig) -> Self {
+        let dispatchers: RoadDispatchers = Arc::new(Mutex::new(HashMap::new()));
+        let collected = dispatchers.clone();
+        let collector = metrics::add_collector(
+            ServiceKind::Road,
+            Box::new(move || Box::pin(collect(collected.clone()))),
+        );
+        metrics::register_counter(
+            ServiceKind::Road,
+            "protohack_tickets_issued_total",
+            "Tickets generated from plate observations.",
+            &TICKETS_ISSUED,
+        );
+        Self {
+            dispatchers,
+            plate_storage: Arc::new(Mutex::new(PlateStorage::new())),
+            settings: config.connection_settings(),
+            _collector: collector,
+        }
+    }
+}
+
+async fn collect(dispatchers: RoadDispatchers) -> Vec<Sample> {
+    let queued = queued_tickets(&dispatchers).await;
+    vec![Sample::gauge(
+        "protohack_tickets_queued",
+        "Tickets waiting for a dispatcher to connect.",
+        queued as f64,
+    )]
+}
+
+async fn queued_tickets(dispatchers: &RoadDispatchers) -> usize {
+    dispatchers
+        .lock()
+        .await
+        .values()
+        .map(|dispatcher| dispatcher.queued())
+        .sum()
+}
+
+impl TcpService for RoadService {
+    fn handle(
+        &self,
+        socket: TcpStream,
+        shutdown: CancellationToken,
+    ) -> BoxFuture<'static, anyhow::Result<()>> {
+        Box::pin(handle_road(
+            socket,
+            self.dispatchers.clone(),
+            self.plate_storage.clone(),
+            self.settings,
+            shutdown,
+        ))
+    }
+
+    fn reject(&self, socket: TcpStream, rejection: Rejection) -> BoxFuture<'static, ()> {
+        Box::pin(async move {
+            let mut framed = Framed::new(socket, Codec);
+            let _ = framed.send(RespValue::Error(rejection.to_string())).await;
+        })
+    }
+
+    fn finish(self: Box<Self>) -> BoxFuture<'static, anyhow::Result<()>> {
+        Box::pin(async move {
+            let undelivered = queued_tickets(&self.dispatchers).await;
+            if undelivered > 0 {
+                warn!(undelivered, "Tickets never reached a dispatcher");
+            }
+            Ok(())
+        })
+    }
+}
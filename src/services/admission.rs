@@ -0,0 +1,139 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
+
+use crate::config::LimitsConfig;
+
+/// Why a connection was turned away before reaching its handler.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rejection {
+    TooManyConnections,
+    TooManyFromAddress,
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::TooManyConnections => f.write_str("too many connections"),
+            Rejection::TooManyFromAddress => f.write_str("too many connections from your address"),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Open {
+    total: usize,
+    per_ip: HashMap<IpAddr, usize>,
+}
+
+/// Caps concurrent connections for one service, globally and per source IP.
+#[derive(Debug, Clone, Default)]
+pub struct Admission {
+    max_connections: Option<usize>,
+    max_connections_per_ip: Option<usize>,
+    open: Arc<Mutex<Open>>,
+}
+
+impl Admission {
+    pub fn new(limits: &LimitsConfig) -> Self {
+        Self {
+            max_connections: limits.max_connections,
+            max_connections_per_ip: limits.max_connections_per_ip,
+            open: Arc::default(),
+        }
+    }
+
+    /// Reserves a slot for `ip` until the returned permit is dropped.
+    pub fn admit(&self, ip: IpAddr) -> Result<Permit, Rejection> {
+        let mut open = self.open.lock().unwrap();
+        if self.max_connections.is_some_and(|max| open.total >= max) {
+            return Err(Rejection::TooManyConnections);
+        }
+        let from_ip = open.per_ip.get(&ip).copied().unwrap_or(0);
+        if self
+            .max_connections_per_ip
+            .is_some_and(|max| from_ip >= max)
+        {
+            return Err(Rejection::TooManyFromAddress);
+        }
+        open.total += 1;
+        open.per_ip.insert(ip, from_ip + 1);
+        Ok(Permit {
+            ip,
+            open: Arc::clone(&self.open),
+        })
+    }
+}
+
+pub struct Permit {
+    ip: IpAddr,
+    open: Arc<Mutex<Open>>,
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let mut open = self.open.lock().unwrap();
+        open.total -= 1;
+        if let Some(count) = open.per_ip.get_mut(&self.ip) {
+            *count -= 1;
+            if *count == 0 {
+                open.per_ip.remove(&self.ip);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(total: Option<usize>, per_ip: Option<usize>) -> LimitsConfig {
+        LimitsConfig {
+            max_connections: total,
+            max_connections_per_ip: per_ip,
+            ..LimitsConfig::default()
+        }
+    }
+
+    #[test]
+    fn test_per_ip_limit() {
+        let admission = Admission::new(&limits(None, Some(2)));
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let first = admission.admit(a).unwrap();
+        let _second = admission.admit(a).unwrap();
+        assert_eq!(
+            admission.admit(a).err(),
+            Some(Rejection::TooManyFromAddress)
+        );
+        assert!(admission.admit(b).is_ok());
+
+        drop(first);
+        assert!(admission.admit(a).is_ok());
+    }
+
+    #[test]
+    fn test_global_limit() {
+        let admission = Admission::new(&limits(Some(1), None));
+        let permit = admission.admit("10.0.0.1".parse().unwrap()).unwrap();
+        assert_eq!(
+            admission.admit("10.0.0.2".parse().unwrap()).err(),
+            Some(Rejection::TooManyConnections)
+        );
+        drop(permit);
+        assert!(admission.admit("10.0.0.2".parse().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let admission = Admission::default();
+        let permits: Vec<_> = (0..100)
+            .map(|_| admission.admit("10.0.0.1".parse().unwrap()).unwrap())
+            .collect();
+        assert_eq!(permits.len(), 100);
+    }
+}
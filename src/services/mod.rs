@@ -0,0 +1,444 @@
+use std::{
+    fmt,
+    net::SocketAddr,
+    str::FromStr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use futures::future::BoxFuture;
+use serde::{Deserialize, Deserializer, de};
+use tokio::{
+    net::{TcpListener, TcpStream, UdpSocket},
+    task::JoinSet,
+    time::timeout,
+};
+use tokio_util::sync::CancellationToken;
+use tracing::{Instrument, error, info, info_span, warn};
+
+use crate::metrics;
+
+mod admission;
+mod rate_limit;
+mod registry;
+
+pub use admission::{Admission, Permit, Rejection};
+pub use rate_limit::{RateLimit, RateLimited, Throttled, TokenBucket};
+pub use registry::{Registry, ServiceFactory};
+
+static CONN_COUNTER: AtomicUsize = AtomicUsize::new(1);
+
+/// How long a listener waits after a failed accept, e.g. with file descriptors exhausted,
+/// before trying again.
+pub const ACCEPT_BACKOFF: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ServiceKind {
+    Prime,
+    MeansToEnd,
+    Chat,
+    Road,
+    Crypto,
+    Database,
+    VersionControl,
+    JobCenter,
+}
+
+impl ServiceKind {
+    pub const ALL: [ServiceKind; 8] = [
+        ServiceKind::Prime,
+        ServiceKind::MeansToEnd,
+        ServiceKind::Chat,
+        ServiceKind::Road,
+        ServiceKind::Crypto,
+        ServiceKind::Database,
+        ServiceKind::VersionControl,
+        ServiceKind::JobCenter,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ServiceKind::Prime => "prime",
+            ServiceKind::MeansToEnd => "mte",
+            ServiceKind::Chat => "chat",
+            ServiceKind::Road => "road",
+            ServiceKind::Crypto => "crypto",
+            ServiceKind::Database => "database",
+            ServiceKind::VersionControl => "version_control",
+            ServiceKind::JobCenter => "job_center",
+        }
+    }
+}
+
+impl fmt::Display for ServiceKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for ServiceKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ServiceKind::ALL
+            .into_iter()
+            .find(|kind| kind.name() == s)
+            .ok_or_else(|| {
+                let known: Vec<&str> = ServiceKind::ALL.iter().map(|k| k.name()).collect();
+                anyhow::anyhow!(
+                    "unknown service `{}`, expected one of: {}",
+                    s,
+                    known.join(", ")
+                )
+            })
+    }
+}
+
+// Goes through `FromStr` rather than a derived enum so config errors name the offending key.
+impl<'de> Deserialize<'de> for ServiceKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let name = String::deserialize(deserializer)?;
+        name.parse().map_err(de::Error::custom)
+    }
+}
+
+/// A service together with the address it should listen on, parsed from `name=addr`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServiceSpec {
+    pub kind: ServiceKind,
+    pub addr: String,
+}
+
+impl FromStr for ServiceSpec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, addr) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("expected `name=addr`, got `{}`", s))?;
+        if addr.is_empty() {
+            anyhow::bail!("missing address for service `{}`", name);
+        }
+        Ok(Self {
+            kind: name.parse()?,
+            addr: addr.to_string(),
+        })
+    }
+}
+
+/// A protocol that is served one TCP connection at a time. The accept loop, connection spans
+/// and draining live in [`Service::serve`], so an implementation only owns its shared state.
+pub trait TcpService: Send + Sync + 'static {
+    fn handle(
+        &self,
+        socket: TcpStream,
+        shutdown: CancellationToken,
+    ) -> BoxFuture<'static, anyhow::Result<()>>;
+
+    /// Tells a client turned away by the connection limits why, in the protocol's own terms.
+    /// The default just closes the socket.
+    fn reject(&self, socket: TcpStream, rejection: Rejection) -> BoxFuture<'static, ()> {
+        let _ = (socket, rejection);
+        Box::pin(async {})
+    }
+
+    /// Runs once every connection has finished or been aborted, e.g. to stop an actor and
+    /// report what it still held.
+    fn finish(self: Box<Self>) -> BoxFuture<'static, anyhow::Result<()>> {
+        Box::pin(async { Ok(()) })
+    }
+}
+
+/// A protocol that owns its socket outright.
+pub trait UdpService: Send + Sync + 'static {
+    fn serve(
+        self: Box<Self>,
+        socket: UdpSocket,
+        shutdown: CancellationToken,
+    ) -> BoxFuture<'static, anyhow::Result<()>>;
+}
+
+pub enum Protocol {
+    Tcp(Box<dyn TcpService>),
+    Udp(Box<dyn UdpService>),
+}
+
+pub enum Listener {
+    Tcp(TcpListener),
+    Udp(UdpSocket),
+}
+
+impl Listener {
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        match self {
+            Listener::Tcp(listener) => listener.local_addr(),
+            Listener::Udp(socket) => socket.local_addr(),
+        }
+    }
+}
+
+/// A protocol built by the [`Registry`], ready to be bound and served.
+pub struct Service {
+    kind: ServiceKind,
+    protocol: Protocol,
+    admission: Admission,
+}
+
+/// What a service left behind once it stopped accepting and drained its connections.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct DrainSummary {
+    /// Connections that finished on their own after the shutdown signal.
+    pub closed: usize,
+    /// Connections still running when the drain timeout hit and were aborted.
+    pub forced: usize,
+}
+
+impl Service {
+    pub fn new(kind: ServiceKind, protocol: Protocol) -> Self {
+        Self {
+            kind,
+            protocol,
+            admission: Admission::default(),
+        }
+    }
+
+    /// Caps concurrent connections; only TCP services accept connections to cap.
+    pub fn with_admission(mut self, admission: Admission) -> Self {
+        self.admission = admission;
+        self
+    }
+
+    pub fn kind(&self) -> ServiceKind {
+        self.kind
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.kind.name()
+    }
+
+    /// Binds the socket type this protocol speaks.
+    pub async fn bind(&self, addr: &str) -> anyhow::Result<Listener> {
+        let listener = match self.protocol {
+            Protocol::Tcp(_) => Listener::Tcp(TcpListener::bind(addr).await?),
+            Protocol::Udp(_) => Listener::Udp(UdpSocket::bind(addr).await?),
+        };
+        info!(service = %self.kind, addr = %listener.local_addr()?, "Listening");
+        Ok(listener)
+    }
+
+    /// Serves until `shutdown` is cancelled, then gives live connections `drain_timeout` to
+    /// flush their responses before aborting them.
+    pub async fn serve(
+        self,
+        listener: Listener,
+        shutdown: CancellationToken,
+        drain_timeout: Duration,
+    ) -> anyhow::Result<DrainSummary> {
+        match (self.protocol, listener) {
+            (Protocol::Tcp(service), Listener::Tcp(listener)) => {
+                let summary = serve_tcp(
+                    self.kind,
+                    &*service,
+                    &self.admission,
+                    listener,
+                    shutdown,
+                    drain_timeout,
+                )
+                .await?;
+                service.finish().await?;
+                Ok(summary)
+            }
+            (Protocol::Udp(service), Listener::Udp(socket)) => {
+                service
+                    .serve(socket, shutdown)
+                    .instrument(info_span!("udp", service = %self.kind))
+                    .await?;
+                Ok(DrainSummary::default())
+            }
+            _ => anyhow::bail!("service {} was given the wrong kind of listener", self.kind),
+        }
+    }
+}
+
+async fn serve_tcp(
+    kind: ServiceKind,
+    service: &dyn TcpService,
+    admission: &Admission,
+    listener: TcpListener,
+    shutdown: CancellationToken,
+    drain_timeout: Duration,
+) -> anyhow::Result<DrainSummary> {
+    let mut connections = JoinSet::new();
+    loop {
+        tokio::select! {
+            biased;
+            _ = shutdown.cancelled() => break,
+            // Reap finished connections so the set only holds live ones
+            Some(_) = connections.join_next(), if !connections.is_empty() => {}
+            accepted = listener.accept() => {
+                let (socket, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(e) => {
+                        warn!(service = %kind, error = %e, "Accept failed, retrying");
+                        tokio::time::sleep(ACCEPT_BACKOFF).await;
+                        continue;
+                    }
+                };
+                let conn_id = CONN_COUNTER.fetch_add(1, Ordering::Relaxed);
+                let metrics = metrics::service(kind);
+                metrics.connections_total.inc();
+
+                let span = info_span!("conn", service = %kind, conn_id, %peer);
+                let permit = match admission.admit(peer.ip()) {
+                    Ok(permit) => permit,
+                    Err(rejection) => {
+                        metrics.connections_rejected.inc();
+                        warn!(parent: &span, %rejection, "Connection rejected");
+                        connections.spawn(service.reject(socket, rejection).instrument(span));
+                        continue;
+                    }
+                };
+                let connection = service.handle(socket, shutdown.child_token());
+                let active = metrics.connections_active.track();
+
+                connections.spawn(
+                    async move {
+                        let _admitted = (permit, active);
+                        info!("New connection established");
+                        match connection.await {
+                            Ok(()) => info!("Connection ended cleanly"),
+                            Err(e) => error!(error = format!("{:#}", e), "Connection ended with error"),
+                        }
+                    }
+                    .instrument(span),
+                );
+            }
+        }
+    }
+    drop(listener);
+
+    let live = connections.len();
+    info!(service = %kind, live, "Stopped accepting, draining connections");
+    let drained = timeout(drain_timeout, async {
+        while connections.join_next().await.is_some() {}
+    })
+    .await;
+    let forced = connections.len();
+    if drained.is_err() {
+        warn!(service = %kind, forced, "Drain timeout hit, aborting connections");
+        connections.shutdown().await;
+    }
+    Ok(DrainSummary {
+        closed: live - forced,
+        forced,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_service_spec() {
+        let spec: ServiceSpec = "road=0.0.0.0:4000".parse().unwrap();
+        assert_eq!(spec.kind, ServiceKind::Road);
+        assert_eq!(spec.addr, "0.0.0.0:4000");
+    }
+
+    #[test]
+    fn test_parse_service_spec_errors() {
+        assert!("road".parse::<ServiceSpec>().is_err());
+        assert!("road=".parse::<ServiceSpec>().is_err());
+        let err = "nope=127.0.0.1:1".parse::<ServiceSpec>().unwrap_err();
+        assert!(err.to_string().contains("unknown service `nope`"));
+    }
+
+    #[test]
+    fn test_service_names_round_trip() {
+        for kind in ServiceKind::ALL {
+            assert_eq!(kind.name().parse::<ServiceKind>().unwrap(), kind);
+        }
+    }
+
+    /// Holds every connection open until shutdown, then takes `flush` to close it, or never
+    /// closes it when `flush` is `None`.
+    struct Lingering {
+        flush: Option<Duration>,
+    }
+
+    impl TcpService for Lingering {
+        fn handle(
+            &self,
+            _socket: TcpStream,
+            shutdown: CancellationToken,
+        ) -> BoxFuture<'static, anyhow::Result<()>> {
+            let flush = self.flush;
+            Box::pin(async move {
+                shutdown.cancelled().await;
+                match flush {
+                    Some(flush) => tokio::time::sleep(flush).await,
+                    None => std::future::pending().await,
+                }
+                Ok(())
+            })
+        }
+    }
+
+    async fn start(
+        flush: Option<Duration>,
+        drain_timeout: Duration,
+    ) -> (
+        tokio::task::JoinHandle<anyhow::Result<DrainSummary>>,
+        SocketAddr,
+        CancellationToken,
+    ) {
+        let service = Service::new(
+            ServiceKind::Prime,
+            Protocol::Tcp(Box::new(Lingering { flush })),
+        );
+        let listener = service.bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let serving = tokio::spawn(service.serve(listener, shutdown.clone(), drain_timeout));
+        (serving, addr, shutdown)
+    }
+
+    #[tokio::test]
+    async fn test_drain_waits_for_connections() {
+        let (serving, addr, shutdown) =
+            start(Some(Duration::from_millis(50)), Duration::from_secs(5)).await;
+
+        let _client = TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown.cancel();
+
+        let summary = serving.await.unwrap().unwrap();
+        assert_eq!(
+            summary,
+            DrainSummary {
+                closed: 1,
+                forced: 0
+            }
+        );
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_drain_timeout_aborts_stragglers() {
+        let (serving, addr, shutdown) = start(None, Duration::from_millis(50)).await;
+
+        let _first = TcpStream::connect(addr).await.unwrap();
+        let _second = TcpStream::connect(addr).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        shutdown.cancel();
+
+        let summary = serving.await.unwrap().unwrap();
+        assert_eq!(
+            summary,
+            DrainSummary {
+                closed: 0,
+                forced: 2
+            }
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use std::{
+    pin::Pin,
+    task::{Context, Poll, ready},
+};
+
+use futures::{Stream, StreamExt};
+use tokio::time::Instant;
+
+use crate::metrics::ServiceMetrics;
+
+/// Messages a single connection may send: `per_sec` sustained, up to `burst` at once.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateLimit {
+    pub per_sec: f64,
+    pub burst: u32,
+}
+
+pub struct TokenBucket {
+    limit: RateLimit,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub fn new(limit: RateLimit) -> Self {
+        Self {
+            limit,
+            tokens: limit.burst as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    pub fn try_acquire(&mut self) -> bool {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.limit.per_sec).min(self.limit.burst as f64);
+        self.refilled_at = now;
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// A message dropped because the connection ran out of tokens.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Throttled;
+
+impl Throttled {
+    pub const MESSAGE: &'static str = "rate limit exceeded, message dropped";
+}
+
+/// Wraps the read half of a `Framed` so every decoded item spends a token. Items that arrive
+/// with the bucket empty come out as `Err(Throttled)` so the handler can answer in its own
+/// protocol and keep reading.
+pub struct RateLimited<S> {
+    inner: S,
+    bucket: Option<TokenBucket>,
+    metrics: &'static ServiceMetrics,
+}
+
+impl<S> RateLimited<S> {
+    /// `limit: None` passes every item through untouched.
+    pub fn new(inner: S, limit: Option<RateLimit>, metrics: &'static ServiceMetrics) -> Self {
+        Self {
+            inner,
+            bucket: limit.map(TokenBucket::new),
+            metrics,
+        }
+    }
+}
+
+impl<S: Stream + Unpin> Stream for RateLimited<S> {
+    type Item = Result<S::Item, Throttled>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let Some(item) = ready!(self.inner.poll_next_unpin(cx)) else {
+            return Poll::Ready(None);
+        };
+        let allowed = self
+            .bucket
+            .as_mut()
+            .is_none_or(|bucket| bucket.try_acquire());
+        if allowed {
+            Poll::Ready(Some(Ok(item)))
+        } else {
+            self.metrics.messages_throttled.inc();
+            Poll::Ready(Some(Err(Throttled)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use futures::stream;
+
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn test_bucket_refills() {
+        let mut bucket = TokenBucket::new(RateLimit {
+            per_sec: 2.0,
+            burst: 2,
+        });
+        assert!(bucket.try_acquire());
+        assert!(bucket.try_acquire());
+        assert!(!bucket.try_acquire());
+
+        tokio::time::advance(Duration::from_millis(500)).await;
+        assert!(bucket.try_acquire());
+        assert!(!bucket.try_acquire());
+
+        // Never holds more than the burst, however long it sits idle
+        tokio::time::advance(Duration::from_secs(60)).await;
+        assert!(bucket.try_acquire());
+        assert!(bucket.try_acquire());
+        assert!(!bucket.try_acquire());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn test_stream_throttles() {
+        static METRICS: ServiceMetrics = ServiceMetrics::new();
+        let limit = RateLimit {
+            per_sec: 1.0,
+            burst: 1,
+        };
+        let items: Vec<_> = RateLimited::new(stream::iter([1, 2, 3]), Some(limit), &METRICS)
+            .collect()
+            .await;
+        assert_eq!(items, vec![Ok(1), Err(Throttled), Err(Throttled)]);
+        assert_eq!(METRICS.messages_throttled.get(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_unlimited_stream() {
+        static METRICS: ServiceMetrics = ServiceMetrics::new();
+        let items: Vec<_> = RateLimited::new(stream::iter([1, 2, 3]), None, &METRICS)
+            .collect()
+            .await;
+        assert_eq!(items, vec![Ok(1), Ok(2), Ok(3)]);
+    }
+}
@@ -0,0 +1,102 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    chat::service::ChatService,
+    config::ServiceConfig,
+    crypto::service::CryptoService,
+    database_server::DatabaseService,
+    handle_is_prime::PrimeService,
+    handle_mte::MteService,
+    job_center::service::JobCenterService,
+    road::service::RoadService,
+    services::{Admission, Protocol, Service, ServiceKind},
+    version_control::service::VersionControlService,
+};
+
+/// Builds a protocol's shared state from its config section, failing if that state cannot be
+/// loaded.
+pub type ServiceFactory = fn(&ServiceConfig) -> anyhow::Result<Protocol>;
+
+/// Maps every service name to the factory that builds it. Both the binary and the integration
+/// tests go through here, so a new protocol only has to be registered in [`Registry::default`].
+pub struct Registry {
+    factories: BTreeMap<ServiceKind, ServiceFactory>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        let mut registry = Self::empty();
+        registry
+            .register(ServiceKind::Prime, |config| {
+                Ok(Protocol::Tcp(Box::new(PrimeService::new(config))))
+            })
+            .register(ServiceKind::MeansToEnd, |config| {
+                Ok(Protocol::Tcp(Box::new(MteService::new(config)?)))
+            })
+            .register(ServiceKind::Chat, |config| {
+                Ok(Protocol::Tcp(Box::new(ChatService::new(config))))
+            })
+            .register(ServiceKind::Road, |config| {
+                Ok(Protocol::Tcp(Box::new(RoadService::new(config))))
+            })
+            .register(ServiceKind::Crypto, |_| {
+                Ok(Protocol::Tcp(Box::new(CryptoService)))
+            })
+            .register(ServiceKind::Database, |config| {
+                Ok(Protocol::Udp(Box::new(DatabaseService::new(config))))
+            })
+            .register(ServiceKind::VersionControl, |config| {
+                Ok(Protocol::Tcp(Box::new(VersionControlService::new(config))))
+            })
+            .register(ServiceKind::JobCenter, |config| {
+                Ok(Protocol::Tcp(Box::new(JobCenterService::new(config))))
+            });
+        registry
+    }
+}
+
+impl Registry {
+    pub fn empty() -> Self {
+        Self {
+            factories: BTreeMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, kind: ServiceKind, factory: ServiceFactory) -> &mut Self {
+        self.factories.insert(kind, factory);
+        self
+    }
+
+    pub fn kinds(&self) -> impl Iterator<Item = ServiceKind> + '_ {
+        self.factories.keys().copied()
+    }
+
+    /// Builds `kind`. Actor-backed services spawn their actor here, so this needs a runtime.
+    pub fn build(&self, kind: ServiceKind, config: &ServiceConfig) -> anyhow::Result<Service> {
+        let factory = self
+            .factories
+            .get(&kind)
+            .ok_or_else(|| anyhow::anyhow!("service {} is not registered", kind))?;
+        Ok(Service::new(kind, factory(config)?).with_admission(Admission::new(&config.limits)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_service_is_registered() {
+        let registry = Registry::default();
+        assert!(registry.kinds().eq(ServiceKind::ALL));
+    }
+
+    #[test]
+    fn test_unregistered_service() {
+        let err = Registry::empty()
+            .build(ServiceKind::Road, &ServiceConfig::new("127.0.0.1:0"))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "service road is not registered");
+    }
+}
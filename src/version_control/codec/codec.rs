@@ -3,11 +3,14 @@ use tokio_util::{
     bytes::{Buf, BufMut, BytesMut},
     codec::{Decoder, Encoder},
 };
-use tracing::info;
+use tracing::trace;
+
+use crate::{metrics, services::ServiceKind};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RespValue {
     Text(String),
+    File(Vec<u8>),
 }
 
 #[derive(Debug, PartialEq)]
@@ -18,6 +21,17 @@ pub enum VersionControlCommand {
     Put(Option<(String, String)>),
 }
 
+impl VersionControlCommand {
+    pub fn command(&self) -> &'static str {
+        match self {
+            VersionControlCommand::Help => "HELP",
+            VersionControlCommand::List(_) => "LIST",
+            VersionControlCommand::Get(_) => "GET",
+            VersionControlCommand::Put(_) => "PUT",
+        }
+    }
+}
+
 pub struct Codec;
 
 impl Decoder for Codec {
@@ -25,27 +39,35 @@ impl Decoder for Codec {
     type Error = anyhow::Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>> {
+        let decoded = self.decode_frame(src);
+        metrics::service(ServiceKind::VersionControl).record_decode(&decoded);
+        decoded
+    }
+}
+
+impl Codec {
+    fn decode_frame(&mut self, src: &mut BytesMut) -> Result<Option<VersionControlCommand>> {
         if src.is_empty() {
             return Ok(None);
         }
 
-        info!("Decoding {:?}", src);
+        trace!(buffered = src.len(), "Decoding");
 
         // Clone src for parsing, so we don't consume original unless successful
         let mut buf = src.clone();
 
         match buf.windows(1).position(|ch| ch == b"\n") {
-            None => return Ok(None),
+            None => Ok(None),
             Some(position) => {
                 let line = buf.split_to(position + 1);
                 if let Some((consume, command)) = self.parse_command(&line, &mut buf)? {
                     src.advance(consume);
-                    return Ok(Some(command));
+                    Ok(Some(command))
                 } else {
-                    return Ok(None);
+                    Ok(None)
                 }
             }
-        };
+        }
     }
 }
 
@@ -69,14 +91,14 @@ impl Codec {
                     "LIST" => Ok(Some((to_consume, VersionControlCommand::List(Some(args[0].to_string()))))),
                     "GET" => Ok(Some((to_consume, VersionControlCommand::Get(Some(args[0].to_string()))))),
                     "PUT" => {
-                        let file = args.get(0).expect("Filename not given");
+                        let file = args.first().expect("Filename not given");
                         let length = args.get(1).expect("Length not given").parse::<usize>()?;
                         if buf.len() < length {
                             Ok(None)
                         } else {
                             let data = String::from_utf8(buf[..length].to_vec())?;
                             let command = VersionControlCommand::Put(Some((file.to_string(), data)));
-                            return Ok(Some((to_consume + length, command)));
+                            Ok(Some((to_consume + length, command)))
                         }
                     }
                     _ => bail!("Illegal Method"),
@@ -91,11 +113,23 @@ impl Encoder<RespValue> for Codec {
     type Error = anyhow::Error;
 
     fn encode(&mut self, item: RespValue, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        self.encode_frame(item, dst)?;
+        metrics::service(ServiceKind::VersionControl).messages_encoded.inc();
+        Ok(())
+    }
+}
+
+impl Codec {
+    fn encode_frame(&mut self, item: RespValue, dst: &mut BytesMut) -> Result<()> {
         match item {
             RespValue::Text(string) => {
                 dst.put(string.as_bytes());
                 dst.put("\n".as_bytes());
             }
+            RespValue::File(data) => {
+                dst.put(format!("OK {}\n", data.len()).as_bytes());
+                dst.put(data.as_slice());
+            }
         }
         Ok(())
     }
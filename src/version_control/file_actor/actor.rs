@@ -1,8 +1,8 @@
-use std::path::Path;
+use std::path::{Component, Path};
 
 use anyhow::Ok;
 use tokio::sync::oneshot::Sender;
-use tracing::{info, warn};
+use tracing::debug;
 
 use crate::version_control::{
     codec::codec::{RespValue, VersionControlCommand},
@@ -12,7 +12,7 @@ use crate::version_control::{
 impl FileManager {
     pub async fn file_actor(&mut self) -> anyhow::Result<()> {
         while let Some((command, tx)) = self.rx.recv().await {
-            info!("WORKING ON {:?}", command);
+            debug!(command = command.command(), "Handling command");
             let resp = match command {
                 VersionControlCommand::Help => {
                     RespValue::Text("OK usage: HELP|GET|PUT|LIST".to_string())
@@ -20,12 +20,11 @@ impl FileManager {
 
                 VersionControlCommand::List(dir) => {
                     match dir {
-                        Some(dir) => { 
-                            let absolute_path = Path::new(&dir);
-                            let dir = absolute_path.parent().unwrap();
-                            let count = self.dirs.get(dir.to_path_buf());
-                            RespValue::Text(format!("OK {}", count).to_string())
-                        },
+                        Some(dir) if is_legal_path(&dir) => {
+                            let count = self.dirs.get(Path::new(&dir));
+                            RespValue::Text(format!("OK {}", count))
+                        }
+                        Some(_) => RespValue::Text("ERR illegal dir name".to_string()),
                         None => {
                             RespValue::Text("ERR usage: LIST dir".to_string())
                         }
@@ -34,7 +33,19 @@ impl FileManager {
 
                 VersionControlCommand::Get(filename) => {
                     match filename {
-                        Some(filename) => {RespValue::Text("Fix".to_string())}
+                        Some(filename) if is_legal_path(&filename) => {
+                            let path = Path::new(&filename);
+                            let data = path
+                                .parent()
+                                .zip(path.file_name().and_then(|name| name.to_str()))
+                                .and_then(|(dir, name)| self.dirs.find(dir, name))
+                                .and_then(|file| file.get(None));
+                            match data {
+                                Some(data) => RespValue::File(data.to_vec()),
+                                None => RespValue::Text("ERR no such file".to_string()),
+                            }
+                        }
+                        Some(_) => RespValue::Text("ERR illegal file name".to_string()),
                         None => {
                             RespValue::Text("ERR usage: GET file [revision]".to_string())
                         }
@@ -65,6 +76,13 @@ impl FileManager {
     }
 }
 
+fn is_legal_path(path: &str) -> bool {
+    path.starts_with('/')
+        && Path::new(path)
+            .components()
+            .all(|component| matches!(component, Component::RootDir | Component::Normal(_)))
+}
+
 fn respond<T>(tx: Sender<T>, val: T)
 where
     T: std::fmt::Debug,
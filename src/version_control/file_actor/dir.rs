@@ -1,4 +1,7 @@
-use std::{collections::HashMap, path::PathBuf};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
 
 use crate::version_control::file_actor::file::File;
 
@@ -14,14 +17,16 @@ impl Dir {
         }
     }
     pub fn find_or_create(&mut self, path: PathBuf, file_name: String) -> &mut File {
-        let dir = self.folders.entry(path).or_insert_with(|| HashMap::new());
+        let dir = self.folders.entry(path).or_default();
 
-        let file = dir.entry(file_name).or_insert_with(|| 
-            File::new());
-        file
+        dir.entry(file_name).or_default()
     }
 
-    pub fn get(&mut self, dir: PathBuf) -> usize {
-        self.folders.get(&dir).iter().count()
+    pub fn find(&self, path: &Path, file_name: &str) -> Option<&File> {
+        self.folders.get(path)?.get(file_name)
+    }
+
+    pub fn get(&self, dir: &Path) -> usize {
+        self.folders.get(dir).map_or(0, HashMap::len)
     }
 }
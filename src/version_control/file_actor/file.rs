@@ -30,14 +30,8 @@ impl File {
         *entry
     }
 
-    pub fn get(&mut self, revision: Option<usize>) -> Vec<u8> {
-        match revision {
-            None => {
-                self.version_lookup.get(&self.version).unwrap().clone()
-            }
-            Some(number) => {
-               self.version_lookup.get(&number).unwrap().clone()
-            }
-        }
+    pub fn get(&self, revision: Option<usize>) -> Option<&[u8]> {
+        let revision = revision.unwrap_or(self.version);
+        self.version_lookup.get(&revision).map(Vec::as_slice)
     }
 }
@@ -1,6 +1,4 @@
-use std::{collections::HashMap, fs::File, hash::Hash};
-
-use tokio::sync::{mpsc::{Receiver, Sender}, oneshot};
+use tokio::sync::{mpsc::Receiver, oneshot};
 
 use crate::version_control::{codec::codec::{RespValue, VersionControlCommand}, file_actor::dir::Dir};
 
@@ -4,10 +4,14 @@ use tokio::{
     sync::mpsc::{self, Sender},
     sync::oneshot
 };
-use tokio_util::codec::{Framed};
-
-use crate::version_control::{
-    codec::codec::{Codec, RespValue, VersionControlCommand}, request_handler::handle_request, response_handler::response_handler,
+use tokio_util::{codec::Framed, sync::CancellationToken};
+use tracing::{Instrument, error, warn};
+
+use crate::{
+    config::ConnectionSettings,
+    version_control::{
+        codec::codec::{Codec, RespValue, VersionControlCommand}, request_handler::handle_request, response_handler::response_handler,
+    },
 };
 
 pub type  FileManagerSender = Sender<(VersionControlCommand, oneshot::Sender<RespValue>)>;
@@ -15,22 +19,21 @@ pub type  FileManagerSender = Sender<(VersionControlCommand, oneshot::Sender<Res
 pub async fn handle_version_control(
     socket: TcpStream,
     version_control_manager: FileManagerSender,
+    settings: ConnectionSettings,
+    shutdown: CancellationToken,
 ) -> anyhow::Result<()> {
-    let addr = socket.peer_addr()?;
-
     let mut framed = Framed::new(socket, Codec);
     framed.send(RespValue::Text("READY".to_string())).await?;
     let (writer, reader) = framed.split();
 
-    let (mut tx, rx) = mpsc::channel(1_000);
+    let (mut tx, rx) = mpsc::channel(settings.channel_capacity);
 
-    let writer_task = { tokio::spawn(async move { response_handler(writer, rx).await }) };
+    let writer_task = tokio::spawn(response_handler(writer, rx).in_current_span());
 
-    let reader_task = {
-        tokio::spawn(
-            async move { handle_request(reader, version_control_manager, &mut tx).await },
-        )
-    };
+    let reader_task = tokio::spawn(
+        async move { handle_request(reader, version_control_manager, &mut tx, shutdown).await }
+            .in_current_span(),
+    );
 
     // FIXED: Wait for BOTH tasks to complete before exiting
     let (reader_result, writer_result) = tokio::join!(reader_task, writer_task);
@@ -38,14 +41,14 @@ pub async fn handle_version_control(
     // Handle results properly
     match reader_result {
         Ok(Ok(())) => {}
-        Ok(Err(e)) => eprintln!("Reader error: {:?} for {}", e, addr),
-        Err(e) => eprintln!("Reader task panicked: {:?}", e),
+        Ok(Err(e)) => warn!(error = format!("{:#}", e), "Reader failed"),
+        Err(e) => error!(error = %e, "Reader task panicked"),
     }
 
     match writer_result {
         Ok(Ok(())) => {}
-        Ok(Err(e)) => eprintln!("Writer error: {:?} for {}", e, addr),
-        Err(e) => eprintln!("Writer task panicked: {:?}", e),
+        Ok(Err(e)) => warn!(error = format!("{:#}", e), "Writer failed"),
+        Err(e) => error!(error = %e, "Writer task panicked"),
     }
 
     Ok(())
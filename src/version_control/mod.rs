@@ -3,4 +3,5 @@ pub mod file_actor;
 pub mod handler_version_control;
 mod request_handler;
 mod response_handler;
+pub mod service;
 
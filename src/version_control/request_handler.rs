@@ -1,12 +1,10 @@
-use std::net::SocketAddr;
-
 use futures::{StreamExt, stream::SplitStream};
 use tokio::{
     net::TcpStream,
     sync::{mpsc::Sender, oneshot},
 };
-use tokio_util::codec::Framed;
-use tracing::{info, warn};
+use tokio_util::{codec::Framed, sync::CancellationToken};
+use tracing::debug;
 
 use crate::version_control::{codec::codec::{Codec, RespValue, VersionControlCommand}, handler_version_control::FileManagerSender};
 
@@ -14,9 +12,15 @@ pub async fn handle_request(
     mut reader: SplitStream<Framed<TcpStream, Codec>>,
     version_control_sender: FileManagerSender,
     writer_tx: &mut Sender<RespValue>,
+    shutdown: CancellationToken,
 ) -> anyhow::Result<()> {
-    while let Some(command) = reader.next().await.transpose()? {
-        info!("Received {:?}", command);
+    while let Some(command) = shutdown
+        .run_until_cancelled(reader.next())
+        .await
+        .flatten()
+        .transpose()?
+    {
+        debug!(command = command.command(), "Received request");
         match command {
             VersionControlCommand::Help => {
                 let (tx, rx) = oneshot::channel::<RespValue>();
@@ -2,7 +2,7 @@ use anyhow::Context;
 use futures::{stream::SplitSink, SinkExt};
 use tokio::{net::TcpStream, sync::mpsc::Receiver};
 use tokio_util::codec::Framed;
-use tracing::info;
+use tracing::{debug, warn};
 
 use crate::version_control::codec::codec::{Codec, RespValue};
 
@@ -12,12 +12,16 @@ pub async fn response_handler(
     mut rx: Receiver<RespValue>,
 ) -> anyhow::Result<()> {
     while let Some(msg) = rx.recv().await {
-        info!("Sending {:?}", msg);
+        debug!(response = ?msg, "Sending response");
         if let Err(e) = writer.send(msg.clone()).await.context("Failed to send response") {
-            eprintln!("Writer error ({}), shutting down response handler for msg: {:?}", e, msg);
+            warn!(
+                error = format!("{:#}", e),
+                response = ?msg,
+                "Writer failed, closing response handler"
+            );
             break;
         }
     }
-    println!("Response handler exiting");
+    debug!("Response handler exiting");
     Ok(())
 }
\ No newline at end of file
@@ -0,0 +1,60 @@
+use futures::future::BoxFuture;
+use tokio::{net::TcpStream, sync::mpsc, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tracing::error;
+
+use crate::{
+    config::{ConnectionSettings, ServiceConfig},
+    services::TcpService,
+    version_control::{
+        file_actor::manager::FileManager,
+        handler_version_control::{FileManagerSender, handle_version_control},
+    },
+};
+
+pub struct VersionControlService {
+    sender: FileManagerSender,
+    actor: JoinHandle<()>,
+    settings: ConnectionSettings,
+}
+
+impl VersionControlService {
+    pub fn new(config: &ServiceConfig) -> Self {
+        let (sender, rx) = mpsc::channel(config.actor_channel_capacity());
+        let mut file_manager = FileManager::new(rx);
+        let actor = tokio::spawn(async move {
+            if let Err(e) = file_manager.file_actor().await {
+                error!("FileManager exited with error: {:?}", e);
+            }
+        });
+        Self {
+            sender,
+            actor,
+            settings: config.connection_settings(),
+        }
+    }
+}
+
+impl TcpService for VersionControlService {
+    fn handle(
+        &self,
+        socket: TcpStream,
+        shutdown: CancellationToken,
+    ) -> BoxFuture<'static, anyhow::Result<()>> {
+        Box::pin(handle_version_control(
+            socket,
+            self.sender.clone(),
+            self.settings,
+            shutdown,
+        ))
+    }
+
+    fn finish(self: Box<Self>) -> BoxFuture<'static, anyhow::Result<()>> {
+        let VersionControlService { sender, actor, .. } = *self;
+        drop(sender);
+        Box::pin(async move {
+            actor.await?;
+            Ok(())
+        })
+    }
+}
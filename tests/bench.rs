@@ -0,0 +1,69 @@
+use prime_time::bench::{self, BenchConfig};
+
+/// Every workload for a moment against its in-process service, a handful of clients each.
+const SCENARIOS: &str = r#"
+[[scenario]]
+name = "jobs"
+clients = 4
+duration_secs = 1
+
+[scenario.workload]
+protocol = "job_center"
+queues = 2
+abort_ratio = 0.5
+
+[[scenario]]
+name = "road"
+clients = 4
+duration_secs = 1
+think_ms = 10
+
+[scenario.workload]
+protocol = "road"
+roads = 2
+dispatchers = 1
+speeding_ratio = 1.0
+
+[[scenario]]
+name = "chat"
+clients = 3
+duration_secs = 1
+think_ms = 20
+
+[scenario.workload]
+protocol = "chat"
+
+[[scenario]]
+name = "mte"
+clients = 4
+duration_secs = 1
+
+[scenario.workload]
+protocol = "mte"
+insert_ratio = 0.5
+"#;
+
+#[tokio::test]
+async fn test_every_workload_runs_locally() {
+    let config = BenchConfig::from_toml(SCENARIOS).unwrap();
+    let expected_ops = [
+        ("jobs", vec!["put", "get"]),
+        ("road", vec!["plate", "ticket"]),
+        ("chat", vec!["send", "deliver"]),
+        ("mte", vec!["insert", "query"]),
+    ];
+
+    for (scenario, (name, ops)) in config.scenarios.iter().zip(expected_ops) {
+        let report = bench::run(scenario).await.unwrap();
+        assert_eq!(report.scenario, name);
+        assert_eq!(report.failed_clients, 0, "{}", report);
+        for op in ops {
+            let summary = report
+                .op(op)
+                .unwrap_or_else(|| panic!("no {} in {}", op, report));
+            assert!(summary.count > 0, "{}", report);
+            assert_eq!(summary.errors, 0, "{}", report);
+            assert!(summary.p50_us <= summary.p99_us);
+        }
+    }
+}
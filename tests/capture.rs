@@ -0,0 +1,203 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use prime_time::capture::{self, Capture, Direction, Event, Recorder, Transport};
+use prime_time::services::ServiceKind;
+use tokio::net::{TcpListener, UdpSocket};
+use tokio_util::sync::CancellationToken;
+
+mod service_harness;
+mod test_util;
+use service_harness::ServiceHarness;
+use test_util::TestClient;
+
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+fn capture_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("prime_time-{}-{}.jsonl", name, std::process::id()))
+}
+
+/// Records a short isPrime session through the proxy and returns the parsed capture.
+async fn capture_prime_session(harness: &ServiceHarness, path: &Path) -> Capture {
+    let (recorder, writer) = Recorder::create(path, Transport::Tcp, &harness.addr.to_string())
+        .await
+        .unwrap();
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = listener.local_addr().unwrap();
+    let shutdown = CancellationToken::new();
+    let proxy = tokio::spawn(capture::proxy_tcp(
+        listener,
+        harness.addr.to_string(),
+        recorder,
+        shutdown.clone(),
+    ));
+
+    let mut client = TestClient::connect(&proxy_addr.to_string()).await.unwrap();
+    // Split mid-request so the capture holds the exact chunking the service saw
+    client.send(r#"{"method":"isPrime","#).await.unwrap();
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    client.send("\"number\":7}\n").await.unwrap();
+    assert!(client.read_line().await.unwrap().contains("true"));
+    client
+        .send("{\"method\":\"isPrime\",\"number\":8}\n")
+        .await
+        .unwrap();
+    assert!(client.read_line().await.unwrap().contains("false"));
+    drop(client);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+
+    shutdown.cancel();
+    proxy.await.unwrap().unwrap();
+    writer.await.unwrap().unwrap();
+    Capture::load(path).unwrap()
+}
+
+#[tokio::test]
+async fn test_replay_matches_capture() {
+    let harness = ServiceHarness::direct(ServiceKind::Prime).await;
+    let path = capture_path("replay-matches");
+    let recorded = capture_prime_session(&harness, &path).await;
+    std::fs::remove_file(&path).unwrap();
+
+    let inbound: Vec<&[u8]> = recorded
+        .events
+        .iter()
+        .filter_map(|(_, event)| match event {
+            Event::Data {
+                dir: Direction::In,
+                bytes,
+                ..
+            } => Some(bytes.as_slice()),
+            _ => None,
+        })
+        .collect();
+    assert_eq!(inbound.len(), 3);
+    assert!(matches!(
+        recorded.events.last(),
+        Some((_, Event::Close { .. }))
+    ));
+
+    let report = capture::replay(&recorded, &harness.addr.to_string(), READ_TIMEOUT)
+        .await
+        .unwrap();
+    assert_eq!(report.connections, 1);
+    assert_eq!(report.responses, 2);
+    assert!(report.mismatches.is_empty(), "{:?}", report.mismatches);
+}
+
+#[tokio::test]
+async fn test_replay_reports_mismatch() {
+    let harness = ServiceHarness::direct(ServiceKind::Prime).await;
+    let path = capture_path("replay-mismatch");
+    let mut recorded = capture_prime_session(&harness, &path).await;
+    std::fs::remove_file(&path).unwrap();
+
+    // Pretend the service used to answer the first request differently
+    let (line, first_response) = recorded
+        .events
+        .iter_mut()
+        .find_map(|(line, event)| match event {
+            Event::Data {
+                dir: Direction::Out,
+                bytes,
+                ..
+            } => Some((*line, bytes)),
+            _ => None,
+        })
+        .unwrap();
+    *first_response = b"{\"method\":\"isPrime\",\"prime\":false}\n".to_vec();
+
+    let report = capture::replay(&recorded, &harness.addr.to_string(), READ_TIMEOUT)
+        .await
+        .unwrap();
+    assert_eq!(report.mismatches.len(), 1);
+    let mismatch = &report.mismatches[0];
+    assert_eq!(mismatch.line, line);
+    let diff = mismatch.diff();
+    assert!(
+        diff.contains("-{\"method\":\"isPrime\",\"prime\":false}"),
+        "{}",
+        diff
+    );
+    assert!(
+        diff.contains("+{\"method\":\"isPrime\",\"prime\":true}"),
+        "{}",
+        diff
+    );
+}
+
+#[tokio::test]
+async fn test_replay_charges_extra_bytes_to_their_response() {
+    let harness = ServiceHarness::direct(ServiceKind::Prime).await;
+    let path = capture_path("replay-extra");
+    let mut recorded = capture_prime_session(&harness, &path).await;
+    std::fs::remove_file(&path).unwrap();
+
+    // Pretend the service used to send a shorter first answer; the rest of what it sends now
+    // must not leak into the second response
+    let (line, first_response) = recorded
+        .events
+        .iter_mut()
+        .find_map(|(line, event)| match event {
+            Event::Data {
+                dir: Direction::Out,
+                bytes,
+                ..
+            } => Some((*line, bytes)),
+            _ => None,
+        })
+        .unwrap();
+    let actual = first_response.clone();
+    first_response.truncate(10);
+
+    let report = capture::replay(&recorded, &harness.addr.to_string(), READ_TIMEOUT)
+        .await
+        .unwrap();
+    assert_eq!(report.responses, 2);
+    assert_eq!(report.mismatches.len(), 1, "{:?}", report.mismatches);
+    assert_eq!(report.mismatches[0].line, line);
+    assert_eq!(report.mismatches[0].actual, actual);
+}
+
+#[tokio::test]
+async fn test_udp_capture_replays_against_fresh_service() {
+    let harness = ServiceHarness::direct(ServiceKind::Database).await;
+    let path = capture_path("udp");
+    let (recorder, writer) = Recorder::create(&path, Transport::Udp, &harness.addr.to_string())
+        .await
+        .unwrap();
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let proxy_addr = socket.local_addr().unwrap();
+    let shutdown = CancellationToken::new();
+    let proxy = tokio::spawn(capture::proxy_udp(
+        socket,
+        harness.addr.to_string(),
+        recorder,
+        shutdown.clone(),
+    ));
+
+    let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    client.connect(proxy_addr).await.unwrap();
+    client.send(b"foo=bar").await.unwrap();
+    client.send(b"foo").await.unwrap();
+    let mut buf = [0u8; 1000];
+    let n = tokio::time::timeout(READ_TIMEOUT, client.recv(&mut buf))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(&buf[..n], b"foo=bar");
+
+    shutdown.cancel();
+    proxy.await.unwrap().unwrap();
+    writer.await.unwrap().unwrap();
+    let recorded = Capture::load(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+    assert_eq!(recorded.header.transport, Transport::Udp);
+
+    let fresh = ServiceHarness::direct(ServiceKind::Database).await;
+    let report = capture::replay(&recorded, &fresh.addr.to_string(), READ_TIMEOUT)
+        .await
+        .unwrap();
+    assert_eq!(report.responses, 1);
+    assert!(report.mismatches.is_empty(), "{:?}", report.mismatches);
+}
@@ -0,0 +1,160 @@
+use std::time::Duration;
+
+use prime_time::config::ServiceConfig;
+use prime_time::faults::Faults;
+use prime_time::services::ServiceKind;
+use tokio::net::UdpSocket;
+use tokio::time::timeout;
+
+mod service_harness;
+mod test_util;
+use service_harness::ServiceHarness;
+use test_util::TestClient;
+
+const READ_TIMEOUT: Duration = Duration::from_millis(500);
+
+/// Every byte in its own segment, with a pause long enough that the service reads each one
+/// separately.
+fn bytewise(seed: u64) -> Faults {
+    Faults {
+        seed,
+        max_chunk: Some(1),
+        max_delay: Duration::from_millis(1),
+        ..Faults::default()
+    }
+}
+
+async fn start(kind: ServiceKind, faults: Faults) -> ServiceHarness {
+    ServiceHarness::with_faults(kind, ServiceConfig::new("127.0.0.1:0"), Some(faults)).await
+}
+
+#[tokio::test]
+async fn test_road_frames_split_bytewise() {
+    let harness = start(ServiceKind::Road, bytewise(1)).await;
+    let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
+
+    // IAmCamera, a plate with a length-prefixed string, then WantHeartbeat every 0.1s
+    let mut request = vec![0x80, 0x00, 0x42, 0x00, 0x08, 0x00, 0x3c];
+    request.extend([0x20, 0x04, b'U', b'N', b'1', b'X', 0x00, 0x00, 0x03, 0xe8]);
+    request.extend([0x40, 0x00, 0x00, 0x00, 0x01]);
+    client.send_bytes(&request).await.unwrap();
+
+    let heartbeat = timeout(READ_TIMEOUT, client.read_exact(1))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(heartbeat, [0x41]);
+}
+
+#[tokio::test]
+async fn test_crypto_cipher_spec_split_bytewise() {
+    let harness = start(ServiceKind::Crypto, bytewise(2)).await;
+    let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
+
+    // xor(0x01): the spec is only complete once its trailing 0x00 arrives
+    let xor = |bytes: &[u8]| bytes.iter().map(|b| b ^ 0x01).collect::<Vec<u8>>();
+    let mut request = vec![0x02, 0x01, 0x00];
+    request.extend(xor(b"5x car,4x dog\n"));
+    client.send_bytes(&request).await.unwrap();
+
+    let response = timeout(READ_TIMEOUT, client.read_exact(7))
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(xor(&response), b"5x car\n");
+}
+
+#[tokio::test]
+async fn test_version_control_put_split_bytewise() {
+    let harness = start(ServiceKind::VersionControl, bytewise(3)).await;
+    let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
+    assert_eq!(client.read_line().await.unwrap(), "READY\n");
+
+    client
+        .send("PUT /test/file.txt 13\nHello, world!")
+        .await
+        .unwrap();
+    let response = timeout(READ_TIMEOUT, client.read_line())
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(response, "OK r1\n");
+}
+
+#[tokio::test]
+async fn test_reset_cuts_connection_not_service() {
+    let faults = Faults {
+        seed: 4,
+        reset: 1.0,
+        ..Faults::default()
+    };
+    let harness = start(ServiceKind::Prime, faults).await;
+    let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
+
+    client
+        .send("{\"method\":\"isPrime\",\"number\":7}\n")
+        .await
+        .unwrap();
+    let err = timeout(READ_TIMEOUT, client.read_line())
+        .await
+        .unwrap()
+        .unwrap_err();
+    let io = err.downcast_ref::<std::io::Error>().unwrap();
+    assert_eq!(io.kind(), std::io::ErrorKind::ConnectionReset);
+
+    // The service only saw its connection vanish and keeps serving
+    let mut direct = TestClient::connect(&harness.service_addr.to_string())
+        .await
+        .unwrap();
+    direct
+        .send("{\"method\":\"isPrime\",\"number\":7}\n")
+        .await
+        .unwrap();
+    assert!(direct.read_line().await.unwrap().contains("true"));
+}
+
+async fn udp_client(harness: &ServiceHarness) -> UdpSocket {
+    let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    socket.connect(harness.addr).await.unwrap();
+    socket
+}
+
+async fn recv_all(socket: &UdpSocket) -> Vec<String> {
+    let mut received = vec![];
+    let mut buf = [0u8; 1000];
+    while let Ok(Ok(n)) = timeout(Duration::from_millis(200), socket.recv(&mut buf)).await {
+        received.push(String::from_utf8(buf[..n].to_vec()).unwrap());
+    }
+    received
+}
+
+#[tokio::test]
+async fn test_udp_duplicates_both_ways() {
+    let faults = Faults {
+        seed: 5,
+        duplicate: 1.0,
+        ..Faults::default()
+    };
+    let harness = start(ServiceKind::Database, faults).await;
+    let client = udp_client(&harness).await;
+
+    client.send(b"foo=bar").await.unwrap();
+    client.send(b"foo").await.unwrap();
+
+    // Two queries reach the service and each answer is delivered twice
+    assert_eq!(recv_all(&client).await, vec!["foo=bar"; 4]);
+}
+
+#[tokio::test]
+async fn test_udp_drops_everything() {
+    let faults = Faults {
+        seed: 6,
+        drop: 1.0,
+        ..Faults::default()
+    };
+    let harness = start(ServiceKind::Database, faults).await;
+    let client = udp_client(&harness).await;
+
+    client.send(b"version").await.unwrap();
+    assert!(recv_all(&client).await.is_empty());
+}
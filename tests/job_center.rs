@@ -1,39 +1,12 @@
-use prime_time::job_center::{
-    actor_scheduler::{actor::JobCommand, manager::JobManager},
-    handle_jobs_center::handle_job_center,
-    Request, Response,
-};
-use tokio::{net::TcpListener, sync::mpsc};
-
-mod job_center_harness;
+use prime_time::config::ServiceConfig;
+use prime_time::job_center::{Request, Response};
+use prime_time::services::ServiceKind;
+
+mod service_harness;
 mod test_util;
-use job_center_harness::{JobCenterHarness, JobServer};
+use service_harness::ServiceHarness;
 use test_util::TestClient;
 
-struct TestJobServer;
-
-impl JobServer for TestJobServer {
-    async fn run(listener: TcpListener) -> anyhow::Result<()> {
-        let (tx, rx) = mpsc::channel::<JobCommand>(32);
-        let mut job_manager = JobManager::new(rx);
-        tokio::spawn(async move {
-            if let Err(e) = job_manager.job_actor().await {
-                tracing::error!("JobManager exited with error: {:?}", e);
-            }
-        });
-
-        loop {
-            let (socket, _addr) = listener.accept().await?;
-            let tx_clone = tx.clone();
-            tokio::spawn(async move {
-                if let Err(e) = handle_job_center(socket, tx_clone).await {
-                    tracing::error!("Error handling job center connection: {}", e);
-                }
-            });
-        }
-    }
-}
-
 async fn send_request(client: &mut TestClient, request: Request) -> anyhow::Result<()> {
     let mut request_str = serde_json::to_string(&request)?;
     request_str.push('\n');
@@ -72,12 +45,12 @@ async fn put_job_and_get_id(
 
 // #[tokio::test]
 // async fn test_job_center_startup() {
-//     let _harness = JobCenterHarness::<TestJobServer>::new().await;
+//     let _harness = ServiceHarness::start(ServiceKind::JobCenter).await;
 // }
 
 #[tokio::test]
 async fn test_put_job() {
-    let harness = JobCenterHarness::<TestJobServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::JobCenter).await;
     let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
 
     send_request(
@@ -104,7 +77,7 @@ async fn test_put_job() {
 
 #[tokio::test]
 async fn test_get_no_job() {
-    let harness = JobCenterHarness::<TestJobServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::JobCenter).await;
     let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
 
     send_request(
@@ -123,7 +96,7 @@ async fn test_get_no_job() {
 
 #[tokio::test]
 async fn test_get_job() {
-    let harness = JobCenterHarness::<TestJobServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::JobCenter).await;
     let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
 
     send_request(
@@ -163,7 +136,7 @@ async fn test_get_job() {
 
 #[tokio::test]
 async fn test_abort_job() {
-    let harness = JobCenterHarness::<TestJobServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::JobCenter).await;
     let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
 
     let job_id = put_job_and_get_id(
@@ -224,7 +197,7 @@ async fn test_abort_job() {
 
 #[tokio::test]
 async fn test_abort_job_different_client() {
-    let harness = JobCenterHarness::<TestJobServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::JobCenter).await;
     let mut client1 = TestClient::connect(&harness.endpoint()).await.unwrap();
     let mut client2 = TestClient::connect(&harness.endpoint()).await.unwrap();
 
@@ -273,7 +246,7 @@ async fn test_abort_job_different_client() {
 
 #[tokio::test]
 async fn test_abort_non_existent_job() {
-    let harness = JobCenterHarness::<TestJobServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::JobCenter).await;
     let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
 
     // Attempt to abort a non-existent job
@@ -292,7 +265,7 @@ async fn test_abort_non_existent_job() {
 
 #[tokio::test]
 async fn test_delete_job() {
-    let harness = JobCenterHarness::<TestJobServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::JobCenter).await;
     let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
 
     let job_id = put_job_and_get_id(
@@ -323,7 +296,7 @@ async fn test_delete_job() {
 
 #[tokio::test]
 async fn test_delete_then_get() {
-    let harness = JobCenterHarness::<TestJobServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::JobCenter).await;
     let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
 
     // Put a high-priority job
@@ -375,3 +348,78 @@ async fn test_delete_then_get() {
 }
 
 
+
+#[tokio::test]
+async fn test_shutdown_releases_waiting_get() {
+    let harness = ServiceHarness::start(ServiceKind::JobCenter).await;
+    let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
+
+    // Blocks inside the job manager until a job arrives, which never happens
+    send_request(
+        &mut client,
+        Request::Get {
+            queues: vec!["empty".to_string()],
+            wait: Some(true),
+        },
+    )
+    .await
+    .unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+    let summary = harness.shutdown().await;
+    assert_eq!(summary.closed, 1);
+    assert_eq!(summary.forced, 0);
+}
+
+#[tokio::test]
+async fn test_per_ip_limit_rejects_extra_connection() {
+    let mut config = ServiceConfig::new("127.0.0.1:0");
+    config.limits.max_connections_per_ip = Some(1);
+    let harness = ServiceHarness::with_config(ServiceKind::JobCenter, config).await;
+
+    let mut first = TestClient::connect(&harness.endpoint()).await.unwrap();
+    let mut second = TestClient::connect(&harness.endpoint()).await.unwrap();
+
+    let response = recv_response(&mut second).await.unwrap();
+    assert_eq!(
+        response,
+        Response::Error {
+            error: "too many connections from your address".to_string()
+        }
+    );
+    assert!(second.read_line().await.is_err());
+
+    // The admitted connection keeps working
+    put_job_and_get_id(&mut first, "test".to_string(), serde_json::json!({}), 1)
+        .await
+        .unwrap();
+}
+
+#[tokio::test]
+async fn test_rate_limit_drops_burst() {
+    let mut config = ServiceConfig::new("127.0.0.1:0");
+    config.limits.messages_per_sec = Some(0.5);
+    config.limits.burst = Some(1);
+    let harness = ServiceHarness::with_config(ServiceKind::JobCenter, config).await;
+    let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
+
+    put_job_and_get_id(&mut client, "test".to_string(), serde_json::json!({}), 1)
+        .await
+        .unwrap();
+    send_request(
+        &mut client,
+        Request::Get {
+            queues: vec!["test".to_string()],
+            wait: None,
+        },
+    )
+    .await
+    .unwrap();
+    let response = recv_response(&mut client).await.unwrap();
+    assert_eq!(
+        response,
+        Response::Error {
+            error: "rate limit exceeded, message dropped".to_string()
+        }
+    );
+}
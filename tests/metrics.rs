@@ -0,0 +1,108 @@
+use prime_time::job_center::{Request, Response};
+use prime_time::metrics::serve_metrics;
+use prime_time::services::ServiceKind;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_util::sync::CancellationToken;
+
+mod service_harness;
+mod test_util;
+use service_harness::ServiceHarness;
+use test_util::TestClient;
+
+struct MetricsServer {
+    addr: std::net::SocketAddr,
+    shutdown: CancellationToken,
+}
+
+impl MetricsServer {
+    async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        tokio::spawn(serve_metrics(listener, shutdown.clone()));
+        Self { addr, shutdown }
+    }
+
+    /// Issues a bare HTTP/1.1 request and returns the status line and body.
+    async fn get(&self, path: &str) -> (String, String) {
+        let mut stream = TcpStream::connect(self.addr).await.unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        (head.lines().next().unwrap().to_string(), body.to_string())
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+    }
+}
+
+async fn request(client: &mut TestClient, request: Request) -> Response {
+    let mut line = serde_json::to_string(&request).unwrap();
+    line.push('\n');
+    client.send(&line).await.unwrap();
+    serde_json::from_str(&client.read_line().await.unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn test_scrape_job_center() {
+    let harness = ServiceHarness::start(ServiceKind::JobCenter).await;
+    let metrics = MetricsServer::start().await;
+    let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
+
+    for pri in [1, 2] {
+        request(
+            &mut client,
+            Request::Put {
+                queue: "scrape".to_string(),
+                job: serde_json::json!({}),
+                pri,
+            },
+        )
+        .await;
+    }
+    request(
+        &mut client,
+        Request::Get {
+            queues: vec!["scrape".to_string()],
+            wait: None,
+        },
+    )
+    .await;
+
+    let (status, body) = metrics.get("/metrics").await;
+    assert_eq!(status, "HTTP/1.1 200 OK");
+    assert!(body.contains("# TYPE protohack_jobs gauge"), "{}", body);
+    assert!(
+        body.contains(r#"protohack_jobs{service="job_center",queue="scrape",state="given"} 1"#),
+        "{}",
+        body
+    );
+    assert!(
+        body.contains(r#"protohack_jobs{service="job_center",queue="scrape",state="ready"} 1"#),
+        "{}",
+        body
+    );
+    assert!(
+        body.contains(r#"protohack_connections_active{service="job_center"} 1"#),
+        "{}",
+        body
+    );
+    let decoded = body
+        .lines()
+        .find(|line| line.starts_with(r#"protohack_messages_decoded_total{service="job_center"}"#))
+        .unwrap();
+    assert_eq!(decoded.rsplit(' ').next(), Some("3"));
+}
+
+#[tokio::test]
+async fn test_unknown_path() {
+    let metrics = MetricsServer::start().await;
+    let (status, _) = metrics.get("/nope").await;
+    assert_eq!(status, "HTTP/1.1 404 Not Found");
+}
@@ -0,0 +1,127 @@
+use std::time::Duration;
+
+use prime_time::config::{DuplicatePolicy, ServiceConfig};
+use prime_time::services::ServiceKind;
+
+mod service_harness;
+mod test_util;
+use service_harness::ServiceHarness;
+use test_util::TestClient;
+
+fn message(opcode: u8, a: i32, b: i32) -> Vec<u8> {
+    let mut buf = vec![opcode];
+    buf.extend_from_slice(&a.to_be_bytes());
+    buf.extend_from_slice(&b.to_be_bytes());
+    buf
+}
+
+async fn query(client: &mut TestClient, min: i32, max: i32) -> i32 {
+    client.send_bytes(&message(b'Q', min, max)).await.unwrap();
+    let bytes = client.read_exact(4).await.unwrap();
+    i32::from_be_bytes(bytes.try_into().unwrap())
+}
+
+async fn assert_closed(client: &mut TestClient) {
+    assert!(client.read_exact(1).await.is_err(), "connection left open");
+}
+
+#[tokio::test]
+async fn test_disconnect_ends_connection() {
+    let harness = ServiceHarness::start(ServiceKind::MeansToEnd).await;
+    let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
+    client.send_bytes(&message(b'I', 1, 100)).await.unwrap();
+    assert_eq!(query(&mut client, 1, 1).await, 100);
+    drop(client);
+
+    // Nothing is left for shutdown to close once the handler has seen the EOF
+    tokio::time::sleep(Duration::from_millis(200)).await;
+    let summary = harness.shutdown().await;
+    assert_eq!((summary.closed, summary.forced), (0, 0));
+}
+
+#[tokio::test]
+async fn test_duplicate_timestamps() {
+    for (policy, expected) in [
+        (DuplicatePolicy::Overwrite, Some(300)),
+        (DuplicatePolicy::KeepFirst, Some(100)),
+        (DuplicatePolicy::Reject, None),
+    ] {
+        let mut config = ServiceConfig::new("127.0.0.1:0");
+        config.duplicate_timestamps = Some(policy);
+        let harness = ServiceHarness::with_config(ServiceKind::MeansToEnd, config).await;
+        let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
+        client.send_bytes(&message(b'I', 1, 100)).await.unwrap();
+        assert_eq!(query(&mut client, 1, 1).await, 100);
+        client.send_bytes(&message(b'I', 1, 300)).await.unwrap();
+        match expected {
+            Some(mean) => assert_eq!(query(&mut client, 1, 1).await, mean, "{:?}", policy),
+            None => assert_closed(&mut client).await,
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_idle_timeout() {
+    let mut config = ServiceConfig::new("127.0.0.1:0");
+    config.idle_timeout_ms = Some(100);
+    let harness = ServiceHarness::with_config(ServiceKind::MeansToEnd, config).await;
+    let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
+    client.send_bytes(&message(b'I', 1, 100)).await.unwrap();
+    assert_eq!(query(&mut client, 1, 1).await, 100);
+    assert_closed(&mut client).await;
+}
+
+#[tokio::test]
+async fn test_price_limit() {
+    let mut config = ServiceConfig::new("127.0.0.1:0");
+    config.max_prices = Some(2);
+    let harness = ServiceHarness::with_config(ServiceKind::MeansToEnd, config).await;
+    let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
+    client.send_bytes(&message(b'S', 5, 0)).await.unwrap();
+    for (timestamp, price) in [(1, 10), (2, 20), (2, 30)] {
+        client
+            .send_bytes(&message(b'I', timestamp, price))
+            .await
+            .unwrap();
+    }
+    assert_eq!(query(&mut client, 1, 2).await, 20);
+    client.send_bytes(&message(b'I', 3, 40)).await.unwrap();
+    assert_closed(&mut client).await;
+
+    // The session kept what it had before the refused insert
+    let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
+    client.send_bytes(&message(b'S', 5, 0)).await.unwrap();
+    assert_eq!(query(&mut client, 0, 10).await, 20);
+}
+
+#[tokio::test]
+async fn test_session_survives_restart() {
+    let log = std::env::temp_dir().join(format!("prime_time-mte-{}.log", std::process::id()));
+    let _ = std::fs::remove_file(&log);
+    let mut config = ServiceConfig::new("127.0.0.1:0");
+    config.session_log = Some(log.clone());
+
+    let harness = ServiceHarness::with_config(ServiceKind::MeansToEnd, config.clone()).await;
+    let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
+    client.send_bytes(&message(b'S', 9, 0)).await.unwrap();
+    for (timestamp, price) in [(10, 100), (20, 200), (30, 600)] {
+        client
+            .send_bytes(&message(b'I', timestamp, price))
+            .await
+            .unwrap();
+    }
+    assert_eq!(query(&mut client, 0, 100).await, 300);
+    drop(client);
+    harness.shutdown().await;
+
+    let harness = ServiceHarness::with_config(ServiceKind::MeansToEnd, config).await;
+    let mut client = TestClient::connect(&harness.endpoint()).await.unwrap();
+    client.send_bytes(&message(b'S', 9, 0)).await.unwrap();
+    assert_eq!(query(&mut client, 0, 100).await, 300);
+    assert_eq!(query(&mut client, 15, 25).await, 200);
+
+    // Without a session a connection starts empty, as in the plain protocol
+    let mut anonymous = TestClient::connect(&harness.endpoint()).await.unwrap();
+    assert_eq!(query(&mut anonymous, 0, 100).await, 0);
+    std::fs::remove_file(&log).unwrap();
+}
@@ -0,0 +1,30 @@
+use prime_time::services::ServiceKind;
+
+mod service_harness;
+mod test_util;
+use service_harness::ServiceHarness;
+use test_util::TestClient;
+
+#[tokio::test]
+async fn test_malformed_request_closes_only_that_connection() {
+    let harness = ServiceHarness::start(ServiceKind::Prime).await;
+    let mut bad = TestClient::connect(&harness.endpoint()).await.unwrap();
+    let mut good = TestClient::connect(&harness.endpoint()).await.unwrap();
+
+    bad.send("{\"method\":\"isPrime\",\"number\":\"7\"}\n")
+        .await
+        .unwrap();
+    assert_eq!(
+        bad.read_line().await.unwrap(),
+        "{\"error\":\"malformed\"}\n"
+    );
+    assert!(bad.read_line().await.is_err(), "connection left open");
+
+    good.send("{\"method\":\"isPrime\",\"number\":7,\"extra\":true}\n")
+        .await
+        .unwrap();
+    assert_eq!(
+        good.read_line().await.unwrap(),
+        "{\"method\":\"isPrime\",\"prime\":true}\n"
+    );
+}
@@ -0,0 +1,139 @@
+#![allow(dead_code)]
+
+use std::net::SocketAddr;
+use std::sync::Once;
+use std::time::Duration;
+
+use prime_time::config::ServiceConfig;
+use prime_time::faults::{self, Faults};
+use prime_time::services::{DrainSummary, Listener, Registry, ServiceKind};
+use tokio::net::{TcpListener, UdpSocket};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+use tracing_subscriber::{EnvFilter, fmt::TestWriter};
+
+static TRACING_INIT: Once = Once::new();
+
+const FAULT_SEED_VAR: &str = "PRIME_TIME_FAULT_SEED";
+const DEFAULT_FAULT_SEED: u64 = 0x5eed;
+
+/// Runs one registered service on an ephemeral port, the same way the binary does.
+///
+/// Clients reach it through a fault proxy that forwards TCP data in chunks of a few bytes and
+/// delays datagrams, so every suite also covers partial frames. `PRIME_TIME_FAULT_SEED` picks
+/// the seed, and `PRIME_TIME_FAULT_SEED=off` connects clients to the service directly.
+pub struct ServiceHarness {
+    /// Where clients connect: the fault proxy, or the service itself when faults are off.
+    pub addr: SocketAddr,
+    pub service_addr: SocketAddr,
+    shutdown: CancellationToken,
+    handle: Option<JoinHandle<anyhow::Result<DrainSummary>>>,
+}
+
+impl ServiceHarness {
+    pub async fn start(kind: ServiceKind) -> Self {
+        Self::with_config(kind, ServiceConfig::new("127.0.0.1:0")).await
+    }
+
+    /// Without the fault proxy, for tests that depend on how the service itself chunks writes.
+    pub async fn direct(kind: ServiceKind) -> Self {
+        Self::with_faults(kind, ServiceConfig::new("127.0.0.1:0"), None).await
+    }
+
+    pub async fn with_config(kind: ServiceKind, config: ServiceConfig) -> Self {
+        Self::with_faults(kind, config, default_faults()).await
+    }
+
+    /// Starts the service behind a proxy injecting `faults`, or directly reachable for `None`.
+    pub async fn with_faults(
+        kind: ServiceKind,
+        config: ServiceConfig,
+        faults: Option<Faults>,
+    ) -> Self {
+        // Initialize tracing once
+        TRACING_INIT.call_once(|| {
+            tracing_subscriber::fmt()
+                .with_env_filter(EnvFilter::new("trace"))
+                .with_writer(TestWriter::new())
+                .init();
+        });
+
+        let service = Registry::default().build(kind, &config).unwrap();
+        let listener = service.bind(&config.bind).await.unwrap();
+        let service_addr = listener.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let addr = match faults {
+            Some(faults) => front(&listener, service_addr, faults, shutdown.clone()).await,
+            None => service_addr,
+        };
+
+        let handle =
+            tokio::spawn(service.serve(listener, shutdown.clone(), Duration::from_secs(1)));
+
+        Self {
+            addr,
+            service_addr,
+            shutdown,
+            handle: Some(handle),
+        }
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("localhost:{}", self.addr.port())
+    }
+
+    /// Cancels the service and waits for it to drain.
+    pub async fn shutdown(mut self) -> DrainSummary {
+        self.shutdown.cancel();
+        self.handle.take().unwrap().await.unwrap().unwrap()
+    }
+}
+
+fn default_faults() -> Option<Faults> {
+    let seed = match std::env::var(FAULT_SEED_VAR) {
+        Ok(value) if value == "off" => return None,
+        Ok(value) => value
+            .parse()
+            .unwrap_or_else(|_| panic!("{} must be a number or `off`", FAULT_SEED_VAR)),
+        Err(_) => DEFAULT_FAULT_SEED,
+    };
+    Some(Faults {
+        seed,
+        max_chunk: Some(4),
+        max_delay: Duration::from_micros(500),
+        ..Faults::default()
+    })
+}
+
+/// Binds a fault proxy of the same transport as `listener` and returns its address.
+async fn front(
+    listener: &Listener,
+    service_addr: SocketAddr,
+    faults: Faults,
+    shutdown: CancellationToken,
+) -> SocketAddr {
+    let upstream = service_addr.to_string();
+    match listener {
+        Listener::Tcp(_) => {
+            let proxy = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = proxy.local_addr().unwrap();
+            tokio::spawn(faults::proxy_tcp(proxy, upstream, faults, shutdown));
+            addr
+        }
+        Listener::Udp(_) => {
+            let proxy = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+            let addr = proxy.local_addr().unwrap();
+            tokio::spawn(faults::proxy_udp(proxy, upstream, faults, shutdown));
+            addr
+        }
+    }
+}
+
+impl Drop for ServiceHarness {
+    fn drop(&mut self) {
+        self.shutdown.cancel();
+        if let Some(handle) = &self.handle {
+            handle.abort();
+        }
+    }
+}
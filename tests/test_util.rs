@@ -1,8 +1,8 @@
-#[warn(dead_code)]
+#![allow(dead_code)]
 
-use tokio::net::TcpStream;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, WriteHalf, ReadHalf, AsyncReadExt};
 use tokio::io::split;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::net::TcpStream;
 pub struct TestClient {
     writer: WriteHalf<TcpStream>,
     reader: BufReader<ReadHalf<TcpStream>>,
@@ -17,7 +17,7 @@ impl TestClient {
             reader: BufReader::new(read_half),
         })
     }
-    
+
     pub async fn send(&mut self, msg: &str) -> anyhow::Result<()> {
         self.writer.write_all(msg.as_bytes()).await?;
         self.writer.flush().await?;
@@ -44,4 +44,4 @@ impl TestClient {
         self.reader.read_exact(&mut buf).await?;
         Ok(buf)
     }
-}
\ No newline at end of file
+}
@@ -1,20 +1,10 @@
-use std::{collections::HashMap, sync::Arc, time::Duration};
-use tokio::{net::UdpSocket, sync::Mutex, time::timeout};
-use prime_time::database_server::run_udp_server_with_socket;
-use crate::udp_server_harness::{UdpServer, UdpServerHarness};
+use std::time::Duration;
+use prime_time::config::{ServiceConfig, WalConfig};
+use prime_time::services::ServiceKind;
+use tokio::{net::UdpSocket, time::timeout};
+use crate::service_harness::ServiceHarness;
 
-mod udp_server_harness;
-
-struct UdpDatabaseServer;
-
-impl UdpServer for UdpDatabaseServer {
-    fn run(socket: UdpSocket) -> impl std::future::Future<Output = anyhow::Result<()>> + Send {
-        async move {
-            let storage = Arc::new(Mutex::new(HashMap::new()));
-            run_udp_server_with_socket(socket, storage).await
-        }
-    }
-}
+mod service_harness;
 
 struct UdpTestClient {
     socket: UdpSocket,
@@ -41,9 +31,282 @@ impl UdpTestClient {
     }
 }
 
+impl UdpTestClient {
+    /// Sets `key` and waits until a read sees it, since datagrams may be delayed or reordered.
+    async fn set(&self, key: &str, value: &str) -> anyhow::Result<()> {
+        self.write(&format!("{}={}", key, value), key, value).await
+    }
+
+    async fn set_with_ttl(&self, key: &str, value: &str, ttl_ms: u64) -> anyhow::Result<()> {
+        let datagram = format!("@ttl={} {}={}", ttl_ms, key, value);
+        self.write(&datagram, key, value).await
+    }
+
+    async fn write(&self, datagram: &str, key: &str, value: &str) -> anyhow::Result<()> {
+        let expected = format!("{}={}", key, value);
+        self.send(datagram.as_bytes()).await?;
+        for _ in 0..50 {
+            self.send(key.as_bytes()).await?;
+            if self.recv_with_timeout().await?.as_deref() == Some(expected.as_str()) {
+                return Ok(());
+            }
+        }
+        anyhow::bail!("{} never became visible", expected)
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<String>> {
+        self.send(key.as_bytes()).await?;
+        self.recv_with_timeout().await
+    }
+
+    async fn recv_bytes_with_timeout(&self) -> anyhow::Result<Option<Vec<u8>>> {
+        let mut buf = [0u8; 2000];
+        match timeout(Duration::from_millis(200), self.socket.recv(&mut buf)).await {
+            Ok(Ok(len)) => Ok(Some(buf[..len].to_vec())),
+            _ => Ok(None),
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_first_equals_splits() -> anyhow::Result<()> {
+    let harness = ServiceHarness::start(ServiceKind::Database).await;
+    let client = UdpTestClient::new(&harness.endpoint()).await?;
+
+    client.set("foo", "bar=baz=").await?;
+    client.set("empty", "").await?;
+    client.set("", "no key").await?;
+    assert_eq!(client.get("foo").await?.as_deref(), Some("foo=bar=baz="));
+    assert_eq!(client.get("empty").await?.as_deref(), Some("empty="));
+    assert_eq!(client.get("").await?.as_deref(), Some("=no key"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_version_cannot_be_overwritten() -> anyhow::Result<()> {
+    let harness = ServiceHarness::start(ServiceKind::Database).await;
+    let client = UdpTestClient::new(&harness.endpoint()).await?;
+
+    client.send(b"version=hacked").await?;
+    client.set("marker", "1").await?;
+    assert_eq!(
+        client.get("version").await?.as_deref(),
+        Some("version=Ken's Key-Value Store 1.0")
+    );
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_oversize_datagram_dropped() -> anyhow::Result<()> {
+    let harness = ServiceHarness::start(ServiceKind::Database).await;
+    let client = UdpTestClient::new(&harness.endpoint()).await?;
+
+    // 1001 bytes would have been cut to `big=` plus 996 bytes before
+    let oversize = format!("big={}", "x".repeat(997));
+    client.send(oversize.as_bytes()).await?;
+    let largest = "y".repeat(996);
+    client.set("max", &largest).await?;
+    assert_eq!(client.get("big").await?.as_deref(), Some("big"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_binary_keys() -> anyhow::Result<()> {
+    let harness = ServiceHarness::start(ServiceKind::Database).await;
+    let client = UdpTestClient::new(&harness.endpoint()).await?;
+
+    client.send(b"\xff\x00key=\xfe").await?;
+    for _ in 0..50 {
+        client.send(b"\xff\x00key").await?;
+        if let Some(response) = client.recv_bytes_with_timeout().await?
+            && response != b"\xff\x00key"
+        {
+            assert_eq!(response, b"\xff\x00key=\xfe");
+            return Ok(());
+        }
+    }
+    anyhow::bail!("binary insert never became visible")
+}
+
+#[tokio::test]
+async fn test_ttl_key_expires() -> anyhow::Result<()> {
+    let mut config = ServiceConfig::new("127.0.0.1:0");
+    config.reap_interval_ms = Some(20);
+    let harness = ServiceHarness::with_config(ServiceKind::Database, config).await;
+    let client = UdpTestClient::new(&harness.endpoint()).await?;
+
+    client.set_with_ttl("temp", "1", 500).await?;
+    client.set("kept", "2").await?;
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(client.get("temp").await?.as_deref(), Some("temp"));
+    assert_eq!(client.get("kept").await?.as_deref(), Some("kept=2"));
+
+    // A plain insert takes the TTL off again
+    client.set_with_ttl("temp", "3", 500).await?;
+    client.set("temp", "4").await?;
+    tokio::time::sleep(Duration::from_millis(600)).await;
+    assert_eq!(client.get("temp").await?.as_deref(), Some("temp=4"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_malformed_ttl_is_plain_insert() -> anyhow::Result<()> {
+    let harness = ServiceHarness::start(ServiceKind::Database).await;
+    let client = UdpTestClient::new(&harness.endpoint()).await?;
+
+    client.set("@ttl", "soon foo=bar").await?;
+    assert_eq!(client.get("foo").await?.as_deref(), Some("foo"));
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_byte_cap_evicts_least_recently_used() -> anyhow::Result<()> {
+    let mut config = ServiceConfig::new("127.0.0.1:0");
+    // Room for three 2-byte entries
+    config.max_bytes = Some(6);
+    let harness = ServiceHarness::with_config(ServiceKind::Database, config).await;
+    let client = UdpTestClient::new(&harness.endpoint()).await?;
+
+    client.set("a", "1").await?;
+    client.set("b", "2").await?;
+    client.set("c", "3").await?;
+    assert_eq!(client.get("a").await?.as_deref(), Some("a=1"));
+    client.set("d", "4").await?;
+    assert_eq!(client.get("b").await?.as_deref(), Some("b"));
+    for (key, expected) in [("a", "a=1"), ("c", "c=3"), ("d", "d=4")] {
+        assert_eq!(client.get(key).await?.as_deref(), Some(expected));
+    }
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_concurrent_clients_across_workers() -> anyhow::Result<()> {
+    let mut config = ServiceConfig::new("127.0.0.1:0");
+    config.workers = Some(4);
+    config.shards = Some(8);
+    let harness = ServiceHarness::with_config(ServiceKind::Database, config).await;
+
+    let mut clients = vec![];
+    for c in 0..8 {
+        let endpoint = harness.endpoint();
+        clients.push(tokio::spawn(async move {
+            let client = UdpTestClient::new(&endpoint).await?;
+            for n in 0..20 {
+                client.set(&format!("client{}-{}", c, n), &n.to_string()).await?;
+            }
+            anyhow::Ok(())
+        }));
+    }
+    for client in clients {
+        client.await??;
+    }
+
+    // Every client's keys are visible to any other
+    let client = UdpTestClient::new(&harness.endpoint()).await?;
+    for c in 0..8 {
+        for n in 0..20 {
+            let key = format!("client{}-{}", c, n);
+            let expected = format!("{}={}", key, n);
+            assert_eq!(client.get(&key).await?, Some(expected));
+        }
+    }
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_wal_recovers_from_torn_record() -> anyhow::Result<()> {
+    let dir = std::env::temp_dir().join(format!("prime_time-udp-wal-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut config = ServiceConfig::new("127.0.0.1:0");
+    config.wal = Some(WalConfig::new(&dir));
+
+    let harness = ServiceHarness::with_config(ServiceKind::Database, config.clone()).await;
+    let client = UdpTestClient::new(&harness.endpoint()).await?;
+    client.set("a", "1").await?;
+    client.set("b", "two=2").await?;
+    harness.shutdown().await;
+
+    // Crash partway through writing the last record
+    let log = dir.join("wal.log");
+    let len = std::fs::metadata(&log)?.len();
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&log)?
+        .set_len(len - 3)?;
+
+    let harness = ServiceHarness::with_config(ServiceKind::Database, config.clone()).await;
+    let client = UdpTestClient::new(&harness.endpoint()).await?;
+    assert_eq!(client.get("a").await?.as_deref(), Some("a=1"));
+    assert_eq!(client.get("b").await?.as_deref(), Some("b"));
+    client.set("c", "3").await?;
+    harness.shutdown().await;
+
+    let harness = ServiceHarness::with_config(ServiceKind::Database, config).await;
+    let client = UdpTestClient::new(&harness.endpoint()).await?;
+    assert_eq!(client.get("a").await?.as_deref(), Some("a=1"));
+    assert_eq!(client.get("c").await?.as_deref(), Some("c=3"));
+    harness.shutdown().await;
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_reads_see_own_inserts_with_wal() -> anyhow::Result<()> {
+    let dir = std::env::temp_dir().join(format!("prime_time-udp-ryw-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut config = ServiceConfig::new("127.0.0.1:0");
+    config.wal = Some(WalConfig::new(&dir));
+    // Straight to the service, as the fault proxy may reorder datagrams
+    let harness = ServiceHarness::with_faults(ServiceKind::Database, config, None).await;
+    let client = UdpTestClient::new(&harness.endpoint()).await?;
+
+    // Each read follows its insert straight away, with no waiting for it to show up
+    for n in 0..20 {
+        client.send(format!("key={}", n).as_bytes()).await?;
+        assert_eq!(client.get("key").await?, Some(format!("key={}", n)));
+    }
+    harness.shutdown().await;
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn test_reads_see_own_inserts_across_workers() -> anyhow::Result<()> {
+    let dir = std::env::temp_dir()
+        .join(format!("prime_time-udp-ryw-workers-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    // Workers store inserts themselves without a WAL, and hand them over with one
+    for wal in [None, Some(WalConfig::new(&dir))] {
+        let mut config = ServiceConfig::new("127.0.0.1:0");
+        config.workers = Some(4);
+        config.wal = wal;
+        let harness = ServiceHarness::with_faults(ServiceKind::Database, config, None).await;
+
+        let mut clients = vec![];
+        for c in 0..16 {
+            let endpoint = harness.endpoint();
+            clients.push(tokio::spawn(async move {
+                let client = UdpTestClient::new(&endpoint).await?;
+                let key = format!("client{}", c);
+                for n in 0..20 {
+                    client.send(format!("{}={}", key, n).as_bytes()).await?;
+                    assert_eq!(client.get(&key).await?, Some(format!("{}={}", key, n)));
+                }
+                anyhow::Ok(())
+            }));
+        }
+        for client in clients {
+            client.await??;
+        }
+        harness.shutdown().await;
+    }
+    std::fs::remove_dir_all(&dir)?;
+    Ok(())
+}
+
 #[tokio::test]
 async fn test_basic_set_and_get() -> anyhow::Result<()> {
-    let harness = UdpServerHarness::<UdpDatabaseServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::Database).await;
     let client = UdpTestClient::new(&harness.endpoint()).await?;
 
     client.send(b"foo=bar").await?;
@@ -63,7 +326,7 @@ async fn test_basic_set_and_get() -> anyhow::Result<()> {
 
 #[tokio::test]
 async fn test_unknown_key() -> anyhow::Result<()> {
-    let harness = UdpServerHarness::<UdpDatabaseServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::Database).await;
     let client = UdpTestClient::new(&harness.endpoint()).await?;
 
     client.send(b"missingkey").await?;
@@ -75,7 +338,7 @@ async fn test_unknown_key() -> anyhow::Result<()> {
 
 #[tokio::test]
 async fn test_version() -> anyhow::Result<()> {
-    let harness = UdpServerHarness::<UdpDatabaseServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::Database).await;
     let client = UdpTestClient::new(&harness.endpoint()).await?;
 
     client.send(b"version").await?;
@@ -0,0 +1,372 @@
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+
+use prime_time::config::{ReplicationConfig, WalConfig};
+use prime_time::database_server::{
+    Replica, ServerOptions, ShardedStorage, run_udp_server_with_socket,
+};
+use prime_time::faults::{self, Faults};
+use tokio::net::UdpSocket;
+use tokio::task::JoinHandle;
+use tokio::time::{Instant, timeout};
+use tokio_util::sync::CancellationToken;
+
+const NODES: usize = 3;
+const CONVERGE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Loses a fifth of the datagrams between two nodes and delivers some of the rest twice.
+fn lossy(seed: u64) -> Faults {
+    Faults {
+        seed,
+        max_delay: Duration::from_millis(2),
+        drop: 0.2,
+        duplicate: 0.05,
+        ..Faults::default()
+    }
+}
+
+struct Node {
+    addr: SocketAddr,
+    shutdown: CancellationToken,
+    handle: JoinHandle<anyhow::Result<()>>,
+}
+
+/// Stores on loopback whose replication traffic passes through a lossy proxy between each pair
+/// of nodes. Clients reach the nodes directly.
+struct Group {
+    /// Where each node receives replication traffic, which is what the proxies forward to.
+    replication: Vec<SocketAddr>,
+    /// The addresses each node reaches its peers at, which are also the ones their traffic
+    /// comes from: the proxy for a peer with a higher index, and the proxy's upstream socket
+    /// for one with a lower index.
+    peers: Vec<Vec<String>>,
+    log_capacity: usize,
+    /// Where node `i` keeps its WAL, in subdirectory `i`, if the nodes have one.
+    wal_dir: Option<PathBuf>,
+    nodes: Vec<Option<Node>>,
+    proxies: CancellationToken,
+}
+
+impl Group {
+    async fn start(log_capacity: usize) -> Self {
+        Self::start_with_wal(log_capacity, None).await
+    }
+
+    async fn start_with_wal(log_capacity: usize, wal_dir: Option<PathBuf>) -> Self {
+        let mut sockets = vec![];
+        for _ in 0..NODES {
+            sockets.push(UdpSocket::bind("127.0.0.1:0").await.unwrap());
+        }
+        let replication: Vec<_> = sockets
+            .iter()
+            .map(|socket| socket.local_addr().unwrap())
+            .collect();
+        let proxies = CancellationToken::new();
+        let mut peers = vec![vec![]; NODES];
+        for from in 0..NODES {
+            for to in from + 1..NODES {
+                let proxy = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+                let proxy_addr = proxy.local_addr().unwrap();
+                tokio::spawn(faults::proxy_udp(
+                    proxy,
+                    replication[to].to_string(),
+                    lossy((from * NODES + to) as u64),
+                    proxies.clone(),
+                ));
+                let upstream = upstream_of(&sockets[from], &sockets[to], proxy_addr).await;
+                peers[from].push(proxy_addr.to_string());
+                peers[to].push(upstream.to_string());
+            }
+        }
+
+        let mut group = Self {
+            replication,
+            peers,
+            log_capacity,
+            wal_dir,
+            nodes: vec![],
+            proxies,
+        };
+        for (i, socket) in sockets.into_iter().enumerate() {
+            let node = group.spawn(i, socket).await;
+            group.nodes.push(Some(node));
+        }
+        group
+    }
+
+    async fn spawn(&self, i: usize, socket: UdpSocket) -> Node {
+        let mut config = ReplicationConfig::new(self.replication[i].to_string());
+        config.peers = self.peers[i].clone();
+        config.retransmit_ms = Some(20);
+        config.log_capacity = Some(self.log_capacity);
+        let options = ServerOptions {
+            wal: self
+                .wal_dir
+                .as_ref()
+                .map(|dir| WalConfig::new(dir.join(i.to_string()))),
+            replica: Some(Replica::with_socket(socket, &config).await.unwrap()),
+            ..ServerOptions::default()
+        };
+        let client_socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = client_socket.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let handle = tokio::spawn(run_udp_server_with_socket(
+            client_socket,
+            Arc::new(ShardedStorage::default()),
+            options,
+            shutdown.clone(),
+        ));
+        Node {
+            addr,
+            shutdown,
+            handle,
+        }
+    }
+
+    async fn stop(&mut self, i: usize) {
+        let node = self.nodes[i].take().unwrap();
+        node.shutdown.cancel();
+        node.handle.await.unwrap().unwrap();
+    }
+
+    /// Starts node `i` again on its old replication address, with nothing in its store but what
+    /// its WAL replays.
+    async fn restart(&mut self, i: usize) {
+        let socket = UdpSocket::bind(self.replication[i]).await.unwrap();
+        self.nodes[i] = Some(self.spawn(i, socket).await);
+    }
+
+    async fn client(&self, i: usize) -> Client {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        socket
+            .connect(self.nodes[i].as_ref().unwrap().addr)
+            .await
+            .unwrap();
+        Client { socket }
+    }
+
+    /// Waits until every running node answers each key with its expected value.
+    async fn converge(&self, expected: &[(String, String)]) {
+        let deadline = Instant::now() + CONVERGE_TIMEOUT;
+        for i in (0..NODES).filter(|&i| self.nodes[i].is_some()) {
+            let client = self.client(i).await;
+            for (key, value) in expected {
+                let want = format!("{}={}", key, value);
+                loop {
+                    let got = client.get(key).await;
+                    if got.as_deref() == Some(want.as_str()) {
+                        break;
+                    }
+                    assert!(
+                        Instant::now() < deadline,
+                        "node {} answered {:?}, expected {}",
+                        i,
+                        got,
+                        want
+                    );
+                    tokio::time::sleep(Duration::from_millis(20)).await;
+                }
+            }
+        }
+    }
+}
+
+/// The address the proxy at `proxy` forwards `from`'s datagrams to `to` from, learnt by sending
+/// through it until one gets across. Stray copies reach `to`'s node later and are dropped as
+/// malformed.
+async fn upstream_of(from: &UdpSocket, to: &UdpSocket, proxy: SocketAddr) -> SocketAddr {
+    let mut buf = [0u8; 16];
+    loop {
+        from.send_to(b"hello", proxy).await.unwrap();
+        if let Ok(received) = timeout(Duration::from_millis(50), to.recv_from(&mut buf)).await {
+            return received.unwrap().1;
+        }
+    }
+}
+
+impl Drop for Group {
+    fn drop(&mut self) {
+        self.proxies.cancel();
+        for node in self.nodes.iter().flatten() {
+            node.shutdown.cancel();
+        }
+    }
+}
+
+struct Client {
+    socket: UdpSocket,
+}
+
+impl Client {
+    /// Inserts and waits until the node itself answers with the new value.
+    async fn set(&self, key: &str, value: &str) {
+        let want = format!("{}={}", key, value);
+        self.socket.send(want.as_bytes()).await.unwrap();
+        for _ in 0..50 {
+            if self.get(key).await.as_deref() == Some(want.as_str()) {
+                return;
+            }
+        }
+        panic!("{} never became visible", want);
+    }
+
+    async fn get(&self, key: &str) -> Option<String> {
+        self.socket.send(key.as_bytes()).await.unwrap();
+        let mut buf = [0u8; 1000];
+        let len = timeout(Duration::from_millis(200), self.socket.recv(&mut buf))
+            .await
+            .ok()?
+            .unwrap();
+        Some(String::from_utf8(buf[..len].to_vec()).unwrap())
+    }
+}
+
+fn entry(key: impl ToString, value: impl ToString) -> (String, String) {
+    (key.to_string(), value.to_string())
+}
+
+#[tokio::test]
+async fn test_inserts_converge_under_loss() {
+    let group = Group::start(1_000).await;
+    let mut expected = vec![];
+    for i in 0..NODES {
+        let client = group.client(i).await;
+        for n in 0..20 {
+            let (key, value) = entry(format!("node{}-{}", i, n), n);
+            client.set(&key, &value).await;
+            expected.push((key, value));
+        }
+    }
+    group.converge(&expected).await;
+
+    // Writes of one key on every node at once settle on the same winner everywhere
+    let mut clients = vec![];
+    for i in 0..NODES {
+        clients.push(group.client(i).await);
+    }
+    for (i, client) in clients.iter().enumerate() {
+        client
+            .socket
+            .send(format!("shared={}", i).as_bytes())
+            .await
+            .unwrap();
+    }
+    let deadline = Instant::now() + CONVERGE_TIMEOUT;
+    loop {
+        let mut answers = vec![];
+        for client in &clients {
+            answers.push(client.get("shared").await);
+        }
+        if answers[0].is_some() && answers.iter().all(|answer| *answer == answers[0]) {
+            break;
+        }
+        assert!(Instant::now() < deadline, "nodes disagree: {:?}", answers);
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+}
+
+#[tokio::test]
+async fn test_non_peer_gets_no_snapshot() {
+    let group = Group::start(1_000).await;
+    group.client(0).await.set("key", "value").await;
+
+    let stranger = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    // A request for a new snapshot: kind 4, id 0, no missing chunks
+    let request = [&[4u8][..], &0u64.to_be_bytes(), &0u32.to_be_bytes()].concat();
+    stranger
+        .send_to(&request, group.replication[0])
+        .await
+        .unwrap();
+    let mut buf = [0u8; 1000];
+    assert!(
+        timeout(Duration::from_millis(200), stranger.recv_from(&mut buf))
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn test_restarted_node_catches_up() {
+    // Too short a log to bring the restarted node up to date by retransmission alone
+    let mut group = Group::start(4).await;
+    let client = group.client(0).await;
+    client.set("before", "1").await;
+    group.converge(&[entry("before", 1)]).await;
+
+    group.stop(2).await;
+    let mut expected = vec![entry("before", 1)];
+    for i in 0..2 {
+        let client = group.client(i).await;
+        for n in 0..10 {
+            let (key, value) = entry(format!("down{}-{}", i, n), n);
+            client.set(&key, &value).await;
+            expected.push((key, value));
+        }
+    }
+    group.restart(2).await;
+    group.converge(&expected).await;
+
+    // The restarted node's own inserts reach the others too
+    group.client(2).await.set("after", "2").await;
+    expected.push(entry("after", 2));
+    group.converge(&expected).await;
+}
+
+#[tokio::test]
+async fn test_write_during_catch_up_outranks_snapshot() {
+    let mut group = Group::start(1_000).await;
+    let client = group.client(0).await;
+    for n in 0..5 {
+        client.set("key", &n.to_string()).await;
+    }
+    group.converge(&[entry("key", 4)]).await;
+
+    // Without a WAL the restarted node's clock starts over, behind every write in the snapshot
+    // it is about to fetch
+    group.stop(2).await;
+    group.restart(2).await;
+    group.client(2).await.set("key", "restarted").await;
+    group.converge(&[entry("key", "restarted")]).await;
+}
+
+#[tokio::test]
+async fn test_lone_node_accepts_writes() {
+    let mut group = Group::start(1_000).await;
+    for i in 0..NODES {
+        group.stop(i).await;
+    }
+    // No peer answers its snapshot requests, so inserts stop waiting for one
+    group.restart(0).await;
+    group.client(0).await.set("key", "alone").await;
+}
+
+#[tokio::test]
+async fn test_nodes_restarted_from_wal_converge() {
+    let dir = std::env::temp_dir().join(format!("prime_time-replication-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let mut group = Group::start_with_wal(1_000, Some(dir.clone())).await;
+    group.client(0).await.set("key", "1").await;
+    group.converge(&[entry("key", 1)]).await;
+
+    // Only node 0 sees the overwrite before every node goes down
+    group.stop(1).await;
+    group.stop(2).await;
+    group.client(0).await.set("key", "2").await;
+    group.stop(0).await;
+
+    // Node 1 replays the older write and catches up from node 0, the only peer running, whose
+    // replayed write must still outrank it
+    group.restart(0).await;
+    group.restart(1).await;
+    group.converge(&[entry("key", 2)]).await;
+    group.restart(2).await;
+    group.converge(&[entry("key", 2)]).await;
+
+    // A restarted node's own inserts still outrank everything it replayed
+    group.client(1).await.set("key", "3").await;
+    group.converge(&[entry("key", 3)]).await;
+    drop(group);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
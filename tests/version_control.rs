@@ -1,43 +1,9 @@
-
-use tokio::net::TcpListener;
-use prime_time::version_control::{
-    handler_version_control::handle_version_control,
-    file_actor::manager::FileManager,
-    codec::codec::{VersionControlCommand, RespValue}
-};
-use tokio::sync::{mpsc, oneshot};
+use prime_time::services::ServiceKind;
 
 mod test_util;
-mod server_harness;
-
-use server_harness::{Server, ServerHarness};
-
-struct VersionControlServer;
-
-impl Server for VersionControlServer {
-    fn run(listener: TcpListener) -> impl std::future::Future<Output = anyhow::Result<()>> + Send {
-        async move {
-            let (tx, rx) = mpsc::channel::<(VersionControlCommand, oneshot::Sender<RespValue>)>(32);
-            let mut file_manager = FileManager::new(rx);
-
-            tokio::spawn(async move {
-                if let Err(e) = file_manager.file_actor().await {
-                    tracing::error!("FileManager exited with error: {:?}", e);
-                }
-            });
-
-            loop {
-                let (socket, _addr) = listener.accept().await?;
-                let tx_clone = tx.clone();
-                tokio::spawn(async move {
-                    if let Err(e) = handle_version_control(socket, tx_clone).await {
-                        tracing::error!("Error handling version control connection: {}", e);
-                    }
-                });
-            }
-        }
-    }
-}
+mod service_harness;
+
+use service_harness::ServiceHarness;
 
 async fn send_request(client: &mut test_util::TestClient, request: &str) -> anyhow::Result<()> {
     let mut request_str = request.to_string();
@@ -53,7 +19,7 @@ async fn recv_response(client: &mut test_util::TestClient) -> anyhow::Result<Str
 
 #[tokio::test]
 async fn test_version_control_server() {
-    let harness = ServerHarness::<VersionControlServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::VersionControl).await;
     let client = test_util::TestClient::connect(&harness.endpoint()).await.unwrap();
 
     // Close the connection
@@ -62,8 +28,9 @@ async fn test_version_control_server() {
 
 #[tokio::test]
 async fn test_help_command() {
-    let harness = ServerHarness::<VersionControlServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::VersionControl).await;
     let mut client = test_util::TestClient::connect(&harness.endpoint()).await.unwrap();
+    assert_eq!(recv_response(&mut client).await.unwrap(), "READY\n");
 
     send_request(&mut client, "HELP").await.unwrap();
     let response = recv_response(&mut client).await.unwrap();
@@ -73,8 +40,9 @@ async fn test_help_command() {
 
 #[tokio::test]
 async fn test_put_file() {
-    let harness = ServerHarness::<VersionControlServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::VersionControl).await;
     let mut client = test_util::TestClient::connect(&harness.endpoint()).await.unwrap();
+    assert_eq!(recv_response(&mut client).await.unwrap(), "READY\n");
 
     let file_path = "/test/file.txt";
     let file_content = "Hello, world!";
@@ -91,8 +59,9 @@ async fn test_put_file() {
 
 #[tokio::test]
 async fn test_list_root_empty() {
-    let harness = ServerHarness::<VersionControlServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::VersionControl).await;
     let mut client = test_util::TestClient::connect(&harness.endpoint()).await.unwrap();
+    assert_eq!(recv_response(&mut client).await.unwrap(), "READY\n");
 
     send_request(&mut client, "LIST /").await.unwrap();
     let response = recv_response(&mut client).await.unwrap();
@@ -102,8 +71,9 @@ async fn test_list_root_empty() {
 
 #[tokio::test]
 async fn test_list_illegal_dir_dot() {
-    let harness = ServerHarness::<VersionControlServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::VersionControl).await;
     let mut client = test_util::TestClient::connect(&harness.endpoint()).await.unwrap();
+    assert_eq!(recv_response(&mut client).await.unwrap(), "READY\n");
 
     send_request(&mut client, "LIST .").await.unwrap();
     let response = recv_response(&mut client).await.unwrap();
@@ -113,8 +83,9 @@ async fn test_list_illegal_dir_dot() {
 
 #[tokio::test]
 async fn test_list_illegal_dir_dot_dot() {
-    let harness = ServerHarness::<VersionControlServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::VersionControl).await;
     let mut client = test_util::TestClient::connect(&harness.endpoint()).await.unwrap();
+    assert_eq!(recv_response(&mut client).await.unwrap(), "READY\n");
 
     send_request(&mut client, "LIST ..").await.unwrap();
     let response = recv_response(&mut client).await.unwrap();
@@ -124,8 +95,9 @@ async fn test_list_illegal_dir_dot_dot() {
 
 #[tokio::test]
 async fn test_list_illegal_dir_arbitrary() {
-    let harness = ServerHarness::<VersionControlServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::VersionControl).await;
     let mut client = test_util::TestClient::connect(&harness.endpoint()).await.unwrap();
+    assert_eq!(recv_response(&mut client).await.unwrap(), "READY\n");
 
     send_request(&mut client, "LIST asd").await.unwrap();
     let response = recv_response(&mut client).await.unwrap();
@@ -135,32 +107,37 @@ async fn test_list_illegal_dir_arbitrary() {
 
 #[tokio::test]
 async fn test_get_non_existent_file() {
-    let harness = ServerHarness::<VersionControlServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::VersionControl).await;
     let mut client = test_util::TestClient::connect(&harness.endpoint()).await.unwrap();
+    assert_eq!(recv_response(&mut client).await.unwrap(), "READY\n");
 
     send_request(&mut client, "GET /non_existent_file.txt").await.unwrap();
     let response = recv_response(&mut client).await.unwrap();
 
-    assert_eq!(response, "ERR usage: GET file [revision]\n");
+    assert_eq!(response, "ERR no such file\n");
 }
 
 #[tokio::test]
 async fn test_get_file_after_put() {
-    let harness = ServerHarness::<VersionControlServer>::new().await;
+    let harness = ServiceHarness::start(ServiceKind::VersionControl).await;
     let mut client = test_util::TestClient::connect(&harness.endpoint()).await.unwrap();
+    assert_eq!(recv_response(&mut client).await.unwrap(), "READY\n");
 
     let file_path = "/test/get_file.txt";
     let file_content = "This is content for GET test.";
     let content_length = file_content.len();
 
     let put_request = format!("PUT {} {}\n{}", file_path, content_length, file_content);
-    send_request(&mut client, &put_request).await.unwrap();
+    client.send(&put_request).await.unwrap();
     let put_response = recv_response(&mut client).await.unwrap();
     assert!(put_response.starts_with("OK r"));
+    assert_eq!(recv_response(&mut client).await.unwrap(), "READY\n");
 
     send_request(&mut client, &format!("GET {}", file_path)).await.unwrap();
     let get_response = recv_response(&mut client).await.unwrap();
+    assert_eq!(get_response, format!("OK {}\n", content_length));
 
-    // Currently, the server returns "Fix" for GET. This will need to be updated in production code.
-    assert_eq!(get_response, "Fix\n");
+    let data = client.read_exact(content_length).await.unwrap();
+    assert_eq!(data, file_content.as_bytes());
+    assert_eq!(recv_response(&mut client).await.unwrap(), "READY\n");
 }